@@ -0,0 +1,78 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use raytracing::sampling::{random_in_unit_sphere, random_unit_vector};
+use raytracing::vector::Vec3;
+
+const SAMPLES: u64 = 10_000_000;
+
+/// Counts the random numbers drawn through it.
+struct Counted<R> {
+    rng: R,
+    draws: u64,
+}
+
+impl<R: RngCore> RngCore for Counted<R> {
+    fn next_u32(&mut self) -> u32 {
+        self.draws += 1;
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.draws += 1;
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// The rejection sampling the direct mappings replaced, as a baseline.
+fn rejection_in_unit_sphere<T: Rng + ?Sized>(rng: &mut T) -> Vec3 {
+    loop {
+        let p = Vec3::random_range(rng, -1., 1.);
+        if p.length_squared() < 1.0 {
+            return p;
+        }
+    }
+}
+
+fn rejection_unit_vector<T: Rng + ?Sized>(rng: &mut T) -> Vec3 {
+    rejection_in_unit_sphere(rng).unit_vector()
+}
+
+/// Times `sample` with the generator the renderer uses, printing the time
+/// and random numbers drawn per sample.
+fn bench(name: &str, sample: fn(&mut Counted<StdRng>) -> Vec3) {
+    let mut rng = Counted {
+        rng: StdRng::seed_from_u64(0),
+        draws: 0,
+    };
+    let start = Instant::now();
+    for _ in 0..SAMPLES {
+        black_box(sample(&mut rng));
+    }
+    let elapsed = start.elapsed();
+    println!(
+        "{:<24} {:>7.2} ns/sample {:>6.3} draws/sample",
+        name,
+        elapsed.as_nanos() as f64 / SAMPLES as f64,
+        rng.draws as f64 / SAMPLES as f64
+    );
+}
+
+/// Compares the direct sampling of the unit ball and sphere with the
+/// rejection loops it replaced. Run with `cargo bench --bench sampling`.
+fn main() {
+    bench("rejection unit ball", rejection_in_unit_sphere);
+    bench("direct unit ball", random_in_unit_sphere);
+    bench("rejection unit vector", rejection_unit_vector);
+    bench("direct unit vector", random_unit_vector);
+}
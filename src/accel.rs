@@ -0,0 +1,294 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::bounds::{LinearBVH, AABB, BVH};
+use crate::grid::Grid;
+use crate::kdtree::KdTree;
+use crate::ray::Hit;
+use crate::world::World;
+
+/// A spatial structure that speeds up finding the closest object along a ray.
+pub trait Accelerator {
+    /// Builds the structure over `objects` as they are during `time`.
+    fn build(objects: Vec<Box<dyn Hit>>, time: (f64, f64)) -> Box<dyn Hit> {
+        Self::build_with_stats(objects, time).0
+    }
+
+    /// Like `build`, also describing the structure that was built.
+    fn build_with_stats(objects: Vec<Box<dyn Hit>>, time: (f64, f64))
+        -> (Box<dyn Hit>, AccelStats);
+}
+
+/// The shape of a built acceleration structure, for reports and tuning.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AccelStats {
+    /// Objects placed in the structure.
+    pub objects: usize,
+    /// Objects tested alongside the structure rather than placed in it,
+    /// such as those without bounds.
+    pub unbounded: usize,
+    pub interior_nodes: usize,
+    pub leaves: usize,
+    /// Most interior nodes passed through on the way from the root to a
+    /// leaf.
+    pub max_depth: usize,
+}
+
+impl AccelStats {
+    /// Stats of a single leaf holding `objects` objects.
+    pub(crate) fn leaf(objects: usize) -> Self {
+        Self {
+            objects,
+            leaves: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Stats of an interior node with subtrees `left` and `right`.
+    pub(crate) fn join(left: Self, right: Self) -> Self {
+        Self {
+            objects: left.objects + right.objects,
+            unbounded: left.unbounded + right.unbounded,
+            interior_nodes: left.interior_nodes + right.interior_nodes + 1,
+            leaves: left.leaves + right.leaves,
+            max_depth: left.max_depth.max(right.max_depth) + 1,
+        }
+    }
+}
+
+impl Accelerator for World {
+    /// Tests every object in turn, which is only sensible for tiny scenes or
+    /// as a baseline to benchmark the other structures against.
+    fn build_with_stats(
+        objects: Vec<Box<dyn Hit>>,
+        _time: (f64, f64),
+    ) -> (Box<dyn Hit>, AccelStats) {
+        let stats = AccelStats::leaf(objects.len());
+        (Box::new(World::new(objects)), stats)
+    }
+}
+
+/// A node of a built acceleration structure, for drawing the structure to
+/// see how well it fits the objects.
+#[derive(Clone, Copy)]
+pub struct AccelNode {
+    pub bounds: AABB,
+    /// Levels below the root; the root is 0.
+    pub depth: usize,
+    /// Whether the node holds objects rather than other nodes.
+    pub leaf: bool,
+}
+
+/// The nodes of the structure `world`, which is built over objects as they
+/// are during `time`. Objects tested alongside the structure, such as
+/// unbounded ones, are left out, as are structures inside objects such as
+/// meshes.
+pub fn accel_nodes(world: &dyn Hit, time: (f64, f64)) -> Vec<AccelNode> {
+    let mut nodes = vec![];
+    world.accel_nodes(0, time, &mut nodes);
+    nodes
+}
+
+/// Which of an acceleration structure's nodes to write out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NodeSelection {
+    /// Every node, grouped by level.
+    #[default]
+    All,
+    /// Only the leaves, which show how tightly the objects are bounded.
+    Leaves,
+    /// The nodes at one level below the root.
+    Level(usize),
+}
+
+impl NodeSelection {
+    pub fn contains(self, node: &AccelNode) -> bool {
+        match self {
+            NodeSelection::All => true,
+            NodeSelection::Leaves => node.leaf,
+            NodeSelection::Level(depth) => node.depth == depth,
+        }
+    }
+}
+
+impl FromStr for NodeSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(NodeSelection::All),
+            "leaves" => Ok(NodeSelection::Leaves),
+            _ => s
+                .parse()
+                .map(NodeSelection::Level)
+                .map_err(|_| format!("unknown node selection {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for NodeSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NodeSelection::All => write!(f, "all"),
+            NodeSelection::Leaves => write!(f, "leaves"),
+            NodeSelection::Level(depth) => write!(f, "{}", depth),
+        }
+    }
+}
+
+/// Writes the `selection` of `nodes` as a Wavefront OBJ wireframe of their
+/// boxes' edges, with a group for each level (`level_0` is the root) and
+/// one for the leaves, for opening in a 3D modelling package.
+pub fn write_obj<W: Write>(
+    nodes: &[AccelNode],
+    selection: NodeSelection,
+    w: &mut W,
+) -> io::Result<()> {
+    const EDGES: [(usize, usize); 12] = [
+        (0, 1),
+        (2, 3),
+        (4, 5),
+        (6, 7),
+        (0, 2),
+        (1, 3),
+        (4, 6),
+        (5, 7),
+        (0, 4),
+        (1, 5),
+        (2, 6),
+        (3, 7),
+    ];
+
+    let group = |node: &AccelNode| {
+        if node.leaf {
+            "leaves".to_string()
+        } else {
+            format!("level_{}", node.depth)
+        }
+    };
+    let mut selected: Vec<&AccelNode> = nodes.iter().filter(|n| selection.contains(n)).collect();
+    selected.sort_by_key(|n| (n.leaf, n.depth));
+
+    writeln!(w, "# {} acceleration structure nodes", selected.len())?;
+    let mut current = None;
+    for (i, node) in selected.iter().enumerate() {
+        let name = group(node);
+        if current.as_ref() != Some(&name) {
+            writeln!(w, "g {}", name)?;
+            current = Some(name);
+        }
+
+        let AABB { min, max } = node.bounds;
+        // Corner k takes max on the axes whose bit is set in k
+        for k in 0..8 {
+            let x = if k & 1 == 0 { min.x() } else { max.x() };
+            let y = if k & 2 == 0 { min.y() } else { max.y() };
+            let z = if k & 4 == 0 { min.z() } else { max.z() };
+            writeln!(w, "v {} {} {}", x, y, z)?;
+        }
+        // OBJ indices count from 1
+        let first = 8 * i + 1;
+        for (a, b) in EDGES {
+            writeln!(w, "l {} {}", first + a, first + b)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Selects which `Accelerator` to build the scene with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AcceleratorKind {
+    #[default]
+    Bvh,
+    /// A BVH built from Morton codes, quicker to build but slower to trace.
+    Lbvh,
+    KdTree,
+    Grid,
+    None,
+}
+
+impl AcceleratorKind {
+    pub fn build(self, objects: Vec<Box<dyn Hit>>, time: (f64, f64)) -> Box<dyn Hit> {
+        self.build_with_stats(objects, time).0
+    }
+
+    pub fn build_with_stats(
+        self,
+        objects: Vec<Box<dyn Hit>>,
+        time: (f64, f64),
+    ) -> (Box<dyn Hit>, AccelStats) {
+        match self {
+            AcceleratorKind::Bvh => BVH::build_with_stats(objects, time),
+            AcceleratorKind::Lbvh => LinearBVH::build_with_stats(objects, time),
+            AcceleratorKind::KdTree => KdTree::build_with_stats(objects, time),
+            AcceleratorKind::Grid => Grid::build_with_stats(objects, time),
+            AcceleratorKind::None => World::build_with_stats(objects, time),
+        }
+    }
+}
+
+impl FromStr for AcceleratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bvh" => Ok(AcceleratorKind::Bvh),
+            "lbvh" => Ok(AcceleratorKind::Lbvh),
+            "kdtree" => Ok(AcceleratorKind::KdTree),
+            "grid" => Ok(AcceleratorKind::Grid),
+            "none" => Ok(AcceleratorKind::None),
+            _ => Err(format!("unknown accelerator {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for AcceleratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            AcceleratorKind::Bvh => "bvh",
+            AcceleratorKind::Lbvh => "lbvh",
+            AcceleratorKind::KdTree => "kdtree",
+            AcceleratorKind::Grid => "grid",
+            AcceleratorKind::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// An object along with its bounds.
+pub(crate) type Bounded = (AABB, Box<dyn Hit>);
+
+/// Separates objects that have bounds over `time`, which can be placed in an
+/// acceleration structure, from those that don't and must always be tested.
+pub(crate) fn partition_bounded(
+    objects: Vec<Box<dyn Hit>>,
+    time: (f64, f64),
+) -> (Vec<Bounded>, Vec<Box<dyn Hit>>) {
+    let mut bounded = vec![];
+    let mut unbounded = vec![];
+
+    for object in objects {
+        match object.bounds(time) {
+            Some(bounds) => bounded.push((bounds, object)),
+            None => unbounded.push(object),
+        }
+    }
+
+    (bounded, unbounded)
+}
+
+/// Combines a structure built over the bounded objects with the unbounded ones.
+pub(crate) fn with_unbounded(
+    structure: Option<Box<dyn Hit>>,
+    mut unbounded: Vec<Box<dyn Hit>>,
+) -> Box<dyn Hit> {
+    unbounded.extend(structure);
+
+    if unbounded.len() == 1 {
+        return unbounded.pop().unwrap();
+    }
+
+    Box::new(World::new(unbounded))
+}
@@ -0,0 +1,240 @@
+use std::fmt;
+use std::ops::{Add, Mul};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::bounds::AABB;
+use crate::export::Tessellation;
+use crate::instance::{to_object, transform_hit};
+use crate::ray::{Hit, HitRecord, Ray};
+use crate::scene::SceneStats;
+use crate::transform::Transform;
+use crate::vector::Vec3;
+
+/// How a track fills in values between its keyframes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Straight lines between keys, with sudden changes of speed at each.
+    #[default]
+    Linear,
+    /// A cubic curve through every key, which moves smoothly through them.
+    /// Tangents come from the neighbouring keys, so no handles are needed.
+    CatmullRom,
+}
+
+impl FromStr for Interpolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(Interpolation::Linear),
+            "catmull-rom" => Ok(Interpolation::CatmullRom),
+            _ => Err(format!("unknown interpolation {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Interpolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Interpolation::Linear => "linear",
+            Interpolation::CatmullRom => "catmull-rom",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A value that changes over time, given by keyframes. Before the first key
+/// and after the last the value holds still.
+#[derive(Clone, Debug)]
+pub struct Track<T> {
+    keys: Vec<(f64, T)>,
+    interpolation: Interpolation,
+}
+
+impl<T> Track<T>
+where
+    T: Copy + Add<Output = T> + Mul<f64, Output = T>,
+{
+    /// A track through `keys`, given as `(time, value)` pairs in any order.
+    ///
+    /// # Panics
+    ///
+    /// If there are no keys.
+    pub fn new(mut keys: Vec<(f64, T)>, interpolation: Interpolation) -> Self {
+        assert!(!keys.is_empty(), "a track needs at least one key");
+        keys.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self {
+            keys,
+            interpolation,
+        }
+    }
+
+    /// A track that never changes.
+    pub fn constant(value: T) -> Self {
+        Self::new(vec![(0., value)], Interpolation::Linear)
+    }
+
+    pub fn keys(&self) -> &[(f64, T)] {
+        &self.keys
+    }
+
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.keys.len() == 1
+    }
+
+    pub fn at(&self, time: f64) -> T {
+        let keys = &self.keys;
+        let last = keys.len() - 1;
+        if time <= keys[0].0 {
+            return keys[0].1;
+        }
+        if time >= keys[last].0 {
+            return keys[last].1;
+        }
+
+        // The segment from key i to key i + 1 holds `time`
+        let i = keys.partition_point(|&(t, _)| t <= time) - 1;
+        let (t1, p1) = keys[i];
+        let (t2, p2) = keys[i + 1];
+        let s = (time - t1) / (t2 - t1);
+
+        match self.interpolation {
+            Interpolation::Linear => p1 * (1. - s) + p2 * s,
+            Interpolation::CatmullRom => {
+                // The end keys stand in for their missing neighbours
+                let p0 = keys[i.saturating_sub(1)].1;
+                let p3 = keys[(i + 2).min(last)].1;
+
+                let s2 = s * s;
+                let s3 = s2 * s;
+                p0 * (0.5 * (-s3 + 2. * s2 - s))
+                    + p1 * (0.5 * (3. * s3 - 5. * s2 + 2.))
+                    + p2 * (0.5 * (-3. * s3 + 4. * s2 + s))
+                    + p3 * (0.5 * (s3 - s2))
+            }
+        }
+    }
+}
+
+/// An object transform made from separately animated parts. At each moment
+/// the object is scaled, then rotated about the x, y and z axes in turn, and
+/// then moved into place.
+#[derive(Clone)]
+pub struct AnimatedTransform {
+    pub translation: Track<Vec3>,
+    /// Rotations about the x, y and z axes in degrees.
+    pub rotation: Track<Vec3>,
+    pub scale: Track<Vec3>,
+}
+
+impl AnimatedTransform {
+    pub fn at(&self, time: f64) -> Transform {
+        let rotation = self.rotation.at(time);
+        Transform::scale(self.scale.at(time))
+            .then(Transform::rotate_x(rotation.x()))
+            .then(Transform::rotate_y(rotation.y()))
+            .then(Transform::rotate_z(rotation.z()))
+            .then(Transform::translate(self.translation.at(time)))
+    }
+
+    /// Times at which to sample the transform over `time` to find how far an
+    /// object moves: both ends, every key in between and some evenly spaced
+    /// times to follow the curves and rotations.
+    fn sample_times(&self, time: (f64, f64)) -> Vec<f64> {
+        const STEPS: usize = 16;
+
+        let keys = [&self.translation, &self.rotation, &self.scale]
+            .into_iter()
+            .flat_map(|track| track.keys().iter().map(|&(t, _)| t))
+            .filter(|&t| time.0 < t && t < time.1);
+        let steps = (0..=STEPS).map(|i| time.0 + (time.1 - time.0) * i as f64 / STEPS as f64);
+
+        steps.chain(keys).collect()
+    }
+}
+
+impl Default for AnimatedTransform {
+    fn default() -> Self {
+        Self {
+            translation: Track::constant(Vec3::zero()),
+            rotation: Track::constant(Vec3::zero()),
+            scale: Track::constant(Vec3::new(1., 1., 1.)),
+        }
+    }
+}
+
+/// The time either side of a hit to see where the transform moves it, for its
+/// velocity.
+const VELOCITY_STEP: f64 = 1e-4;
+
+/// Like an `Instance`, but with a transform that changes over time. Each ray
+/// sees the object where it is at the ray's time, which blurs it over the
+/// shutter interval.
+pub struct AnimatedInstance {
+    object: Arc<dyn Hit>,
+    transform: AnimatedTransform,
+}
+
+impl AnimatedInstance {
+    pub fn new(object: Arc<dyn Hit>, transform: AnimatedTransform) -> Self {
+        Self { object, transform }
+    }
+
+    pub fn transform(&self) -> &AnimatedTransform {
+        &self.transform
+    }
+}
+
+impl Hit for AnimatedInstance {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let transform = self.transform.at(r.time);
+        let mut hit = transform_hit(self.object.as_ref(), transform, r, t_min, t_max)?;
+
+        // The hit point is carried along by the transform as well as moving
+        // with the object
+        let local = transform.inverse().point(hit.p);
+        let before = self.transform.at(r.time - VELOCITY_STEP).point(local);
+        let after = self.transform.at(r.time + VELOCITY_STEP).point(local);
+        hit.velocity += (after - before) / (2. * VELOCITY_STEP);
+
+        Some(hit)
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        let local = to_object(self.transform.at(r.time), r);
+        self.object.occluded(local, t_min, t_max)
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        let local = to_object(self.transform.at(r.time), r);
+        self.object.transmittance(local, t_min, t_max)
+    }
+
+    /// The union of the object's bounds at a number of times over the
+    /// interval. Between those times a rotating object can poke slightly
+    /// outside it.
+    fn bounds(&self, time: (f64, f64)) -> Option<AABB> {
+        let bounds = self.object.bounds(time)?;
+        self.transform
+            .sample_times(time)
+            .into_iter()
+            .map(|t| self.transform.at(t).bounds(bounds))
+            .reduce(|a, b| a + b)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add("animated instance", std::mem::size_of::<Self>());
+        stats.add_shared(&self.object);
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        let mut local = Tessellation::new(mesh.tolerance);
+        self.object.tessellate(time, &mut local);
+        mesh.add_transformed(local, &self.transform.at(time));
+    }
+}
@@ -0,0 +1,325 @@
+use std::f64::consts::PI;
+
+use crate::color::{Color, BLACK, WHITE};
+use crate::ray::Ray;
+use crate::sun::SolarTime;
+use crate::vector::Vec3;
+
+/// Radiance arriving along rays that escape the scene.
+pub trait Background {
+    fn color(&self, r: Ray) -> Color;
+}
+
+pub struct SolidColor {
+    pub color: Color,
+}
+
+impl SolidColor {
+    pub const fn new(color: Color) -> Self {
+        Self { color }
+    }
+}
+
+impl Background for SolidColor {
+    fn color(&self, _r: Ray) -> Color {
+        self.color
+    }
+}
+
+/// Blends linearly between two colors based on the ray's vertical direction.
+pub struct VerticalGradient {
+    pub bottom: Color,
+    pub top: Color,
+}
+
+impl VerticalGradient {
+    pub const fn new(bottom: Color, top: Color) -> Self {
+        Self { bottom, top }
+    }
+}
+
+impl Default for VerticalGradient {
+    fn default() -> Self {
+        Self::new(WHITE, Color::new(0.5, 0.7, 1.))
+    }
+}
+
+impl Background for VerticalGradient {
+    fn color(&self, r: Ray) -> Color {
+        let unit_direction = r.direction.unit_vector();
+        let t = 0.5 * (unit_direction.y() + 1.);
+        self.bottom.lerp(self.top, t)
+    }
+}
+
+/// An equirectangular (latitude/longitude) environment image.
+pub struct Hdri {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    pub intensity: f64,
+    /// Rotation around the vertical axis, in degrees.
+    pub rotation: f64,
+}
+
+impl Hdri {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height, "pixel count mismatch");
+
+        Self {
+            width,
+            height,
+            pixels,
+            intensity: 1.,
+            rotation: 0.,
+        }
+    }
+}
+
+impl Background for Hdri {
+    fn color(&self, r: Ray) -> Color {
+        let d = r.direction.unit_vector();
+
+        let phi = d.z().atan2(d.x()) + self.rotation.to_radians();
+        let theta = d.y().clamp(-1., 1.).acos();
+
+        let u = (phi / (2. * PI)).rem_euclid(1.);
+        let v = theta / PI;
+
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+
+        self.pixels[j * self.width + i] * self.intensity
+    }
+}
+
+/// A simple procedural daylight sky with a sun disk.
+pub struct Sky {
+    pub zenith: Color,
+    pub horizon: Color,
+    pub ground: Color,
+    pub sun_direction: Vec3,
+    pub sun_color: Color,
+    /// Angular radius of the sun disk, in degrees.
+    pub sun_radius: f64,
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Self {
+            zenith: Color::new(0.2, 0.4, 0.9),
+            horizon: Color::new(0.8, 0.85, 0.9),
+            ground: Color::new(0.3, 0.28, 0.25),
+            sun_direction: Vec3::new(0.3, 0.6, 0.4).unit_vector(),
+            sun_color: Color::new(20., 18., 15.),
+            sun_radius: 0.5,
+        }
+    }
+}
+
+impl Sky {
+    /// The default sky with the sun where it is at `time`, seen from its
+    /// place. Only the sun moves; the sky's colors stay the same.
+    pub fn at(time: &SolarTime) -> Self {
+        Self {
+            sun_direction: time.sun_direction(),
+            ..Self::default()
+        }
+    }
+}
+
+impl Background for Sky {
+    fn color(&self, r: Ray) -> Color {
+        let d = r.direction.unit_vector();
+
+        if d.dot_product(self.sun_direction) >= self.sun_radius.to_radians().cos() {
+            return self.sun_color;
+        }
+
+        let elevation = d.y();
+        if elevation < 0. {
+            return self.horizon.lerp(self.ground, (-elevation * 4.).min(1.));
+        }
+
+        self.horizon.lerp(self.zenith, elevation.sqrt())
+    }
+}
+
+/// A sky worked out from how sunlight scatters in a planet's atmosphere:
+/// Rayleigh scattering off air molecules, which favors blue and gives the
+/// blue sky and red sunsets, and Mie scattering off haze, which brightens
+/// the sky around the sun. Each ray gathers light scattered towards it once,
+/// dimmed on the way in and out by the air it passes through.
+///
+/// The scene is taken to be a small patch at `altitude` on the planet's
+/// surface, with +y up, so the sky only depends on the ray's direction.
+/// Lengths are in meters and coefficients per meter; the defaults are
+/// Earth's.
+pub struct Atmosphere {
+    pub planet_radius: f64,
+    /// Thickness of the atmosphere, past which the air is taken to be too
+    /// thin to scatter.
+    pub atmosphere_height: f64,
+    /// Height of the viewer above the surface. Above `atmosphere_height`,
+    /// the planet and its atmosphere are seen from space.
+    pub altitude: f64,
+    /// Rayleigh scattering at the surface, for red, green and blue light.
+    pub rayleigh: Color,
+    /// Height over which air thins by a factor of e.
+    pub rayleigh_scale_height: f64,
+    /// Mie scattering at the surface, the same for every color.
+    pub mie: f64,
+    /// Mie absorption as a fraction of its scattering.
+    pub mie_absorption: f64,
+    /// Height over which haze thins by a factor of e.
+    pub mie_scale_height: f64,
+    /// How strongly haze scatters light forward, from 0 for evenly to 1.
+    pub mie_anisotropy: f64,
+    pub sun_direction: Vec3,
+    /// Brightness of sunlight before it enters the atmosphere, which is
+    /// also how bright the sun disk is drawn.
+    pub sun_intensity: f64,
+    /// Angular radius of the sun disk, in degrees.
+    pub sun_radius: f64,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Self {
+            planet_radius: 6_360_000.,
+            atmosphere_height: 60_000.,
+            altitude: 2.,
+            rayleigh: Color::new(5.802e-6, 13.558e-6, 33.1e-6),
+            rayleigh_scale_height: 8_000.,
+            mie: 3.996e-6,
+            mie_absorption: 0.11,
+            mie_scale_height: 1_200.,
+            mie_anisotropy: 0.8,
+            sun_direction: Sky::default().sun_direction,
+            sun_intensity: 20.,
+            sun_radius: 0.27,
+        }
+    }
+}
+
+/// Points along each ray at which light scattered towards it is gathered,
+/// and along the path from each of those to the sun.
+const VIEW_STEPS: usize = 16;
+const SUN_STEPS: usize = 8;
+
+impl Atmosphere {
+    /// Earth's atmosphere with the sun where it is at `time`, seen from its
+    /// place.
+    pub fn at(time: &SolarTime) -> Self {
+        Self {
+            sun_direction: time.sun_direction(),
+            ..Self::default()
+        }
+    }
+
+    fn top(&self) -> f64 {
+        self.planet_radius + self.atmosphere_height
+    }
+
+    /// Rayleigh and Mie densities at `p`, relative to the surface, with the
+    /// planet's center at the origin.
+    fn density(&self, p: Vec3) -> (f64, f64) {
+        let height = (p.length() - self.planet_radius).max(0.);
+        (
+            (-height / self.rayleigh_scale_height).exp(),
+            (-height / self.mie_scale_height).exp(),
+        )
+    }
+
+    /// Fraction of each color that makes it through `depth`, the Rayleigh
+    /// and Mie densities summed along a path.
+    fn transmittance(&self, (rayleigh, mie): (f64, f64)) -> Color {
+        let mie_extinction = self.mie * (1. + self.mie_absorption);
+        let tau = self.rayleigh * rayleigh + WHITE * (mie_extinction * mie);
+        Color::new((-tau.r()).exp(), (-tau.g()).exp(), (-tau.b()).exp())
+    }
+
+    /// Densities summed along the path from `p` to the sun, or None if the
+    /// planet is in the way.
+    fn depth_to_sun(&self, p: Vec3) -> Option<(f64, f64)> {
+        if sphere_span(p, self.sun_direction, self.planet_radius).is_some_and(|(t, _)| t > 0.) {
+            return None;
+        }
+        let (_, far) = sphere_span(p, self.sun_direction, self.top())?;
+        let step = far.max(0.) / SUN_STEPS as f64;
+
+        Some((0..SUN_STEPS).fold((0., 0.), |(rayleigh, mie), i| {
+            let (r, m) = self.density(p + self.sun_direction * ((i as f64 + 0.5) * step));
+            (rayleigh + r * step, mie + m * step)
+        }))
+    }
+
+    /// The sun disk, if `d` points at it, dimmed by `depth` of air.
+    fn sun_disk(&self, d: Vec3, depth: (f64, f64)) -> Color {
+        if d.dot_product(self.sun_direction) < self.sun_radius.to_radians().cos() {
+            return BLACK;
+        }
+        self.transmittance(depth) * self.sun_intensity
+    }
+}
+
+impl Background for Atmosphere {
+    fn color(&self, r: Ray) -> Color {
+        let d = r.direction.unit_vector();
+        let origin = Vec3::new(0., self.planet_radius + self.altitude, 0.);
+
+        let Some((near, far)) = sphere_span(origin, d, self.top()).filter(|&(_, far)| far > 0.)
+        else {
+            return self.sun_disk(d, (0., 0.));
+        };
+        let ground = sphere_span(origin, d, self.planet_radius)
+            .map(|(t, _)| t)
+            .filter(|&t| t > 0.);
+        let start = near.max(0.);
+        let end = ground.unwrap_or(far);
+        let step = (end - start) / VIEW_STEPS as f64;
+
+        let mut depth = (0., 0.);
+        let mut rayleigh = BLACK;
+        let mut mie = BLACK;
+        for i in 0..VIEW_STEPS {
+            let p = origin + d * (start + (i as f64 + 0.5) * step);
+            let (r, m) = self.density(p);
+            // Half this step's air lies between the sample and the viewer
+            let to_here = (depth.0 + 0.5 * r * step, depth.1 + 0.5 * m * step);
+            depth = (depth.0 + r * step, depth.1 + m * step);
+
+            let Some(to_sun) = self.depth_to_sun(p) else {
+                continue;
+            };
+            let t = self.transmittance((to_here.0 + to_sun.0, to_here.1 + to_sun.1));
+            rayleigh += t * (r * step);
+            mie += t * (m * step);
+        }
+
+        let mu = d.dot_product(self.sun_direction);
+        let rayleigh_phase = 3. / (16. * PI) * (1. + mu * mu);
+        let g = self.mie_anisotropy;
+        let mie_phase = (1. - g * g) / (4. * PI * (1. + g * g - 2. * g * mu).powf(1.5));
+        let sky = (rayleigh * self.rayleigh * rayleigh_phase + mie * (self.mie * mie_phase))
+            * self.sun_intensity;
+
+        if ground.is_some() {
+            return sky;
+        }
+        sky + self.sun_disk(d, depth)
+    }
+}
+
+/// Distances along the unit direction `d` from `p` at which it enters and
+/// leaves a sphere of `radius` around the origin, or None if it misses.
+fn sphere_span(p: Vec3, d: Vec3, radius: f64) -> Option<(f64, f64)> {
+    let half_b = p.dot_product(d);
+    let c = p.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - c;
+    if discriminant < 0. {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    Some((-half_b - root, -half_b + root))
+}
@@ -0,0 +1,127 @@
+use std::str::FromStr;
+
+use rayon::prelude::*;
+
+use crate::color::Color;
+
+/// The glow of light scattered in a lens and the eye around anything
+/// brighter than the display can show, such as lights and their
+/// reflections. It's worked out from the whole image after rendering.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bloom {
+    /// Luminance above which pixels glow; 1 is the brightest the display
+    /// shows.
+    pub threshold: f64,
+    /// How much of the light above the threshold spreads out into the glow.
+    pub intensity: f64,
+    /// How far the glow spreads before it fades out, as a fraction of the
+    /// image's height.
+    pub radius: f64,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 1.,
+            intensity: 0.2,
+            radius: 0.02,
+        }
+    }
+}
+
+impl Bloom {
+    /// The glow to add to an image of `width` by `height` `pixels`, in rows.
+    /// Light above the threshold is blurred with a Gaussian, in rows and
+    /// then columns, and spreads out of the image where it's near an edge.
+    pub fn glow(&self, width: usize, height: usize, pixels: &[Color]) -> Vec<Color> {
+        let bright: Vec<Color> = pixels
+            .iter()
+            .map(|&c| {
+                let luminance = c.luminance();
+                if luminance > self.threshold {
+                    c * ((luminance - self.threshold) / luminance * self.intensity)
+                } else {
+                    Color::zero()
+                }
+            })
+            .collect();
+
+        // The glow fades out at three standard deviations
+        let sigma = (self.radius * height as f64 / 3.).max(0.5);
+        let reach = (3. * sigma).ceil() as usize;
+        let weights: Vec<f64> = (0..=reach)
+            .map(|i| (-((i * i) as f64) / (2. * sigma * sigma)).exp())
+            .collect();
+        let total = weights[0] + 2. * weights[1..].iter().sum::<f64>();
+        let kernel: Vec<f64> = weights.iter().map(|w| w / total).collect();
+
+        let blur = |at: &dyn Fn(usize) -> Color, i: usize, len: usize| {
+            let start = i.saturating_sub(reach);
+            let end = (i + reach).min(len - 1);
+            (start..=end)
+                .map(|j| at(j) * kernel[i.abs_diff(j)])
+                .sum::<Color>()
+        };
+
+        let mut rows = vec![Color::zero(); width * height];
+        rows.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+            let line = &bright[y * width..(y + 1) * width];
+            for (x, out) in row.iter_mut().enumerate() {
+                *out = blur(&|j| line[j], x, width);
+            }
+        });
+
+        let mut glow = vec![Color::zero(); width * height];
+        glow.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+            for (x, out) in row.iter_mut().enumerate() {
+                *out = blur(&|j| rows[j * width + x], y, height);
+            }
+        });
+        glow
+    }
+}
+
+impl FromStr for Bloom {
+    type Err = String;
+
+    /// Parses `THRESHOLD[,INTENSITY[,RADIUS]]`, e.g. `1` or `1,0.1,0.05`,
+    /// leaving anything not given at its default.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid bloom {:?}", s);
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let default = Bloom::default();
+        let bloom = match values[..] {
+            [threshold] => Bloom {
+                threshold,
+                ..default
+            },
+            [threshold, intensity] => Bloom {
+                threshold,
+                intensity,
+                ..default
+            },
+            [threshold, intensity, radius] => Bloom {
+                threshold,
+                intensity,
+                radius,
+            },
+            _ => return Err(invalid()),
+        };
+
+        let valid = bloom.threshold >= 0.
+            && bloom.intensity >= 0.
+            && bloom.radius > 0.
+            && [bloom.threshold, bloom.intensity, bloom.radius]
+                .iter()
+                .all(|v| v.is_finite());
+        if valid {
+            Ok(bloom)
+        } else {
+            Err(invalid())
+        }
+    }
+}
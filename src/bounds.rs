@@ -1,6 +1,12 @@
+use rayon::prelude::*;
+
+use crate::accel::{partition_bounded, with_unbounded, AccelNode, AccelStats, Accelerator};
+use crate::export::Tessellation;
 use crate::ray::{Hit, HitRecord, Ray};
+use crate::scene::SceneStats;
 use crate::vector::Point3;
 
+#[derive(Clone, Copy)]
 pub struct AABB {
     pub min: Point3,
     pub max: Point3,
@@ -11,41 +17,52 @@ impl AABB {
         Self { min, max }
     }
 
-    pub fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
-        // X axis
-        let inv_d = r.direction.x().recip();
-        let t0 = (self.min.x() - r.origin.x()) * inv_d;
-        let t1 = (self.max.x() - r.origin.x()) * inv_d;
-        let (t0, t1) = if inv_d < 0. { (t1, t0) } else { (t0, t1) };
-        let t_min = if t0 > t_min { t0 } else { t_min };
-        let t_max = if t1 < t_max { t1 } else { t_max };
-        if t_max <= t_min {
-            return false;
-        }
+    pub fn centroid(&self) -> Point3 {
+        (self.min + self.max) * 0.5
+    }
 
-        // Y axis
-        let inv_d = r.direction.y().recip();
-        let t0 = (self.min.y() - r.origin.y()) * inv_d;
-        let t1 = (self.max.y() - r.origin.y()) * inv_d;
-        let (t0, t1) = if inv_d < 0. { (t1, t0) } else { (t0, t1) };
-        let t_min = if t0 > t_min { t0 } else { t_min };
-        let t_max = if t1 < t_max { t1 } else { t_max };
-        if t_max <= t_min {
-            return false;
-        }
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2. * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    /// Grows the box to include `p`.
+    pub fn include(self, p: Point3) -> Self {
+        self + AABB::new(p, p)
+    }
 
-        // Z axis
-        let inv_d = r.direction.z().recip();
-        let t0 = (self.min.z() - r.origin.z()) * inv_d;
-        let t1 = (self.max.z() - r.origin.z()) * inv_d;
-        let (t0, t1) = if inv_d < 0. { (t1, t0) } else { (t0, t1) };
-        let t_min = if t0 > t_min { t0 } else { t_min };
-        let t_max = if t1 < t_max { t1 } else { t_max };
-        if t_max <= t_min {
-            return false;
+    /// The part of `t_min` to `t_max` where `r` is inside the box, as the
+    /// distances along it where it enters and leaves, or None if it misses.
+    pub fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+
+        for axis in 0..3 {
+            let origin = r.origin.axis(axis);
+            let direction = r.direction.axis(axis);
+
+            // A ray parallel to the slab is inside it everywhere or nowhere.
+            // Dividing by zero instead would give 0 * inf = NaN for rays
+            // starting on the boundary.
+            if direction == 0. {
+                if origin < self.min.axis(axis) || origin > self.max.axis(axis) {
+                    return None;
+                }
+                continue;
+            }
+
+            let inv_d = r.inv_direction().axis(axis);
+            let t0 = (self.min.axis(axis) - origin) * inv_d;
+            let t1 = (self.max.axis(axis) - origin) * inv_d;
+            let (t0, t1) = if inv_d < 0. { (t1, t0) } else { (t0, t1) };
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return None;
+            }
         }
 
-        true
+        Some((t_min, t_max))
     }
 }
 
@@ -67,34 +84,836 @@ impl std::ops::Add for AABB {
     }
 }
 
-pub struct BVH {
-    left: Box<dyn Hit>,
-    right: Box<dyn Hit>,
+/// Number of buckets candidate SAH splits are evaluated over, per axis.
+const SAH_BINS: usize = 16;
+
+/// Cost of visiting an interior node relative to intersecting one primitive.
+const TRAVERSAL_COST: f64 = 0.125;
+
+/// Leaves never hold more than this many primitives.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// Subtrees with fewer primitives than this are built on the current thread,
+/// where spawning tasks would cost more than it saves.
+const PARALLEL_THRESHOLD: usize = 4096;
+
+/// Bits per axis of the Morton codes `LinearBVH` sorts objects by.
+const MORTON_BITS: u32 = 10;
+
+/// A bounding volume hierarchy over objects of type `T`, kept flat: its
+/// nodes in one array, each interior node followed by its left child, and
+/// its objects in another, with each leaf's objects next to each other.
+///
+/// However many objects there are, building one makes a handful of
+/// allocations rather than one per node, and dropping it frees them at once.
+/// A hierarchy over boxed objects can mix any kinds of objects; one over a
+/// single kind, such as a mesh's triangles, stores them in place without
+/// boxing each. Boxed objects, and their materials' textures, are still
+/// allocated one at a time when the scene is built.
+pub struct BVH<T: Hit = Box<dyn Hit>> {
+    nodes: Vec<Node>,
+    objects: Vec<T>,
+}
+
+#[derive(Clone, Copy)]
+struct Node {
     bounds: AABB,
+    kind: NodeKind,
 }
 
-impl BVH {}
+#[derive(Clone, Copy)]
+enum NodeKind {
+    /// Index of the right child; the left one follows this node.
+    Interior { right: usize },
+    /// The objects at `first..first + count`.
+    Leaf { first: usize, count: usize },
+}
 
-impl Hit for BVH {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if !self.bounds.hit(r, t_min, t_max) {
-            return None;
+impl Node {
+    /// The node moved along with the `nodes` and `objects` before it.
+    fn offset(self, nodes: usize, objects: usize) -> Self {
+        let kind = match self.kind {
+            NodeKind::Interior { right } => NodeKind::Interior {
+                right: right + nodes,
+            },
+            NodeKind::Leaf { first, count } => NodeKind::Leaf {
+                first: first + objects,
+                count,
+            },
+        };
+        Self { kind, ..self }
+    }
+}
+
+struct Primitive<T> {
+    object: T,
+    bounds: AABB,
+    centroid: Point3,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Bin {
+    bounds: Option<AABB>,
+    count: usize,
+}
+
+impl Bin {
+    fn add(self, bounds: AABB) -> Self {
+        Self {
+            bounds: Some(self.bounds.map_or(bounds, |b| b + bounds)),
+            count: self.count + 1,
+        }
+    }
+
+    fn merge(self, other: Self) -> Self {
+        let bounds = match (self.bounds, other.bounds) {
+            (Some(a), Some(b)) => Some(a + b),
+            (a, b) => a.or(b),
+        };
+
+        Self {
+            bounds,
+            count: self.count + other.count,
+        }
+    }
+}
+
+impl Accelerator for BVH {
+    /// Builds a hierarchy over `objects` using binned SAH splits, building
+    /// large subtrees in parallel.
+    ///
+    /// Objects without bounds over `time` cannot be placed in the hierarchy
+    /// and are tested alongside it instead.
+    fn build_with_stats(
+        objects: Vec<Box<dyn Hit>>,
+        time: (f64, f64),
+    ) -> (Box<dyn Hit>, AccelStats) {
+        let (bounded, unbounded) = partition_bounded(objects, time);
+
+        let (root, stats) = if bounded.is_empty() {
+            (None, AccelStats::default())
+        } else {
+            let (bvh, stats) = BVH::from_bounded(bounded);
+            (Some(Box::new(bvh) as Box<dyn Hit>), stats)
+        };
+        let stats = AccelStats {
+            unbounded: unbounded.len(),
+            ..stats
+        };
+
+        (with_unbounded(root, unbounded), stats)
+    }
+}
+
+impl<T: Hit> BVH<T> {
+    /// Builds a hierarchy over `objects` as they are during `time`, like
+    /// `Accelerator::build`. Every object must have bounds.
+    pub fn new(objects: Vec<T>, time: (f64, f64)) -> Self {
+        let bounded = objects
+            .into_iter()
+            .map(|object| {
+                let bounds = object
+                    .bounds(time)
+                    .expect("objects in a BVH must be bounded");
+                (bounds, object)
+            })
+            .collect();
+        Self::from_bounded(bounded).0
+    }
+
+    fn from_bounded(bounded: Vec<(AABB, T)>) -> (Self, AccelStats) {
+        let primitives: Vec<Primitive<T>> = bounded
+            .into_iter()
+            .map(|(bounds, object)| Primitive {
+                object,
+                bounds,
+                centroid: bounds.centroid(),
+            })
+            .collect();
+
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(2 * primitives.len()),
+            objects: Vec::with_capacity(primitives.len()),
+        };
+        let stats = if primitives.is_empty() {
+            AccelStats::default()
+        } else {
+            build_node(primitives, &mut bvh.nodes, &mut bvh.objects)
+        };
+        bvh.nodes.shrink_to_fit();
+
+        (bvh, stats)
+    }
+
+    fn leaf_objects(&self, first: usize, count: usize) -> &[T] {
+        &self.objects[first..first + count]
+    }
+
+    fn hit_node(&self, index: usize, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let node = &self.nodes[index];
+        node.bounds.hit(r, t_min, t_max)?;
+
+        match node.kind {
+            NodeKind::Leaf { first, count } => {
+                let mut closest: Option<HitRecord> = None;
+                for object in self.leaf_objects(first, count) {
+                    let t_max = closest.map_or(t_max, |hit| hit.t);
+                    if let Some(hit) = object.hit(r, t_min, t_max) {
+                        closest = Some(hit);
+                    }
+                }
+                closest
+            }
+            NodeKind::Interior { right } => {
+                let hit_left = self.hit_node(index + 1, r, t_min, t_max);
+
+                let t_max = if let Some(hit) = hit_left {
+                    hit.t
+                } else {
+                    t_max
+                };
+
+                let hit_right = self.hit_node(right, r, t_min, t_max);
+
+                hit_right.or(hit_left)
+            }
+        }
+    }
+
+    fn occluded_node(&self, index: usize, r: Ray, t_min: f64, t_max: f64) -> bool {
+        let node = &self.nodes[index];
+        node.bounds.hit(r, t_min, t_max).is_some()
+            && match node.kind {
+                NodeKind::Leaf { first, count } => self
+                    .leaf_objects(first, count)
+                    .iter()
+                    .any(|object| object.occluded(r, t_min, t_max)),
+                NodeKind::Interior { right } => {
+                    self.occluded_node(index + 1, r, t_min, t_max)
+                        || self.occluded_node(right, r, t_min, t_max)
+                }
+            }
+    }
+
+    fn transmittance_node(&self, index: usize, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        let node = &self.nodes[index];
+        if node.bounds.hit(r, t_min, t_max).is_none() {
+            return 1.;
+        }
+        match node.kind {
+            NodeKind::Leaf { first, count } => {
+                let mut transmittance = 1.;
+                for object in self.leaf_objects(first, count) {
+                    transmittance *= object.transmittance(r, t_min, t_max);
+                    if transmittance <= 0. {
+                        break;
+                    }
+                }
+                transmittance
+            }
+            NodeKind::Interior { right } => {
+                match self.transmittance_node(index + 1, r, t_min, t_max) {
+                    0. => 0.,
+                    left => left * self.transmittance_node(right, r, t_min, t_max),
+                }
+            }
+        }
+    }
+
+    fn hits_node<'a>(
+        &'a self,
+        index: usize,
+        r: Ray,
+        t_min: f64,
+        t_max: f64,
+        hits: &mut Vec<HitRecord<'a>>,
+    ) {
+        let node = &self.nodes[index];
+        if node.bounds.hit(r, t_min, t_max).is_none() {
+            return;
+        }
+        match node.kind {
+            NodeKind::Leaf { first, count } => {
+                for object in self.leaf_objects(first, count) {
+                    object.hits(r, t_min, t_max, hits);
+                }
+            }
+            NodeKind::Interior { right } => {
+                self.hits_node(index + 1, r, t_min, t_max, hits);
+                self.hits_node(right, r, t_min, t_max, hits);
+            }
+        }
+    }
+
+    fn node_accel_nodes(&self, index: usize, depth: usize, nodes: &mut Vec<AccelNode>) {
+        let node = &self.nodes[index];
+        let leaf = matches!(node.kind, NodeKind::Leaf { .. });
+        nodes.push(AccelNode {
+            bounds: node.bounds,
+            depth,
+            leaf,
+        });
+        if let NodeKind::Interior { right } = node.kind {
+            self.node_accel_nodes(index + 1, depth + 1, nodes);
+            self.node_accel_nodes(right, depth + 1, nodes);
+        }
+    }
+}
+
+/// Builds a `BVH` by sorting objects along a Morton (Z-order) curve through
+/// their centroids and splitting wherever the codes first differ, rather
+/// than by the surface area heuristic.
+///
+/// Building takes a few linear passes over the objects, so even millions
+/// of them are ready almost at once, but the tree is looser and rays take
+/// longer to trace through it. It suits previews and scenes rebuilt every
+/// frame, where build time matters more.
+pub struct LinearBVH;
+
+impl Accelerator for LinearBVH {
+    fn build_with_stats(
+        objects: Vec<Box<dyn Hit>>,
+        time: (f64, f64),
+    ) -> (Box<dyn Hit>, AccelStats) {
+        let (bounded, unbounded) = partition_bounded(objects, time);
+
+        let (root, stats) = if bounded.is_empty() {
+            (None, AccelStats::default())
+        } else {
+            let (bvh, stats) = BVH::linear_from_bounded(bounded);
+            (Some(Box::new(bvh) as Box<dyn Hit>), stats)
+        };
+        let stats = AccelStats {
+            unbounded: unbounded.len(),
+            ..stats
+        };
+
+        (with_unbounded(root, unbounded), stats)
+    }
+}
+
+impl<T: Hit> BVH<T> {
+    fn linear_from_bounded(bounded: Vec<(AABB, T)>) -> (Self, AccelStats) {
+        let centroid_bounds = bounded
+            .par_iter()
+            .map(|(bounds, _)| AABB::new(bounds.centroid(), bounds.centroid()))
+            .reduce_with(|a, b| a + b)
+            .expect("linear BVH needs at least one object");
+
+        // Each centroid's position in a grid of 2^MORTON_BITS cells along
+        // each axis, with the bits of the three coordinates interleaved
+        let cells = 1u32 << MORTON_BITS;
+        let mut codes: Vec<(u32, usize)> = bounded
+            .par_iter()
+            .enumerate()
+            .map(|(i, (bounds, _))| {
+                let centroid = bounds.centroid();
+                let cell = |axis: usize| {
+                    let min = centroid_bounds.min.axis(axis);
+                    let extent = centroid_bounds.max.axis(axis) - min;
+                    let t = if extent > 0. {
+                        (centroid.axis(axis) - min) / extent
+                    } else {
+                        0.
+                    };
+                    ((t * cells as f64) as u32).min(cells - 1)
+                };
+                let code =
+                    spread_bits(cell(0)) | spread_bits(cell(1)) << 1 | spread_bits(cell(2)) << 2;
+                (code, i)
+            })
+            .collect();
+        radix_sort(&mut codes);
+
+        // Objects in the order they lie along the curve
+        let mut slots: Vec<Option<(AABB, T)>> = bounded.into_iter().map(Some).collect();
+        let (bounds, objects): (Vec<AABB>, Vec<T>) =
+            codes.iter().map(|&(_, i)| slots[i].take().unwrap()).unzip();
+        let codes: Vec<u32> = codes.into_iter().map(|(code, _)| code).collect();
+
+        let mut nodes = Vec::with_capacity(2 * objects.len());
+        let stats = build_linear_node(&codes, &bounds, 0, 3 * MORTON_BITS, &mut nodes);
+        nodes.shrink_to_fit();
+
+        (Self { nodes, objects }, stats)
+    }
+}
+
+/// Spreads the low `MORTON_BITS` bits of `x` out to every third bit.
+fn spread_bits(x: u32) -> u32 {
+    let mut x = x & ((1 << MORTON_BITS) - 1);
+    x = (x | (x << 16)) & 0x0300_00ff;
+    x = (x | (x << 8)) & 0x0300_f00f;
+    x = (x | (x << 4)) & 0x030c_30c3;
+    x = (x | (x << 2)) & 0x0924_9249;
+    x
+}
+
+/// Sorts `items` by their codes a byte at a time, lowest first, which is
+/// stable and takes linear time.
+fn radix_sort(items: &mut Vec<(u32, usize)>) {
+    let mut sorted = vec![(0, 0); items.len()];
+    for shift in [0, 8, 16, 24] {
+        let digit = |&(code, _): &(u32, usize)| ((code >> shift) & 0xff) as usize;
+
+        // Where each digit's items start in the sorted order
+        let mut offsets = [0; 256];
+        for item in items.iter() {
+            offsets[digit(item)] += 1;
+        }
+        let mut total = 0;
+        for offset in offsets.iter_mut() {
+            (*offset, total) = (total, total + *offset);
+        }
+
+        for item in items.iter() {
+            let d = digit(item);
+            sorted[offsets[d]] = *item;
+            offsets[d] += 1;
+        }
+        std::mem::swap(items, &mut sorted);
+    }
+}
+
+/// Builds the subtree over objects `first..` with sorted Morton `codes` and
+/// `bounds`, which all share the bits of their codes above `bit`, adding its
+/// nodes to `nodes`.
+fn build_linear_node(
+    codes: &[u32],
+    bounds: &[AABB],
+    first: usize,
+    bit: u32,
+    nodes: &mut Vec<Node>,
+) -> AccelStats {
+    let n = codes.len();
+    if n <= MAX_LEAF_SIZE {
+        nodes.push(Node {
+            bounds: bounds.iter().copied().reduce(|a, b| a + b).unwrap(),
+            kind: NodeKind::Leaf { first, count: n },
+        });
+        return AccelStats::leaf(n);
+    }
+
+    // Codes are sorted, so those without the highest bit they differ in come
+    // first. Objects sharing a code are split evenly.
+    let mut bit = bit;
+    let split = loop {
+        if bit == 0 {
+            break n / 2;
         }
+        bit -= 1;
+        let split = codes.partition_point(|code| code & (1 << bit) == 0);
+        if split > 0 && split < n {
+            break split;
+        }
+    };
+
+    let index = nodes.len();
+    nodes.push(Node {
+        bounds: bounds[0],
+        kind: NodeKind::Interior { right: 0 },
+    });
+    let left = build_linear_node(&codes[..split], &bounds[..split], first, bit, nodes);
+    let right_index = nodes.len();
+    let right = build_linear_node(&codes[split..], &bounds[split..], first + split, bit, nodes);
+    nodes[index] = Node {
+        bounds: nodes[index + 1].bounds + nodes[right_index].bounds,
+        kind: NodeKind::Interior { right: right_index },
+    };
+
+    AccelStats::join(left, right)
+}
+
+/// Builds the subtree over `primitives`, adding its nodes to `nodes` and
+/// its objects to `objects`.
+fn build_node<T: Hit>(
+    mut primitives: Vec<Primitive<T>>,
+    nodes: &mut Vec<Node>,
+    objects: &mut Vec<T>,
+) -> AccelStats {
+    let n = primitives.len();
+    if n == 1 {
+        return make_leaf(primitives, nodes, objects);
+    }
 
-        let hit_left = self.left.hit(r, t_min, t_max);
+    let parallel = n >= PARALLEL_THRESHOLD;
+
+    let bounds = reduce_bounds(&primitives, parallel, |p| p.bounds);
+    let centroid_bounds =
+        reduce_bounds(&primitives, parallel, |p| AABB::new(p.centroid, p.centroid));
+
+    let split = find_split(&primitives, &bounds, &centroid_bounds, parallel);
+
+    let (left, right): (Vec<_>, Vec<_>) = match split {
+        Some((axis, bin)) => primitives
+            .into_iter()
+            .partition(|p| bin_index(p.centroid, &centroid_bounds, axis) <= bin),
+        None if n <= MAX_LEAF_SIZE => return make_leaf(primitives, nodes, objects),
+        None => {
+            // Splitting by cost is not possible (e.g. coincident centroids),
+            // so split the primitives evenly along the widest axis.
+            let axis = widest_axis(&centroid_bounds);
+            primitives.sort_by(|a, b| a.centroid.axis(axis).total_cmp(&b.centroid.axis(axis)));
+            let right = primitives.split_off(n / 2);
+            (primitives, right)
+        }
+    };
+
+    let index = nodes.len();
+    nodes.push(Node {
+        bounds,
+        kind: NodeKind::Interior { right: 0 },
+    });
+    let set_right = |nodes: &mut Vec<Node>| {
+        let right = nodes.len();
+        nodes[index].kind = NodeKind::Interior { right };
+    };
+
+    let (left_stats, right_stats) = if parallel {
+        // Each side builds into arrays of its own, which are then appended
+        let ((left, left_stats), (right, right_stats)) =
+            rayon::join(|| build_subtree(left), || build_subtree(right));
+        append_subtree(left, nodes, objects);
+        set_right(nodes);
+        append_subtree(right, nodes, objects);
+        (left_stats, right_stats)
+    } else {
+        let left_stats = build_node(left, nodes, objects);
+        set_right(nodes);
+        let right_stats = build_node(right, nodes, objects);
+        (left_stats, right_stats)
+    };
+
+    AccelStats::join(left_stats, right_stats)
+}
+
+/// Builds the subtree over `primitives` into arrays of its own.
+fn build_subtree<T: Hit>(primitives: Vec<Primitive<T>>) -> ((Vec<Node>, Vec<T>), AccelStats) {
+    let mut nodes = Vec::with_capacity(2 * primitives.len());
+    let mut objects = Vec::with_capacity(primitives.len());
+    let stats = build_node(primitives, &mut nodes, &mut objects);
+    ((nodes, objects), stats)
+}
+
+/// Appends a subtree built on its own to `nodes` and `objects`.
+fn append_subtree<T: Hit>(
+    (subtree_nodes, subtree_objects): (Vec<Node>, Vec<T>),
+    nodes: &mut Vec<Node>,
+    objects: &mut Vec<T>,
+) {
+    let (node_offset, object_offset) = (nodes.len(), objects.len());
+    nodes.extend(
+        subtree_nodes
+            .into_iter()
+            .map(|node| node.offset(node_offset, object_offset)),
+    );
+    objects.extend(subtree_objects);
+}
 
-        let t_max = if let Some(hit) = hit_left {
-            hit.t
+fn make_leaf<T: Hit>(
+    primitives: Vec<Primitive<T>>,
+    nodes: &mut Vec<Node>,
+    objects: &mut Vec<T>,
+) -> AccelStats {
+    let stats = AccelStats::leaf(primitives.len());
+    let bounds = primitives
+        .iter()
+        .map(|p| p.bounds)
+        .reduce(|a, b| a + b)
+        .expect("leaves hold at least one primitive");
+    nodes.push(Node {
+        bounds,
+        kind: NodeKind::Leaf {
+            first: objects.len(),
+            count: primitives.len(),
+        },
+    });
+    objects.extend(primitives.into_iter().map(|p| p.object));
+    stats
+}
+
+fn reduce_bounds<T: Hit, F>(primitives: &[Primitive<T>], parallel: bool, f: F) -> AABB
+where
+    F: Fn(&Primitive<T>) -> AABB + Send + Sync,
+{
+    let first = f(&primitives[0]);
+    if parallel {
+        primitives.par_iter().map(f).reduce(|| first, |a, b| a + b)
+    } else {
+        primitives.iter().map(f).fold(first, |a, b| a + b)
+    }
+}
+
+fn widest_axis(bounds: &AABB) -> usize {
+    let extent = bounds.max - bounds.min;
+    (0..3)
+        .max_by(|&a, &b| extent.axis(a).total_cmp(&extent.axis(b)))
+        .unwrap()
+}
+
+fn bin_index(centroid: Point3, centroid_bounds: &AABB, axis: usize) -> usize {
+    let min = centroid_bounds.min.axis(axis);
+    let extent = centroid_bounds.max.axis(axis) - min;
+    let t = (centroid.axis(axis) - min) / extent;
+    ((t * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+}
+
+/// Finds the cheapest split as (axis, last bin on the left side), or `None`
+/// if making a leaf is cheaper than any split.
+fn find_split<T: Hit>(
+    primitives: &[Primitive<T>],
+    bounds: &AABB,
+    centroid_bounds: &AABB,
+    parallel: bool,
+) -> Option<(usize, usize)> {
+    let n = primitives.len();
+    let leaf_cost = if n <= MAX_LEAF_SIZE {
+        n as f64
+    } else {
+        f64::INFINITY
+    };
+
+    let mut best: Option<(f64, usize, usize)> = None;
+
+    for axis in 0..3 {
+        let extent = centroid_bounds.max.axis(axis) - centroid_bounds.min.axis(axis);
+        if extent <= 0. {
+            continue;
+        }
+
+        let fill = |mut bins: [Bin; SAH_BINS], p: &Primitive<T>| {
+            let i = bin_index(p.centroid, centroid_bounds, axis);
+            bins[i] = bins[i].add(p.bounds);
+            bins
+        };
+        let merge = |a: [Bin; SAH_BINS], b: [Bin; SAH_BINS]| {
+            let mut merged = a;
+            for (m, b) in merged.iter_mut().zip(b) {
+                *m = m.merge(b);
+            }
+            merged
+        };
+
+        let bins = if parallel {
+            primitives
+                .par_iter()
+                .fold(|| [Bin::default(); SAH_BINS], fill)
+                .reduce(|| [Bin::default(); SAH_BINS], merge)
         } else {
-            t_max
+            primitives.iter().fold([Bin::default(); SAH_BINS], fill)
         };
 
-        let hit_right = self.right.hit(r, t_min, t_max);
+        // Sweep from the right to get the cost of everything right of each split
+        let mut right_area = [0.; SAH_BINS];
+        let mut right_count = [0; SAH_BINS];
+        let mut acc = Bin::default();
+        for i in (1..SAH_BINS).rev() {
+            acc = acc.merge(bins[i]);
+            right_area[i] = acc.bounds.map_or(0., |b| b.surface_area());
+            right_count[i] = acc.count;
+        }
+
+        let mut acc = Bin::default();
+        for i in 0..SAH_BINS - 1 {
+            acc = acc.merge(bins[i]);
+            if acc.count == 0 || right_count[i + 1] == 0 {
+                continue;
+            }
+
+            let left_area = acc.bounds.map_or(0., |b| b.surface_area());
+            let cost = TRAVERSAL_COST
+                + (left_area * acc.count as f64 + right_area[i + 1] * right_count[i + 1] as f64)
+                    / bounds.surface_area();
+
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, i));
+            }
+        }
+    }
+
+    match best {
+        Some((cost, axis, bin)) if cost < leaf_cost => Some((axis, bin)),
+        _ => None,
+    }
+}
+
+impl<T: Hit> Hit for BVH<T> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        self.hit_node(0, r, t_min, t_max)
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        !self.nodes.is_empty() && self.occluded_node(0, r, t_min, t_max)
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        if self.nodes.is_empty() {
+            return 1.;
+        }
+        self.transmittance_node(0, r, t_min, t_max)
+    }
+
+    fn hits<'a>(&'a self, r: Ray, t_min: f64, t_max: f64, hits: &mut Vec<HitRecord<'a>>) {
+        if !self.nodes.is_empty() {
+            self.hits_node(0, r, t_min, t_max, hits);
+        }
+    }
 
-        hit_right.or(hit_left)
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
+        self.nodes.first().map(|node| node.bounds)
     }
 
-    fn bounds(&self, time: (f64, f64)) -> Option<AABB> {
-        None
+    /// Recomputes node bounds bottom-up, keeping the tree topology.
+    ///
+    /// This is much cheaper than rebuilding, at the cost of the tree getting
+    /// looser as objects move away from where they were when it was built.
+    fn refit(&mut self, time: (f64, f64)) -> Option<AABB> {
+        // Checked before any node changes, so the tree is never left with
+        // some nodes refit and others not
+        let object_bounds: Option<Vec<AABB>> = self
+            .objects
+            .par_iter_mut()
+            .map(|object| object.refit(time))
+            .collect();
+        let object_bounds = object_bounds.expect("objects in a BVH must stay bounded");
+
+        // Children come after their parents, so going backwards reaches
+        // every node after its children
+        for index in (0..self.nodes.len()).rev() {
+            let bounds = match self.nodes[index].kind {
+                NodeKind::Leaf { first, count } => object_bounds[first..first + count]
+                    .iter()
+                    .copied()
+                    .reduce(|sum, item| sum + item)
+                    .expect("leaves hold at least one object"),
+                NodeKind::Interior { right } => {
+                    self.nodes[index + 1].bounds + self.nodes[right].bounds
+                }
+            };
+            self.nodes[index].bounds = bounds;
+        }
+
+        self.bounds(time)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        // Objects count themselves, so only the room left over is added
+        stats.memory += std::mem::size_of::<Self>()
+            + self.nodes.capacity() * std::mem::size_of::<Node>()
+            + (self.objects.capacity() - self.objects.len()) * std::mem::size_of::<T>();
+        for object in &self.objects {
+            object.count(stats);
+        }
+    }
+
+    fn accel_nodes(&self, depth: usize, _time: (f64, f64), nodes: &mut Vec<AccelNode>) {
+        if !self.nodes.is_empty() {
+            self.node_accel_nodes(0, depth, nodes);
+        }
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        for object in &self.objects {
+            object.tessellate(time, mesh);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Material;
+    use crate::vector::Vec3;
+    use crate::world::MovingSphere;
+
+    fn unit_box() -> AABB {
+        AABB::new(Point3::new(0., 0., 0.), Point3::new(1., 1., 1.))
+    }
+
+    fn hit(origin: (f64, f64, f64), direction: (f64, f64, f64)) -> Option<(f64, f64)> {
+        let r = Ray::new(
+            Point3::new(origin.0, origin.1, origin.2),
+            Vec3::new(direction.0, direction.1, direction.2),
+            0.,
+        );
+        unit_box().hit(r, 0., f64::INFINITY)
+    }
+
+    #[test]
+    fn axis_parallel_ray_inside_slabs_hits() {
+        assert_eq!(hit((-5., 0.5, 0.5), (1., 0., 0.)), Some((5., 6.)));
+        assert_eq!(hit((0.5, 5., 0.5), (0., -1., 0.)), Some((4., 5.)));
+        assert_eq!(hit((0.5, 0.5, -2.), (0., 0., 2.)), Some((1., 1.5)));
+    }
+
+    #[test]
+    fn axis_parallel_ray_outside_slabs_misses() {
+        assert_eq!(hit((-5., 2., 0.5), (1., 0., 0.)), None);
+        assert_eq!(hit((-5., 0.5, -0.1), (1., 0., 0.)), None);
+        assert_eq!(hit((0.5, 1.5, 5.), (0., 0., -1.)), None);
+    }
+
+    #[test]
+    fn ray_starting_on_slab_plane_parallel_to_it_hits() {
+        // Dividing by the zero direction would give (1 - 1) * inf = NaN
+        assert_eq!(hit((-5., 1., 0.5), (1., 0., 0.)), Some((5., 6.)));
+        assert_eq!(hit((-5., 0., 0.5), (1., 0., 0.)), Some((5., 6.)));
+        assert_eq!(hit((0.5, 0.5, 0.), (0., 1., 0.)), Some((0., 0.5)));
+    }
+
+    #[test]
+    fn ray_starting_on_slab_plane_across_it_hits() {
+        assert_eq!(hit((0., 0.5, 0.5), (1., 0., 0.)), Some((0., 1.)));
+        assert_eq!(hit((1., 0.5, 0.5), (1., 0., 0.)), Some((0., 0.)));
+        assert_eq!(hit((1., 0.5, 0.5), (-1., 0., 0.)), Some((0., 1.)));
+    }
+
+    #[test]
+    fn ray_along_box_edge_hits() {
+        assert_eq!(hit((-5., 1., 1.), (1., 0., 0.)), Some((5., 6.)));
+        assert_eq!(hit((0., -5., 0.), (0., 1., 0.)), Some((5., 6.)));
+        assert_eq!(hit((-5., 1. + 1e-9, 1.), (1., 0., 0.)), None);
+    }
+
+    #[test]
+    fn diagonal_ray_through_box_corner_hits() {
+        // Touching the box only along its edge at x = 0, y = 1
+        assert_eq!(hit((-1., 0., 0.5), (1., 1., 0.)), Some((1., 1.)));
+        assert_eq!(hit((-1., -1., 0.5), (1., 1., 0.)), Some((1., 2.)));
+        assert_eq!(hit((-1., -1., -1.), (1., 1., 1.)), Some((1., 2.)));
+    }
+
+    #[test]
+    fn hit_is_limited_to_ray_interval() {
+        let r = Ray::new(Point3::new(-5., 0.5, 0.5), Vec3::new(1., 0., 0.), 0.);
+        assert_eq!(unit_box().hit(r, 0., 4.), None);
+        assert_eq!(unit_box().hit(r, 5.5, 10.), Some((5.5, 6.)));
+        assert_eq!(unit_box().hit(r, 7., 10.), None);
+    }
+
+    #[test]
+    fn refit_follows_moving_objects() {
+        let material = Material::Lambertian {
+            albedo: crate::color::WHITE.into(),
+        };
+        let spheres = (0..4)
+            .map(|i| {
+                let from = Point3::new(0., 3. * i as f64, 0.);
+                let to = Point3::new(10., 3. * i as f64, 0.);
+                MovingSphere::new((0., 1.), (from, to), 0.5, material.clone())
+            })
+            .collect();
+        let mut bvh = BVH::new(spheres, (0., 0.));
+
+        let bounds = bvh.refit((1., 1.)).unwrap();
+        assert_eq!(
+            (bounds.min.x(), bounds.max.x(), bounds.max.y()),
+            (9.5, 10.5, 9.5)
+        );
+        let r = Ray::new(Point3::new(10., 6., -5.), Vec3::new(0., 0., 1.), 1.);
+        assert!(bvh.hit(r, 0., f64::INFINITY).is_some());
     }
 }
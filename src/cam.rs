@@ -1,22 +1,347 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
 use rand::Rng;
 
-use crate::ray::Ray;
-use crate::vector::{random_in_unit_disk, Point3, Vec3};
+use crate::animation::Track;
+use crate::glare::Glare;
+use crate::ray::{Ray, RayDifferential};
+use crate::sampling::random_in_unit_disk;
+use crate::vector::{Point3, Vec3};
 
 pub struct Camera {
+    view: View,
+    time: (f64, f64),
+    /// When set, the view is rebuilt at each ray's time instead of using
+    /// `view`, which then holds the view at the start of the shutter.
+    animation: Option<Arc<CameraAnimation>>,
+    exposure: Option<Exposure>,
+    shutter_curve: ShutterCurve,
+    /// Fraction of the shutter interval taken to read the sensor out from
+    /// top to bottom, if it's read a row at a time.
+    rolling_shutter: Option<f64>,
+    projection: Projection,
+    /// The starburst the aperture spreads around the brightest points, if
+    /// any, added once the image is rendered.
+    glare: Option<Glare>,
+    lens: Lens,
+    /// Distances in front of the camera that camera rays see between.
+    clip: (f64, f64),
+}
+
+/// How the image is laid out from the camera's view.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Projection {
+    /// An ordinary perspective view.
+    #[default]
+    Perspective,
+    /// A stereo pair for 3D displays: the left eye's view on the left half
+    /// of the image and the right eye's on the right, from either side of
+    /// the camera `interocular` apart. Each eye sees the middle half of the
+    /// camera's width, and the views line up at the focus distance, which
+    /// objects nearer than seem to stand out of the screen.
+    Stereo { interocular: f64 },
+    /// Omni-directional stereo, for VR headsets: equirectangular panoramas
+    /// of everything around the camera for the left eye above the right,
+    /// each twice as wide as it is high. Each direction is seen from the
+    /// eyes as they'd be with the head turned to face it, so depth looks
+    /// right all the way round but the top and bottom are distorted.
+    /// Depth of field is ignored.
+    Ods { interocular: f64 },
+}
+
+impl Projection {
+    /// Average human eye separation, in meters.
+    pub const DEFAULT_INTEROCULAR: f64 = 0.065;
+}
+
+impl FromStr for Projection {
+    type Err = String;
+
+    /// Accepts `perspective`, or `stereo` or `ods` optionally followed by
+    /// the interocular distance, e.g. `stereo:0.1`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("unknown projection {:?}", s);
+        let (name, interocular) = match s.split_once(':') {
+            Some((name, interocular)) => {
+                let interocular = interocular.trim().parse::<f64>().map_err(|_| invalid())?;
+                if interocular.is_nan() || interocular < 0. {
+                    return Err(invalid());
+                }
+                (name, Some(interocular))
+            }
+            None => (s, None),
+        };
+        let interocular = interocular.unwrap_or(Self::DEFAULT_INTEROCULAR);
+
+        match name {
+            "perspective" if s == name => Ok(Projection::Perspective),
+            "stereo" => Ok(Projection::Stereo { interocular }),
+            "ods" => Ok(Projection::Ods { interocular }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Projection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Projection::Perspective => write!(f, "perspective"),
+            Projection::Stereo { interocular } => write!(f, "stereo:{}", interocular),
+            Projection::Ods { interocular } => write!(f, "ods:{}", interocular),
+        }
+    }
+}
+
+/// How far open the shutter is over the time it's open, which shapes the
+/// streaks moving objects leave.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ShutterCurve {
+    /// Fully open the whole time, giving streaks of even brightness that
+    /// end abruptly.
+    #[default]
+    Box,
+    /// Opening steadily until halfway, then closing again, so streaks fade
+    /// out at both ends, more like a real shutter's.
+    Triangle,
+    /// How far open the shutter is at evenly spaced times from opening to
+    /// closing, with straight lines in between. For example, 0,1,1,0 takes
+    /// a third of the time to open and a third to close.
+    Custom(Vec<f64>),
+}
+
+impl ShutterCurve {
+    /// A fraction of the way through the shutter interval, picked in
+    /// proportion to how far open the shutter is then, using the uniform
+    /// random number `u`.
+    pub fn sample(&self, u: f64) -> f64 {
+        match self {
+            ShutterCurve::Box => u,
+            ShutterCurve::Triangle if u < 0.5 => (u / 2.).sqrt(),
+            ShutterCurve::Triangle => 1. - ((1. - u) / 2.).sqrt(),
+            ShutterCurve::Custom(openness) => {
+                // Each segment's share of the total light, found until the
+                // one `u` lands in
+                let total: f64 = openness.windows(2).map(|pair| pair[0] + pair[1]).sum();
+                let mut target = u * total;
+                let segments = openness.len() - 1;
+                for (i, pair) in openness.windows(2).enumerate() {
+                    let (a, b) = (pair[0], pair[1]);
+                    let area = a + b;
+                    if target < area || i == segments - 1 {
+                        // Where the area under the line from a to b reaches
+                        // `target`, solving (b - a) x² + 2ax = target
+                        let x = if (b - a).abs() < 1e-9 {
+                            target / area
+                        } else {
+                            ((a * a + (b - a) * target).max(0.).sqrt() - a) / (b - a)
+                        };
+                        return (i as f64 + x.clamp(0., 1.)) / segments as f64;
+                    }
+                    target -= area;
+                }
+                u
+            }
+        }
+    }
+}
+
+impl FromStr for ShutterCurve {
+    type Err = String;
+
+    /// Accepts `box`, `triangle` or comma separated values for a custom
+    /// curve.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => return Ok(ShutterCurve::Box),
+            "triangle" => return Ok(ShutterCurve::Triangle),
+            _ => {}
+        }
+
+        let invalid = || format!("unknown shutter curve {:?}", s);
+        let openness = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        let valid = openness.iter().all(|&v| v >= 0. && v.is_finite());
+        if openness.len() < 2 || !valid || openness.iter().sum::<f64>() <= 0. {
+            return Err(invalid());
+        }
+        Ok(ShutterCurve::Custom(openness))
+    }
+}
+
+impl fmt::Display for ShutterCurve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShutterCurve::Box => write!(f, "box"),
+            ShutterCurve::Triangle => write!(f, "triangle"),
+            ShutterCurve::Custom(openness) => {
+                let values: Vec<String> = openness.iter().map(f64::to_string).collect();
+                write!(f, "{}", values.join(","))
+            }
+        }
+    }
+}
+
+/// How much light a physical camera lets onto its sensor and how sensitive
+/// the sensor is, for scenes lit in physical units, with radiance in
+/// candelas per square meter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Exposure {
+    /// Sensor sensitivity.
+    pub iso: f64,
+    /// Focal length over aperture diameter.
+    pub f_number: f64,
+    /// Seconds the shutter is open.
+    pub shutter: f64,
+}
+
+impl Default for Exposure {
+    /// The "sunny 16" rule: ISO 100 at f/16 for 1/100 s exposes a sunlit
+    /// scene well.
+    fn default() -> Self {
+        Self {
+            iso: 100.,
+            f_number: 16.,
+            shutter: 0.01,
+        }
+    }
+}
+
+impl Exposure {
+    /// Height of a full frame 35mm sensor, in meters.
+    const SENSOR_HEIGHT: f64 = 0.024;
+
+    /// The exposure value at ISO 100 that lets in as much light.
+    pub fn ev100(&self) -> f64 {
+        (self.f_number * self.f_number / self.shutter * 100. / self.iso).log2()
+    }
+
+    /// The factor radiance is multiplied by to give pixel values, where 1
+    /// is the brightest the sensor records without clipping.
+    pub fn scale(&self) -> f64 {
+        // Saturation based sensitivity (ISO 12232), with the usual 78 / 65
+        // between the saturating and metered luminance
+        1. / (1.2 * self.ev100().exp2())
+    }
+
+    /// Diameter of the lens opening, in meters, for a full frame camera
+    /// with a `vertical_fov` in degrees. Narrower views come from longer
+    /// lenses, whose wider openings give shallower depth of field.
+    pub fn aperture(&self, vertical_fov: f64) -> f64 {
+        let focal_length = Self::SENSOR_HEIGHT / (2. * (vertical_fov.to_radians() / 2.).tan());
+        focal_length / self.f_number
+    }
+}
+
+/// The imperfections of a real lens, for matching renders to photographs
+/// or just making them look less perfect. All are off by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Lens {
+    /// Radial distortion: positive bows straight lines out from the middle
+    /// like a wide angle lens does (barrel), negative in towards it
+    /// (pincushion). At 0.1 the corners show what's 10% further out.
+    pub distortion: f64,
+    /// The fraction of light lost in the corners of the image, falling off
+    /// smoothly from none in the middle.
+    pub vignetting: f64,
+    /// Lateral chromatic aberration: how much larger the red image is than
+    /// the green, and the blue smaller, as a fraction, fringing edges
+    /// towards the corners. 0.005 is easily noticed. It's applied to the
+    /// image after rendering.
+    pub chromatic_aberration: f64,
+}
+
+impl Lens {
+    /// How far (s, t) is from the middle of an image of `aspect_ratio`,
+    /// squared, with the corners at 1.
+    fn radius_squared(s: f64, t: f64, aspect_ratio: f64) -> f64 {
+        let (x, y) = ((s - 0.5) * aspect_ratio, t - 0.5);
+        (x * x + y * y) / ((aspect_ratio * aspect_ratio + 1.) / 4.)
+    }
+
+    /// Where in the view the lens shows at (s, t) in the image.
+    pub fn distort(&self, s: f64, t: f64, aspect_ratio: f64) -> (f64, f64) {
+        if self.distortion == 0. {
+            return (s, t);
+        }
+        let scale = 1. + self.distortion * Self::radius_squared(s, t, aspect_ratio);
+        (0.5 + (s - 0.5) * scale, 0.5 + (t - 0.5) * scale)
+    }
+
+    /// Where in the image the lens shows (s, t) in the view, the inverse of
+    /// `distort`.
+    pub fn undistort(&self, s: f64, t: f64, aspect_ratio: f64) -> (f64, f64) {
+        if self.distortion == 0. {
+            return (s, t);
+        }
+        // Fixed point iteration converges quickly for moderate distortion
+        let (mut x, mut y) = (s, t);
+        for _ in 0..16 {
+            let scale = 1. + self.distortion * Self::radius_squared(x, y, aspect_ratio);
+            x = 0.5 + (s - 0.5) / scale;
+            y = 0.5 + (t - 0.5) / scale;
+        }
+        (x, y)
+    }
+
+    /// The fraction of light reaching (s, t) in the image.
+    pub fn transmission(&self, s: f64, t: f64, aspect_ratio: f64) -> f64 {
+        if self.vignetting == 0. {
+            return 1.;
+        }
+        let r2 = Self::radius_squared(s, t, aspect_ratio).min(1.);
+        // Smooth in the middle, like the cos^4 falloff of a real lens
+        1. - self.vignetting * r2 * r2
+    }
+}
+
+/// Where the camera is and what it sees, precomputed for generating rays.
+#[derive(Clone, Copy)]
+struct View {
     origin: Point3,
     lower_left_corner: Point3,
     horizontal: Vec3,
     vertical: Vec3,
     u: Vec3,
     v: Vec3,
-    w: Vec3,
     lens_radius: f64,
-    time: (f64, f64),
 }
 
-impl Camera {
-    pub fn new(
+/// Keyframed camera parameters, for a camera that moves, zooms or pulls
+/// focus over an animation.
+#[derive(Clone)]
+pub struct CameraAnimation {
+    pub look_from: Track<Point3>,
+    pub look_at: Track<Point3>,
+    pub view_up: Vec3,
+    /// Vertical field of view in degrees.
+    pub vertical_fov: Track<f64>,
+    pub aspect_ratio: f64,
+    pub aperture: Track<f64>,
+    pub focus_distance: Track<f64>,
+}
+
+impl CameraAnimation {
+    fn view(&self, time: f64) -> View {
+        View::new(
+            self.look_from.at(time),
+            self.look_at.at(time),
+            self.view_up,
+            self.vertical_fov.at(time),
+            self.aspect_ratio,
+            self.aperture.at(time),
+            self.focus_distance.at(time),
+        )
+    }
+}
+
+impl View {
+    fn new(
         look_from: Point3,
         look_at: Point3,
         view_up: Vec3,
@@ -24,7 +349,6 @@ impl Camera {
         aspect_ratio: f64,
         aperture: f64,
         focus_distance: f64,
-        time: (f64, f64),
     ) -> Self {
         let theta = vertical_fov.to_radians();
         let h = (theta / 2.0).tan();
@@ -49,22 +373,416 @@ impl Camera {
             vertical,
             u,
             v,
-            w,
             lens_radius,
+        }
+    }
+
+    /// A random point on the lens, relative to its center. A pinhole
+    /// camera, with no aperture, only has the center.
+    fn lens_offset<T: Rng>(&self, rng: &mut T) -> Vec3 {
+        if self.lens_radius <= 0. {
+            return Vec3::zero();
+        }
+        let rd = random_in_unit_disk(rng) * self.lens_radius;
+        self.u * rd.x() + self.v * rd.y()
+    }
+
+    /// The fraction of the image height taken up by a sphere of `radius`
+    /// at `center`, ignoring perspective distortion towards the edges.
+    fn screen_size(&self, center: Point3, radius: f64) -> f64 {
+        let to_focus = self.direction(0.5, 0.5, Vec3::zero());
+        let tan_half_fov = self.vertical.length() / (2. * to_focus.length());
+        let distance = (center - self.origin).length();
+        radius / (distance * tan_half_fov)
+    }
+
+    /// Where in the view `p` appears, if it's in front of the camera.
+    fn project(&self, p: Point3) -> Option<(f64, f64)> {
+        let normal = self.horizontal.cross_product(self.vertical);
+        let to_plane = (self.lower_left_corner - self.origin).dot_product(normal);
+        let to_p = p - self.origin;
+        let along = to_p.dot_product(normal);
+        if along * to_plane <= 0. {
+            return None;
+        }
+
+        let q = to_p * (to_plane / along) + self.origin - self.lower_left_corner;
+        Some((
+            q.dot_product(self.horizontal) / self.horizontal.length_squared(),
+            q.dot_product(self.vertical) / self.vertical.length_squared(),
+        ))
+    }
+
+    fn aspect_ratio(&self) -> f64 {
+        self.horizontal.length() / self.vertical.length()
+    }
+
+    fn direction(&self, s: f64, t: f64, lens_offset: Vec3) -> Vec3 {
+        self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - lens_offset
+    }
+
+    /// The origin and direction of the ray through `s` and `t` of an
+    /// omni-directional stereo image, with the left eye's panorama in the
+    /// top half. Longitude runs across each panorama with the view
+    /// direction in the middle, and latitude up it.
+    fn ods(&self, interocular: f64, s: f64, t: f64) -> (Point3, Vec3) {
+        let (side, t) = if t >= 0.5 {
+            (-0.5, 2. * t - 1.)
+        } else {
+            (0.5, 2. * t)
+        };
+        let longitude = (s - 0.5) * 2. * PI;
+        let latitude = (t - 0.5) * PI;
+
+        let forward = self.v.cross_product(self.u);
+        let (sin_long, cos_long) = longitude.sin_cos();
+        let (sin_lat, cos_lat) = latitude.sin_cos();
+        let facing = forward * cos_long + self.u * sin_long;
+        let direction = facing * cos_lat + self.v * sin_lat;
+
+        // The eyes on either side of a head turned towards `facing`
+        let right = self.u * cos_long - forward * sin_long;
+        (self.origin + right * (side * interocular), direction)
+    }
+}
+
+impl Camera {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        look_from: Point3,
+        look_at: Point3,
+        view_up: Vec3,
+        vertical_fov: f64,
+        aspect_ratio: f64,
+        aperture: f64,
+        focus_distance: f64,
+        time: (f64, f64),
+    ) -> Self {
+        Self {
+            view: View::new(
+                look_from,
+                look_at,
+                view_up,
+                vertical_fov,
+                aspect_ratio,
+                aperture,
+                focus_distance,
+            ),
+            time,
+            animation: None,
+            exposure: None,
+            shutter_curve: ShutterCurve::Box,
+            rolling_shutter: None,
+            projection: Projection::Perspective,
+            glare: None,
+            lens: Lens::default(),
+            clip: (0., f64::INFINITY),
+        }
+    }
+
+    /// The camera, with radiance scaled by `exposure` as a physical camera
+    /// would record it. Depth of field is left as it is; see
+    /// `Exposure::aperture` for the aperture to match.
+    pub fn with_exposure(self, exposure: Exposure) -> Self {
+        Self {
+            exposure: Some(exposure),
+            ..self
+        }
+    }
+
+    pub fn exposure(&self) -> Option<Exposure> {
+        self.exposure
+    }
+
+    /// The camera, with ray times picked following `shutter_curve` rather
+    /// than evenly while the shutter is open.
+    pub fn with_shutter_curve(self, shutter_curve: ShutterCurve) -> Self {
+        Self {
+            shutter_curve,
+            ..self
+        }
+    }
+
+    pub fn shutter_curve(&self) -> &ShutterCurve {
+        &self.shutter_curve
+    }
+
+    /// The camera with a rolling shutter, like most video and phone
+    /// cameras: rows of the image are exposed one after another from the
+    /// top, so fast moving objects lean over. Reading out the whole image
+    /// takes `readout`, from 0 to 1, of the shutter interval, and each row
+    /// is exposed for the rest of it.
+    pub fn with_rolling_shutter(self, readout: f64) -> Self {
+        Self {
+            rolling_shutter: Some(readout.clamp(0., 1.)),
+            ..self
+        }
+    }
+
+    pub fn rolling_shutter(&self) -> Option<f64> {
+        self.rolling_shutter
+    }
+
+    /// The camera laying out its image as `projection` says. The aspect
+    /// ratio is still the whole image's, e.g. 32:9 for a pair of 16:9
+    /// stereo views, or 1:1 for ODS.
+    pub fn with_projection(self, projection: Projection) -> Self {
+        Self { projection, ..self }
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// The camera with `glare` from its aperture around the brightest
+    /// points in the image.
+    pub fn with_glare(self, glare: Glare) -> Self {
+        Self {
+            glare: Some(glare),
+            ..self
+        }
+    }
+
+    pub fn glare(&self) -> Option<Glare> {
+        self.glare
+    }
+
+    /// The camera with the imperfections of `lens`. Distortion and
+    /// vignetting are left out of ODS images.
+    pub fn with_lens(self, lens: Lens) -> Self {
+        Self { lens, ..self }
+    }
+
+    pub fn lens(&self) -> Lens {
+        self.lens
+    }
+
+    /// The camera, seeing only what's between the planes `near` and `far`
+    /// in front of it, for cutaway views through objects or to leave out
+    /// distant ones. Light from outside still reaches what's seen. ODS
+    /// panoramas clip at those distances from the camera instead.
+    pub fn with_clip(self, near: f64, far: f64) -> Self {
+        Self {
+            clip: (near, far),
+            ..self
+        }
+    }
+
+    pub fn clip(&self) -> (f64, f64) {
+        self.clip
+    }
+
+    /// The part of the camera ray `r`, from `get_ray`, between the clipping
+    /// planes, as distances along it.
+    pub fn clip_range(&self, r: &Ray) -> (f64, f64) {
+        let (near, far) = self.clip;
+        let along = match self.projection {
+            Projection::Ods { .. } => r.direction.length(),
+            _ => {
+                let view = self.view_at(r.time);
+                r.direction.dot_product(view.v.cross_product(view.u))
+            }
+        };
+        if along <= 0. {
+            return (f64::INFINITY, f64::INFINITY);
+        }
+        (near / along, far / along)
+    }
+
+    /// The fraction of light the lens lets through to (s, t) in the image.
+    pub fn vignetting(&self, s: f64, t: f64) -> f64 {
+        match self.projection {
+            Projection::Perspective => self.lens.transmission(s, t, self.view.aspect_ratio()),
+            // Each eye's view is vignetted on its own
+            Projection::Stereo { .. } => {
+                let s = if s < 0.5 { 2. * s } else { 2. * s - 1. };
+                self.lens.transmission(s, t, self.view.aspect_ratio() / 2.)
+            }
+            Projection::Ods { .. } => 1.,
+        }
+    }
+
+    /// The factor radiance is multiplied by to give pixel values: 1 unless
+    /// the camera has an exposure.
+    pub fn exposure_scale(&self) -> f64 {
+        self.exposure.map_or(1., |exposure| exposure.scale())
+    }
+
+    /// A camera following `animation`. Each ray is generated from where the
+    /// camera is at the ray's time, so fast camera moves blur.
+    pub fn animated(animation: CameraAnimation, time: (f64, f64)) -> Self {
+        Self {
+            view: animation.view(time.0),
             time,
+            animation: Some(Arc::new(animation)),
+            exposure: None,
+            shutter_curve: ShutterCurve::Box,
+            rolling_shutter: None,
+            projection: Projection::Perspective,
+            glare: None,
+            lens: Lens::default(),
+            clip: (0., f64::INFINITY),
+        }
+    }
+
+    pub fn animation(&self) -> Option<&CameraAnimation> {
+        self.animation.as_deref()
+    }
+
+    /// The interval over which the shutter is open. An empty one, opening
+    /// and closing at the same time, takes every ray at that instant, for
+    /// scenes with nothing moving to blur.
+    pub fn time(&self) -> (f64, f64) {
+        self.time
+    }
+
+    /// Sets the interval over which the shutter is open.
+    pub fn set_time(&mut self, time: (f64, f64)) {
+        self.time = time;
+        if let Some(animation) = &self.animation {
+            self.view = animation.view(time.0);
+        }
+    }
+
+    /// Where the camera is at the start of the shutter interval.
+    pub fn origin(&self) -> Point3 {
+        self.view.origin
+    }
+
+    /// The fraction of the image height a sphere of `radius` at `center`
+    /// covers at the start of the shutter interval, which can be more than
+    /// one for objects close to the camera.
+    pub fn screen_size(&self, center: Point3, radius: f64) -> f64 {
+        self.view.screen_size(center, radius)
+    }
+
+    /// Where in the image `p` appears at `time`, as the `s` and `t` that
+    /// `get_ray` takes, if it's in front of the camera. Only perspective
+    /// views can be projected onto; stereo and ODS images give None.
+    pub fn project(&self, p: Point3, time: f64) -> Option<(f64, f64)> {
+        match self.projection {
+            Projection::Perspective => {
+                let view = self.view_at(time);
+                let (s, t) = view.project(p)?;
+                Some(self.lens.undistort(s, t, view.aspect_ratio()))
+            }
+            _ => None,
         }
     }
 
     pub fn get_ray<T: Rng>(&self, rng: &mut T, s: f64, t: f64) -> Ray {
-        let rd = random_in_unit_disk(rng) * self.lens_radius;
-        let offset = self.u * rd.x() + self.v * rd.y();
+        let time = self.ray_time(rng, t);
+        let view = self.view_at(time);
+
+        if let Projection::Ods { interocular } = self.projection {
+            let (origin, direction) = view.ods(interocular, s, t);
+            return Ray::new(origin, direction, time);
+        }
+
+        let (view, s) = self.eye_view(view, s);
+        let (s, t) = self.distort(&view, s, t);
+        let offset = view.lens_offset(rng);
+        Ray::new(view.origin + offset, view.direction(s, t, offset), time)
+    }
+
+    /// Like `get_ray`, but also generates differentials for rays `ds` and
+    /// `dt` over, through the same point on the lens.
+    pub fn get_ray_differential<T: Rng>(
+        &self,
+        rng: &mut T,
+        s: f64,
+        t: f64,
+        ds: f64,
+        dt: f64,
+    ) -> Ray {
+        let time = self.ray_time(rng, t);
+        let view = self.view_at(time);
+
+        if let Projection::Ods { interocular } = self.projection {
+            let (origin, direction) = view.ods(interocular, s, t);
+            let (rx_origin, rx_direction) = view.ods(interocular, s + ds, t);
+            let (ry_origin, ry_direction) = view.ods(interocular, s, t + dt);
+            return Ray::new(origin, direction, time).with_differential(RayDifferential {
+                rx_origin,
+                rx_direction,
+                ry_origin,
+                ry_direction,
+            });
+        }
 
-        let origin = self.origin + offset;
-        let direction =
-            self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset;
+        let (view, s) = self.eye_view(view, s);
+        let offset = view.lens_offset(rng);
+        let origin = view.origin + offset;
+        let direction = |s, t| {
+            let (s, t) = self.distort(&view, s, t);
+            view.direction(s, t, offset)
+        };
 
-        let time = rng.gen_range(self.time.0..self.time.1);
+        Ray::new(origin, direction(s, t), time).with_differential(RayDifferential {
+            rx_origin: origin,
+            rx_direction: direction(s + ds, t),
+            ry_origin: origin,
+            ry_direction: direction(s, t + dt),
+        })
+    }
+
+    /// For a stereo pair, the view of the eye whose half of the image `s`
+    /// is in, and where in the camera's view to look through; otherwise
+    /// the view and `s` as they are.
+    fn eye_view(&self, view: View, s: f64) -> (View, f64) {
+        let Projection::Stereo { interocular } = self.projection else {
+            return (view, s);
+        };
+
+        // Moving the eye while keeping the view's corners where they are
+        // skews its view to line up with the other's at the focus distance
+        let (side, s) = if s < 0.5 {
+            (-0.5, s + 0.25)
+        } else {
+            (0.5, s - 0.25)
+        };
+        let eye = View {
+            origin: view.origin + view.u * (side * interocular),
+            ..view
+        };
+        (eye, s)
+    }
+
+    /// Where in `view` the lens shows (s, t), as `eye_view` gives them. A
+    /// stereo pair's eyes are each distorted about their own middle.
+    fn distort(&self, view: &View, s: f64, t: f64) -> (f64, f64) {
+        match self.projection {
+            Projection::Stereo { .. } => {
+                let local = (s - 0.25) * 2.;
+                let (local, t) = self.lens.distort(local, t, view.aspect_ratio() / 2.);
+                (0.25 + local / 2., t)
+            }
+            _ => self.lens.distort(s, t, view.aspect_ratio()),
+        }
+    }
+
+    fn view_at(&self, time: f64) -> View {
+        match &self.animation {
+            Some(animation) => animation.view(time),
+            None => self.view,
+        }
+    }
 
-        Ray::new(origin, direction, time)
+    /// A random time while the shutter is open, following the shutter
+    /// curve, or the instant it opens if it closes straight away. With a
+    /// rolling shutter, it's while the row at `t`, from 0 at the bottom of
+    /// the image to 1 at the top, is exposed.
+    fn ray_time<T: Rng>(&self, rng: &mut T, t: f64) -> f64 {
+        let (mut open, mut close) = self.time;
+        if let Some(readout) = self.rolling_shutter {
+            let length = close - open;
+            open += (1. - t.clamp(0., 1.)) * readout * length;
+            close = open + (1. - readout) * length;
+        }
+        match self.shutter_curve {
+            _ if open >= close => open,
+            ShutterCurve::Box => rng.gen_range(open..close),
+            ref curve => open + (close - open) * curve.sample(rng.gen()),
+        }
     }
 }
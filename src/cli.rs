@@ -0,0 +1,1573 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use raytracing::accel::{AcceleratorKind, NodeSelection};
+use raytracing::bloom::Bloom;
+use raytracing::color::{Color, ColorSpace, ToneMap, WhiteBalance};
+use raytracing::depth_fog::DepthFog;
+use raytracing::film::ImageFormat;
+use raytracing::filter::Filter;
+use raytracing::light::DEFAULT_LIGHT_GROUP;
+use raytracing::material_library::{parse_color, parse_material, parse_material_with_textures};
+use raytracing::ray::{Material, MaterialOverride};
+use raytracing::render::BounceLimits;
+use raytracing::rng::SamplerFactory;
+use raytracing::sun::SolarTime;
+use raytracing::texture_cache::TextureCache;
+use raytracing::tile::{Tile, TileOrder};
+use raytracing::vector::Point3;
+use raytracing::volume::FogSampling;
+
+pub const USAGE: &str = "\
+Usage: raytracing [OPTIONS] > image.ppm
+       raytracing merge [OPTIONS] <CHECKPOINT>... > image.ppm
+       raytracing compare [OPTIONS] <IMAGE> <REFERENCE>
+       raytracing convert [OPTIONS] <INPUT> <OUTPUT>
+       raytracing check [OPTIONS]
+       raytracing stats [OPTIONS]
+
+check builds the built-in scene, as the options change it, without rendering
+it, and lists any problems with it, such as invalid materials, missing textures,
+a camera inside an object or emitters that aren't sampled as lights. stats
+builds it and prints what it's made of: objects by kind, emitters and the
+lights sampled directly, its bounds and an estimate of the memory it takes.
+Neither takes a scene file.
+
+Options:
+  -o, --output <FILE>  Write the image to FILE instead of stdout
+  --format <NAME>      Image format: ppm, png (16-bit), pfm (32-bit float) or exr
+                       (32-bit float, with normal, depth, albedo, ID, motion
+                       vector and light group layers);
+                       guessed from the output file's extension by default
+  --checkpoint <FILE>  Also save the raw accumulation buffer to FILE
+  --heatmap <FILE>     Also write a false-color image of the time spent on each
+                       pixel to FILE, in the format matching its extension
+  --report <FILE>      Also write a JSON summary of the render to FILE: resolution,
+                       samples, times, rays traced, acceleration structure,
+                       image statistics as for --stats and files written
+  --stats              Print the image's average, log average and maximum
+                       luminance, the share of pixels clipped and a histogram
+                       of its luminance, to help choose the exposure
+  --transparent        Leave the background out of the image with zero alpha, for
+                       compositing (png or exr only)
+  --white-balance <KELVIN>[,<TINT>]
+                       Show light of this color temperature as white, e.g.
+                       3200 for tungsten; TINT moves it towards green
+                       (positive) or magenta, in thousandths of delta uv
+  --bloom <THRESHOLD>[,<INTENSITY>[,<RADIUS>]]
+                       Make anything brighter than THRESHOLD (e.g. 1, the
+                       display's white) glow, spreading INTENSITY (default
+                       0.2) of the light above it over RADIUS (default 0.02)
+                       of the image's height
+  --depth-fog <COLOR>:<DENSITY>[:<FALLOFF>]
+                       Fade surfaces into COLOR (R,G,B or #rrggbb) with their
+                       distance from the camera, a cheap stand-in for --fog;
+                       FALLOFF (default 1) above 1 keeps the foreground clearer
+  --lut <FILE>         Grade the image with a .cube color lookup table, 1D or
+                       3D, applied to sRGB-encoded colors after the white
+                       balance
+  --color-space <NAME> Color space to write the image in: srgb (default),
+                       display-p3 (or p3) for wide gamut displays, or acescg
+                       (pfm or exr only); scene colors are still sRGB
+  --shadow-catcher     Make the ground a shadow catcher, showing only the shadows
+                       and bounce light the spheres cast onto it
+  --transparent-shadows
+                       Let sampled light through glass, tinted by what fills
+                       it, so glass casts colored shadows rather than black
+  --sun <LAT>,<LON>,<DATE>
+                       Light the scene with a daylight sky, with the sun where
+                       it is from that place at that UTC time, or with an
+                       offset, e.g. 51.5,-0.13,2024-06-21T14:30+01:00; north
+                       is -z
+  --atmosphere <ALTITUDE>
+                       Light the scene with a sky worked out from sunlight
+                       scattering in Earth's atmosphere, seen from ALTITUDE
+                       meters up, e.g. 2 or 400000 from orbit; the sun is
+                       placed by --sun if given
+  --fog <DENSITY>      Fill the space around the objects with haze that scatters
+                       DENSITY of the light crossing it per unit distance,
+                       e.g. 0.05, showing up beams of light; light from the
+                       sky is lost in it, so it suits scenes with --light or
+                       light materials such as --material matte=light:4,4,4,
+                       and without any it only darkens the image
+  --fog-sampling <NAME>
+                       Where in the fog to sample the lights from:
+                       equiangular (default), anywhere along each ray, best
+                       for beams through thin fog, or distance, only where
+                       rays scatter, best for dense fog
+  --frames <N>         Render an animation of N frames; '#'s in the output and
+                       checkpoint paths are replaced by the frame number
+  --temporal-reuse <KEEP>
+                       Add each frame's samples into the next wherever it
+                       sees the same surface, keeping KEEP (0 to 1) of their
+                       weight, so fewer --samples are needed per frame; suits
+                       slow camera moves around still objects
+  --samples <N>        Samples per pixel (default 100, unlimited with --time-limit)
+  --light-samples <N>  Paths traced from each sample's first diffuse hit
+                       (default 1); cheaper lighting noise reduction than
+                       more samples, which also refine edges and blur
+  --max-depth <N>      Most vertices on a path, of any kind (default 50)
+  --diffuse-depth <N>, --glossy-depth <N>, --transmission-depth <N>
+                       Most bounces off diffuse surfaces, mirror-like
+                       reflections and refractions on a path (default: only
+                       --max-depth), e.g. --diffuse-depth 4 for glass-heavy
+                       scenes that need a high --max-depth
+  --clamp-indirect <MAX>
+                       Limit the luminance of light reaching the image from
+                       beyond each path's first diffuse bounce to MAX, e.g.
+                       10, removing fireflies without dimming lights, what
+                       mirrors and glass show of them, or the light they
+                       shine straight onto surfaces
+  --time-limit <TIME>  Stop rendering after TIME, e.g. 300s, 5m, 1h or 500ms
+  --noise-threshold <NOISE>
+                       Stop rendering once the noise left, estimated as the
+                       samples double, is below NOISE as a fraction of pixel
+                       values, e.g. 0.01; samples are unlimited unless given
+  --seed <N>           Seed for the sampling pattern (default 0); the same
+                       seed gives the same image on any number of threads
+  --rng <NAME>         Random number generator to sample with: chacha
+                       (default; the same on every platform), xoshiro or pcg
+                       (both faster)
+  --seeding <WHEN>     Seed a new generator for every sample (default), pixel
+                       or tile in each pass; seeding less often is quicker,
+                       but images then change with the samples per pass
+  --accel <NAME>       Acceleration structure: bvh (default), lbvh (quicker to
+                       build, slower to render), kdtree, grid (for many evenly
+                       spread objects of similar size) or none
+  --accel-obj <FILE>   Also write the acceleration structure's node boxes to
+                       FILE as an OBJ wireframe, with a group for each level
+                       and one for the leaves, to inspect in a 3D package
+  --accel-obj-nodes <WHICH>
+                       Nodes to write with --accel-obj: all (default), leaves,
+                       or the level N below the root (0 is the root)
+  --export-obj <FILE>  Also write the scene as triangles to FILE as OBJ, with
+                       its materials approximated in a .mtl file beside it, to
+                       render the same geometry elsewhere; with stats, exports
+                       without rendering
+  --threads <N>        Threads to build and render with (default: one per
+                       logical core)
+  --material <NAME>=<MATERIAL>
+                       Replace one of the scene's named materials (ground,
+                       matte, glass or mirror), e.g. mirror=metal:0.9,0.8,0.3:0.1;
+                       MATERIAL is lambertian:R,G,B, metal:R,G,B[:FUZZ],
+                       checker:R,G,B:R,G,B[:SCALE] (a diffuse checkerboard,
+                       e.g. ground=checker:0.2,0.3,0.1:0.9,0.9,0.9),
+                       glass[:IOR[:ABSORPTION[:SCATTERING]]],
+                       frosted:IOR:ROUGHNESS[:TRANSMISSION_ROUGHNESS],
+                       image:FILE (a diffuse surface colored by the image in
+                       FILE) or light:R,G,B[:POWER][@GROUP], where
+                       ABSORPTION (R,G,B) and SCATTERING are per unit
+                       distance inside the glass, POWER is the light's total
+                       output in lumens or watts of light, not electricity,
+                       e.g. 800lm or 1.2W, and GROUP the light group EXR
+                       files split its light into (default: default). May be
+                       repeated
+  --light <LIGHT>      Add a light: sphere:X,Y,Z:RADIUS:R,G,B[:POWER],
+                       disk:X,Y,Z:RADIUS:R,G,B[:POWER], facing down, with
+                       POWER as for light materials, e.g.
+                       disk:0,5,0:0.5:1,0.9,0.8:800lm; point:X,Y,Z:R,G,B, a
+                       bare bulb of R,G,B candelas; or ies:X,Y,Z:FILE, a
+                       fixture hanging down shaped by the IES profile in
+                       FILE. Any can end in @GROUP, as light materials can.
+                       May be repeated
+  --material-library <FILE>
+                       Replace the scene's named materials with those in FILE,
+                       one NAME=MATERIAL per line as for --material, before
+                       any --material; lines starting with # are comments
+  --watch              Render again whenever the --material-library file
+                       changes, until interrupted; needs --output, and can't
+                       be used with check or stats
+  --set <PATH>=<VALUE>  Change a scene parameter, e.g. camera.fov=30 or
+                       materials.mirror.roughness=0.2; see below. May be repeated
+  --override-material <NAME>
+                       Render every surface except lights with one material:
+                       clay (neutral gray diffuse)
+  --epsilon <DIST>     Distance along each ray to ignore hits within, to stop
+                       surfaces shadowing themselves (default: scaled with the
+                       scene's size)
+  --guiding            Learn where light comes from while rendering and aim
+                       diffuse bounces towards it
+  --tile-order <NAME>  Order to render tiles in: scanline (default), hilbert,
+                       spiral (from the center) or random
+  --region <X0,Y0,X1,Y1>
+                       Render only the pixels from (X0, Y0) up to (X1, Y1),
+                       counted from the top left, e.g. to refine part of a
+                       render with more samples: save it with --checkpoint
+                       and merge it with a checkpoint of the whole image
+  --filter <NAME>      Pixel filter: box (default), tent, gaussian or mitchell
+  --filter-radius <PIXELS>
+                       Filter radius, overriding the filter's default
+  --res <PRESET>       Resolution preset: 720p, 1080p, 1440p, 4k, square512, square1024
+  --width <PIXELS>     Image width
+  --height <PIXELS>    Image height
+  --aspect <RATIO>     Aspect ratio, either W:H (e.g. 16:9) or a decimal (e.g. 1.5)
+  -h, --help           Print this help
+
+Scene parameters for --set:
+  camera.look_from, camera.look_at   Position as X,Y,Z
+  camera.fov                         Vertical field of view in degrees
+  camera.aperture, camera.focus_distance
+  camera.iso, camera.f_stop, camera.shutter
+                                     Exposure for lights in physical units,
+                                     e.g. camera.shutter=1/125; setting any
+                                     starts from ISO 100, f/16, 1/100 s and
+                                     sets the aperture from the f-stop
+  camera.shutter_curve               How far open the shutter is while
+                                     it's open, shaping motion blur: box
+                                     (default), triangle, or values at
+                                     even times, e.g. 0,1,1,0
+  camera.rolling_shutter             Expose the image a row at a time from
+                                     the top, taking this fraction of the
+                                     shutter time (0 to 1) to read out
+  camera.projection                  perspective (default); stereo for a
+                                     side-by-side pair, e.g. with
+                                     --aspect 32:9; or ods for VR
+                                     panoramas, left eye on top, with
+                                     --aspect 1:1. Either takes the eye
+                                     separation, e.g. stereo:0.065
+  camera.glare                       Starburst from the aperture around
+                                     points brighter than a threshold, as
+                                     THRESHOLD[,INTENSITY[,BLADES[,LENGTH
+                                     [,ROTATION]]]]: defaults 0.05 of the
+                                     light, 6 blades, 0.15 of the height,
+                                     0 degrees, e.g. camera.glare=1,0.1,5
+  camera.distortion                  Lens distortion: positive for barrel,
+                                     negative for pincushion, e.g. 0.1
+  camera.vignetting                  Fraction of light lost in the corners
+                                     (0 to 1)
+  camera.chromatic_aberration        Red image larger and blue smaller by
+                                     this fraction, e.g. 0.005
+  camera.near, camera.far            Clipping distances in front of the
+                                     camera: only what's between them is
+                                     seen, e.g. camera.near=12 for a
+                                     cutaway (default 0 and inf)
+  materials.<NAME>.<PROPERTY>        albedo (R,G,B or hex), fuzz/roughness,
+                                     reflection_roughness,
+                                     transmission_roughness, ior,
+                                     absorption, scattering, priority,
+                                     emit or power, depending on the
+                                     material";
+
+pub const MERGE_USAGE: &str = "\
+Usage: raytracing merge [OPTIONS] <CHECKPOINT>... > image.ppm
+
+Combines checkpoints of the same image rendered separately, e.g. with
+different seeds or on different machines, weighting each by its samples.
+
+Options:
+  -o, --output <FILE>  Write the image to FILE instead of stdout
+  --format <NAME>      Image format: ppm, png, pfm or exr; guessed from the
+                       output file's extension by default
+  --checkpoint <FILE>  Also save the merged accumulation buffer to FILE
+  --white-balance <KELVIN>[,<TINT>]
+                       Show light of this color temperature as white, e.g.
+                       3200 for tungsten; TINT moves it towards green
+                       (positive) or magenta, in thousandths of delta uv
+  --bloom <THRESHOLD>[,<INTENSITY>[,<RADIUS>]]
+                       Make anything brighter than THRESHOLD (e.g. 1, the
+                       display's white) glow, spreading INTENSITY (default
+                       0.2) of the light above it over RADIUS (default 0.02)
+                       of the image's height
+  --lut <FILE>         Grade the image with a .cube color lookup table, 1D or
+                       3D, applied to sRGB-encoded colors after the white
+                       balance
+  --color-space <NAME> Color space to write the image in: srgb (default),
+                       display-p3 (or p3) for wide gamut displays, or acescg
+                       (pfm or exr only); scene colors are still sRGB
+  -h, --help           Print this help";
+
+pub const COMPARE_USAGE: &str = "\
+Usage: raytracing compare [OPTIONS] <IMAGE> <REFERENCE>
+
+Measures how an image differs from a reference render of the same size, in
+any format images are written in (uncompressed EXR only): the mean squared
+error and relative MSE of the linear values, and the PSNR and SSIM of the
+image as displayed.
+
+Options:
+  --error-map <FILE>   Also write a false-color image of each pixel's
+                       relative squared error, brightest at the 99th
+                       percentile, to FILE
+  --ssim-map <FILE>    Also write a false-color image of each pixel's
+                       dissimilarity, from black where it's the same as the
+                       reference to white, to FILE
+  -h, --help           Print this help";
+
+pub const CONVERT_USAGE: &str = "\
+Usage: raytracing convert [OPTIONS] <INPUT> <OUTPUT>
+
+Converts an image between the formats images are written in, e.g. an EXR
+render to a PNG to share (uncompressed EXR only).
+
+Options:
+  --format <NAME>      Output format: ppm, png, pfm or exr; guessed from the
+                       output file's extension by default
+  --exposure <STOPS>   Brighten the image by STOPS, or darken it if negative
+  --tone-map <NAME>    Bring bright colors into the display's range: clamp
+                       (default), reinhard or aces
+  -h, --help           Print this help";
+
+const DEFAULT_WIDTH: u32 = 400;
+const DEFAULT_ASPECT: f64 = 16. / 9.;
+const DEFAULT_SAMPLES: u32 = 100;
+/// Bytes of image textures to keep loaded at once.
+const TEXTURE_BUDGET: usize = 1 << 30;
+
+pub struct Args {
+    pub resolution: Resolution,
+    pub samples_per_pixel: Option<u32>,
+    pub light_samples: u32,
+    pub max_depth: i32,
+    pub bounce_limits: BounceLimits,
+    pub indirect_clamp: Option<f64>,
+    pub time_limit: Option<Duration>,
+    pub noise_threshold: Option<f64>,
+    pub seed: u64,
+    /// The random number generator to sample with, and how often it's seeded.
+    pub sampler: SamplerFactory,
+    pub output: Option<PathBuf>,
+    pub format: ImageFormat,
+    pub transparent: bool,
+    /// The color of light to show as white, if not the sRGB white.
+    pub white_balance: Option<WhiteBalance>,
+    /// Glow added around the brightest parts of the image, if any.
+    pub bloom: Option<Bloom>,
+    /// Haze laid over the image by the depth of its surfaces, if any.
+    pub depth_fog: Option<DepthFog>,
+    /// A `.cube` file to grade the image with.
+    pub lut: Option<PathBuf>,
+    pub color_space: ColorSpace,
+    pub shadow_catcher: bool,
+    pub transparent_shadows: bool,
+    /// Where and when to place the sun in a daylight sky, if anywhere.
+    pub sun: Option<SolarTime>,
+    /// Altitude to see a physically scattered sky from, if it's to be used
+    /// rather than the simple one.
+    pub atmosphere: Option<f64>,
+    /// Chance per unit distance of light scattering in haze around the
+    /// objects, if there is any.
+    pub fog: Option<f64>,
+    pub fog_sampling: FogSampling,
+    pub checkpoint: Option<PathBuf>,
+    pub heatmap: Option<PathBuf>,
+    pub report: Option<PathBuf>,
+    /// Whether to print statistics of the image's brightness.
+    pub stats: bool,
+    pub frames: u32,
+    /// Fraction of the weight of each animation frame's samples to reuse in
+    /// the next, if they're to be reused.
+    pub temporal_reuse: Option<f64>,
+    pub accelerator: AcceleratorKind,
+    /// Where to write the acceleration structure's nodes as a wireframe.
+    pub accel_obj: Option<PathBuf>,
+    pub accel_obj_nodes: NodeSelection,
+    /// Where to write the scene's geometry as OBJ.
+    pub export_obj: Option<PathBuf>,
+    /// Worker threads, or None for one per logical core.
+    pub threads: Option<usize>,
+    /// Named materials to replace, in the order given.
+    pub materials: Vec<(String, Material)>,
+    /// A file of named materials to replace before `materials`.
+    pub material_library: Option<PathBuf>,
+    /// Whether to render again whenever `material_library` changes.
+    pub watch: bool,
+    /// Where the image textures `materials` refer to are loaded into.
+    pub textures: Arc<TextureCache>,
+    /// Lights to add to the scene, in the order given.
+    pub lights: Vec<LightSpec>,
+    /// Names of the light groups lights were put in, by index. The first is
+    /// the default group.
+    pub light_groups: Vec<String>,
+    /// Scene parameters to change, as dotted paths and unparsed values, in
+    /// the order given.
+    pub settings: Vec<(String, String)>,
+    pub material_override: Option<MaterialOverride>,
+    pub epsilon: Option<f64>,
+    pub guiding: bool,
+    pub filter: Filter,
+    pub tile_order: TileOrder,
+    /// The only pixels to render, if not all of them.
+    pub region: Option<Tile>,
+}
+
+/// A light added with `--light`.
+#[derive(Clone)]
+pub enum LightSpec {
+    /// A sphere giving off `material`'s light.
+    Sphere {
+        center: Point3,
+        radius: f64,
+        material: Material,
+    },
+    /// A disk giving off `material`'s light straight down.
+    Disk {
+        center: Point3,
+        radius: f64,
+        material: Material,
+    },
+    /// A point shining `intensity` candelas every way.
+    Point {
+        position: Point3,
+        intensity: Color,
+        group: usize,
+    },
+    /// A point hanging straight down shaped by the IES profile at `path`.
+    Ies {
+        position: Point3,
+        path: PathBuf,
+        group: usize,
+    },
+}
+
+pub struct MergeArgs {
+    pub inputs: Vec<PathBuf>,
+    pub output: Option<PathBuf>,
+    pub format: ImageFormat,
+    pub checkpoint: Option<PathBuf>,
+    pub white_balance: Option<WhiteBalance>,
+    pub bloom: Option<Bloom>,
+    pub lut: Option<PathBuf>,
+    pub color_space: ColorSpace,
+}
+
+pub struct CompareArgs {
+    pub image: PathBuf,
+    pub reference: PathBuf,
+    pub error_map: Option<PathBuf>,
+    pub ssim_map: Option<PathBuf>,
+}
+
+pub struct ConvertArgs {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub format: ImageFormat,
+    /// Stops to scale the image by before tone mapping.
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Resolution {
+    pub const fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+
+    pub fn preset(name: &str) -> Option<Self> {
+        let (width, height) = match name {
+            "720p" => (1280, 720),
+            "1080p" => (1920, 1080),
+            "1440p" => (2560, 1440),
+            "4k" => (3840, 2160),
+            "square512" => (512, 512),
+            "square1024" => (1024, 1024),
+            _ => return None,
+        };
+
+        Some(Self::new(width, height))
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        self.width as f64 / self.height as f64
+    }
+
+    /// Fills in whichever of width, height or aspect ratio was not given.
+    ///
+    /// When all three are given they must agree to within a pixel.
+    pub fn derive(
+        width: Option<u32>,
+        height: Option<u32>,
+        aspect: Option<f64>,
+    ) -> Result<Self, CliError> {
+        let resolution = match (width, height, aspect) {
+            (Some(w), Some(h), None) => Self::new(w, h),
+            (Some(w), None, a) => {
+                let a = a.unwrap_or(DEFAULT_ASPECT);
+                Self::new(w, (w as f64 / a).round() as u32)
+            }
+            (None, Some(h), a) => {
+                let a = a.unwrap_or(DEFAULT_ASPECT);
+                Self::new((h as f64 * a).round() as u32, h)
+            }
+            (None, None, a) => {
+                let a = a.unwrap_or(DEFAULT_ASPECT);
+                Self::new(DEFAULT_WIDTH, (DEFAULT_WIDTH as f64 / a).round() as u32)
+            }
+            (Some(w), Some(h), Some(a)) => {
+                let expected = w as f64 / a;
+                if (expected - h as f64).abs() > 1. {
+                    return Err(CliError::InconsistentResolution(format!(
+                        "{}x{} does not have aspect ratio {}",
+                        w, h, a
+                    )));
+                }
+                Self::new(w, h)
+            }
+        };
+
+        if resolution.width == 0 || resolution.height == 0 {
+            return Err(CliError::InconsistentResolution(format!(
+                "{}x{} has a zero dimension",
+                resolution.width, resolution.height
+            )));
+        }
+
+        Ok(resolution)
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    Help,
+    MissingValue(String),
+    InvalidValue { flag: String, value: String },
+    UnknownArgument(String),
+    UnknownPreset(String),
+    InconsistentResolution(String),
+    Conflict(String),
+    NoInputs,
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::MissingValue(flag) => write!(f, "missing value for {}", flag),
+            CliError::InvalidValue { flag, value } => {
+                write!(f, "invalid value {:?} for {}", value, flag)
+            }
+            CliError::UnknownArgument(arg) => write!(f, "unknown argument {:?}", arg),
+            CliError::UnknownPreset(name) => write!(f, "unknown resolution preset {:?}", name),
+            CliError::InconsistentResolution(reason) => {
+                write!(f, "inconsistent resolution: {}", reason)
+            }
+            CliError::Conflict(reason) => write!(f, "{}", reason),
+            CliError::NoInputs => write!(f, "no checkpoints to merge"),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Args, CliError> {
+    let mut preset = None;
+    let mut width = None;
+    let mut height = None;
+    let mut aspect = None;
+    let mut samples_per_pixel = None;
+    let mut light_samples = 1;
+    let mut max_depth = 50;
+    let mut bounce_limits = BounceLimits::default();
+    let mut indirect_clamp: Option<f64> = None;
+    let mut time_limit = None;
+    let mut noise_threshold: Option<f64> = None;
+    let mut seed = 0;
+    let mut sampler = SamplerFactory::default();
+    let mut output: Option<PathBuf> = None;
+    let mut format = None;
+    let mut transparent = false;
+    let mut white_balance = None;
+    let mut bloom = None;
+    let mut depth_fog = None;
+    let mut lut = None;
+    let mut color_space = ColorSpace::default();
+    let mut shadow_catcher = false;
+    let mut transparent_shadows = false;
+    let mut sun = None;
+    let mut atmosphere = None;
+    let mut fog: Option<f64> = None;
+    let mut fog_sampling = FogSampling::default();
+    let mut checkpoint = None;
+    let mut heatmap = None;
+    let mut report = None;
+    let mut stats = false;
+    let mut frames = 1;
+    let mut temporal_reuse: Option<f64> = None;
+    let mut accelerator = AcceleratorKind::default();
+    let mut accel_obj = None;
+    let mut accel_obj_nodes: Option<NodeSelection> = None;
+    let mut export_obj = None;
+    let mut threads = None;
+    let mut materials = vec![];
+    let textures = Arc::new(TextureCache::new(TEXTURE_BUDGET));
+    let mut material_library = None;
+    let mut watch = false;
+    let mut lights = vec![];
+    let mut light_groups = vec!["default".to_string()];
+    let mut settings = vec![];
+    let mut material_override = None;
+    let mut epsilon: Option<f64> = None;
+    let mut guiding = false;
+    let mut filter = Filter::default();
+    let mut filter_radius: Option<f64> = None;
+    let mut tile_order = TileOrder::default();
+    let mut region = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| CliError::MissingValue(arg.clone()))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "--res" => {
+                let name = value()?;
+                preset = Some(Resolution::preset(&name).ok_or(CliError::UnknownPreset(name))?);
+            }
+            "--width" => width = Some(parse_value(&arg, &value()?)?),
+            "--height" => height = Some(parse_value(&arg, &value()?)?),
+            "--aspect" => aspect = Some(parse_aspect(&arg, &value()?)?),
+            "--samples" => samples_per_pixel = Some(parse_value(&arg, &value()?)?),
+            "--light-samples" => light_samples = parse_value(&arg, &value()?)?,
+            "--max-depth" => max_depth = parse_value(&arg, &value()?)?,
+            "--diffuse-depth" => bounce_limits.diffuse = parse_value(&arg, &value()?)?,
+            "--glossy-depth" => bounce_limits.glossy = parse_value(&arg, &value()?)?,
+            "--clamp-indirect" => indirect_clamp = Some(parse_value(&arg, &value()?)?),
+            "--transmission-depth" => bounce_limits.transmission = parse_value(&arg, &value()?)?,
+            "--time-limit" => time_limit = Some(parse_duration(&arg, &value()?)?),
+            "--noise-threshold" => noise_threshold = Some(parse_value(&arg, &value()?)?),
+            "--seed" => seed = parse_value(&arg, &value()?)?,
+            "--rng" => sampler.generator = parse_value(&arg, &value()?)?,
+            "--seeding" => sampler.seeding = parse_value(&arg, &value()?)?,
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "--format" => format = Some(parse_value(&arg, &value()?)?),
+            "--transparent" => transparent = true,
+            "--white-balance" => white_balance = Some(parse_value(&arg, &value()?)?),
+            "--bloom" => bloom = Some(parse_value(&arg, &value()?)?),
+            "--depth-fog" => depth_fog = Some(parse_value(&arg, &value()?)?),
+            "--lut" => lut = Some(PathBuf::from(value()?)),
+            "--color-space" => color_space = parse_value(&arg, &value()?)?,
+            "--shadow-catcher" => shadow_catcher = true,
+            "--transparent-shadows" => transparent_shadows = true,
+            "--sun" => sun = Some(parse_value(&arg, &value()?)?),
+            "--atmosphere" => atmosphere = Some(parse_value(&arg, &value()?)?),
+            "--fog" => fog = Some(parse_value(&arg, &value()?)?),
+            "--fog-sampling" => fog_sampling = parse_value(&arg, &value()?)?,
+            "--checkpoint" => checkpoint = Some(PathBuf::from(value()?)),
+            "--heatmap" => heatmap = Some(PathBuf::from(value()?)),
+            "--report" => report = Some(PathBuf::from(value()?)),
+            "--stats" => stats = true,
+            "--frames" => frames = parse_value(&arg, &value()?)?,
+            "--temporal-reuse" => temporal_reuse = Some(parse_value(&arg, &value()?)?),
+            "--accel" => accelerator = parse_value(&arg, &value()?)?,
+            "--accel-obj" => accel_obj = Some(PathBuf::from(value()?)),
+            "--accel-obj-nodes" => accel_obj_nodes = Some(parse_value(&arg, &value()?)?),
+            "--export-obj" => export_obj = Some(PathBuf::from(value()?)),
+            "--threads" => threads = Some(parse_value(&arg, &value()?)?),
+            "--material" => {
+                let value = value()?;
+                let material = parse_named_material(&arg, &value, &textures, &mut light_groups)?;
+                materials.push(material)
+            }
+            "--material-library" => material_library = Some(PathBuf::from(value()?)),
+            "--watch" => watch = true,
+            "--light" => lights.push(parse_light(&arg, &value()?, &mut light_groups)?),
+            "--set" => settings.push(parse_setting(&arg, &value()?)?),
+            "--override-material" => material_override = Some(parse_value(&arg, &value()?)?),
+            "--epsilon" => epsilon = Some(parse_value(&arg, &value()?)?),
+            "--guiding" => guiding = true,
+            "--filter" => filter = parse_value(&arg, &value()?)?,
+            "--tile-order" => tile_order = parse_value(&arg, &value()?)?,
+            "--region" => region = Some((arg.clone(), value()?)),
+            "--filter-radius" => filter_radius = Some(parse_value(&arg, &value()?)?),
+            _ => return Err(CliError::UnknownArgument(arg)),
+        }
+    }
+
+    let resolution = match preset {
+        Some(preset) => {
+            if width.is_some() || height.is_some() {
+                return Err(CliError::InconsistentResolution(
+                    "--res cannot be combined with --width or --height".to_string(),
+                ));
+            }
+            Resolution::derive(Some(preset.width), Some(preset.height), aspect)?
+        }
+        None => Resolution::derive(width, height, aspect)?,
+    };
+
+    if let Some(epsilon) = epsilon {
+        if !(epsilon.is_finite() && epsilon >= 0.) {
+            return Err(CliError::InvalidValue {
+                flag: "--epsilon".to_string(),
+                value: epsilon.to_string(),
+            });
+        }
+    }
+
+    if light_samples == 0 {
+        return Err(CliError::InvalidValue {
+            flag: "--light-samples".to_string(),
+            value: "0".to_string(),
+        });
+    }
+
+    if max_depth < 1 {
+        return Err(CliError::InvalidValue {
+            flag: "--max-depth".to_string(),
+            value: max_depth.to_string(),
+        });
+    }
+    for (flag, limit) in [
+        ("--diffuse-depth", bounce_limits.diffuse),
+        ("--glossy-depth", bounce_limits.glossy),
+        ("--transmission-depth", bounce_limits.transmission),
+    ] {
+        if limit < 0 {
+            return Err(CliError::InvalidValue {
+                flag: flag.to_string(),
+                value: limit.to_string(),
+            });
+        }
+    }
+
+    if let Some(max) = indirect_clamp.filter(|max| max.is_nan() || *max <= 0.) {
+        return Err(CliError::InvalidValue {
+            flag: "--clamp-indirect".to_string(),
+            value: max.to_string(),
+        });
+    }
+
+    if let Some(density) = fog.filter(|density| !density.is_finite() || *density <= 0.) {
+        return Err(CliError::InvalidValue {
+            flag: "--fog".to_string(),
+            value: density.to_string(),
+        });
+    }
+
+    if threads == Some(0) {
+        return Err(CliError::InvalidValue {
+            flag: "--threads".to_string(),
+            value: "0".to_string(),
+        });
+    }
+
+    if frames == 0 {
+        return Err(CliError::InvalidValue {
+            flag: "--frames".to_string(),
+            value: "0".to_string(),
+        });
+    }
+
+    if let Some(keep) = temporal_reuse {
+        if !(0. ..=1.).contains(&keep) {
+            return Err(CliError::InvalidValue {
+                flag: "--temporal-reuse".to_string(),
+                value: keep.to_string(),
+            });
+        }
+    }
+
+    if accel_obj_nodes.is_some() && accel_obj.is_none() {
+        return Err(CliError::Conflict(
+            "--accel-obj-nodes requires --accel-obj".to_string(),
+        ));
+    }
+
+    if frames > 1 && output.is_none() {
+        return Err(CliError::Conflict("--frames requires --output".to_string()));
+    }
+
+    if watch && material_library.is_none() {
+        return Err(CliError::Conflict(
+            "--watch requires --material-library".to_string(),
+        ));
+    }
+    if watch && output.is_none() {
+        return Err(CliError::Conflict("--watch requires --output".to_string()));
+    }
+
+    let region = match region {
+        Some((flag, value)) => Some(parse_region(
+            &flag,
+            &value,
+            resolution.width as usize,
+            resolution.height as usize,
+        )?),
+        None => None,
+    };
+
+    if let Some(radius) = filter_radius {
+        if radius.is_nan() || radius <= 0. {
+            return Err(CliError::InvalidValue {
+                flag: "--filter-radius".to_string(),
+                value: radius.to_string(),
+            });
+        }
+        filter = filter.with_radius(radius);
+    }
+
+    let format = format
+        .or_else(|| output.as_deref().and_then(ImageFormat::from_path))
+        .unwrap_or_default();
+    check_color_space(color_space, format)?;
+
+    if transparent && !matches!(format, ImageFormat::Png | ImageFormat::Exr) {
+        return Err(CliError::Conflict(format!(
+            "--transparent requires png or exr output, not {}",
+            format
+        )));
+    }
+
+    if let Some(noise) = noise_threshold.filter(|noise| noise.is_nan() || *noise <= 0.) {
+        return Err(CliError::InvalidValue {
+            flag: "--noise-threshold".to_string(),
+            value: noise.to_string(),
+        });
+    }
+
+    // A time or noise budget alone means "as many samples as it takes"
+    if time_limit.is_none() && noise_threshold.is_none() {
+        samples_per_pixel = samples_per_pixel.or(Some(DEFAULT_SAMPLES));
+    }
+
+    Ok(Args {
+        resolution,
+        samples_per_pixel,
+        light_samples,
+        max_depth,
+        bounce_limits,
+        indirect_clamp,
+        time_limit,
+        noise_threshold,
+        seed,
+        sampler,
+        output,
+        format,
+        transparent,
+        white_balance,
+        bloom,
+        depth_fog,
+        lut,
+        color_space,
+        shadow_catcher,
+        transparent_shadows,
+        sun,
+        atmosphere,
+        fog,
+        fog_sampling,
+        checkpoint,
+        heatmap,
+        report,
+        stats,
+        frames,
+        temporal_reuse,
+        accelerator,
+        accel_obj,
+        accel_obj_nodes: accel_obj_nodes.unwrap_or_default(),
+        export_obj,
+        threads,
+        materials,
+        material_library,
+        watch,
+        textures,
+        lights,
+        light_groups,
+        settings,
+        material_override,
+        epsilon,
+        guiding,
+        filter,
+        tile_order,
+        region,
+    })
+}
+
+pub fn parse_merge_args<I: IntoIterator<Item = String>>(args: I) -> Result<MergeArgs, CliError> {
+    let mut inputs = vec![];
+    let mut output: Option<PathBuf> = None;
+    let mut format = None;
+    let mut checkpoint = None;
+    let mut white_balance = None;
+    let mut bloom = None;
+    let mut lut = None;
+    let mut color_space = ColorSpace::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| CliError::MissingValue(arg.clone()))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            "--format" => format = Some(parse_value(&arg, &value()?)?),
+            "--checkpoint" => checkpoint = Some(PathBuf::from(value()?)),
+            "--white-balance" => white_balance = Some(parse_value(&arg, &value()?)?),
+            "--bloom" => bloom = Some(parse_value(&arg, &value()?)?),
+            "--lut" => lut = Some(PathBuf::from(value()?)),
+            "--color-space" => color_space = parse_value(&arg, &value()?)?,
+            _ if arg.starts_with('-') => return Err(CliError::UnknownArgument(arg)),
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if inputs.is_empty() {
+        return Err(CliError::NoInputs);
+    }
+
+    let format = format
+        .or_else(|| output.as_deref().and_then(ImageFormat::from_path))
+        .unwrap_or_default();
+    check_color_space(color_space, format)?;
+
+    Ok(MergeArgs {
+        inputs,
+        output,
+        format,
+        checkpoint,
+        white_balance,
+        bloom,
+        lut,
+        color_space,
+    })
+}
+
+pub fn parse_compare_args<I: IntoIterator<Item = String>>(
+    args: I,
+) -> Result<CompareArgs, CliError> {
+    let mut inputs = vec![];
+    let mut error_map = None;
+    let mut ssim_map = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| CliError::MissingValue(arg.clone()))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "--error-map" => error_map = Some(PathBuf::from(value()?)),
+            "--ssim-map" => ssim_map = Some(PathBuf::from(value()?)),
+            _ if arg.starts_with('-') => return Err(CliError::UnknownArgument(arg)),
+            _ => inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [image, reference]: [PathBuf; 2] = inputs.try_into().map_err(|inputs: Vec<_>| {
+        CliError::Conflict(format!(
+            "compare takes an image and a reference, not {} files",
+            inputs.len()
+        ))
+    })?;
+
+    Ok(CompareArgs {
+        image,
+        reference,
+        error_map,
+        ssim_map,
+    })
+}
+
+pub fn parse_convert_args<I: IntoIterator<Item = String>>(
+    args: I,
+) -> Result<ConvertArgs, CliError> {
+    let mut paths = vec![];
+    let mut format = None;
+    let mut exposure = 0.;
+    let mut tone_map = ToneMap::default();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| CliError::MissingValue(arg.clone()))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "--format" => format = Some(parse_value(&arg, &value()?)?),
+            "--exposure" => exposure = parse_value(&arg, &value()?)?,
+            "--tone-map" => tone_map = parse_value(&arg, &value()?)?,
+            _ if arg.starts_with('-') => return Err(CliError::UnknownArgument(arg)),
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+
+    let [input, output]: [PathBuf; 2] = paths.try_into().map_err(|paths: Vec<_>| {
+        CliError::Conflict(format!(
+            "convert takes an input and an output, not {} files",
+            paths.len()
+        ))
+    })?;
+
+    if !f64::is_finite(exposure) {
+        return Err(CliError::InvalidValue {
+            flag: "--exposure".to_string(),
+            value: exposure.to_string(),
+        });
+    }
+
+    let format = format
+        .or_else(|| ImageFormat::from_path(&output))
+        .unwrap_or_default();
+
+    Ok(ConvertArgs {
+        input,
+        output,
+        format,
+        exposure,
+        tone_map,
+    })
+}
+
+/// Linear color spaces can't be shown right from a format with a transfer
+/// function.
+fn check_color_space(color_space: ColorSpace, format: ImageFormat) -> Result<(), CliError> {
+    if color_space == ColorSpace::AcesCg && !matches!(format, ImageFormat::Pfm | ImageFormat::Exr) {
+        return Err(CliError::Conflict(format!(
+            "--color-space {} requires pfm or exr output, not {}",
+            color_space, format
+        )));
+    }
+    Ok(())
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, CliError> {
+    value.parse().map_err(|_| CliError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.to_string(),
+    })
+}
+
+/// Parses `NAME=MATERIAL`, where a light may end in `@GROUP` to put it in
+/// that light group, which is added to `light_groups` if it's new.
+fn parse_named_material(
+    flag: &str,
+    value: &str,
+    textures: &Arc<TextureCache>,
+    light_groups: &mut Vec<String>,
+) -> Result<(String, Material), CliError> {
+    let invalid = || CliError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.to_string(),
+    };
+    let (name, spec) = value.split_once('=').ok_or_else(invalid)?;
+    if name.is_empty() {
+        return Err(invalid());
+    }
+    let (spec, group) = match spec.split_once('@') {
+        Some((spec, group)) => (spec, Some(group)),
+        None => (spec, None),
+    };
+    let mut material = parse_material_with_textures(spec, textures).map_err(|_| invalid())?;
+    if let Some(group) = group {
+        match &mut material {
+            Material::DiffuseLight { group: index, .. } if !group.is_empty() => {
+                *index = light_group(light_groups, group)
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok((name.to_string(), material))
+}
+
+/// Parses a material library file's `NAME=MATERIAL` lines, as given to
+/// `--material`, skipping blank lines and `#` comments. Errors start with
+/// the line number.
+pub fn parse_material_library(
+    text: &str,
+    textures: &Arc<TextureCache>,
+    light_groups: &mut Vec<String>,
+) -> Result<Vec<(String, Material)>, String> {
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(number, line)| {
+            parse_named_material("--material-library", line, textures, light_groups)
+                .map_err(|_| format!("{}: invalid material {:?}", number, line))
+        })
+        .collect()
+}
+
+/// Parses `--light`'s `KIND:X,Y,Z:...[@GROUP]`, adding the group to
+/// `light_groups` if it's new.
+fn parse_light(
+    flag: &str,
+    value: &str,
+    light_groups: &mut Vec<String>,
+) -> Result<LightSpec, CliError> {
+    let invalid = || CliError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.to_string(),
+    };
+    let (spec, group) = match value.rsplit_once('@') {
+        Some((spec, group)) if !group.is_empty() => (spec, light_group(light_groups, group)),
+        Some(_) => return Err(invalid()),
+        None => (value, DEFAULT_LIGHT_GROUP),
+    };
+
+    let mut parts = spec.splitn(3, ':');
+    let (kind, position, rest) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(kind), Some(position), Some(rest)) => (kind, position, rest),
+        _ => return Err(invalid()),
+    };
+    let coords = position
+        .split(',')
+        .map(|c| c.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid())?;
+    let position = match coords[..] {
+        [x, y, z] => Point3::new(x, y, z),
+        _ => return Err(invalid()),
+    };
+
+    // An area light's radius, then its material's `R,G,B[:POWER]`
+    let area_light = || {
+        let (radius, emit) = rest.split_once(':').ok_or_else(invalid)?;
+        let radius = match radius.trim().parse::<f64>() {
+            Ok(radius) if radius.is_finite() && radius > 0. => radius,
+            _ => return Err(invalid()),
+        };
+        let material = match parse_material(&format!("light:{}", emit)) {
+            Ok(Material::DiffuseLight { emit, power, .. }) => {
+                Material::DiffuseLight { emit, group, power }
+            }
+            _ => return Err(invalid()),
+        };
+        Ok((radius, material))
+    };
+
+    match kind {
+        "sphere" => {
+            let (radius, material) = area_light()?;
+            Ok(LightSpec::Sphere {
+                center: position,
+                radius,
+                material,
+            })
+        }
+        "disk" => {
+            let (radius, material) = area_light()?;
+            Ok(LightSpec::Disk {
+                center: position,
+                radius,
+                material,
+            })
+        }
+        "point" => Ok(LightSpec::Point {
+            position,
+            intensity: parse_color(rest).ok_or_else(invalid)?,
+            group,
+        }),
+        "ies" if !rest.is_empty() => Ok(LightSpec::Ies {
+            position,
+            path: PathBuf::from(rest),
+            group,
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+/// The index of the light group called `name`, added at the end of
+/// `light_groups` if it isn't there yet.
+fn light_group(light_groups: &mut Vec<String>, name: &str) -> usize {
+    match light_groups.iter().position(|group| group == name) {
+        Some(index) => index,
+        None => {
+            light_groups.push(name.to_string());
+            light_groups.len() - 1
+        }
+    }
+}
+
+fn parse_setting(flag: &str, value: &str) -> Result<(String, String), CliError> {
+    match value.split_once('=') {
+        Some((path, setting)) if !path.is_empty() => Ok((path.to_string(), setting.to_string())),
+        _ => Err(CliError::InvalidValue {
+            flag: flag.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+fn parse_aspect(flag: &str, value: &str) -> Result<f64, CliError> {
+    let aspect = match value.split_once(':') {
+        Some((w, h)) => parse_value::<f64>(flag, w)? / parse_value::<f64>(flag, h)?,
+        None => parse_value(flag, value)?,
+    };
+
+    if !aspect.is_finite() || aspect <= 0. {
+        return Err(CliError::InvalidValue {
+            flag: flag.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    Ok(aspect)
+}
+
+/// Parses a rectangle of pixels `X0,Y0,X1,Y1` within a `width` by `height`
+/// image, up to but not including `(X1, Y1)`.
+fn parse_region(flag: &str, value: &str, width: usize, height: usize) -> Result<Tile, CliError> {
+    let invalid = || CliError::InvalidValue {
+        flag: flag.to_string(),
+        value: value.to_string(),
+    };
+    let coords = value
+        .split(',')
+        .map(|v| parse_value::<usize>(flag, v.trim()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let &[x0, y0, x1, y1] = coords.as_slice() else {
+        return Err(invalid());
+    };
+
+    if x0 >= x1 || y0 >= y1 || x1 > width || y1 > height {
+        return Err(invalid());
+    }
+    Ok(Tile { x0, y0, x1, y1 })
+}
+
+fn parse_duration(flag: &str, value: &str) -> Result<Duration, CliError> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = parse_value(flag, number)?;
+
+    let seconds = match unit {
+        "ms" => number / 1000.,
+        "" | "s" => number,
+        "m" => number * 60.,
+        "h" => number * 3600.,
+        _ => {
+            return Err(CliError::InvalidValue {
+                flag: flag.to_string(),
+                value: value.to_string(),
+            })
+        }
+    };
+
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use raytracing::texture::Texture;
+
+    use super::*;
+
+    fn args(args: &[&str]) -> Result<Args, CliError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn derive_fills_in_height_from_width_and_aspect() {
+        let resolution = Resolution::derive(Some(1920), None, Some(16. / 9.)).unwrap();
+        assert_eq!(resolution, Resolution::new(1920, 1080));
+
+        // The default aspect ratio is 16:9
+        let resolution = Resolution::derive(Some(800), None, None).unwrap();
+        assert_eq!(resolution, Resolution::new(800, 450));
+    }
+
+    #[test]
+    fn derive_fills_in_width_from_height_and_aspect() {
+        let resolution = Resolution::derive(None, Some(1000), Some(1.5)).unwrap();
+        assert_eq!(resolution, Resolution::new(1500, 1000));
+    }
+
+    #[test]
+    fn derive_defaults_to_400_wide() {
+        let resolution = Resolution::derive(None, None, None).unwrap();
+        assert_eq!(resolution, Resolution::new(400, 225));
+
+        let resolution = Resolution::derive(None, None, Some(1.)).unwrap();
+        assert_eq!(resolution, Resolution::new(400, 400));
+    }
+
+    #[test]
+    fn derive_keeps_width_and_height_given() {
+        let resolution = Resolution::derive(Some(640), Some(480), None).unwrap();
+        assert_eq!(resolution, Resolution::new(640, 480));
+        assert_eq!(resolution.aspect_ratio(), 4. / 3.);
+    }
+
+    #[test]
+    fn derive_checks_all_three_agree() {
+        assert_eq!(
+            Resolution::derive(Some(1920), Some(1081), Some(16. / 9.)).unwrap(),
+            Resolution::new(1920, 1081)
+        );
+        assert!(matches!(
+            Resolution::derive(Some(1920), Some(1200), Some(16. / 9.)),
+            Err(CliError::InconsistentResolution(_))
+        ));
+    }
+
+    #[test]
+    fn derive_rejects_zero_dimensions() {
+        assert!(matches!(
+            Resolution::derive(Some(0), Some(100), None),
+            Err(CliError::InconsistentResolution(_))
+        ));
+        // Too wide an aspect rounds the height down to nothing
+        assert!(matches!(
+            Resolution::derive(Some(10), None, Some(100.)),
+            Err(CliError::InconsistentResolution(_))
+        ));
+    }
+
+    #[test]
+    fn presets_combine_with_aspect_but_not_size() {
+        let resolution = args(&["--res", "4k"]).unwrap().resolution;
+        assert_eq!(resolution, Resolution::new(3840, 2160));
+        assert!(matches!(
+            args(&["--res", "1080p", "--aspect", "4:3"]),
+            Err(CliError::InconsistentResolution(_))
+        ));
+        assert!(matches!(
+            args(&["--res", "720p", "--width", "640"]),
+            Err(CliError::InconsistentResolution(_))
+        ));
+        assert!(matches!(
+            args(&["--res", "8k"]),
+            Err(CliError::UnknownPreset(_))
+        ));
+    }
+
+    #[test]
+    fn fog_density_must_be_positive_and_finite() {
+        assert_eq!(args(&["--fog", "0.05"]).unwrap().fog, Some(0.05));
+        for density in ["0", "-1", "nan", "inf"] {
+            assert!(
+                matches!(
+                    args(&["--fog", density]),
+                    Err(CliError::InvalidValue { ref flag, .. }) if flag == "--fog"
+                ),
+                "--fog {} was accepted",
+                density
+            );
+        }
+    }
+
+    #[test]
+    fn lights_are_put_in_groups_by_name() {
+        let args = args(&[
+            "--material",
+            "matte=light:4,4,4@key",
+            "--material",
+            "mirror=light:1,1,1:800lm@fill",
+            "--material",
+            "glass=light:1,1,1@key",
+            "--material",
+            "ground=light:1,1,1@default",
+        ])
+        .unwrap();
+        assert_eq!(args.light_groups, ["default", "key", "fill"]);
+        let groups: Vec<_> = args
+            .materials
+            .iter()
+            .map(|(name, material)| (name.as_str(), material.light_group()))
+            .collect();
+        assert_eq!(
+            groups,
+            [("matte", 1), ("mirror", 2), ("glass", 1), ("ground", 0)]
+        );
+    }
+
+    #[test]
+    fn only_lights_go_in_groups() {
+        for spec in ["matte=lambertian:0.5,0.5,0.5@key", "matte=light:4,4,4@"] {
+            assert!(matches!(
+                args(&["--material", spec]),
+                Err(CliError::InvalidValue { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn lights_are_parsed() {
+        let args = args(&[
+            "--light",
+            "sphere:0,4,0:0.5:4,4,4",
+            "--light",
+            "disk:1,5,-1:0.25:1,0.9,0.8:800lm@key",
+        ])
+        .unwrap();
+        assert_eq!(args.light_groups, ["default", "key"]);
+        match &args.lights[..] {
+            [LightSpec::Sphere {
+                center,
+                radius,
+                material,
+            }, LightSpec::Disk {
+                center: disk_center,
+                radius: disk_radius,
+                material: disk_material,
+            }] => {
+                assert_eq!((center.x(), center.y(), center.z()), (0., 4., 0.));
+                assert_eq!(*radius, 0.5);
+                assert_eq!(material.light_group(), 0);
+                let disk_center = (disk_center.x(), disk_center.y(), disk_center.z());
+                assert_eq!(disk_center, (1., 5., -1.));
+                assert_eq!(*disk_radius, 0.25);
+                assert_eq!(disk_material.light_group(), 1);
+            }
+            _ => panic!("expected a sphere and a disk"),
+        }
+    }
+
+    #[test]
+    fn invalid_lights_are_rejected() {
+        for light in [
+            "sphere:0,4,0:0.5",
+            "sphere:0,4:0.5:4,4,4",
+            "sphere:0,4,0:0:4,4,4",
+            "sphere:0,4,0:nan:4,4,4",
+            "disk:0,4,0:0.5:4,4,4@",
+            "point:0,4,0:0.5:4,4,4",
+            "ies:0,4,0:",
+            "cube:0,4,0:0.5:4,4,4",
+        ] {
+            assert!(
+                matches!(
+                    args(&["--light", light]),
+                    Err(CliError::InvalidValue { .. })
+                ),
+                "--light {} was accepted",
+                light
+            );
+        }
+    }
+
+    #[test]
+    fn point_lights_are_parsed() {
+        let args = args(&[
+            "--light",
+            "point:0,4,0:100,90,80@bulbs",
+            "--light",
+            "ies:1,3,0:fixtures/downlight.ies",
+        ])
+        .unwrap();
+        match &args.lights[..] {
+            [LightSpec::Point {
+                intensity, group, ..
+            }, LightSpec::Ies { path, group: 0, .. }] => {
+                assert_eq!(*group, 1);
+                assert_eq!(intensity.r(), 100.);
+                assert_eq!(path, Path::new("fixtures/downlight.ies"));
+            }
+            _ => panic!("expected a point light and an IES light"),
+        }
+    }
+
+    #[test]
+    fn image_materials_share_the_texture_cache() {
+        let parsed = args(&[
+            "--material",
+            "ground=image:grass.ppm",
+            "--material",
+            "matte=image:grass.ppm",
+        ])
+        .unwrap();
+        for (_, material) in &parsed.materials {
+            match material {
+                Material::Lambertian {
+                    albedo: Texture::CachedImage { path, cache, .. },
+                } => {
+                    assert_eq!(path.as_ref(), Path::new("grass.ppm"));
+                    assert!(Arc::ptr_eq(cache, &parsed.textures));
+                }
+                _ => panic!("expected an image texture"),
+            }
+        }
+        assert!(args(&["--material", "ground=image:"]).is_err());
+    }
+
+    #[test]
+    fn material_library_is_parsed_by_line() {
+        let textures = Arc::new(TextureCache::new(0));
+        let mut light_groups = vec!["default".to_string()];
+        let text = "# Studio look\n\nground=lambertian:0.2,0.2,0.2\nmatte=light:4,4,4@key\n";
+        let library = parse_material_library(text, &textures, &mut light_groups).unwrap();
+        let names: Vec<_> = library.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["ground", "matte"]);
+        assert_eq!(light_groups, ["default", "key"]);
+
+        let err = parse_material_library(
+            "ground=lambertian:1\n\nmatte=nope",
+            &textures,
+            &mut light_groups,
+        );
+        assert_eq!(
+            err.err().unwrap(),
+            "1: invalid material \"ground=lambertian:1\""
+        );
+    }
+
+    #[test]
+    fn watching_needs_a_library_and_an_output() {
+        assert!(
+            args(&[
+                "--watch",
+                "--material-library",
+                "looks.txt",
+                "-o",
+                "out.png"
+            ])
+            .unwrap()
+            .watch
+        );
+        for flags in [
+            &["--watch", "-o", "out.png"][..],
+            &["--watch", "--material-library", "looks.txt"],
+        ] {
+            assert!(matches!(args(flags), Err(CliError::Conflict(_))));
+        }
+    }
+
+    #[test]
+    fn region_must_lie_within_the_image() {
+        let parsed = args(&["--width", "64", "--height", "32", "--region", "8, 4,40,32"]).unwrap();
+        assert_eq!(
+            parsed.region,
+            Some(Tile {
+                x0: 8,
+                y0: 4,
+                x1: 40,
+                y1: 32
+            })
+        );
+
+        for region in [
+            "8,4,40",
+            "8,4,40,32,1",
+            "8,4,-40,32",
+            "40,4,8,32",
+            "8,4,8,32",
+            "8,4,65,32",
+            "8,4,40,33",
+        ] {
+            assert!(
+                matches!(
+                    args(&["--width", "64", "--height", "32", "--region", region]),
+                    Err(CliError::InvalidValue { .. })
+                ),
+                "{}",
+                region
+            );
+        }
+    }
+}
@@ -1,4 +1,600 @@
-use crate::vector::Color;
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
 
 pub const BLACK: Color = Color::zero();
 pub const WHITE: Color = Color::new(1., 1., 1.);
+
+/// A linear RGB color.
+///
+/// Kept distinct from `Vec3` so that positions, directions and colors cannot
+/// be mixed up by accident.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Color(pub f64, pub f64, pub f64);
+
+impl Color {
+    pub const fn new(r: f64, g: f64, b: f64) -> Self {
+        Self(r, g, b)
+    }
+
+    pub const fn zero() -> Self {
+        Self(0., 0., 0.)
+    }
+
+    pub fn random<T: Rng>(rng: &mut T) -> Self {
+        Self(rng.gen(), rng.gen(), rng.gen())
+    }
+
+    pub fn random_range<T: Rng>(rng: &mut T, min: f64, max: f64) -> Self {
+        let r = rng.gen_range(min..max);
+        let g = rng.gen_range(min..max);
+        let b = rng.gen_range(min..max);
+        Self(r, g, b)
+    }
+
+    /// Parses an sRGB hex color such as `#80c0ff` or `80c0ff` into linear RGB.
+    pub fn from_hex(hex: &str) -> Result<Self, ParseColorError> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        let length = digits.chars().count();
+        if length != 6 {
+            return Err(ParseColorError::InvalidLength(length));
+        }
+        // `from_str_radix` would also take a sign, e.g. "+f"
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(ParseColorError::InvalidDigit(digits.to_string()));
+        }
+
+        let channel = |i: usize| {
+            u8::from_str_radix(&digits[i..i + 2], 16)
+                .map(|v| srgb_to_linear(v as f64 / 255.))
+                .map_err(|_| ParseColorError::InvalidDigit(digits[i..i + 2].to_string()))
+        };
+
+        Ok(Self(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    pub fn r(self) -> f64 {
+        self.0
+    }
+
+    pub fn g(self) -> f64 {
+        self.1
+    }
+
+    pub fn b(self) -> f64 {
+        self.2
+    }
+
+    /// Relative luminance using the Rec. 709 / sRGB primaries.
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.0 + 0.7152 * self.1 + 0.0722 * self.2
+    }
+
+    pub fn lerp(self, other: Self, t: f64) -> Self {
+        self * (1. - t) + other * t
+    }
+
+    /// Applies the sRGB transfer function to each channel.
+    pub fn to_srgb(self) -> Self {
+        Self(
+            linear_to_srgb(self.0),
+            linear_to_srgb(self.1),
+            linear_to_srgb(self.2),
+        )
+    }
+
+    /// Inverts the sRGB transfer function, returning linear values.
+    pub fn from_srgb(self) -> Self {
+        Self(
+            srgb_to_linear(self.0),
+            srgb_to_linear(self.1),
+            srgb_to_linear(self.2),
+        )
+    }
+}
+
+fn linear_to_srgb(c: f64) -> f64 {
+    if c <= 0.003_130_8 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.040_45 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear sRGB to CIE XYZ.
+const SRGB_TO_XYZ: ColorMatrix = ColorMatrix([
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041],
+]);
+
+/// CIE XYZ to linear sRGB.
+const XYZ_TO_SRGB: ColorMatrix = ColorMatrix([
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+]);
+
+/// CIE XYZ to the Bradford cone responses.
+const BRADFORD: ColorMatrix = ColorMatrix([
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+]);
+
+const BRADFORD_INVERSE: ColorMatrix = ColorMatrix([
+    [0.9869929, -0.1470543, 0.1599627],
+    [0.4323053, 0.5183603, 0.0492912],
+    [-0.0085287, 0.0400428, 0.9684867],
+]);
+
+/// The sRGB white point, D65, in CIE xy.
+const D65: (f64, f64) = (0.31271, 0.32902);
+
+/// A linear transform of colors, such as from one color space to another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorMatrix([[f64; 3]; 3]);
+
+impl ColorMatrix {
+    pub fn apply(&self, c: Color) -> Color {
+        let [r0, r1, r2] = self.0;
+        let row = |r: [f64; 3]| r[0] * c.0 + r[1] * c.1 + r[2] * c.2;
+        Color(row(r0), row(r1), row(r2))
+    }
+
+    /// The transform undoing `self`, which must have one.
+    fn inverse(&self) -> ColorMatrix {
+        let m = self.0;
+        let cofactor = |i: usize, j: usize| {
+            let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+            let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let determinant: f64 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+
+        let mut inverse = [[0.; 3]; 3];
+        for (i, row) in inverse.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = cofactor(j, i) / determinant;
+            }
+        }
+        ColorMatrix(inverse)
+    }
+
+    /// The transform applying `self` after `first`.
+    fn after(&self, first: &ColorMatrix) -> ColorMatrix {
+        let mut m = [[0.; 3]; 3];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| self.0[i][k] * first.0[k][j]).sum();
+            }
+        }
+        ColorMatrix(m)
+    }
+}
+
+/// The RGB color spaces images can be written in. Scenes are always
+/// described and rendered in linear sRGB, which is converted to the output's
+/// space as it's written out; a wider space shows colors that sRGB can't
+/// where it's displayed or graded, such as the glow of saturated lights.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// sRGB and Rec. 709 primaries with a D65 white.
+    #[default]
+    Srgb,
+    /// The wider primaries of Apple's displays and DCI-P3 with a D65 white,
+    /// for wide gamut displays. Uses the sRGB transfer function where the
+    /// format has one.
+    DisplayP3,
+    /// The ACES AP1 primaries with the ACES white, close to D60, for VFX and
+    /// grading pipelines. Linear, so only for floating point formats.
+    AcesCg,
+}
+
+impl ColorSpace {
+    /// The CIE xy chromaticities of the red, green and blue primaries and
+    /// the white point.
+    pub fn chromaticities(self) -> [(f64, f64); 4] {
+        match self {
+            ColorSpace::Srgb => [(0.64, 0.33), (0.30, 0.60), (0.15, 0.06), D65],
+            ColorSpace::DisplayP3 => [(0.68, 0.32), (0.265, 0.69), (0.15, 0.06), D65],
+            ColorSpace::AcesCg => [
+                (0.713, 0.293),
+                (0.165, 0.83),
+                (0.128, 0.044),
+                (0.32168, 0.33767),
+            ],
+        }
+    }
+
+    /// The transform of linear sRGB colors into this space, adapting the
+    /// sRGB white to this space's white.
+    pub fn from_srgb(self) -> ColorMatrix {
+        let [red, green, blue, white] = self.chromaticities();
+        let [r, g, b] = [red, green, blue].map(white_xyz);
+        let primaries = ColorMatrix([[r.0, g.0, b.0], [r.1, g.1, b.1], [r.2, g.2, b.2]]);
+        // Scales of the primaries adding up to the white
+        let scale = primaries.inverse().apply(white_xyz(white));
+        let to_xyz = primaries.after(&ColorMatrix([
+            [scale.0, 0., 0.],
+            [0., scale.1, 0.],
+            [0., 0., scale.2],
+        ]));
+
+        to_xyz
+            .inverse()
+            .after(&adaptation(D65, white))
+            .after(&SRGB_TO_XYZ)
+    }
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srgb" => Ok(ColorSpace::Srgb),
+            "p3" | "display-p3" => Ok(ColorSpace::DisplayP3),
+            "acescg" => Ok(ColorSpace::AcesCg),
+            _ => Err(format!("unknown color space {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColorSpace::Srgb => write!(f, "srgb"),
+            ColorSpace::DisplayP3 => write!(f, "display-p3"),
+            ColorSpace::AcesCg => write!(f, "acescg"),
+        }
+    }
+}
+
+/// How high dynamic range colors are brought into the range a display can
+/// show, for writing renders or converting them to 8 or 16-bit images.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ToneMap {
+    /// Colors as they are, clipped to the display's range where it has one.
+    #[default]
+    Clamp,
+    /// Reinhard's `L / (1 + L)` on the luminance, keeping hues and
+    /// compressing highlights gently towards white.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve, on each channel: more
+    /// contrast, and very bright colors desaturate towards white.
+    Aces,
+}
+
+impl ToneMap {
+    pub fn apply(self, c: Color) -> Color {
+        match self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => c / (1. + c.luminance().max(0.)),
+            ToneMap::Aces => {
+                // The fit expects colors exposed a little darker than usual
+                let curve = |x: f64| {
+                    let x = 0.6 * x.max(0.);
+                    (x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14)).clamp(0., 1.)
+                };
+                Color(curve(c.0), curve(c.1), curve(c.2))
+            }
+        }
+    }
+}
+
+impl FromStr for ToneMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(format!("unknown tone map {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ToneMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ToneMap::Clamp => write!(f, "clamp"),
+            ToneMap::Reinhard => write!(f, "reinhard"),
+            ToneMap::Aces => write!(f, "aces"),
+        }
+    }
+}
+
+/// The color of the light a scene is lit by, to be shown as white, like a
+/// camera's white balance setting. Neutralizes the orange of tungsten light
+/// at around 3200 K, or warms up a scene under a blue sky at 8000 K or more.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WhiteBalance {
+    /// The light's correlated color temperature in kelvins, from 1667 to
+    /// 25000. 6504 K is the sRGB white, which is left as it is.
+    pub temperature: f64,
+    /// How far the light is off the temperature's white towards green,
+    /// positive, or magenta, negative, in thousandths of CIE 1960 Δuv, up
+    /// to 50 either way; fluorescent lights are often about +5.
+    pub tint: f64,
+}
+
+impl WhiteBalance {
+    /// The transform of linear sRGB colors taking the light's white to the
+    /// sRGB white, using the Bradford chromatic adaptation transform.
+    pub fn transform(&self) -> ColorMatrix {
+        XYZ_TO_SRGB
+            .after(&adaptation(self.white(), D65))
+            .after(&SRGB_TO_XYZ)
+    }
+
+    /// The light's white in CIE xy: daylight at its temperature, or for
+    /// warmer lights below 4000 K, a black body, blending from one to the
+    /// other up to 5000 K. The tint moves it across the black body curve.
+    fn white(&self) -> (f64, f64) {
+        let t = self.temperature;
+        let (x, y) = match t {
+            _ if t <= 4000. => planckian(t),
+            _ if t >= 5000. => daylight(t),
+            _ => {
+                let (px, py) = planckian(t);
+                let (dx, dy) = daylight(t);
+                let s = (t - 4000.) / 1000.;
+                (px + (dx - px) * s, py + (dy - py) * s)
+            }
+        };
+
+        // Perpendicular to the black body curve in CIE 1960 uv, towards
+        // green, which is up
+        let (u0, v0) = xy_to_uv(planckian(t - 1.));
+        let (u1, v1) = xy_to_uv(planckian(t + 1.));
+        let (du, dv) = (u1 - u0, v1 - v0);
+        let length = (du * du + dv * dv).sqrt();
+        let (nu, nv) = if du < 0. {
+            (dv / length, -du / length)
+        } else {
+            (-dv / length, du / length)
+        };
+
+        let (u, v) = xy_to_uv((x, y));
+        let shift = self.tint * 0.001;
+        uv_to_xy((u + nu * shift, v + nv * shift))
+    }
+}
+
+impl FromStr for WhiteBalance {
+    type Err = String;
+
+    /// Parses a temperature in kelvins optionally followed by a tint, e.g.
+    /// `3200` or `4000,5`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid white balance {:?}", s);
+        let number = |s: &str| s.trim().parse::<f64>().map_err(|_| invalid());
+
+        let (temperature, tint) = match s.split_once(',') {
+            Some((temperature, tint)) => (number(temperature)?, number(tint)?),
+            None => (number(s)?, 0.),
+        };
+        if !(1667. ..=25000.).contains(&temperature) || !(-50. ..=50.).contains(&tint) {
+            return Err(invalid());
+        }
+
+        Ok(Self { temperature, tint })
+    }
+}
+
+/// The transform of CIE XYZ colors seen under light of white `from` to how
+/// they look under light of white `to`, both in CIE xy, using the Bradford
+/// chromatic adaptation transform.
+fn adaptation(from: (f64, f64), to: (f64, f64)) -> ColorMatrix {
+    let source = BRADFORD.apply(white_xyz(from));
+    let target = BRADFORD.apply(white_xyz(to));
+    let scale = ColorMatrix([
+        [target.0 / source.0, 0., 0.],
+        [0., target.1 / source.1, 0.],
+        [0., 0., target.2 / source.2],
+    ]);
+
+    BRADFORD_INVERSE.after(&scale).after(&BRADFORD)
+}
+
+/// The CIE XYZ color with chromaticity `x`, `y` and unit luminance.
+fn white_xyz((x, y): (f64, f64)) -> Color {
+    Color(x / y, 1., (1. - x - y) / y)
+}
+
+/// The chromaticity of a black body at `t` kelvins, using Kim et al.'s
+/// cubic spline fit to the Planckian locus, accurate from 1667 K to 25000 K.
+fn planckian(t: f64) -> (f64, f64) {
+    let (t1, t2, t3) = (1e3 / t, 1e6 / (t * t), 1e9 / (t * t * t));
+    let x = if t <= 4000. {
+        -0.2661239 * t3 - 0.2343589 * t2 + 0.8776956 * t1 + 0.179910
+    } else {
+        -3.0258469 * t3 + 2.1070379 * t2 + 0.2226347 * t1 + 0.240390
+    };
+    let y = if t <= 2222. {
+        -1.1063814 * x.powi(3) - 1.3481102 * x * x + 2.18555832 * x - 0.20219683
+    } else if t <= 4000. {
+        -0.9549476 * x.powi(3) - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+    } else {
+        3.081758 * x.powi(3) - 5.8733867 * x * x + 3.75112997 * x - 0.37001483
+    };
+    (x, y)
+}
+
+/// The chromaticity of CIE daylight at `t` kelvins, from 4000 K to 25000 K.
+fn daylight(t: f64) -> (f64, f64) {
+    let (t1, t2, t3) = (1e3 / t, 1e6 / (t * t), 1e9 / (t * t * t));
+    let x = if t <= 7000. {
+        -4.607 * t3 + 2.9678 * t2 + 0.09911 * t1 + 0.244063
+    } else {
+        -2.0064 * t3 + 1.9018 * t2 + 0.24748 * t1 + 0.23704
+    };
+    (x, -3. * x * x + 2.87 * x - 0.275)
+}
+
+fn xy_to_uv((x, y): (f64, f64)) -> (f64, f64) {
+    let d = -2. * x + 12. * y + 3.;
+    (4. * x / d, 6. * y / d)
+}
+
+fn uv_to_xy((u, v): (f64, f64)) -> (f64, f64) {
+    let d = 2. * u - 8. * v + 4.;
+    (3. * u / d, 2. * v / d)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseColorError {
+    InvalidLength(usize),
+    InvalidDigit(String),
+}
+
+impl fmt::Display for ParseColorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseColorError::InvalidLength(len) => {
+                write!(f, "expected 6 hex digits, got {} characters", len)
+            }
+            ParseColorError::InvalidDigit(digits) => write!(f, "invalid hex digits {:?}", digits),
+        }
+    }
+}
+
+impl std::error::Error for ParseColorError {}
+
+// Binary Operators
+
+impl std::ops::Add for Color {
+    type Output = Color;
+
+    fn add(self, other: Self) -> Self::Output {
+        Color(self.0 + other.0, self.1 + other.1, self.2 + other.2)
+    }
+}
+
+impl std::ops::AddAssign<Color> for Color {
+    fn add_assign(&mut self, other: Color) {
+        *self = *self + other;
+    }
+}
+
+impl std::ops::Sub for Color {
+    type Output = Color;
+
+    fn sub(self, other: Self) -> Self::Output {
+        Color(self.0 - other.0, self.1 - other.1, self.2 - other.2)
+    }
+}
+
+impl std::ops::Mul<f64> for Color {
+    type Output = Color;
+
+    fn mul(self, rhs: f64) -> Self::Output {
+        Color(self.0 * rhs, self.1 * rhs, self.2 * rhs)
+    }
+}
+
+impl std::ops::MulAssign<f64> for Color {
+    fn mul_assign(&mut self, rhs: f64) {
+        *self = *self * rhs;
+    }
+}
+
+impl std::ops::Mul<Color> for Color {
+    type Output = Color;
+
+    fn mul(self, rhs: Color) -> Self::Output {
+        Color(self.0 * rhs.0, self.1 * rhs.1, self.2 * rhs.2)
+    }
+}
+
+impl std::ops::MulAssign<Color> for Color {
+    fn mul_assign(&mut self, rhs: Color) {
+        *self = *self * rhs;
+    }
+}
+
+impl std::ops::Div<f64> for Color {
+    type Output = Color;
+
+    fn div(self, rhs: f64) -> Self::Output {
+        Color(self.0 / rhs, self.1 / rhs, self.2 / rhs)
+    }
+}
+
+impl std::ops::DivAssign<f64> for Color {
+    fn div_assign(&mut self, rhs: f64) {
+        *self = *self / rhs;
+    }
+}
+
+impl std::iter::Sum for Color {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Color::zero(), |a, b| a + b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_hex_parses_with_or_without_hash() {
+        assert_eq!(Color::from_hex("#000000"), Ok(Color::new(0., 0., 0.)));
+        assert_eq!(Color::from_hex("ffffff"), Ok(Color::new(1., 1., 1.)));
+        assert_eq!(Color::from_hex("#FFfFff"), Ok(Color::new(1., 1., 1.)));
+
+        let color = Color::from_hex("#80c0ff").unwrap();
+        assert!((color.r() - srgb_to_linear(128. / 255.)).abs() < 1e-12);
+        assert!((color.g() - srgb_to_linear(192. / 255.)).abs() < 1e-12);
+        assert_eq!(color.b(), 1.);
+    }
+
+    #[test]
+    fn from_hex_rejects_wrong_lengths() {
+        assert_eq!(Color::from_hex(""), Err(ParseColorError::InvalidLength(0)));
+        assert_eq!(
+            Color::from_hex("#fff"),
+            Err(ParseColorError::InvalidLength(3))
+        );
+        assert_eq!(
+            Color::from_hex("#80c0ff00"),
+            Err(ParseColorError::InvalidLength(8))
+        );
+        // Counted in characters, not bytes
+        assert_eq!(
+            Color::from_hex("#ééé"),
+            Err(ParseColorError::InvalidLength(3))
+        );
+    }
+
+    #[test]
+    fn from_hex_rejects_non_hex_digits() {
+        assert!(matches!(
+            Color::from_hex("#80c0fg"),
+            Err(ParseColorError::InvalidDigit(_))
+        ));
+        assert!(matches!(
+            Color::from_hex("#+f+f+f"),
+            Err(ParseColorError::InvalidDigit(_))
+        ));
+        assert!(matches!(
+            Color::from_hex("-1-1-1"),
+            Err(ParseColorError::InvalidDigit(_))
+        ));
+        assert!(matches!(
+            Color::from_hex("#80 c0f"),
+            Err(ParseColorError::InvalidDigit(_))
+        ));
+    }
+}
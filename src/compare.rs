@@ -0,0 +1,217 @@
+use std::fmt;
+
+use rayon::prelude::*;
+
+use crate::color::Color;
+use crate::image::Image;
+
+/// Pixels this much brighter than black count as if they were, in the
+/// relative error, so noise in near-black pixels doesn't swamp the rest.
+const RELATIVE_MSE_OFFSET: f64 = 0.01;
+
+/// Standard deviation of the Gaussian window SSIM is measured over, in
+/// pixels, and how far out it reaches.
+const SSIM_SIGMA: f64 = 1.5;
+const SSIM_RADIUS: usize = 5;
+
+/// The usual SSIM stabilizing constants, for values from 0 to 1.
+const SSIM_C1: f64 = 0.01 * 0.01;
+const SSIM_C2: f64 = 0.03 * 0.03;
+
+/// How an image differs from a reference of the same size, for judging
+/// changes to sampling and integration by rendering the same scene both
+/// ways.
+///
+/// The squared errors are measured on linear values, which keeps all of
+/// the error in bright parts of the image. PSNR and SSIM are measured on
+/// the sRGB-encoded values clamped to the display's range, as they'd be
+/// seen.
+pub struct Comparison {
+    pub width: usize,
+    pub height: usize,
+    /// Mean squared error over every channel of every pixel.
+    pub mse: f64,
+    /// Mean squared error relative to the reference's value, squared.
+    pub relative_mse: f64,
+    /// Peak signal to noise ratio in decibels; infinite for identical
+    /// images.
+    pub psnr: f64,
+    /// Mean structural similarity of the luma, from 1 for identical images
+    /// down.
+    pub ssim: f64,
+    /// Each pixel's relative squared error, averaged over its channels, in
+    /// rows from the top.
+    pub error_map: Vec<f64>,
+    /// Each pixel's structural similarity, in rows from the top.
+    pub ssim_map: Vec<f64>,
+}
+
+impl Comparison {
+    /// Compares `image` with `reference`, or says why they can't be.
+    pub fn new(image: &Image, reference: &Image) -> Result<Self, String> {
+        let (width, height) = (reference.width(), reference.height());
+        if (image.width(), image.height()) != (width, height) {
+            return Err(format!(
+                "cannot compare a {}x{} image with a {}x{} one",
+                image.width(),
+                image.height(),
+                width,
+                height
+            ));
+        }
+
+        let pixels = |image: &Image| -> Vec<Color> {
+            (0..width * height)
+                .map(|i| image.pixel(i % width, i / width))
+                .collect()
+        };
+        let (a, b) = (pixels(image), pixels(reference));
+        let count = (width * height) as f64;
+
+        let channels = |c: Color| [c.r(), c.g(), c.b()];
+        let squared_error = |a: Color, b: Color| -> f64 {
+            channels(a)
+                .iter()
+                .zip(channels(b))
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<f64>()
+                / 3.
+        };
+        let relative_error = |a: Color, b: Color| -> f64 {
+            channels(a)
+                .iter()
+                .zip(channels(b))
+                .map(|(a, b)| (a - b) * (a - b) / (b * b + RELATIVE_MSE_OFFSET))
+                .sum::<f64>()
+                / 3.
+        };
+
+        let mse = a
+            .iter()
+            .zip(&b)
+            .map(|(&a, &b)| squared_error(a, b))
+            .sum::<f64>()
+            / count;
+        let error_map: Vec<f64> = a
+            .iter()
+            .zip(&b)
+            .map(|(&a, &b)| relative_error(a, b))
+            .collect();
+        let relative_mse = error_map.iter().sum::<f64>() / count;
+
+        let display = |c: Color| {
+            let c = c.to_srgb();
+            Color::new(
+                c.r().clamp(0., 1.),
+                c.g().clamp(0., 1.),
+                c.b().clamp(0., 1.),
+            )
+        };
+        let (a, b): (Vec<Color>, Vec<Color>) = (
+            a.iter().map(|&c| display(c)).collect(),
+            b.iter().map(|&c| display(c)).collect(),
+        );
+        let display_mse = a
+            .iter()
+            .zip(&b)
+            .map(|(&a, &b)| squared_error(a, b))
+            .sum::<f64>()
+            / count;
+        let psnr = -10. * display_mse.log10();
+
+        let luma =
+            |pixels: &[Color]| -> Vec<f64> { pixels.iter().map(|c| c.luminance()).collect() };
+        let ssim_map = ssim(width, height, &luma(&a), &luma(&b));
+        let ssim = ssim_map.iter().sum::<f64>() / count;
+
+        Ok(Self {
+            width,
+            height,
+            mse,
+            relative_mse,
+            psnr,
+            ssim,
+            error_map,
+            ssim_map,
+        })
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = [
+            ("MSE", format!("{:.6e}", self.mse)),
+            ("relative MSE", format!("{:.6e}", self.relative_mse)),
+            ("PSNR", format!("{:.2} dB", self.psnr)),
+            ("SSIM", format!("{:.4}", self.ssim)),
+        ];
+        for (i, (name, value)) in rows.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  {:<24} {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// The structural similarity (Wang et al., "Image Quality Assessment: From
+/// Error Visibility to Structural Similarity") of `a` and `b` around each
+/// pixel, in a Gaussian window. The window is cut off at the image's edges.
+fn ssim(width: usize, height: usize, a: &[f64], b: &[f64]) -> Vec<f64> {
+    let weights: Vec<f64> = (0..=SSIM_RADIUS)
+        .map(|i| (-((i * i) as f64) / (2. * SSIM_SIGMA * SSIM_SIGMA)).exp())
+        .collect();
+
+    // A separable blur, renormalized where the window leaves the image
+    let blur = |values: &[f64]| -> Vec<f64> {
+        let along = |at: &dyn Fn(usize) -> f64, i: usize, len: usize| {
+            let start = i.saturating_sub(SSIM_RADIUS);
+            let end = (i + SSIM_RADIUS).min(len - 1);
+            let (sum, total) = (start..=end).fold((0., 0.), |(sum, total), j| {
+                let w = weights[i.abs_diff(j)];
+                (sum + at(j) * w, total + w)
+            });
+            sum / total
+        };
+
+        let mut rows = vec![0.; width * height];
+        rows.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+            let line = &values[y * width..(y + 1) * width];
+            for (x, out) in row.iter_mut().enumerate() {
+                *out = along(&|j| line[j], x, width);
+            }
+        });
+
+        let mut blurred = vec![0.; width * height];
+        blurred
+            .par_chunks_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, out) in row.iter_mut().enumerate() {
+                    *out = along(&|j| rows[j * width + x], y, height);
+                }
+            });
+        blurred
+    };
+
+    let product =
+        |x: &[f64], y: &[f64]| -> Vec<f64> { x.iter().zip(y).map(|(x, y)| x * y).collect() };
+    let (mean_a, mean_b) = (blur(a), blur(b));
+    let (mean_aa, mean_bb, mean_ab) = (
+        blur(&product(a, a)),
+        blur(&product(b, b)),
+        blur(&product(a, b)),
+    );
+
+    (0..width * height)
+        .map(|i| {
+            let (ma, mb) = (mean_a[i], mean_b[i]);
+            let variance_a = mean_aa[i] - ma * ma;
+            let variance_b = mean_bb[i] - mb * mb;
+            let covariance = mean_ab[i] - ma * mb;
+            ((2. * ma * mb + SSIM_C1) * (2. * covariance + SSIM_C2))
+                / ((ma * ma + mb * mb + SSIM_C1) * (variance_a + variance_b + SSIM_C2))
+        })
+        .collect()
+}
@@ -0,0 +1,56 @@
+use std::str::FromStr;
+
+use crate::color::Color;
+use crate::material_library::parse_color;
+
+/// Haze laid over the image by how far away each pixel's surface is, as a
+/// quick cue of depth that costs nothing like rendering fog does.
+///
+/// Surfaces at distance `d` show `exp(-(density * d)^falloff)` of their own
+/// color, and the rest is the fog's. The background is left as it is, so a
+/// color close to the sky's at the horizon fades distant objects into it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DepthFog {
+    pub color: Color,
+    /// How quickly surfaces fade with distance, per unit of the scene's
+    /// length.
+    pub density: f64,
+    /// The shape of the fade: 1 for fog that thickens steadily from the
+    /// camera, higher for clear air up close that thickens more sharply
+    /// further out.
+    pub falloff: f64,
+}
+
+impl DepthFog {
+    /// The fraction of a surface's own color still seen from `depth` away.
+    pub fn transmittance(&self, depth: f64) -> f64 {
+        (-(self.density * depth).powf(self.falloff)).exp()
+    }
+}
+
+impl FromStr for DepthFog {
+    type Err = String;
+
+    /// Parses `COLOR:DENSITY[:FALLOFF]`, with the color as linear `R,G,B` or
+    /// an sRGB hex code, e.g. `#c8d8e8:0.02` or `0.8,0.85,0.9:0.01:2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid depth fog {:?}", s);
+        let number = |s: &str| s.trim().parse::<f64>().map_err(|_| invalid());
+
+        let (color, density, falloff) = match s.split(':').collect::<Vec<_>>()[..] {
+            [color, density] => (color, number(density)?, 1.),
+            [color, density, falloff] => (color, number(density)?, number(falloff)?),
+            _ => return Err(invalid()),
+        };
+        let color = parse_color(color).ok_or_else(invalid)?;
+        if !(density.is_finite() && density >= 0. && falloff.is_finite() && falloff > 0.) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            color,
+            density,
+            falloff,
+        })
+    }
+}
@@ -0,0 +1,292 @@
+use std::f64::consts::PI;
+use std::io::{self, Write};
+
+use crate::color::Color;
+use crate::ray::Material;
+use crate::texture::Texture;
+use crate::transform::Transform;
+use crate::vector::{Point3, Vec3};
+
+/// How far curved surfaces may stray from the triangles standing in for
+/// them by default, in scene units.
+pub const DEFAULT_TOLERANCE: f64 = 0.01;
+
+/// Fewest and most segments a full circle is cut into, however small or
+/// large it is.
+const MIN_SEGMENTS: usize = 12;
+const MAX_SEGMENTS: usize = 1024;
+
+/// Triangles approximating a scene's objects, with their materials, for
+/// writing as Wavefront OBJ and MTL files, so the same geometry can be
+/// rendered in other renderers to compare against.
+///
+/// Curved surfaces are cut finely enough to stay within `tolerance` of the
+/// real ones. MTL can only describe simple materials, so textures are
+/// written as their average color and materials are approximated.
+pub struct Tessellation {
+    pub tolerance: f64,
+    vertices: Vec<Point3>,
+    /// Triangles as indices into `vertices`, with the index of their
+    /// material in `materials`.
+    faces: Vec<([usize; 3], usize)>,
+    /// Materials as MTL statements, each written once.
+    materials: Vec<String>,
+    /// Objects left out because they can't be tessellated, such as volumes.
+    skipped: usize,
+}
+
+impl Tessellation {
+    pub fn new(tolerance: f64) -> Self {
+        Self {
+            tolerance,
+            vertices: vec![],
+            faces: vec![],
+            materials: vec![],
+            skipped: 0,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.faces.len()
+    }
+
+    /// The number of objects left out.
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Notes an object that can't be tessellated.
+    pub fn skip(&mut self) {
+        self.skipped += 1;
+    }
+
+    /// Adds a triangle facing the side its vertices go anticlockwise
+    /// around. Triangles with no area are left out.
+    pub fn add_triangle(&mut self, vertices: [Point3; 3], material: &Material) {
+        let first = self.vertices.len();
+        self.vertices.extend(vertices);
+        let material = self.material_index(material);
+        self.add_face([first, first + 1, first + 2], material);
+    }
+
+    /// Adds a triangle between existing vertices, unless it has no area.
+    fn add_face(&mut self, face: [usize; 3], material: usize) {
+        let [a, b, c] = face.map(|i| self.vertices[i]);
+        if (b - a).cross_product(c - a).length_squared() > 0. {
+            self.faces.push((face, material));
+        }
+    }
+
+    /// Adds a triangle, turned if need be to face along `normal`.
+    pub fn add_triangle_facing(
+        &mut self,
+        vertices: [Point3; 3],
+        normal: Vec3,
+        material: &Material,
+    ) {
+        let [a, b, c] = vertices;
+        if (b - a).cross_product(c - a).dot_product(normal) < 0. {
+            self.add_triangle([a, c, b], material);
+        } else {
+            self.add_triangle(vertices, material);
+        }
+    }
+
+    /// Adds a surface `point(s, t)` for `s` and `t` from 0 to 1, cut into
+    /// `columns` by `rows` quads, facing along the cross product of its
+    /// derivatives in `s` and `t`.
+    pub fn add_grid<F>(&mut self, columns: usize, rows: usize, point: F, material: &Material)
+    where
+        F: Fn(f64, f64) -> Point3,
+    {
+        // Neighbouring quads share their corners
+        let first = self.vertices.len();
+        for j in 0..=rows {
+            for i in 0..=columns {
+                let (s, t) = (i as f64 / columns as f64, j as f64 / rows as f64);
+                self.vertices.push(point(s, t));
+            }
+        }
+
+        let material = self.material_index(material);
+        let at = |i: usize, j: usize| first + j * (columns + 1) + i;
+        for j in 0..rows {
+            for i in 0..columns {
+                let (p00, p10) = (at(i, j), at(i + 1, j));
+                let (p01, p11) = (at(i, j + 1), at(i + 1, j + 1));
+                self.add_face([p00, p10, p11], material);
+                self.add_face([p00, p11, p01], material);
+            }
+        }
+    }
+
+    /// The number of segments to cut `angle` radians of a circle of
+    /// `radius` into, to stay within the tolerance.
+    pub fn segments(&self, radius: f64, angle: f64) -> usize {
+        // A chord spanning `step` radians strays radius * (1 - cos(step / 2))
+        // from the arc
+        let ratio = (1. - self.tolerance / radius.abs()).clamp(-1., 1.);
+        let step = 2. * ratio.acos();
+        let full = if step > 0. {
+            (2. * PI / step).ceil() as usize
+        } else {
+            MAX_SEGMENTS
+        };
+        let full = full.clamp(MIN_SEGMENTS, MAX_SEGMENTS);
+        ((full as f64 * angle / (2. * PI)).ceil() as usize).max(1)
+    }
+
+    /// Adds the part of a sphere between polar angles `theta` and azimuths
+    /// `phi`, in radians, with `theta` from 0 at the bottom pole to pi at
+    /// the top and `phi` around the y axis from -x, facing out.
+    pub fn add_sphere(
+        &mut self,
+        center: Point3,
+        radius: f64,
+        theta: (f64, f64),
+        phi: (f64, f64),
+        material: &Material,
+    ) {
+        let columns = self.segments(radius, phi.1 - phi.0);
+        let rows = self.segments(radius, theta.1 - theta.0);
+        let point = |s: f64, t: f64| {
+            let phi = phi.0 + s * (phi.1 - phi.0);
+            let theta = theta.0 + t * (theta.1 - theta.0);
+            let (sin_theta, cos_theta) = theta.sin_cos();
+            let (sin_phi, cos_phi) = phi.sin_cos();
+            center + Vec3::new(-sin_theta * cos_phi, -cos_theta, sin_theta * sin_phi) * radius
+        };
+        self.add_grid(columns, rows, point, material);
+    }
+
+    /// Adds everything in `other` with its points moved by `transform`.
+    pub fn add_transformed(&mut self, other: Tessellation, transform: &Transform) {
+        let first = self.vertices.len();
+        self.vertices
+            .extend(other.vertices.iter().map(|&p| transform.point(p)));
+
+        let materials: Vec<usize> = other
+            .materials
+            .iter()
+            .map(|statements| self.statements_index(statements))
+            .collect();
+        // A transform that mirrors the points turns the faces inside out
+        let mirrored = transform
+            .vector(Vec3::new(1., 0., 0.))
+            .cross_product(transform.vector(Vec3::new(0., 1., 0.)))
+            .dot_product(transform.vector(Vec3::new(0., 0., 1.)))
+            < 0.;
+        self.faces
+            .extend(other.faces.iter().map(|&([a, b, c], material)| {
+                let face = if mirrored { [a, c, b] } else { [a, b, c] };
+                (face.map(|i| first + i), materials[material])
+            }));
+        self.skipped += other.skipped;
+    }
+
+    fn material_index(&mut self, material: &Material) -> usize {
+        self.statements_index(&mtl(material))
+    }
+
+    fn statements_index(&mut self, statements: &str) -> usize {
+        match self.materials.iter().position(|m| m == statements) {
+            Some(index) => index,
+            None => {
+                self.materials.push(statements.to_string());
+                self.materials.len() - 1
+            }
+        }
+    }
+
+    /// Writes the triangles as OBJ, using the materials written to the MTL
+    /// file `mtl_name` by `write_mtl`.
+    pub fn write_obj<W: Write>(&self, mtl_name: &str, w: &mut W) -> io::Result<()> {
+        writeln!(w, "# {} triangles", self.faces.len())?;
+        writeln!(w, "mtllib {}", mtl_name)?;
+        for p in &self.vertices {
+            writeln!(w, "v {} {} {}", p.x(), p.y(), p.z())?;
+        }
+
+        let mut current = None;
+        for &([a, b, c], material) in &self.faces {
+            if current != Some(material) {
+                writeln!(w, "usemtl material_{}", material)?;
+                current = Some(material);
+            }
+            // OBJ indices count from 1
+            writeln!(w, "f {} {} {}", a + 1, b + 1, c + 1)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_mtl<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        for (i, statements) in self.materials.iter().enumerate() {
+            if i > 0 {
+                writeln!(w)?;
+            }
+            writeln!(w, "newmtl material_{}", i)?;
+            write!(w, "{}", statements)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The MTL statements approximating `material`.
+fn mtl(material: &Material) -> String {
+    let rgb = |c: Color| format!("{} {} {}", c.r(), c.g(), c.b());
+    let black = rgb(Color::zero());
+
+    match material {
+        Material::Lambertian { albedo }
+        | Material::Isotropic { albedo }
+        | Material::ShadowCatcher { albedo } => {
+            format!("illum 1\nKd {}\n", rgb(average(albedo)))
+        }
+        Material::Metal { albedo, fuzz } => {
+            // The Phong exponent matching a microfacet roughness
+            let exponent = if *fuzz > 0. {
+                (2. / (fuzz * fuzz) - 2.).clamp(0., 1000.)
+            } else {
+                1000.
+            };
+            format!(
+                "illum 3\nKd {}\nKs {}\nNs {}\n",
+                black,
+                rgb(*albedo),
+                exponent
+            )
+        }
+        Material::Dialectric {
+            index_of_refraction,
+            medium,
+            ..
+        } => {
+            // Light crossing a unit of the medium
+            let filter = medium.as_ref().map_or(Color::new(1., 1., 1.), |m| {
+                let a = m.absorption;
+                Color::new((-a.r()).exp(), (-a.g()).exp(), (-a.b()).exp())
+            });
+            format!(
+                "illum 7\nKd {}\nKs 1 1 1\nNi {}\nTf {}\nd 1\n",
+                black,
+                index_of_refraction,
+                rgb(filter)
+            )
+        }
+        Material::DiffuseLight { emit, .. } => {
+            format!("illum 0\nKd {}\nKe {}\n", black, rgb(average(emit)))
+        }
+    }
+}
+
+/// A single color standing in for a texture.
+fn average(texture: &Texture) -> Color {
+    match texture {
+        Texture::Solid(c) => *c,
+        Texture::Checker { even, odd, .. } => (average(even) + average(odd)) * 0.5,
+        Texture::Transformed { texture, .. } => average(texture),
+        _ => Color::new(0.5, 0.5, 0.5),
+    }
+}
@@ -0,0 +1,389 @@
+use std::io::{self, Read, Write};
+
+const MAGIC: u32 = 20000630;
+const VERSION: u32 = 2;
+
+/// Pixel type code for 32-bit floats.
+const FLOAT: i32 = 2;
+
+/// A named channel of an OpenEXR image, in rows from top to bottom.
+///
+/// Layers are expressed in the name with a dot, e.g. `normal.X`; channels
+/// without one form the default layer that most viewers show.
+pub struct Channel {
+    pub name: String,
+    pub values: Vec<f32>,
+}
+
+impl Channel {
+    pub fn new(name: &str, values: Vec<f32>) -> Self {
+        Self {
+            name: name.to_string(),
+            values,
+        }
+    }
+}
+
+/// Writes an uncompressed single-part scanline OpenEXR file, tagged with
+/// the CIE xy `chromaticities` of its red, green and blue primaries and its
+/// white point if they're given.
+pub fn write<W: Write>(
+    out: &mut W,
+    width: usize,
+    height: usize,
+    channels: Vec<Channel>,
+    chromaticities: Option<[(f64, f64); 4]>,
+) -> io::Result<()> {
+    let mut channels = channels;
+    for channel in channels.iter() {
+        assert_eq!(
+            channel.values.len(),
+            width * height,
+            "channel size mismatch"
+        );
+    }
+
+    // Readers expect the channel list in alphabetical order
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut header = vec![];
+    header.extend_from_slice(&MAGIC.to_le_bytes());
+    header.extend_from_slice(&VERSION.to_le_bytes());
+
+    let mut chlist = vec![];
+    for channel in channels.iter() {
+        chlist.extend_from_slice(channel.name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&FLOAT.to_le_bytes());
+        // pLinear and three reserved bytes
+        chlist.extend_from_slice(&[0; 4]);
+        // x and y sampling
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+    attribute(&mut header, "channels", "chlist", &chlist);
+
+    if let Some(chromaticities) = chromaticities {
+        let values: Vec<u8> = chromaticities
+            .iter()
+            .flat_map(|&(x, y)| [x as f32, y as f32])
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        attribute(&mut header, "chromaticities", "chromaticities", &values);
+    }
+
+    attribute(&mut header, "compression", "compression", &[0]);
+
+    let window = [0, 0, width as i32 - 1, height as i32 - 1];
+    let window: Vec<u8> = window.iter().flat_map(|v| v.to_le_bytes()).collect();
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+
+    let center: Vec<u8> = [0f32, 0.].iter().flat_map(|v| v.to_le_bytes()).collect();
+    attribute(&mut header, "screenWindowCenter", "v2f", &center);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    out.write_all(&header)?;
+
+    // Every scanline is its own block, found through a table of offsets
+    let line_size = width * channels.len() * 4;
+    let block_size = 8 + line_size;
+    let first_block = header.len() + height * 8;
+    for y in 0..height {
+        let offset = (first_block + y * block_size) as u64;
+        out.write_all(&offset.to_le_bytes())?;
+    }
+
+    for y in 0..height {
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line_size as i32).to_le_bytes())?;
+
+        for channel in channels.iter() {
+            for value in &channel.values[y * width..(y + 1) * width] {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+    }
+
+    out.flush()
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+/// Pixel type code for 16-bit floats.
+const HALF: i32 = 1;
+
+/// Reads a single-part scanline OpenEXR file, returning its width, height
+/// and channels. Only uncompressed files of half or full floats can be
+/// read, such as those `write` writes.
+pub fn read<R: Read>(input: &mut R) -> io::Result<(usize, usize, Vec<Channel>)> {
+    let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+
+    let mut data = vec![];
+    input.read_to_end(&mut data)?;
+    let file_size = data.len();
+    let mut pos = 0;
+    let mut take = |len: usize| -> io::Result<&[u8]> {
+        let bytes = data
+            .get(pos..pos + len)
+            .ok_or_else(|| invalid("file ends before the image data does"))?;
+        pos += len;
+        Ok(bytes)
+    };
+    let read_i32 = |bytes: &[u8]| i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+    if u32::from_le_bytes(take(4)?.try_into().unwrap()) != MAGIC {
+        return Err(invalid("not an OpenEXR file"));
+    }
+    // Bit 9 marks tiled files, and bits 11 and 12 deep and multi-part ones
+    let version = u32::from_le_bytes(take(4)?.try_into().unwrap());
+    if version & 0xff != VERSION || version & 0x1a00 != 0 {
+        return Err(invalid(
+            "only single-part scanline OpenEXR files can be read",
+        ));
+    }
+
+    let mut channels: Vec<(String, i32)> = vec![];
+    let mut window = None;
+    loop {
+        let name = null_terminated(&mut take)?;
+        if name.is_empty() {
+            break;
+        }
+        let kind = null_terminated(&mut take)?;
+        let size = read_i32(take(4)?);
+        let value = take(usize::try_from(size).map_err(|_| invalid("invalid header"))?)?;
+
+        match (name.as_str(), kind.as_str()) {
+            ("channels", "chlist") => {
+                let mut rest = value;
+                while let Some(end) = rest.iter().position(|&b| b == 0) {
+                    if end == 0 {
+                        break;
+                    }
+                    let name = String::from_utf8_lossy(&rest[..end]).into_owned();
+                    let info = rest
+                        .get(end + 1..end + 17)
+                        .ok_or_else(|| invalid("invalid channel list"))?;
+                    channels.push((name, read_i32(info)));
+                    rest = &rest[end + 17..];
+                }
+            }
+            ("compression", _) if value.first() != Some(&0) => {
+                return Err(invalid("only uncompressed OpenEXR files can be read"));
+            }
+            ("dataWindow", "box2i") if value.len() == 16 => {
+                let v: Vec<i32> = value.chunks_exact(4).map(read_i32).collect();
+                window = Some((v[0], v[1], v[2], v[3]));
+            }
+            _ => {}
+        }
+    }
+
+    let (x_min, y_min, x_max, y_max) = window.ok_or_else(|| invalid("no data window"))?;
+    if x_max < x_min || y_max < y_min {
+        return Err(invalid("invalid data window"));
+    }
+    // In 64 bits, as the window can span more than an i32
+    let width = x_max as i64 - x_min as i64 + 1;
+    let height = y_max as i64 - y_min as i64 + 1;
+    if channels.is_empty() {
+        return Err(invalid("no channels"));
+    }
+    if channels
+        .iter()
+        .any(|&(_, kind)| kind != FLOAT && kind != HALF)
+    {
+        return Err(invalid("only half and float channels can be read"));
+    }
+
+    // Channels are stored in the order they're listed, which is
+    // alphabetical, each a whole scanline at a time
+    let pixel_size: i64 = channels
+        .iter()
+        .map(|&(_, kind)| if kind == FLOAT { 4 } else { 2 })
+        .sum();
+    // The offset table, then each scanline with its y and size. Checked
+    // against the file's size before anything is allocated, so a corrupt
+    // header can't ask for more memory than the file could fill.
+    let sizes = width.checked_mul(pixel_size).and_then(|line_size| {
+        let image_size = height.checked_mul(line_size.checked_add(16)?)?;
+        Some((line_size, image_size))
+    });
+    let (line_size, width, height) = match sizes {
+        Some((line_size, image_size)) if image_size <= file_size as i64 => {
+            (line_size as usize, width as usize, height as usize)
+        }
+        _ => return Err(invalid("file ends before the image data does")),
+    };
+
+    let mut values = vec![vec![0f32; width * height]; channels.len()];
+    take(height * 8)?;
+    for _ in 0..height {
+        let y = read_i32(take(4)?) as i64 - y_min as i64;
+        let size = read_i32(take(4)?);
+        if !(0..height as i64).contains(&y) || size as usize != line_size {
+            return Err(invalid("invalid scanline"));
+        }
+        let y = y as usize;
+        for (&(_, kind), values) in channels.iter().zip(values.iter_mut()) {
+            let row = &mut values[y * width..(y + 1) * width];
+            if kind == FLOAT {
+                for (value, bytes) in row.iter_mut().zip(take(width * 4)?.chunks_exact(4)) {
+                    *value = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                }
+            } else {
+                for (value, bytes) in row.iter_mut().zip(take(width * 2)?.chunks_exact(2)) {
+                    *value = half_to_f32(u16::from_le_bytes([bytes[0], bytes[1]]));
+                }
+            }
+        }
+    }
+
+    let channels = channels
+        .into_iter()
+        .zip(values)
+        .map(|((name, _), values)| Channel { name, values })
+        .collect();
+    Ok((width, height, channels))
+}
+
+/// Reads a null-terminated string from the header.
+fn null_terminated<'a>(take: &mut impl FnMut(usize) -> io::Result<&'a [u8]>) -> io::Result<String> {
+    let mut bytes = vec![];
+    loop {
+        match take(1)?[0] {
+            0 => return Ok(String::from_utf8_lossy(&bytes).into_owned()),
+            b => bytes.push(b),
+        }
+    }
+}
+
+fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1. } else { 1. };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    sign * match exponent {
+        0 => mantissa * (-24f32).exp2(),
+        31 if mantissa == 0. => f32::INFINITY,
+        31 => f32::NAN,
+        _ => (1. + mantissa / 1024.) * ((exponent - 15) as f32).exp2(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_file() -> Vec<u8> {
+        let channels = vec![
+            Channel::new("R", vec![0., 0.5, 1., 2., -1., 1e6]),
+            Channel::new("G", vec![1.; 6]),
+            Channel::new("B", (0..6).map(|i| i as f32).collect()),
+            Channel::new("depth.Z", vec![f32::INFINITY; 6]),
+        ];
+        let mut data = vec![];
+        write(&mut data, 3, 2, channels, None).unwrap();
+        data
+    }
+
+    /// A file with the data window at the start of the header replaced by
+    /// `window`.
+    fn with_window(window: [i32; 4]) -> Vec<u8> {
+        let mut data = test_file();
+        let name = b"dataWindow\0box2i\0";
+        let at = data
+            .windows(name.len())
+            .position(|bytes| bytes == name)
+            .unwrap()
+            + name.len()
+            + 4;
+        for (i, v) in window.iter().enumerate() {
+            data[at + i * 4..at + i * 4 + 4].copy_from_slice(&v.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn read_gives_back_what_was_written() {
+        let (width, height, channels) = read(&mut test_file().as_slice()).unwrap();
+        assert_eq!((width, height), (3, 2));
+
+        // In alphabetical order, as they're stored
+        let names: Vec<_> = channels.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["B", "G", "R", "depth.Z"]);
+        assert_eq!(channels[0].values, [0., 1., 2., 3., 4., 5.]);
+        assert_eq!(channels[1].values, [1.; 6]);
+        assert_eq!(channels[2].values, [0., 0.5, 1., 2., -1., 1e6]);
+        assert_eq!(channels[3].values, [f32::INFINITY; 6]);
+    }
+
+    #[test]
+    fn truncated_file_is_an_error() {
+        let data = test_file();
+        for len in [0, 4, 8, 100, data.len() - 1] {
+            let err = read(&mut &data[..len]).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "read {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn not_an_exr_file_is_an_error() {
+        let err = read(&mut &b"PF\n3 2\n-1.0\n"[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn huge_data_window_is_an_error() {
+        for window in [
+            [i32::MIN, i32::MIN, i32::MAX, i32::MAX],
+            [0, 0, 1 << 20, 1 << 20],
+            [0, 0, 2, 1 << 24],
+        ] {
+            let err = read(&mut with_window(window).as_slice()).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn inverted_data_window_is_an_error() {
+        let err = read(&mut with_window([2, 0, 0, 1]).as_slice())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn half_floats_convert() {
+        assert_eq!(half_to_f32(0x0000), 0.);
+        assert_eq!(half_to_f32(0x3c00), 1.);
+        assert_eq!(half_to_f32(0xc000), -2.);
+        assert_eq!(half_to_f32(0x3800), 0.5);
+        assert_eq!(half_to_f32(0x7bff), 65504.);
+        assert_eq!(half_to_f32(0x0001), (-24f32).exp2());
+        assert_eq!(half_to_f32(0x7c00), f32::INFINITY);
+        assert!(half_to_f32(0x7e00).is_nan());
+    }
+}
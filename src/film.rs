@@ -0,0 +1,1379 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use crate::bloom::Bloom;
+use crate::color::{Color, ColorMatrix, ColorSpace, WhiteBalance};
+use crate::depth_fog::DepthFog;
+use crate::exr::{self, Channel};
+use crate::filter::Filter;
+use crate::glare::Glare;
+use crate::lut::Lut;
+use crate::texture::ColorRamp;
+use crate::tile::{Tile, TileOrder};
+use crate::vector::{Point3, Vec3};
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RTFILM03";
+
+/// Checkpoints from before alpha was accumulated, when every pixel was opaque.
+const CHECKPOINT_MAGIC_V2: &[u8; 8] = b"RTFILM02";
+
+/// Checkpoints from before pixels had their own filter weights, when every
+/// pixel's weight was the number of samples.
+const CHECKPOINT_MAGIC_V1: &[u8; 8] = b"RTFILM01";
+
+/// Accumulates radiance samples for every pixel of the image.
+///
+/// Rows are stored top to bottom, matching the order they are written out.
+/// Each sample is spread over the pixels around it by the reconstruction
+/// filter, and pixels keep the weighted sum of their samples along with the
+/// sum of the weights. Alpha is accumulated the same way, so colors are
+/// premultiplied by it.
+#[derive(Clone)]
+pub struct Film {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    alphas: Vec<f64>,
+    weights: Vec<f64>,
+    /// Whether alpha is written out, in formats that support it.
+    alpha: bool,
+    samples: u32,
+    /// Time spent rendering each pixel.
+    times: Vec<Duration>,
+    filter: Filter,
+    tile_order: TileOrder,
+    /// The only pixels rendered, if not all of them.
+    region: Option<Tile>,
+    aovs: Option<Aovs>,
+    light_groups: Option<LightGroups>,
+    /// Applied to the image as it's written out, leaving what's been
+    /// accumulated as it is.
+    color_transform: Option<ColorMatrix>,
+    /// Applied to the image as it's written out, after `color_transform`.
+    lut: Option<Arc<Lut>>,
+    /// The space colors are written out in, and the transform into it from
+    /// sRGB if it's another, applied last.
+    color_space: ColorSpace,
+    space_transform: Option<ColorMatrix>,
+    /// The noise left in the image, as last estimated while rendering.
+    noise: Option<f64>,
+}
+
+/// A radiance sample at a position on the film, measured in pixels from the
+/// top left corner.
+pub struct Sample {
+    pub x: f64,
+    pub y: f64,
+    pub color: Color,
+    /// Coverage: 1 where the camera ray hit something or the background is
+    /// shown, 0 where it is left transparent.
+    pub alpha: f64,
+    /// What the camera ray hit, if the film is collecting AOVs.
+    pub surface: Option<Surface>,
+    /// The part of `color` that came from each light group, if the film is
+    /// collecting them.
+    pub light_groups: Vec<Color>,
+}
+
+/// The samples taken within a pixel in one pass, and how long they took,
+/// along with the pixel's index.
+type PixelPass = (usize, Vec<Sample>, Duration);
+
+/// The first surface seen along a camera ray.
+#[derive(Clone, Copy)]
+pub struct Surface {
+    pub position: Point3,
+    pub normal: Vec3,
+    /// Distance from the camera.
+    pub depth: f64,
+    pub albedo: Color,
+    /// Tells objects apart within one image, but isn't stable between
+    /// renders.
+    pub id: u32,
+    /// How far across and up the image, in pixels, the surface moves from
+    /// shutter open to close.
+    pub motion: (f64, f64),
+}
+
+/// Arbitrary output variables: per-pixel averages of what camera rays hit,
+/// for compositing. Samples only count towards the pixel they were taken in.
+#[derive(Clone)]
+struct Aovs {
+    position: Vec<Point3>,
+    normal: Vec<Vec3>,
+    depth: Vec<f64>,
+    albedo: Vec<Color>,
+    id: Vec<u32>,
+    motion: Vec<(f64, f64)>,
+    hits: Vec<u32>,
+}
+
+/// The image split up by which group of lights the light came from, filtered
+/// like the image itself. The groups add up to the whole image.
+#[derive(Clone)]
+struct LightGroups {
+    names: Vec<String>,
+    /// Every pixel's groups in turn.
+    pixels: Vec<Color>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::with_filter(width, height, Filter::default())
+    }
+
+    pub fn with_filter(width: usize, height: usize, filter: Filter) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::zero(); width * height],
+            alphas: vec![0.; width * height],
+            weights: vec![0.; width * height],
+            alpha: false,
+            samples: 0,
+            times: vec![Duration::ZERO; width * height],
+            filter,
+            tile_order: TileOrder::default(),
+            region: None,
+            aovs: None,
+            light_groups: None,
+            color_transform: None,
+            lut: None,
+            color_space: ColorSpace::Srgb,
+            space_transform: None,
+            noise: None,
+        }
+    }
+
+    /// Also writes alpha, as an alpha channel in PNG and EXR files.
+    pub fn with_alpha(self) -> Self {
+        Self {
+            alpha: true,
+            ..self
+        }
+    }
+
+    /// Renders tiles in `order` rather than row by row.
+    pub fn with_tile_order(self, tile_order: TileOrder) -> Self {
+        Self { tile_order, ..self }
+    }
+
+    /// Renders only the pixels within `region`, leaving the rest without
+    /// samples. Merged into a checkpoint of the whole image, the region's
+    /// samples add to those already there, so a part of the image can be
+    /// refined without rendering the rest again.
+    pub fn with_region(self, region: Tile) -> Self {
+        Self {
+            region: Some(region),
+            ..self
+        }
+    }
+
+    /// Corrects the colors written out for light of the color given by
+    /// `white_balance`. Checkpoints are saved without the correction.
+    pub fn with_white_balance(self, white_balance: WhiteBalance) -> Self {
+        Self {
+            color_transform: Some(white_balance.transform()),
+            ..self
+        }
+    }
+
+    /// Grades the colors written out with `lut`, after any white balance.
+    /// Checkpoints and light groups are saved without it.
+    pub fn with_lut(self, lut: Arc<Lut>) -> Self {
+        Self {
+            lut: Some(lut),
+            ..self
+        }
+    }
+
+    /// Writes colors out in `color_space` rather than sRGB, tagging PNG
+    /// and EXR files with its primaries. Checkpoints stay in sRGB.
+    pub fn with_color_space(self, color_space: ColorSpace) -> Self {
+        let space_transform = match color_space {
+            ColorSpace::Srgb => None,
+            space => Some(space.from_srgb()),
+        };
+        Self {
+            color_space,
+            space_transform,
+            ..self
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.alpha
+    }
+
+    /// Also collects AOVs, which are written out as extra layers of EXR
+    /// files. They aren't saved in checkpoints.
+    pub fn with_aovs(self) -> Self {
+        let size = self.width * self.height;
+        let aovs = Aovs {
+            position: vec![Point3::zero(); size],
+            normal: vec![Vec3::zero(); size],
+            depth: vec![0.; size],
+            albedo: vec![Color::zero(); size],
+            id: vec![0; size],
+            motion: vec![(0., 0.); size],
+            hits: vec![0; size],
+        };
+
+        Self {
+            aovs: Some(aovs),
+            ..self
+        }
+    }
+
+    pub fn has_aovs(&self) -> bool {
+        self.aovs.is_some()
+    }
+
+    /// Also collects the image lit by each group of lights, written out as
+    /// extra layers of EXR files. Groups are named in the order of their
+    /// indices, and aren't saved in checkpoints.
+    pub fn with_light_groups(self, names: Vec<String>) -> Self {
+        let pixels = vec![Color::zero(); self.width * self.height * names.len()];
+        Self {
+            light_groups: Some(LightGroups { names, pixels }),
+            ..self
+        }
+    }
+
+    /// Number of light groups samples should be split into.
+    pub fn light_group_count(&self) -> usize {
+        self.light_groups
+            .as_ref()
+            .map_or(0, |groups| groups.names.len())
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Number of samples accumulated into every pixel.
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// The noise left in the image, as a fraction of pixel values, if it
+    /// was estimated while rendering; see `Budget::noise_threshold`.
+    pub fn noise(&self) -> Option<f64> {
+        self.noise
+    }
+
+    pub(crate) fn set_noise(&mut self, noise: f64) {
+        self.noise = Some(noise);
+    }
+
+    /// An estimate of the noise left in the image, from `half`, its pixel
+    /// averages when it had half as many samples. The samples since are
+    /// independent of those before, so the difference from then has the
+    /// same variance as the image itself. It's the root mean square of the
+    /// differences in luminance, relative to each pixel's, with pixels
+    /// near black counted as if they were a little brighter so the noise
+    /// in them, which is hard to see, doesn't swamp the rest.
+    pub fn noise_against(&self, half: &[Color]) -> f64 {
+        const DARK: f64 = 0.05;
+
+        let (sum, count) = self
+            .averages()
+            .iter()
+            .zip(half)
+            .zip(&self.weights)
+            .filter(|(_, &weight)| weight > 0.)
+            .fold((0., 0), |(sum, count), ((now, then), _)| {
+                let luminance = now.luminance();
+                let error = (luminance - then.luminance()) / (luminance.abs() + DARK);
+                (sum + error * error, count + 1)
+            });
+        match count {
+            0 => 0.,
+            count => (sum / count as f64).sqrt(),
+        }
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    /// Adds `samples` new samples to every pixel, rendering tiles in
+    /// parallel. Threads take the next tile in the film's tile order as they
+    /// become free.
+    ///
+    /// `start_tile(tile)` is called before each tile is rendered, and may
+    /// block to hold the render up. It returns state shared by the tile's
+    /// pixels, such as random numbers, which `f(&mut state, x, y)` is given
+    /// to return the `samples` radiance samples taken within the pixel.
+    /// Either can return `None` to abandon the pass, which leaves the film
+    /// untouched and returns false.
+    /// `tile_done(done, total)` is called as each tile is finished, with the
+    /// number of tiles finished so far in this pass.
+    pub fn accumulate<C, S, F, D>(
+        &mut self,
+        samples: u32,
+        start_tile: S,
+        f: F,
+        tile_done: D,
+    ) -> bool
+    where
+        S: Fn(Tile) -> Option<C> + Sync,
+        F: Fn(&mut C, usize, usize) -> Option<Vec<Sample>> + Sync,
+        D: Fn(usize, usize) + Sync,
+    {
+        let width = self.width;
+        let tiles: Vec<Tile> = self
+            .tile_order
+            .tiles(self.width, self.height)
+            .into_iter()
+            .filter_map(|tile| match self.region {
+                Some(region) => tile.intersection(region),
+                None => Some(tile),
+            })
+            .collect();
+        let next = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+
+        let pass: Option<Vec<Vec<PixelPass>>> = (0..rayon::current_num_threads())
+            .into_par_iter()
+            .map(|_| {
+                let mut rendered = vec![];
+                loop {
+                    let Some(&tile) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                        return Some(rendered);
+                    };
+                    let mut state = start_tile(tile)?;
+
+                    for (x, y) in tile.pixels() {
+                        let start = Instant::now();
+                        let samples = f(&mut state, x, y)?;
+                        rendered.push((y * width + x, samples, start.elapsed()));
+                    }
+                    tile_done(done.fetch_add(1, Ordering::Relaxed) + 1, tiles.len());
+                }
+            })
+            .collect();
+
+        let mut pass: Vec<PixelPass> = match pass {
+            Some(pass) => pass.into_iter().flatten().collect(),
+            None => return false,
+        };
+        // Samples overlapping through the filter are summed in the same order
+        // however the tiles were shared out, so the result doesn't depend on
+        // the thread count
+        pass.sort_unstable_by_key(|&(index, _, _)| index);
+
+        for (index, samples, time) in pass {
+            self.times[index] += time;
+            for sample in samples {
+                self.splat(sample);
+            }
+        }
+
+        self.samples += samples;
+        true
+    }
+
+    /// Adds a sample to every pixel within the filter's radius of it.
+    fn splat(&mut self, sample: Sample) {
+        if let (Some(aovs), Some(surface)) = (&mut self.aovs, sample.surface) {
+            let x = (sample.x as usize).min(self.width - 1);
+            let y = (sample.y as usize).min(self.height - 1);
+            let index = y * self.width + x;
+
+            aovs.position[index] += surface.position;
+            aovs.normal[index] += surface.normal;
+            aovs.depth[index] += surface.depth;
+            aovs.albedo[index] += surface.albedo;
+            aovs.id[index] = surface.id;
+            aovs.motion[index].0 += surface.motion.0;
+            aovs.motion[index].1 += surface.motion.1;
+            aovs.hits[index] += 1;
+        }
+
+        let radius = self.filter.radius();
+
+        // Pixel centers are at half-integer positions
+        let x = sample.x - 0.5;
+        let y = sample.y - 0.5;
+        let x0 = ((x - radius).ceil() as i64).max(0);
+        let y0 = ((y - radius).ceil() as i64).max(0);
+        let x1 = ((x + radius).floor() as i64).min(self.width as i64 - 1);
+        let y1 = ((y + radius).floor() as i64).min(self.height as i64 - 1);
+
+        for py in y0..=y1 {
+            for px in x0..=x1 {
+                let weight = self.filter.evaluate(px as f64 - x, py as f64 - y);
+                if weight != 0. {
+                    let index = py as usize * self.width + px as usize;
+                    self.pixels[index] += sample.color * weight;
+                    self.alphas[index] += sample.alpha * weight;
+                    self.weights[index] += weight;
+
+                    if let Some(groups) = &mut self.light_groups {
+                        let count = groups.names.len();
+                        let pixel = &mut groups.pixels[index * count..(index + 1) * count];
+                        for (total, &color) in pixel.iter_mut().zip(sample.light_groups.iter()) {
+                            *total += color * weight;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds in the samples of `previous`, an earlier frame of an animation,
+    /// wherever it saw the same surface, with `keep` of their weight. Saves
+    /// rendering so many new samples when little changes between frames;
+    /// as `previous` holds what it reused in turn, what each frame adds
+    /// fades out by `keep` a frame.
+    ///
+    /// `locate(p)` gives the pixel `p` was seen in in `previous`, if any.
+    /// Both films must have AOVs, which tell whether it's the same surface:
+    /// the same object, facing the same way, at the same place to within a
+    /// small fraction of its distance. Pixels that didn't hit anything reuse
+    /// nothing, and neither do the AOVs themselves.
+    pub fn reuse<L>(&mut self, previous: &Film, keep: f64, locate: L)
+    where
+        L: Fn(Point3) -> Option<(usize, usize)>,
+    {
+        // How far apart the surfaces seen can be, relative to their distance
+        const TOLERANCE: f64 = 0.01;
+        // The least cosine of the angle between their normals
+        const MIN_COS: f64 = 0.9;
+
+        let (Some(aovs), Some(old)) = (&self.aovs, &previous.aovs) else {
+            return;
+        };
+
+        let groups = self.light_group_count();
+        let reuse_groups = groups > 0 && previous.light_group_count() == groups;
+
+        for i in 0..self.width * self.height {
+            if aovs.hits[i] == 0 {
+                continue;
+            }
+            let hits = aovs.hits[i] as f64;
+            let position = aovs.position[i] / hits;
+            let Some((x, y)) =
+                locate(position).filter(|&(x, y)| x < previous.width && y < previous.height)
+            else {
+                continue;
+            };
+
+            let j = y * previous.width + x;
+            if old.hits[j] == 0 || old.id[j] != aovs.id[i] {
+                continue;
+            }
+            let old_hits = old.hits[j] as f64;
+            let depth = aovs.depth[i] / hits;
+            let moved = (old.position[j] / old_hits - position).length();
+            let turned = aovs.normal[i]
+                .unit_vector()
+                .dot_product(old.normal[j].unit_vector());
+            if !(moved <= TOLERANCE * depth && turned >= MIN_COS) {
+                continue;
+            }
+
+            self.pixels[i] += previous.pixels[j] * keep;
+            self.alphas[i] += previous.alphas[j] * keep;
+            self.weights[i] += previous.weights[j] * keep;
+            if let (true, Some(new), Some(old)) =
+                (reuse_groups, &mut self.light_groups, &previous.light_groups)
+            {
+                for g in 0..groups {
+                    new.pixels[i * groups + g] += old.pixels[j * groups + g] * keep;
+                }
+            }
+        }
+    }
+
+    /// The filtered average of the samples around pixel (x, y).
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.in_space(self.graded(y * self.width + x))
+    }
+
+    /// Pixel `index` white balanced and graded, still in sRGB.
+    fn graded(&self, index: usize) -> Color {
+        let weight = self.weights[index];
+        if weight <= 0. {
+            return Color::zero();
+        }
+
+        let c = self.balance(self.pixels[index] / weight);
+        match &self.lut {
+            // The LUT grades colors as they are where the pixel is covered
+            Some(lut) if self.alpha => match self.alphas[index] / weight {
+                alpha if alpha > 0. => lut.apply(c / alpha) * alpha,
+                _ => c,
+            },
+            Some(lut) => lut.apply(c),
+            None => c,
+        }
+    }
+
+    /// `c` with the white balance applied, which unlike the LUT is linear,
+    /// so it can also be applied to the parts of the image.
+    fn balance(&self, c: Color) -> Color {
+        match &self.color_transform {
+            Some(transform) => transform.apply(c),
+            None => c,
+        }
+    }
+
+    /// The sRGB color `c` in the space the film is written out in.
+    fn in_space(&self, c: Color) -> Color {
+        match &self.space_transform {
+            Some(transform) => transform.apply(c),
+            None => c,
+        }
+    }
+
+    /// The filtered average of the light from light group `group` around
+    /// pixel (x, y), white balanced like the image but not graded by the
+    /// LUT. Black if the film has no such group.
+    pub fn light_group(&self, group: usize, x: usize, y: usize) -> Color {
+        let index = y * self.width + x;
+        match (&self.light_groups, self.weights[index]) {
+            (Some(groups), weight) if weight > 0. && group < groups.names.len() => {
+                let color = groups.pixels[index * groups.names.len() + group] / weight;
+                self.in_space(self.balance(color))
+            }
+            _ => Color::zero(),
+        }
+    }
+
+    /// The filtered average alpha around pixel (x, y).
+    pub fn alpha(&self, x: usize, y: usize) -> f64 {
+        let index = y * self.width + x;
+        let weight = self.weights[index];
+        if weight <= 0. {
+            return 0.;
+        }
+
+        self.alphas[index] / weight
+    }
+
+    /// Statistics of the brightness of the image as it's written out, for
+    /// judging its exposure.
+    pub fn stats(&self) -> ImageStats {
+        let mut histogram = [0; HISTOGRAM_BINS];
+        let (mut total, mut log_total, mut max, mut clipped) = (0., 0., 0_f64, 0);
+
+        for i in 0..self.width * self.height {
+            let c = self.graded(i);
+            let luminance = c.luminance().max(0.);
+            total += luminance;
+            // Offset so black pixels don't send the log average to zero
+            log_total += (luminance + LOG_OFFSET).ln();
+            max = max.max(luminance);
+            if c.r() > 1. || c.g() > 1. || c.b() > 1. {
+                clipped += 1;
+            }
+
+            let stop = luminance.log2().floor() - HISTOGRAM_MIN_STOP as f64;
+            let bin = stop.clamp(0., (HISTOGRAM_BINS - 1) as f64) as usize;
+            histogram[bin] += 1;
+        }
+
+        let count = (self.width * self.height) as f64;
+        ImageStats {
+            average: total / count,
+            log_average: (log_total / count).exp() - LOG_OFFSET,
+            max,
+            clipped: clipped as f64 / count,
+            histogram,
+        }
+    }
+
+    /// A false-color image of how long each pixel took to render, from
+    /// black for the quickest through to white for the slowest.
+    ///
+    /// The brightest color is reached at the 99th percentile, so a few
+    /// extreme pixels don't leave the rest of the image dark.
+    pub fn heatmap(&self) -> Film {
+        let mut sorted: Vec<Duration> = self.times.clone();
+        sorted.sort_unstable();
+        // An empty film has no times to scale by
+        let top = sorted
+            .get(sorted.len().saturating_sub(1) * 99 / 100)
+            .map_or(0., Duration::as_secs_f64);
+
+        let ramp = ColorRamp::heat();
+        let pixels = self
+            .times
+            .iter()
+            .map(|time| match top {
+                top if top > 0. => ramp.at(time.as_secs_f64() / top),
+                _ => ramp.at(0.),
+            })
+            .collect();
+
+        Film::from_pixels(self.width, self.height, pixels)
+    }
+
+    /// A film holding an opaque image of `pixels`, in rows from the top, as
+    /// if each were one sample, for writing out.
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Color>) -> Film {
+        assert_eq!(pixels.len(), width * height, "pixel count mismatch");
+
+        let size = width * height;
+        Film {
+            pixels,
+            alphas: vec![1.; size],
+            weights: vec![1.; size],
+            samples: 1,
+            ..Film::new(width, height)
+        }
+    }
+
+    /// A copy of the film with `bloom` added to the image, for writing out.
+    /// The AOVs and light groups are left without it, so the groups no
+    /// longer add up to the image.
+    pub fn bloom(&self, bloom: &Bloom) -> Film {
+        self.with_light_added(bloom.glow(self.width, self.height, &self.averages()))
+    }
+
+    /// A copy of the film with `glare`'s starburst added to the image, for
+    /// writing out, leaving the AOVs and light groups as `bloom` does.
+    pub fn glare(&self, glare: &Glare) -> Film {
+        self.with_light_added(glare.starburst(self.width, self.height, &self.averages()))
+    }
+
+    /// A copy of the film with lateral chromatic aberration, for writing
+    /// out: the red channel magnified about the middle of the image by
+    /// `1 + amount` and the blue by `1 - amount`. Alpha, the AOVs and the
+    /// light groups are left as they are.
+    pub fn chromatic_aberration(&self, amount: f64) -> Film {
+        let averages = self.averages();
+        let (width, height) = (self.width as f64, self.height as f64);
+
+        // The average around (x, y), in pixels, interpolated bilinearly and
+        // clamped to the edges
+        let at = |x: f64, y: f64| {
+            let x = (x - 0.5).clamp(0., width - 1.);
+            let y = (y - 0.5).clamp(0., height - 1.);
+            let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+            let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+            let (fx, fy) = (x.fract(), y.fract());
+            let row =
+                |y| averages[y * self.width + x0] * (1. - fx) + averages[y * self.width + x1] * fx;
+            row(y0) * (1. - fy) + row(y1) * fy
+        };
+        let magnified = |x: f64, y: f64, scale: f64| {
+            at(
+                width / 2. + (x - width / 2.) / scale,
+                height / 2. + (y - height / 2.) / scale,
+            )
+        };
+
+        let mut film = self.clone();
+        for (i, average) in averages.iter().enumerate() {
+            // Pixel centers are at half-integer positions
+            let x = (i % self.width) as f64 + 0.5;
+            let y = (i / self.width) as f64 + 0.5;
+            let c = Color(
+                magnified(x, y, 1. + amount).0,
+                average.1,
+                magnified(x, y, 1. - amount).2,
+            );
+            if film.weights[i] > 0. {
+                film.pixels[i] = c * film.weights[i];
+            } else {
+                film.pixels[i] = c;
+                film.weights[i] = 1.;
+            }
+        }
+        film
+    }
+
+    /// Every pixel's average color, before any grading.
+    pub(crate) fn averages(&self) -> Vec<Color> {
+        (0..self.width * self.height)
+            .map(|i| match self.weights[i] {
+                weight if weight > 0. => self.pixels[i] / weight,
+                _ => Color::zero(),
+            })
+            .collect()
+    }
+
+    /// A copy of the film with `light` added to each pixel's average.
+    fn with_light_added(&self, light: Vec<Color>) -> Film {
+        let mut film = self.clone();
+        for (i, light) in light.into_iter().enumerate() {
+            if film.weights[i] > 0. {
+                film.pixels[i] += light * film.weights[i];
+            } else {
+                film.pixels[i] = light;
+                film.weights[i] = 1.;
+            }
+        }
+        film
+    }
+
+    /// A copy of the film with `fog` laid over the image by the depth AOV,
+    /// for writing out. Each pixel is fogged in proportion to the share of
+    /// its samples that hit a surface, so the background and the edges
+    /// against it stay clear; the light groups are left without it.
+    ///
+    /// The film is returned as it is if it has no AOVs.
+    pub fn depth_fog(&self, fog: &DepthFog) -> Film {
+        let mut film = self.clone();
+        let Some(aovs) = &self.aovs else {
+            return film;
+        };
+
+        for i in 0..self.width * self.height {
+            let weight = self.weights[i];
+            if aovs.hits[i] == 0 || weight <= 0. {
+                continue;
+            }
+
+            let hits = aovs.hits[i] as f64;
+            let coverage = (hits / self.samples.max(1) as f64).min(1.);
+            let amount = coverage * (1. - fog.transmittance(aovs.depth[i] / hits));
+            let c = self.pixels[i] / weight;
+            film.pixels[i] = (c * (1. - amount) + fog.color * amount) * weight;
+        }
+        film
+    }
+
+    /// Adds the samples accumulated in `other`, a film of the same size
+    /// rendered separately. Pixels are weighted by their samples, as if both
+    /// films' samples had been taken in one render.
+    pub fn merge(&mut self, other: &Film) -> io::Result<()> {
+        if (other.width, other.height) != (self.width, self.height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "cannot merge a {}x{} film into a {}x{} one",
+                    other.width, other.height, self.width, self.height
+                ),
+            ));
+        }
+
+        for i in 0..self.pixels.len() {
+            self.pixels[i] += other.pixels[i];
+            self.alphas[i] += other.alphas[i];
+            self.weights[i] += other.weights[i];
+            self.times[i] += other.times[i];
+        }
+        self.samples += other.samples;
+        // The estimate was for fewer samples than there are now
+        self.noise = None;
+
+        Ok(())
+    }
+
+    /// Pixel (x, y) with its color divided back out of the alpha, as most
+    /// image formats and libraries expect. Without alpha, pixels are opaque.
+    fn straight(&self, x: usize, y: usize) -> (Color, f64) {
+        let c = self.pixel(x, y);
+        if !self.alpha {
+            return (c, 1.);
+        }
+
+        let alpha = self.alpha(x, y);
+        if alpha > 0. {
+            (c / alpha, alpha)
+        } else {
+            (c, alpha)
+        }
+    }
+
+    /// The image as linear RGBA floats in rows from the top, with the color
+    /// premultiplied by alpha. Without alpha, pixels are opaque.
+    pub fn to_rgba_f32(&self) -> Vec<f32> {
+        let mut data = Vec::with_capacity(self.width * self.height * 4);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let c = self.pixel(x, y);
+                let alpha = if self.alpha { self.alpha(x, y) } else { 1. };
+                data.extend([c.r(), c.g(), c.b(), alpha].map(|v| v as f32));
+            }
+        }
+        data
+    }
+
+    /// The image as 8-bit sRGB RGBA in rows from the top, with straight
+    /// alpha. Without alpha, pixels are opaque.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let to_u8 = |value: f64| (value.clamp(0., 1.) * 255.).round() as u8;
+
+        let mut data = Vec::with_capacity(self.width * self.height * 4);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (c, alpha) = self.straight(x, y);
+                let c = c.to_srgb();
+                data.extend([c.r(), c.g(), c.b(), alpha].map(to_u8));
+            }
+        }
+        data
+    }
+
+    /// Writes the raw accumulated sums so the render can be resumed or merged.
+    pub fn write_checkpoint<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(CHECKPOINT_MAGIC)?;
+        out.write_all(&(self.width as u32).to_le_bytes())?;
+        out.write_all(&(self.height as u32).to_le_bytes())?;
+        out.write_all(&self.samples.to_le_bytes())?;
+
+        for i in 0..self.pixels.len() {
+            let pixel = self.pixels[i];
+            out.write_all(&pixel.r().to_le_bytes())?;
+            out.write_all(&pixel.g().to_le_bytes())?;
+            out.write_all(&pixel.b().to_le_bytes())?;
+            out.write_all(&self.alphas[i].to_le_bytes())?;
+            out.write_all(&self.weights[i].to_le_bytes())?;
+        }
+
+        out.flush()
+    }
+
+    pub fn read_checkpoint<R: Read>(input: &mut R) -> io::Result<Self> {
+        let mut data = vec![];
+        input.read_to_end(&mut data)?;
+        let input = &mut data.as_slice();
+
+        let mut magic = [0; CHECKPOINT_MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        let (has_alphas, has_weights) = match &magic {
+            CHECKPOINT_MAGIC => (true, true),
+            CHECKPOINT_MAGIC_V2 => (false, true),
+            CHECKPOINT_MAGIC_V1 => (false, false),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a raytracing checkpoint",
+                ))
+            }
+        };
+
+        let width = read_u32(input)? as usize;
+        let height = read_u32(input)? as usize;
+        let samples = read_u32(input)?;
+
+        // Checked against what's left of the file before anything is
+        // allocated, so a corrupt header can't ask for more memory than the
+        // file could fill
+        let values = 3 + has_alphas as usize + has_weights as usize;
+        let count = width
+            .checked_mul(height)
+            .filter(|count| {
+                count
+                    .checked_mul(values * 8)
+                    .is_some_and(|size| size <= input.len())
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("checkpoint ends before its {}x{} pixels do", width, height),
+                )
+            })?;
+
+        let mut pixels = Vec::with_capacity(count);
+        let mut alphas = Vec::with_capacity(count);
+        let mut weights = Vec::with_capacity(count);
+        for _ in 0..count {
+            let r = read_f64(input)?;
+            let g = read_f64(input)?;
+            let b = read_f64(input)?;
+            pixels.push(Color::new(r, g, b));
+
+            let alpha = if has_alphas {
+                Some(read_f64(input)?)
+            } else {
+                None
+            };
+            let weight = if has_weights {
+                read_f64(input)?
+            } else {
+                samples as f64
+            };
+            alphas.push(alpha.unwrap_or(weight));
+            weights.push(weight);
+        }
+
+        // The filter isn't stored; it only affects samples yet to be added
+        Ok(Self {
+            width,
+            height,
+            pixels,
+            alphas,
+            weights,
+            alpha: false,
+            samples,
+            times: vec![Duration::ZERO; count],
+            filter: Filter::default(),
+            tile_order: TileOrder::default(),
+            region: None,
+            aovs: None,
+            light_groups: None,
+            color_transform: None,
+            lut: None,
+            color_space: ColorSpace::Srgb,
+            space_transform: None,
+            noise: None,
+        })
+    }
+
+    pub fn write<W: Write>(&self, format: ImageFormat, out: &mut W) -> io::Result<()> {
+        match format {
+            ImageFormat::Ppm => self.write_ppm(out),
+            ImageFormat::Png => self.write_png(out),
+            ImageFormat::Pfm => self.write_pfm(out),
+            ImageFormat::Exr => self.write_exr(out),
+        }
+    }
+
+    /// Writes an 8-bit plain text PPM, using the sRGB transfer function
+    /// like the PNG, as `Image::load_ppm` expects.
+    pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "P3\n{} {}\n255", self.width, self.height)?;
+
+        let to_u8 = |value: f64| (value.clamp(0., 1.) * 255.).round() as u8;
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let c = self.pixel(x, y).to_srgb();
+                let [r, g, b] = [c.r(), c.g(), c.b()].map(to_u8);
+                write!(out, "{} {} {} ", r, g, b)?;
+            }
+
+            writeln!(out)?;
+        }
+
+        out.flush()
+    }
+
+    /// Writes a 16-bit PNG, with an alpha channel if the film has one. Uses
+    /// the sRGB transfer function, tagged as sRGB or as a gamma of 2.2 with
+    /// the film's primaries.
+    pub fn write_png<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(&mut *out, self.width as u32, self.height as u32);
+        encoder.set_color(if self.alpha {
+            png::ColorType::Rgba
+        } else {
+            png::ColorType::Rgb
+        });
+        encoder.set_depth(png::BitDepth::Sixteen);
+        match self.color_space {
+            ColorSpace::Srgb => encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual),
+            space => {
+                let [red, green, blue, white] =
+                    space.chromaticities().map(|(x, y)| (x as f32, y as f32));
+                encoder.set_source_gamma(png::ScaledFloat::new(1. / 2.2));
+                encoder.set_source_chromaticities(png::SourceChromaticities::new(
+                    white, red, green, blue,
+                ));
+            }
+        }
+
+        let to_u16 = |value: f64| (value.clamp(0., 1.) * 65535.).round() as u16;
+
+        let mut data = Vec::with_capacity(self.width * self.height * 8);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (c, alpha) = self.straight(x, y);
+                let c = c.to_srgb();
+                for channel in [c.r(), c.g(), c.b()] {
+                    data.extend_from_slice(&to_u16(channel).to_be_bytes());
+                }
+                if self.alpha {
+                    data.extend_from_slice(&to_u16(alpha).to_be_bytes());
+                }
+            }
+        }
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+
+        out.flush()
+    }
+
+    /// Writes a portable float map: linear, unclamped 32-bit floats.
+    pub fn write_pfm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        // A negative scale marks the data as little-endian
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+
+        // Rows run from the bottom of the image to the top
+        for y in (0..self.height).rev() {
+            for x in 0..self.width {
+                let c = self.pixel(x, y);
+                for channel in [c.r(), c.g(), c.b()] {
+                    out.write_all(&(channel as f32).to_le_bytes())?;
+                }
+            }
+        }
+
+        out.flush()
+    }
+
+    /// Writes a 32-bit float OpenEXR file, with premultiplied alpha if the
+    /// film has it and any AOVs and light groups as named layers alongside
+    /// the image. Light groups are in layers named `light.<group>`.
+    pub fn write_exr<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let size = self.width * self.height;
+        let pixels: Vec<Color> = (0..size)
+            .map(|i| self.pixel(i % self.width, i / self.width))
+            .collect();
+
+        let channel = |name: &str, values: &[Color], f: fn(Color) -> f64| {
+            Channel::new(name, values.iter().map(|&c| f(c) as f32).collect())
+        };
+
+        let mut channels = vec![
+            channel("R", &pixels, Color::r),
+            channel("G", &pixels, Color::g),
+            channel("B", &pixels, Color::b),
+        ];
+
+        if self.alpha {
+            let alpha = (0..size)
+                .map(|i| self.alpha(i % self.width, i / self.width) as f32)
+                .collect();
+            channels.push(Channel::new("A", alpha));
+        }
+
+        if let Some(aovs) = &self.aovs {
+            // Average over the samples that hit something
+            let average = |i: usize, sum: f64| match aovs.hits[i] {
+                0 => 0.,
+                hits => sum / hits as f64,
+            };
+            let vector_channel = |name: &str, vectors: &[Vec3], axis: usize| {
+                let values = (0..size)
+                    .map(|i| average(i, vectors[i].axis(axis)) as f32)
+                    .collect();
+                Channel::new(name, values)
+            };
+            let albedo: Vec<Color> = (0..size)
+                .map(|i| match aovs.hits[i] {
+                    0 => Color::zero(),
+                    hits => self.in_space(aovs.albedo[i] / hits as f64),
+                })
+                .collect();
+
+            channels.push(vector_channel("position.X", &aovs.position, 0));
+            channels.push(vector_channel("position.Y", &aovs.position, 1));
+            channels.push(vector_channel("position.Z", &aovs.position, 2));
+            channels.push(vector_channel("normal.X", &aovs.normal, 0));
+            channels.push(vector_channel("normal.Y", &aovs.normal, 1));
+            channels.push(vector_channel("normal.Z", &aovs.normal, 2));
+            channels.push(Channel::new(
+                "depth.Z",
+                (0..size)
+                    .map(|i| match aovs.hits[i] {
+                        0 => f32::INFINITY,
+                        _ => average(i, aovs.depth[i]) as f32,
+                    })
+                    .collect(),
+            ));
+            channels.push(channel("albedo.R", &albedo, Color::r));
+            channels.push(channel("albedo.G", &albedo, Color::g));
+            channels.push(channel("albedo.B", &albedo, Color::b));
+            channels.push(Channel::new(
+                "id.ID",
+                aovs.id.iter().map(|&id| id as f32).collect(),
+            ));
+            channels.push(Channel::new(
+                "motion.X",
+                (0..size)
+                    .map(|i| average(i, aovs.motion[i].0) as f32)
+                    .collect(),
+            ));
+            channels.push(Channel::new(
+                "motion.Y",
+                (0..size)
+                    .map(|i| average(i, aovs.motion[i].1) as f32)
+                    .collect(),
+            ));
+        }
+
+        if let Some(groups) = &self.light_groups {
+            for (g, name) in groups.names.iter().enumerate() {
+                let group: Vec<Color> = (0..size)
+                    .map(|i| self.light_group(g, i % self.width, i / self.width))
+                    .collect();
+
+                channels.push(channel(&format!("light.{}.R", name), &group, Color::r));
+                channels.push(channel(&format!("light.{}.G", name), &group, Color::g));
+                channels.push(channel(&format!("light.{}.B", name), &group, Color::b));
+            }
+        }
+
+        // Readers take images without chromaticities to be sRGB
+        let chromaticities = match self.color_space {
+            ColorSpace::Srgb => None,
+            space => Some(space.chromaticities()),
+        };
+        exr::write(out, self.width, self.height, channels, chromaticities)
+    }
+}
+
+/// Stops below display white covered by the histogram; darker pixels are
+/// counted in its first bin.
+const HISTOGRAM_MIN_STOP: i32 = -12;
+
+/// One-stop bins up to and including the four stops above display white,
+/// the last also counting anything brighter.
+const HISTOGRAM_BINS: usize = 16;
+
+/// Added to luminances before averaging their logarithms.
+const LOG_OFFSET: f64 = 1e-4;
+
+/// How bright an image is, measured by the luminance of its pixels, where 1
+/// is display white. Pixels are in sRGB, after white balance and grading.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageStats {
+    pub average: f64,
+    /// The geometric mean, which matches how bright the image looks overall
+    /// better than the average does.
+    pub log_average: f64,
+    pub max: f64,
+    /// The fraction of pixels with a channel too bright to show.
+    pub clipped: f64,
+    /// The number of pixels in each stop of luminance, starting at
+    /// `HISTOGRAM_MIN_STOP` stops below display white.
+    pub histogram: [u64; HISTOGRAM_BINS],
+}
+
+impl ImageStats {
+    /// The stops of the first histogram bin and the one after the last,
+    /// relative to display white.
+    pub fn histogram_stops(&self) -> (i32, i32) {
+        (
+            HISTOGRAM_MIN_STOP,
+            HISTOGRAM_MIN_STOP + HISTOGRAM_BINS as i32,
+        )
+    }
+
+    /// How many stops to change the exposure by to bring the image's log
+    /// average to middle gray, 18% of display white.
+    pub fn exposure_to_middle_gray(&self) -> f64 {
+        match self.log_average {
+            average if average > 0. => (0.18 / average).log2(),
+            _ => 0.,
+        }
+    }
+}
+
+impl fmt::Display for ImageStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const BAR: usize = 40;
+
+        let exposure = format!("{:+.2} stops", self.exposure_to_middle_gray());
+        let rows = [
+            ("average luminance", format!("{:.4}", self.average)),
+            ("log average luminance", format!("{:.4}", self.log_average)),
+            ("max luminance", format!("{:.4}", self.max)),
+            ("clipped pixels", format!("{:.2}%", 100. * self.clipped)),
+            ("exposure to middle gray", exposure),
+        ];
+        for (name, value) in rows {
+            writeln!(f, "  {:<24} {}", name, value)?;
+        }
+        write!(f, "  pixels by stops from white:")?;
+
+        let most = self.histogram.iter().copied().max().unwrap_or(0).max(1);
+        for (i, &count) in self.histogram.iter().enumerate() {
+            let stop = HISTOGRAM_MIN_STOP + i as i32;
+            let bar = (count as usize * BAR).div_ceil(most as usize);
+            write!(f, "\n  {:>+4}  {:<BAR$}  {}", stop, "#".repeat(bar), count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The file formats a film can be written in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// 8-bit plain text PPM.
+    #[default]
+    Ppm,
+    /// 16-bit PNG.
+    Png,
+    /// 32-bit floating point PFM.
+    Pfm,
+    /// 32-bit floating point OpenEXR, including any AOVs.
+    Exr,
+}
+
+impl ImageFormat {
+    /// Picks the format matching the extension of `path`, if there is one.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        extension.parse().ok()
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ppm" => Ok(ImageFormat::Ppm),
+            "png" => Ok(ImageFormat::Png),
+            "pfm" => Ok(ImageFormat::Pfm),
+            "exr" => Ok(ImageFormat::Exr),
+            _ => Err(format!("unknown image format {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ImageFormat::Ppm => "ppm",
+            ImageFormat::Png => "png",
+            ImageFormat::Pfm => "pfm",
+            ImageFormat::Exr => "exr",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_f64<R: Read>(input: &mut R) -> io::Result<f64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(f64::from_le_bytes(buf))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A film with different sums, alphas and weights in every pixel.
+    fn test_film() -> Film {
+        let mut film = Film::from_pixels(
+            3,
+            2,
+            (0..6)
+                .map(|i| Color::new(i as f64, 0.5 * i as f64, 1. / (i + 1) as f64))
+                .collect(),
+        );
+        for i in 0..6 {
+            film.alphas[i] = 0.25 * i as f64;
+            film.weights[i] = 1. + i as f64;
+        }
+        film.samples = 7;
+        film
+    }
+
+    fn checkpoint(film: &Film) -> Vec<u8> {
+        let mut data = vec![];
+        film.write_checkpoint(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn checkpoint_round_trips() {
+        let film = test_film();
+        let data = checkpoint(&film);
+        let read = Film::read_checkpoint(&mut data.as_slice()).unwrap();
+
+        assert_eq!((read.width(), read.height()), (3, 2));
+        assert_eq!(read.samples(), 7);
+        assert_eq!(read.pixels, film.pixels);
+        assert_eq!(read.alphas, film.alphas);
+        assert_eq!(read.weights, film.weights);
+        assert_eq!(checkpoint(&read), data);
+    }
+
+    #[test]
+    fn older_checkpoints_are_read() {
+        let film = test_film();
+
+        // Version 2 had no alphas, version 1 no weights either
+        let mut v2 = CHECKPOINT_MAGIC_V2.to_vec();
+        let mut v1 = CHECKPOINT_MAGIC_V1.to_vec();
+        for data in [&mut v2, &mut v1] {
+            data.extend(3u32.to_le_bytes());
+            data.extend(2u32.to_le_bytes());
+            data.extend(7u32.to_le_bytes());
+        }
+        for i in 0..6 {
+            let c = film.pixels[i];
+            for data in [&mut v2, &mut v1] {
+                for value in [c.r(), c.g(), c.b()] {
+                    data.extend(value.to_le_bytes());
+                }
+            }
+            v2.extend(film.weights[i].to_le_bytes());
+        }
+
+        let read = Film::read_checkpoint(&mut v2.as_slice()).unwrap();
+        assert_eq!(read.weights, film.weights);
+        assert_eq!(read.alphas, film.weights);
+
+        let read = Film::read_checkpoint(&mut v1.as_slice()).unwrap();
+        assert_eq!(read.pixels, film.pixels);
+        assert_eq!(read.weights, vec![7.; 6]);
+    }
+
+    #[test]
+    fn truncated_checkpoint_is_an_error() {
+        let data = checkpoint(&test_film());
+        for len in [0, 4, 20, data.len() - 1] {
+            let result = Film::read_checkpoint(&mut &data[..len]);
+            assert!(result.is_err(), "read {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn huge_checkpoint_header_is_an_error() {
+        // Sizes that would overflow or exhaust memory if allocated
+        for (width, height) in [(u32::MAX, u32::MAX), (1 << 20, 1 << 20), (u32::MAX, 1)] {
+            let mut data = CHECKPOINT_MAGIC.to_vec();
+            data.extend(width.to_le_bytes());
+            data.extend(height.to_le_bytes());
+            data.extend(1u32.to_le_bytes());
+            data.extend([0; 40]);
+
+            let err = Film::read_checkpoint(&mut data.as_slice()).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn not_a_checkpoint_is_an_error() {
+        let err = Film::read_checkpoint(&mut &b"P3\n3 2\n255\n"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn ppm_is_srgb_encoded() {
+        let gray = Color::new(0.216, 0.216, 0.216);
+        let film = Film::from_pixels(2, 1, vec![gray, Color::new(1., 1., 1.)]);
+        let mut data = vec![];
+        film.write_ppm(&mut data).unwrap();
+
+        // Linear 0.216 is sRGB 128, where a gamma of 2 would give 118
+        let text = String::from_utf8(data).unwrap();
+        let values: Vec<&str> = text.split_whitespace().collect();
+        assert_eq!(
+            values,
+            ["P3", "2", "1", "255", "128", "128", "128", "255", "255", "255"]
+        );
+    }
+}
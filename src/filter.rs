@@ -0,0 +1,121 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// How much a sample contributes to the pixels around it, by its offset from
+/// each pixel's center in pixels.
+///
+/// Filters are separable: the weight is the product of a 1D filter applied to
+/// the x and y offsets.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    /// Equal weight within the radius. With a radius of half a pixel, each
+    /// sample only counts towards the pixel it was taken in.
+    Box { radius: f64 },
+    /// Weight falling off linearly to zero at the radius.
+    Tent { radius: f64 },
+    /// A Gaussian with falloff `alpha`, shifted down to reach zero at the
+    /// radius.
+    Gaussian { radius: f64, alpha: f64 },
+    /// The Mitchell–Netravali cubic, which is sharper than a Gaussian at the
+    /// cost of some ringing from its negative lobes.
+    Mitchell { radius: f64, b: f64, c: f64 },
+}
+
+impl Filter {
+    pub fn radius(self) -> f64 {
+        match self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. } => radius,
+        }
+    }
+
+    /// The same filter stretched or shrunk to `radius`.
+    pub fn with_radius(self, radius: f64) -> Self {
+        match self {
+            Filter::Box { .. } => Filter::Box { radius },
+            Filter::Tent { .. } => Filter::Tent { radius },
+            Filter::Gaussian { alpha, .. } => Filter::Gaussian { radius, alpha },
+            Filter::Mitchell { b, c, .. } => Filter::Mitchell { radius, b, c },
+        }
+    }
+
+    pub fn evaluate(self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    fn evaluate_1d(self, d: f64) -> f64 {
+        let d = d.abs();
+        let radius = self.radius();
+        if d > radius {
+            return 0.;
+        }
+
+        match self {
+            Filter::Box { .. } => 1.,
+            Filter::Tent { .. } => 1. - d / radius,
+            Filter::Gaussian { alpha, .. } => {
+                ((-alpha * d * d).exp() - (-alpha * radius * radius).exp()).max(0.)
+            }
+            Filter::Mitchell { b, c, .. } => mitchell(2. * d / radius, b, c),
+        }
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+/// The Mitchell–Netravali filter, which is nonzero for `|x| < 2`.
+fn mitchell(x: f64, b: f64, c: f64) -> f64 {
+    let x = x.abs();
+    let x2 = x * x;
+    let x3 = x2 * x;
+
+    let value = if x < 1. {
+        (12. - 9. * b - 6. * c) * x3 + (-18. + 12. * b + 6. * c) * x2 + (6. - 2. * b)
+    } else if x < 2. {
+        (-b - 6. * c) * x3 + (6. * b + 30. * c) * x2 + (-12. * b - 48. * c) * x + (8. * b + 24. * c)
+    } else {
+        0.
+    };
+
+    value / 6.
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    /// Parses a filter name, giving each its usual radius.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(Filter::Box { radius: 0.5 }),
+            "tent" => Ok(Filter::Tent { radius: 1. }),
+            "gaussian" => Ok(Filter::Gaussian {
+                radius: 1.5,
+                alpha: 2.,
+            }),
+            "mitchell" => Ok(Filter::Mitchell {
+                radius: 2.,
+                b: 1. / 3.,
+                c: 1. / 3.,
+            }),
+            _ => Err(format!("unknown filter {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Filter::Box { .. } => "box",
+            Filter::Tent { .. } => "tent",
+            Filter::Gaussian { .. } => "gaussian",
+            Filter::Mitchell { .. } => "mitchell",
+        };
+        write!(f, "{}", name)
+    }
+}
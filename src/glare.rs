@@ -0,0 +1,186 @@
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use rayon::prelude::*;
+
+use crate::color::Color;
+
+/// The starburst of streaks a camera's aperture spreads around very bright
+/// points, such as lights and their highlights. It's worked out from the
+/// whole image after rendering.
+///
+/// Light diffracts off each straight edge of the aperture's blades into a
+/// pair of streaks at right angles to it, so an aperture with an even
+/// number of blades shows as many streaks as it has blades, and one with an
+/// odd number twice as many. Longer wavelengths spread further, so the
+/// streaks fade through red at their tips.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glare {
+    /// Luminance above which pixels glare; 1 is the brightest the display
+    /// shows.
+    pub threshold: f64,
+    /// How much of the light above the threshold goes into the streaks.
+    pub intensity: f64,
+    /// The number of blades in the aperture.
+    pub blades: u32,
+    /// How far the streaks reach, as a fraction of the image's height.
+    pub length: f64,
+    /// How far the aperture is turned, in degrees anticlockwise.
+    pub rotation: f64,
+}
+
+impl Default for Glare {
+    fn default() -> Self {
+        Self {
+            threshold: 1.,
+            intensity: 0.05,
+            blades: 6,
+            length: 0.15,
+            rotation: 0.,
+        }
+    }
+}
+
+/// The length of each channel's streaks relative to green's, in proportion
+/// to the wavelengths of the sRGB primaries.
+const SPREAD: [f64; 3] = [1.12, 1., 0.85];
+
+impl Glare {
+    /// The number of streaks around each bright point.
+    pub fn streaks(&self) -> u32 {
+        if self.blades.is_multiple_of(2) {
+            self.blades
+        } else {
+            2 * self.blades
+        }
+    }
+
+    /// The streaks to add to an image of `width` by `height` `pixels`, in
+    /// rows. Each pixel above the threshold spreads the light above it
+    /// along every streak, fading out along its length; light spreading
+    /// past the image's edges is lost.
+    pub fn starburst(&self, width: usize, height: usize, pixels: &[Color]) -> Vec<Color> {
+        let bright: Vec<(usize, usize, Color)> = pixels
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &c)| {
+                let luminance = c.luminance();
+                (luminance > self.threshold).then(|| {
+                    let excess = c * ((luminance - self.threshold) / luminance);
+                    (i % width, i / width, excess)
+                })
+            })
+            .collect();
+
+        let out = vec![Color::zero(); width * height];
+        let streaks = self.streaks();
+        if bright.is_empty() || streaks == 0 || self.intensity <= 0. {
+            return out;
+        }
+
+        let reach = self.length * height as f64;
+        let steps = (reach * SPREAD[0]).ceil().max(1.) as usize;
+
+        // Each channel's share of a streak at each step out from the point,
+        // fading to nothing at the channel's reach
+        let falloff = |channel: usize| -> Vec<f64> {
+            let length = reach * SPREAD[channel];
+            let weights: Vec<f64> = (1..=steps)
+                .map(|d| (1. - d as f64 / length).max(0.).powi(3))
+                .collect();
+            let total: f64 = weights.iter().sum();
+            let share = self.intensity / streaks as f64;
+            weights
+                .iter()
+                .map(|w| if total > 0. { w / total * share } else { 0. })
+                .collect()
+        };
+        let (red, green, blue) = (falloff(0), falloff(1), falloff(2));
+        let falloffs: Vec<Color> = (0..steps)
+            .map(|step| Color(red[step], green[step], blue[step]))
+            .collect();
+
+        // Streaks are drawn in parallel, into an image for each thread
+        (0..streaks)
+            .into_par_iter()
+            .fold(
+                || vec![Color::zero(); width * height],
+                |mut image, k| {
+                    let angle = self.rotation.to_radians() + 2. * PI * k as f64 / streaks as f64;
+                    let (dy, dx) = angle.sin_cos();
+                    for &(x, y, excess) in &bright {
+                        for (step, &falloff) in falloffs.iter().enumerate() {
+                            let d = (step + 1) as f64;
+                            let c = excess * falloff;
+                            // Up the image is down the rows
+                            let (sx, sy) = (x as f64 + dx * d, y as f64 - dy * d);
+                            splat(&mut image, width, height, sx, sy, c);
+                        }
+                    }
+                    image
+                },
+            )
+            .reduce_with(|mut a, b| {
+                for (a, b) in a.iter_mut().zip(b) {
+                    *a += b;
+                }
+                a
+            })
+            .unwrap_or(out)
+    }
+}
+
+/// Adds `c` at (x, y), shared bilinearly between the four pixels around it.
+fn splat(image: &mut [Color], width: usize, height: usize, x: f64, y: f64, c: Color) {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    for (px, py, weight) in [
+        (x0, y0, (1. - fx) * (1. - fy)),
+        (x0 + 1., y0, fx * (1. - fy)),
+        (x0, y0 + 1., (1. - fx) * fy),
+        (x0 + 1., y0 + 1., fx * fy),
+    ] {
+        if px >= 0. && py >= 0. && (px as usize) < width && (py as usize) < height {
+            image[py as usize * width + px as usize] += c * weight;
+        }
+    }
+}
+
+impl FromStr for Glare {
+    type Err = String;
+
+    /// Parses `THRESHOLD[,INTENSITY[,BLADES[,LENGTH[,ROTATION]]]]`, e.g. `1`
+    /// or `2,0.1,5`, leaving anything not given at its default.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid glare {:?}", s);
+        let values = s
+            .split(',')
+            .map(|v| v.trim().parse::<f64>().map_err(|_| invalid()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if values.is_empty() || values.len() > 5 || !values.iter().all(|v| v.is_finite()) {
+            return Err(invalid());
+        }
+
+        let default = Glare::default();
+        let at = |i: usize, default: f64| values.get(i).copied().unwrap_or(default);
+        let blades = at(2, default.blades as f64);
+        let glare = Glare {
+            threshold: at(0, default.threshold),
+            intensity: at(1, default.intensity),
+            blades: blades as u32,
+            length: at(3, default.length),
+            rotation: at(4, default.rotation),
+        };
+
+        let valid = glare.threshold >= 0.
+            && glare.intensity >= 0.
+            && blades.fract() == 0.
+            && (3. ..=32.).contains(&blades)
+            && glare.length > 0.;
+        if valid {
+            Ok(glare)
+        } else {
+            Err(invalid())
+        }
+    }
+}
@@ -0,0 +1,422 @@
+use crate::accel::{
+    partition_bounded, with_unbounded, AccelNode, AccelStats, Accelerator, Bounded,
+};
+use crate::bounds::AABB;
+use crate::export::Tessellation;
+use crate::ray::{Hit, HitRecord, Ray};
+use crate::scene::SceneStats;
+use crate::vector::Vec3;
+
+/// Cells per object the grid aims for.
+const DENSITY: f64 = 3.;
+
+/// Most cells along any axis.
+const MAX_RESOLUTION: usize = 128;
+
+/// Objects with bounds this many times larger across than the median object
+/// are tested alongside the grid rather than placed in it.
+const OVERSIZED: f64 = 16.;
+
+/// Objects tested most recently along a ray, so objects spanning several
+/// cells aren't tested again in each of them.
+const MAILBOX_SIZE: usize = 8;
+
+/// A uniform grid: space is divided into equal cells, each referring to the
+/// objects overlapping it, and rays step from cell to cell in order (a 3D
+/// DDA), stopping at the first cell containing a hit.
+///
+/// Building it is quick and traversal does little work per cell, so it beats
+/// the tree structures on scenes with many similarly sized objects spread
+/// evenly through space, such as particles or the random sphere field. A few
+/// huge objects, such as a ground made of a giant sphere, would make the
+/// cells huge too, so they're tested alongside the grid instead.
+pub struct Grid {
+    objects: Vec<Box<dyn Hit>>,
+    /// Cells along each axis.
+    resolution: [usize; 3],
+    cell_size: Vec3,
+    /// The objects in cell `i` are `indices[offsets[i]..offsets[i + 1]]`,
+    /// with cells numbered along x, then y, then z.
+    offsets: Vec<usize>,
+    indices: Vec<usize>,
+    bounds: AABB,
+    time: (f64, f64),
+}
+
+impl Accelerator for Grid {
+    fn build_with_stats(
+        objects: Vec<Box<dyn Hit>>,
+        time: (f64, f64),
+    ) -> (Box<dyn Hit>, AccelStats) {
+        let (bounded, mut unbounded) = partition_bounded(objects, time);
+        let (bounded, oversized) = partition_oversized(bounded);
+        unbounded.extend(oversized);
+
+        let (grid, stats) = if bounded.is_empty() {
+            (None, AccelStats::default())
+        } else {
+            let (bounds, objects): (Vec<_>, Vec<_>) = bounded.into_iter().unzip();
+            let grid = Grid::new(objects, &bounds, time);
+            let stats = grid.stats();
+            (Some(Box::new(grid) as Box<dyn Hit>), stats)
+        };
+        let stats = AccelStats {
+            unbounded: unbounded.len(),
+            ..stats
+        };
+
+        (with_unbounded(grid, unbounded), stats)
+    }
+}
+
+/// Separates objects far larger across than the median one from the rest.
+fn partition_oversized(mut bounded: Vec<Bounded>) -> (Vec<Bounded>, Vec<Box<dyn Hit>>) {
+    let size = |bounds: &AABB| (bounds.max - bounds.min).length();
+    let mut sizes: Vec<f64> = bounded.iter().map(|(bounds, _)| size(bounds)).collect();
+    if sizes.is_empty() {
+        return (bounded, vec![]);
+    }
+    let middle = sizes.len() / 2;
+    let (_, &mut median, _) = sizes.select_nth_unstable_by(middle, f64::total_cmp);
+
+    let mut oversized = vec![];
+    let mut i = 0;
+    while i < bounded.len() {
+        if size(&bounded[i].0) > OVERSIZED * median {
+            oversized.push(bounded.swap_remove(i).1);
+        } else {
+            i += 1;
+        }
+    }
+
+    (bounded, oversized)
+}
+
+impl Grid {
+    fn new(objects: Vec<Box<dyn Hit>>, object_bounds: &[AABB], time: (f64, f64)) -> Self {
+        let bounds = object_bounds
+            .iter()
+            .copied()
+            .reduce(|a, b| a + b)
+            .expect("grid needs at least one object");
+
+        // Roughly cubic cells, DENSITY per object. Flat or thin scenes get a
+        // single layer of cells along their thin axes.
+        let extent = bounds.max - bounds.min;
+        let longest = (0..3).map(|a| extent.axis(a)).fold(0., f64::max);
+        let thinnest = longest / MAX_RESOLUTION as f64;
+        let volume: f64 = (0..3).map(|a| extent.axis(a).max(thinnest)).product();
+        let cells_per_unit = if volume > 0. {
+            (DENSITY * objects.len() as f64 / volume).cbrt()
+        } else {
+            0.
+        };
+        let resolution = [0, 1, 2]
+            .map(|a| ((extent.axis(a) * cells_per_unit).round() as usize).clamp(1, MAX_RESOLUTION));
+        let cell_size = Vec3::new(
+            extent.x() / resolution[0] as f64,
+            extent.y() / resolution[1] as f64,
+            extent.z() / resolution[2] as f64,
+        );
+
+        let mut grid = Self {
+            objects,
+            resolution,
+            cell_size,
+            offsets: vec![],
+            indices: vec![],
+            bounds,
+            time,
+        };
+
+        // Count the objects in each cell, then fill them in
+        let cell_count = resolution.iter().product();
+        let mut counts = vec![0; cell_count];
+        for b in object_bounds {
+            grid.for_each_cell(b, |cell| counts[cell] += 1);
+        }
+        let mut offsets = Vec::with_capacity(cell_count + 1);
+        offsets.push(0);
+        for count in &counts {
+            offsets.push(offsets.last().unwrap() + count);
+        }
+        let mut indices = vec![0; *offsets.last().unwrap()];
+        let mut next = offsets.clone();
+        for (i, b) in object_bounds.iter().enumerate() {
+            grid.for_each_cell(b, |cell| {
+                indices[next[cell]] = i;
+                next[cell] += 1;
+            });
+        }
+        grid.offsets = offsets;
+        grid.indices = indices;
+
+        grid
+    }
+
+    /// The cell along `axis` containing the coordinate `x`, clamped to the
+    /// grid.
+    fn cell_on_axis(&self, axis: usize, x: f64) -> usize {
+        let size = self.cell_size.axis(axis);
+        if size <= 0. {
+            return 0;
+        }
+        let cell = ((x - self.bounds.min.axis(axis)) / size).floor();
+        (cell.max(0.) as usize).min(self.resolution[axis] - 1)
+    }
+
+    fn cell_index(&self, [x, y, z]: [usize; 3]) -> usize {
+        (z * self.resolution[1] + y) * self.resolution[0] + x
+    }
+
+    /// Calls `f` with the index of every cell `bounds` overlaps.
+    fn for_each_cell<F: FnMut(usize)>(&self, bounds: &AABB, mut f: F) {
+        let first = [0, 1, 2].map(|a| self.cell_on_axis(a, bounds.min.axis(a)));
+        let last = [0, 1, 2].map(|a| self.cell_on_axis(a, bounds.max.axis(a)));
+        for z in first[2]..=last[2] {
+            for y in first[1]..=last[1] {
+                for x in first[0]..=last[0] {
+                    f(self.cell_index([x, y, z]));
+                }
+            }
+        }
+    }
+
+    fn cell_objects(&self, cell: usize) -> &[usize] {
+        &self.indices[self.offsets[cell]..self.offsets[cell + 1]]
+    }
+
+    fn cell_bounds(&self, [x, y, z]: [usize; 3]) -> AABB {
+        let min = self.bounds.min
+            + Vec3::new(
+                x as f64 * self.cell_size.x(),
+                y as f64 * self.cell_size.y(),
+                z as f64 * self.cell_size.z(),
+            );
+        AABB::new(min, min + self.cell_size)
+    }
+
+    /// The grid's shape as a one-level tree, with a leaf for each cell that
+    /// holds any objects.
+    fn stats(&self) -> AccelStats {
+        let leaves = self.offsets.windows(2).filter(|w| w[1] > w[0]).count();
+        AccelStats {
+            objects: self.objects.len(),
+            unbounded: 0,
+            interior_nodes: 1,
+            leaves,
+            max_depth: 1,
+        }
+    }
+
+    /// Calls `visit` with the objects in each cell the ray passes through
+    /// between `t_min` and `t_max`, nearest first, and where the ray is
+    /// inside the cell, until `visit` returns true.
+    fn walk<F>(&self, r: Ray, t_min: f64, t_max: f64, mut visit: F)
+    where
+        F: FnMut(&[usize], f64, f64) -> bool,
+    {
+        let Some((t_enter, t_exit)) = self.bounds.hit(r, t_min, t_max) else {
+            return;
+        };
+
+        // Where the ray crosses into the next cell along each axis, and how
+        // far apart those crossings are
+        let entry = r.at(t_enter);
+        let mut cell = [0, 1, 2].map(|a| self.cell_on_axis(a, entry.axis(a)));
+        let mut next_crossing = [f64::INFINITY; 3];
+        let mut delta = [f64::INFINITY; 3];
+        let mut backwards = [false; 3];
+        for axis in 0..3 {
+            let direction = r.direction.axis(axis);
+            if direction == 0. {
+                continue;
+            }
+            let inv_d = r.inv_direction().axis(axis);
+            let size = self.cell_size.axis(axis);
+            backwards[axis] = direction < 0.;
+            let boundary = self.bounds.min.axis(axis)
+                + size * (cell[axis] + usize::from(!backwards[axis])) as f64;
+            next_crossing[axis] = (boundary - r.origin.axis(axis)) * inv_d;
+            delta[axis] = size * inv_d.abs();
+        }
+
+        let mut t_near = t_enter;
+        loop {
+            let axis = (0..3)
+                .min_by(|&a, &b| next_crossing[a].total_cmp(&next_crossing[b]))
+                .unwrap();
+            let t_far = next_crossing[axis].min(t_exit);
+
+            if visit(self.cell_objects(self.cell_index(cell)), t_near, t_far) {
+                return;
+            }
+
+            if next_crossing[axis] >= t_exit {
+                return;
+            }
+            if backwards[axis] {
+                if cell[axis] == 0 {
+                    return;
+                }
+                cell[axis] -= 1;
+            } else {
+                cell[axis] += 1;
+                if cell[axis] == self.resolution[axis] {
+                    return;
+                }
+            }
+            t_near = next_crossing[axis];
+            next_crossing[axis] += delta[axis];
+        }
+    }
+}
+
+/// The last few objects tested along a ray.
+struct Mailbox {
+    recent: [usize; MAILBOX_SIZE],
+    next: usize,
+}
+
+impl Mailbox {
+    fn new() -> Self {
+        Self {
+            recent: [usize::MAX; MAILBOX_SIZE],
+            next: 0,
+        }
+    }
+
+    /// Whether `object` still needs testing, noting it as tested if so.
+    fn check(&mut self, object: usize) -> bool {
+        if self.recent.contains(&object) {
+            return false;
+        }
+        self.recent[self.next] = object;
+        self.next = (self.next + 1) % MAILBOX_SIZE;
+        true
+    }
+}
+
+impl Hit for Grid {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest: Option<HitRecord> = None;
+        let mut mailbox = Mailbox::new();
+        self.walk(r, t_min, t_max, |objects, _, t_far| {
+            for &i in objects {
+                if !mailbox.check(i) {
+                    continue;
+                }
+                let t_max = closest.map_or(t_max, |hit| hit.t);
+                if let Some(hit) = self.objects[i].hit(r, t_min, t_max) {
+                    closest = Some(hit);
+                }
+            }
+            // Objects can reach past the cell, so a hit found in it may
+            // still lie beyond it
+            closest.is_some_and(|hit| hit.t <= t_far)
+        });
+
+        closest
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        let mut occluded = false;
+        let mut mailbox = Mailbox::new();
+        self.walk(r, t_min, t_max, |objects, _, _| {
+            occluded = objects
+                .iter()
+                .any(|&i| mailbox.check(i) && self.objects[i].occluded(r, t_min, t_max));
+            occluded
+        });
+
+        occluded
+    }
+
+    /// Objects spanning several cells are only crossed within each cell, so
+    /// media aren't counted more than once.
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut transmittance = 1.;
+        self.walk(r, t_min, t_max, |objects, t_near, t_far| {
+            if t_near < t_far {
+                for &i in objects {
+                    transmittance *= self.objects[i].transmittance(r, t_near, t_far);
+                    if transmittance <= 0. {
+                        return true;
+                    }
+                }
+            }
+            false
+        });
+
+        transmittance
+    }
+
+    /// Objects can be in several cells, so each one the ray passes is only
+    /// asked once.
+    fn hits<'a>(&'a self, r: Ray, t_min: f64, t_max: f64, hits: &mut Vec<HitRecord<'a>>) {
+        let mut passed = vec![];
+        self.walk(r, t_min, t_max, |objects, _, _| {
+            passed.extend_from_slice(objects);
+            false
+        });
+        passed.sort_unstable();
+        passed.dedup();
+        for i in passed {
+            self.objects[i].hits(r, t_min, t_max, hits);
+        }
+    }
+
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
+        Some(self.bounds)
+    }
+
+    /// Cells depend on where the objects are, so the grid is rebuilt from
+    /// scratch rather than refit.
+    fn refit(&mut self, time: (f64, f64)) -> Option<AABB> {
+        if time != self.time {
+            let objects = std::mem::take(&mut self.objects);
+            let object_bounds: Option<Vec<AABB>> = objects.iter().map(|o| o.bounds(time)).collect();
+            let object_bounds = object_bounds.expect("objects in a grid must stay bounded");
+            *self = Grid::new(objects, &object_bounds, time);
+        }
+
+        Some(self.bounds)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.memory += std::mem::size_of::<Self>()
+            + (self.offsets.capacity() + self.indices.capacity()) * std::mem::size_of::<usize>()
+            + self.objects.capacity() * std::mem::size_of::<Box<dyn Hit>>();
+        for object in &self.objects {
+            object.count(stats);
+        }
+    }
+
+    fn accel_nodes(&self, depth: usize, _time: (f64, f64), nodes: &mut Vec<AccelNode>) {
+        nodes.push(AccelNode {
+            bounds: self.bounds,
+            depth,
+            leaf: false,
+        });
+        let [nx, ny, nz] = self.resolution;
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    if !self.cell_objects(self.cell_index([x, y, z])).is_empty() {
+                        nodes.push(AccelNode {
+                            bounds: self.cell_bounds([x, y, z]),
+                            depth: depth + 1,
+                            leaf: true,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        for object in &self.objects {
+            object.tessellate(time, mesh);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::bounds::AABB;
+use crate::vector::{Point3, Vec3};
+
+/// Directional bins around the azimuth and along the z axis. Splitting
+/// azimuth and z evenly gives every bin the same solid angle.
+const PHI_BINS: usize = 16;
+const Z_BINS: usize = 8;
+const BINS: usize = PHI_BINS * Z_BINS;
+
+/// Regions that record more samples than this in a pass are split in two.
+const SPLIT_THRESHOLD: u64 = 12_000;
+
+/// Regions keep their previous distribution until they have recorded this
+/// many samples, rather than learning from a handful of noisy ones.
+const MIN_SAMPLES: u64 = 256;
+
+/// Stops the spatial tree growing without bound on long renders.
+const MAX_LEAVES: usize = 1 << 14;
+
+/// Learns where incident light comes from as the render progresses, so
+/// diffuse bounces can be sent towards it.
+///
+/// Space is divided by a binary tree. Each region holds a histogram of the
+/// radiance arriving from each direction, recorded during one pass and used
+/// for sampling during the next. Regions that record many samples are split,
+/// so the tree grows where paths are dense and the light is learned in more
+/// detail there.
+pub struct Guide {
+    tree: RwLock<Arc<Tree>>,
+}
+
+struct Tree {
+    nodes: Vec<Node>,
+    regions: Vec<Region>,
+}
+
+enum Node {
+    Interior {
+        axis: usize,
+        split: f64,
+        /// Index of the child above the split; the one below follows this node.
+        above: usize,
+    },
+    Leaf(usize),
+}
+
+struct Region {
+    bounds: AABB,
+    distribution: Option<Distribution>,
+    /// Sum of the radiance estimates in each bin, as `f64` bits.
+    recorded: Vec<AtomicU64>,
+    samples: AtomicU64,
+}
+
+/// A piecewise-constant distribution over the directional bins.
+#[derive(Clone)]
+struct Distribution {
+    cdf: Vec<f64>,
+}
+
+impl Guide {
+    pub fn new(bounds: AABB) -> Self {
+        let tree = Tree {
+            nodes: vec![Node::Leaf(0)],
+            regions: vec![Region::new(bounds, None)],
+        };
+
+        Self {
+            tree: RwLock::new(Arc::new(tree)),
+        }
+    }
+
+    /// The region of space around `p`.
+    pub fn at(&self, p: Point3) -> GuideRegion {
+        let tree = Arc::clone(&self.tree.read().unwrap());
+        let region = tree.region_index(p);
+        GuideRegion { tree, region }
+    }
+
+    /// Turns the radiance recorded during the last pass into the
+    /// distributions to sample from in the next, splitting busy regions.
+    pub fn refine(&self) {
+        let mut tree = self.tree.write().unwrap();
+        let mut nodes = vec![];
+        let mut regions = vec![];
+        tree.refine_node(0, &mut nodes, &mut regions);
+        *tree = Arc::new(Tree { nodes, regions });
+    }
+}
+
+/// What the guide knows about one region, kept alive while a path uses it
+/// even if the guide is refined in the meantime.
+pub struct GuideRegion {
+    tree: Arc<Tree>,
+    region: usize,
+}
+
+impl GuideRegion {
+    pub fn is_trained(&self) -> bool {
+        self.region().distribution.is_some()
+    }
+
+    /// Picks a direction using the uniform random numbers `u`, or None
+    /// before anything has been learned.
+    pub fn sample(&self, u: (f64, f64, f64)) -> Option<Vec3> {
+        let distribution = self.region().distribution.as_ref()?;
+
+        let bin = distribution
+            .cdf
+            .partition_point(|&c| c <= u.0)
+            .min(BINS - 1);
+        let (phi_bin, z_bin) = (bin % PHI_BINS, bin / PHI_BINS);
+
+        let phi = (phi_bin as f64 + u.1) / PHI_BINS as f64 * 2. * PI;
+        let z = (z_bin as f64 + u.2) / Z_BINS as f64 * 2. - 1.;
+        let r = (1. - z * z).max(0.).sqrt();
+
+        Some(Vec3::new(r * phi.cos(), r * phi.sin(), z))
+    }
+
+    /// The density, per unit solid angle, of sampling `direction`.
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        match &self.region().distribution {
+            Some(distribution) => {
+                let bin = bin_index(direction);
+                let previous = if bin == 0 {
+                    0.
+                } else {
+                    distribution.cdf[bin - 1]
+                };
+                (distribution.cdf[bin] - previous) * BINS as f64 / (4. * PI)
+            }
+            None => 0.,
+        }
+    }
+
+    /// Records a sample of the radiance arriving from `direction`, divided
+    /// by the density `direction` was sampled with.
+    pub fn record(&self, direction: Vec3, weighted_radiance: f64) {
+        if !weighted_radiance.is_finite() {
+            return;
+        }
+
+        let region = self.region();
+        region.samples.fetch_add(1, Ordering::Relaxed);
+
+        let bin = &region.recorded[bin_index(direction)];
+        let _ = bin.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f64::from_bits(bits) + weighted_radiance).to_bits())
+        });
+    }
+
+    fn region(&self) -> &Region {
+        &self.tree.regions[self.region]
+    }
+}
+
+impl Tree {
+    fn region_index(&self, p: Point3) -> usize {
+        let mut index = 0;
+        loop {
+            match self.nodes[index] {
+                Node::Interior { axis, split, above } => {
+                    index = if p.axis(axis) < split {
+                        index + 1
+                    } else {
+                        above
+                    };
+                }
+                Node::Leaf(region) => return region,
+            }
+        }
+    }
+
+    /// Copies the subtree at `index` into `nodes` and `regions`, learning
+    /// from each region's samples and splitting the busy ones.
+    fn refine_node(&self, index: usize, nodes: &mut Vec<Node>, regions: &mut Vec<Region>) {
+        match self.nodes[index] {
+            Node::Interior { axis, split, above } => {
+                let node = nodes.len();
+                nodes.push(Node::Interior {
+                    axis,
+                    split,
+                    above: 0,
+                });
+
+                self.refine_node(index + 1, nodes, regions);
+
+                let above_index = nodes.len();
+                if let Node::Interior { above, .. } = &mut nodes[node] {
+                    *above = above_index;
+                }
+
+                self.refine_node(above, nodes, regions);
+            }
+            Node::Leaf(region) => {
+                let region = &self.regions[region];
+                let samples = region.samples.load(Ordering::Relaxed);
+
+                let learned = if samples >= MIN_SAMPLES {
+                    Distribution::from_bins(&region.recorded)
+                } else {
+                    None
+                };
+                let distribution = learned.or_else(|| region.distribution.clone());
+
+                if samples < SPLIT_THRESHOLD || self.regions.len() >= MAX_LEAVES {
+                    nodes.push(Node::Leaf(regions.len()));
+                    regions.push(Region::new(region.bounds, distribution));
+                    return;
+                }
+
+                // Both halves start out with what the whole region learned
+                let extent = region.bounds.max - region.bounds.min;
+                let axis = (0..3)
+                    .max_by(|&a, &b| extent.axis(a).total_cmp(&extent.axis(b)))
+                    .unwrap();
+                let split = region.bounds.centroid().axis(axis);
+
+                let (mut below, mut above) = (region.bounds, region.bounds);
+                below.max = below.max.with_axis(axis, split);
+                above.min = above.min.with_axis(axis, split);
+
+                nodes.push(Node::Interior {
+                    axis,
+                    split,
+                    above: nodes.len() + 2,
+                });
+                nodes.push(Node::Leaf(regions.len()));
+                regions.push(Region::new(below, distribution.clone()));
+                nodes.push(Node::Leaf(regions.len()));
+                regions.push(Region::new(above, distribution));
+            }
+        }
+    }
+}
+
+impl Region {
+    fn new(bounds: AABB, distribution: Option<Distribution>) -> Self {
+        Self {
+            bounds,
+            distribution,
+            recorded: (0..BINS).map(|_| AtomicU64::new(0f64.to_bits())).collect(),
+            samples: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Distribution {
+    /// Normalizes the recorded bins, or gives None if nothing was recorded.
+    fn from_bins(bins: &[AtomicU64]) -> Option<Self> {
+        let values: Vec<f64> = bins
+            .iter()
+            .map(|bin| f64::from_bits(bin.load(Ordering::Relaxed)).max(0.))
+            .collect();
+        let total: f64 = values.iter().sum();
+        if total <= 0. {
+            return None;
+        }
+
+        let mut sum = 0.;
+        let cdf = values
+            .iter()
+            .map(|value| {
+                sum += value / total;
+                sum
+            })
+            .collect();
+
+        Some(Self { cdf })
+    }
+}
+
+fn bin_index(direction: Vec3) -> usize {
+    let d = direction.unit_vector();
+    let phi = d.y().atan2(d.x()).rem_euclid(2. * PI);
+
+    let phi_bin = ((phi / (2. * PI) * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+    let z_bin = (((d.z() + 1.) / 2. * Z_BINS as f64) as usize).min(Z_BINS - 1);
+
+    z_bin * PHI_BINS + phi_bin
+}
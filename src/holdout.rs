@@ -0,0 +1,49 @@
+use crate::bounds::AABB;
+use crate::ray::{Hit, HitRecord, Ray};
+use crate::scene::SceneStats;
+
+/// Cuts a hole in the image where an object is seen, so live action can show
+/// through when compositing.
+///
+/// The object still blocks light, casts shadows and shows up in reflections;
+/// only camera rays that hit it directly see transparent black.
+pub struct Holdout {
+    object: Box<dyn Hit>,
+}
+
+impl Holdout {
+    pub fn new(object: Box<dyn Hit>) -> Self {
+        Self { object }
+    }
+}
+
+impl Hit for Holdout {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let hit = self.object.hit(r, t_min, t_max)?;
+        Some(HitRecord {
+            holdout: true,
+            ..hit
+        })
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.object.occluded(r, t_min, t_max)
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        self.object.transmittance(r, t_min, t_max)
+    }
+
+    fn bounds(&self, time: (f64, f64)) -> Option<AABB> {
+        self.object.bounds(time)
+    }
+
+    fn refit(&mut self, time: (f64, f64)) -> Option<AABB> {
+        self.object.refit(time)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add("holdout", std::mem::size_of::<Self>());
+        self.object.count(stats);
+    }
+}
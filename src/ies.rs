@@ -0,0 +1,287 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// How a light fixture's brightness varies with direction, from an IES
+/// (IESNA LM-63) photometric file as published by fixture manufacturers.
+///
+/// Only type C photometry is supported, which is what nearly all
+/// architectural fixtures use: vertical angles run from 0 straight down the
+/// fixture's axis to 180 straight up, and horizontal angles around it.
+pub struct IesProfile {
+    /// In degrees, increasing.
+    vertical_angles: Vec<f64>,
+    /// In degrees, increasing, from 0 or, for some fixtures symmetric about
+    /// a plane, 90. Fixtures with symmetry only cover part of the circle;
+    /// see `fold`.
+    horizontal_angles: Vec<f64>,
+    /// Candelas for each horizontal angle, then each vertical one.
+    candelas: Vec<f64>,
+    max_candela: f64,
+}
+
+impl IesProfile {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, IesError> {
+        let data = fs::read(path)?;
+        Self::parse(&String::from_utf8_lossy(&data))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, IesError> {
+        // Keyword lines come first, up to the TILT line; the numbers after
+        // it are whitespace separated, however they're split into lines
+        let mut lines = text.lines();
+        let tilt = lines
+            .by_ref()
+            .map(str::trim)
+            .find_map(|line| line.strip_prefix("TILT="))
+            .ok_or(IesError::Truncated)?;
+        let mut numbers = lines.flat_map(str::split_whitespace).map(|s| {
+            s.parse::<f64>()
+                .map_err(|_| IesError::Invalid(format!("{:?}", s)))
+        });
+        let mut next = || numbers.next().unwrap_or(Err(IesError::Truncated));
+
+        match tilt.trim() {
+            "NONE" => {}
+            // Lamp tilt factors, which only matter for fixtures aimed away
+            // from how they were measured, are skipped
+            "INCLUDE" => {
+                next()?;
+                let pairs = count(next()?)?;
+                for _ in 0..2 * pairs {
+                    next()?;
+                }
+            }
+            _ => return Err(IesError::Unsupported("tilt data in a separate file")),
+        }
+
+        let _lamps = next()?;
+        let _lumens_per_lamp = next()?;
+        let multiplier = next()?;
+        let vertical_count = count(next()?)?;
+        let horizontal_count = count(next()?)?;
+        let photometric_type = next()?;
+        let _units = next()?;
+        let _dimensions = (next()?, next()?, next()?);
+        let ballast_factor = next()?;
+        let ballast_lamp_factor = next()?;
+        let _input_watts = next()?;
+
+        if photometric_type != 1. {
+            return Err(IesError::Unsupported("type A or B photometry"));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(IesError::Invalid("no angles".to_string()));
+        }
+
+        let mut read = |n| (0..n).map(|_| next()).collect::<Result<Vec<_>, _>>();
+        let vertical_angles = read(vertical_count)?;
+        let horizontal_angles = read(horizontal_count)?;
+        let scale = multiplier * ballast_factor * ballast_lamp_factor;
+        let candelas: Vec<f64> = read(vertical_count * horizontal_count)?
+            .into_iter()
+            .map(|candela| candela * scale)
+            .collect();
+
+        let increasing = |angles: &[f64]| angles.windows(2).all(|pair| pair[0] < pair[1]);
+        if !increasing(&vertical_angles) || !increasing(&horizontal_angles) {
+            return Err(IesError::Invalid("angles out of order".to_string()));
+        }
+
+        let max_candela = candelas.iter().copied().fold(0., f64::max);
+        Ok(Self {
+            vertical_angles,
+            horizontal_angles,
+            candelas,
+            max_candela,
+        })
+    }
+
+    /// The brightest the fixture is in any direction, in candelas.
+    pub fn max_candela(&self) -> f64 {
+        self.max_candela
+    }
+
+    /// Candelas at `vertical` degrees from straight down the fixture's axis
+    /// and `horizontal` degrees around it, interpolated between the measured
+    /// angles. Zero outside the vertical angles measured.
+    pub fn candela(&self, vertical: f64, horizontal: f64) -> f64 {
+        let Some((v, v_t)) = bracket(&self.vertical_angles, vertical) else {
+            return 0.;
+        };
+        let Some((h, h_t)) = bracket(&self.horizontal_angles, self.fold(horizontal)) else {
+            return 0.;
+        };
+
+        // The last angle comes back with nothing of the next to blend in, so
+        // it's blended with itself
+        let v_next = (v + 1).min(self.vertical_angles.len() - 1);
+        let h_next = (h + 1).min(self.horizontal_angles.len() - 1);
+        let at = |h: usize, v: usize| self.candelas[h * self.vertical_angles.len() + v];
+        let along = |h: usize| at(h, v) * (1. - v_t) + at(h, v_next) * v_t;
+        along(h) * (1. - h_t) + along(h_next) * h_t
+    }
+
+    /// `horizontal` degrees brought into the range measured, using the
+    /// symmetry implied by the angles it spans: none for 360, about a plane
+    /// for 180, about two for 90 and all the way round for 0. The planes
+    /// run through the first angle, so 90 to 270 is folded about the plane
+    /// through 90 and 270.
+    fn fold(&self, horizontal: f64) -> f64 {
+        let first = self.horizontal_angles[0];
+        let span = *self.horizontal_angles.last().unwrap() - first;
+        let angle = (horizontal - first).rem_euclid(360.);
+        let folded = match span {
+            _ if self.horizontal_angles.len() == 1 => 0.,
+            _ if span <= 90. => {
+                let half = if angle > 180. { 360. - angle } else { angle };
+                if half > 90. {
+                    180. - half
+                } else {
+                    half
+                }
+            }
+            _ if span <= 180. && angle > 180. => 360. - angle,
+            _ => angle,
+        };
+        first + folded
+    }
+}
+
+/// The index of the last of `angles` not after `angle` and how far `angle`
+/// is towards the next, or None if it's outside them.
+fn bracket(angles: &[f64], angle: f64) -> Option<(usize, f64)> {
+    let first = angles[0];
+    let last = *angles.last().unwrap();
+    if angle < first || angle > last {
+        return None;
+    }
+    if angle == last {
+        return Some((angles.len() - 1, 0.));
+    }
+
+    let i = angles.partition_point(|&a| a <= angle) - 1;
+    Some((i, (angle - angles[i]) / (angles[i + 1] - angles[i])))
+}
+
+fn count(n: f64) -> Result<usize, IesError> {
+    if n >= 0. && n.fract() == 0. {
+        Ok(n as usize)
+    } else {
+        Err(IesError::Invalid(format!("count {}", n)))
+    }
+}
+
+#[derive(Debug)]
+pub enum IesError {
+    Io(io::Error),
+    Unsupported(&'static str),
+    Invalid(String),
+    Truncated,
+}
+
+impl fmt::Display for IesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IesError::Io(err) => write!(f, "{}", err),
+            IesError::Unsupported(what) => write!(f, "unsupported IES file: {}", what),
+            IesError::Invalid(message) => write!(f, "invalid IES file: {}", message),
+            IesError::Truncated => write!(f, "IES file ends before its data does"),
+        }
+    }
+}
+
+impl std::error::Error for IesError {}
+
+impl From<io::Error> for IesError {
+    fn from(err: io::Error) -> Self {
+        IesError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A type C profile measured at `vertical` and `horizontal` angles, with
+    /// `candelas` for each horizontal angle in turn.
+    fn profile(vertical: &[f64], horizontal: &[f64], candelas: &[f64]) -> IesProfile {
+        let list = |values: &[f64]| {
+            let values: Vec<String> = values.iter().map(f64::to_string).collect();
+            values.join(" ")
+        };
+        let text = format!(
+            "IESNA:LM-63-2002\n[TEST] fixture\nTILT=NONE\n1 1000 1 {} {} 1 2 0 0 0\n1 1 100\n{}\n{}\n{}\n",
+            vertical.len(),
+            horizontal.len(),
+            list(vertical),
+            list(horizontal),
+            list(candelas),
+        );
+        IesProfile::parse(&text).unwrap()
+    }
+
+    #[test]
+    fn interpolates_between_angles() {
+        let profile = profile(&[0., 90.], &[0.], &[100., 50.]);
+        assert_eq!(profile.max_candela(), 100.);
+        assert_eq!(profile.candela(0., 0.), 100.);
+        assert_eq!(profile.candela(45., 0.), 75.);
+        assert_eq!(profile.candela(90., 0.), 50.);
+        // Beyond the vertical angles measured
+        assert_eq!(profile.candela(120., 0.), 0.);
+    }
+
+    #[test]
+    fn one_horizontal_angle_is_the_same_all_the_way_round() {
+        let profile = profile(&[0., 90.], &[0.], &[100., 50.]);
+        for horizontal in [0., 45., 90., 200., 359., -30.] {
+            assert_eq!(profile.candela(45., horizontal), 75.);
+        }
+    }
+
+    #[test]
+    fn a_quadrant_is_mirrored_into_the_others() {
+        let profile = profile(&[0.], &[0., 90.], &[10., 30.]);
+        assert_eq!(profile.candela(0., 45.), 20.);
+        assert_eq!(profile.candela(0., 135.), 20.);
+        assert_eq!(profile.candela(0., 180.), 10.);
+        assert_eq!(profile.candela(0., 270.), 30.);
+        assert_eq!(profile.candela(0., 315.), 20.);
+    }
+
+    #[test]
+    fn half_from_0_is_mirrored_about_the_0_to_180_plane() {
+        let profile = profile(&[0.], &[0., 90., 180.], &[10., 30., 50.]);
+        assert_eq!(profile.candela(0., 45.), 20.);
+        assert_eq!(profile.candela(0., 315.), 20.);
+        assert_eq!(profile.candela(0., 270.), 30.);
+    }
+
+    #[test]
+    fn half_from_90_is_mirrored_about_the_90_to_270_plane() {
+        let profile = profile(&[0.], &[90., 180., 270.], &[10., 30., 50.]);
+        assert_eq!(profile.candela(0., 135.), 20.);
+        // Angles below 90 mirror those between 90 and 180, and above 270
+        // those between 180 and 270
+        assert_eq!(profile.candela(0., 0.), 30.);
+        assert_eq!(profile.candela(0., 45.), 20.);
+        assert_eq!(profile.candela(0., 315.), 40.);
+    }
+
+    #[test]
+    fn rejects_other_photometry() {
+        let text = "TILT=NONE\n1 1000 1 1 1 2 2 0 0 0\n1 1 100\n0\n0\n100\n";
+        assert!(matches!(
+            IesProfile::parse(text),
+            Err(IesError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn truncated_file_is_an_error() {
+        let text = "TILT=NONE\n1 1000 1 2 1 1 2 0 0 0\n1 1 100\n0 90\n0\n100\n";
+        assert!(matches!(IesProfile::parse(text), Err(IesError::Truncated)));
+    }
+}
@@ -0,0 +1,290 @@
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use crate::color::Color;
+use crate::exr;
+use crate::film::ImageFormat;
+
+/// A grid of linear colors, stored in rows from top to bottom.
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height, "pixel count mismatch");
+
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Loads an image in any of the formats a film can be written in,
+    /// going by the file's extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let path = path.as_ref();
+        match ImageFormat::from_path(path) {
+            Some(ImageFormat::Ppm) => Self::load_ppm(path),
+            Some(ImageFormat::Png) => Self::load_png(path),
+            Some(ImageFormat::Pfm) => Self::load_pfm(path),
+            Some(ImageFormat::Exr) => Self::load_exr(path),
+            None => Err(ImageError::UnsupportedFormat),
+        }
+    }
+
+    /// Loads a binary (P6) or plain (P3) PPM file, converting from sRGB.
+    pub fn load_ppm<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let data = fs::read(path)?;
+        let mut pos = 0;
+
+        let magic = next_token(&data, &mut pos).ok_or(ImageError::Truncated)?;
+        let binary = match magic {
+            b"P6" => true,
+            b"P3" => false,
+            _ => return Err(ImageError::UnsupportedFormat),
+        };
+
+        let mut header = [0; 3];
+        for value in header.iter_mut() {
+            let token = next_token(&data, &mut pos).ok_or(ImageError::Truncated)?;
+            *value = parse_number(token).ok_or(ImageError::InvalidHeader)?;
+        }
+        let [width, height, max_value] = header;
+        if width == 0 || height == 0 || max_value == 0 || max_value > 65535 {
+            return Err(ImageError::InvalidHeader);
+        }
+
+        let count = width * height * 3;
+        let samples: Vec<usize> = if binary {
+            // A single whitespace character separates the header from the data
+            let start = pos + 1;
+            let bytes_per_sample = if max_value < 256 { 1 } else { 2 };
+            let bytes = data
+                .get(start..start + count * bytes_per_sample)
+                .ok_or(ImageError::Truncated)?;
+
+            if bytes_per_sample == 1 {
+                bytes.iter().map(|&b| b as usize).collect()
+            } else {
+                bytes
+                    .chunks_exact(2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+                    .collect()
+            }
+        } else {
+            (0..count)
+                .map(|_| {
+                    let token = next_token(&data, &mut pos).ok_or(ImageError::Truncated)?;
+                    parse_number(token).ok_or(ImageError::InvalidData)
+                })
+                .collect::<Result<_, _>>()?
+        };
+
+        let scale = (max_value as f64).recip();
+        let pixels = samples
+            .chunks_exact(3)
+            .map(|rgb| {
+                let srgb = Color::new(rgb[0] as f64, rgb[1] as f64, rgb[2] as f64) * scale;
+                srgb.from_srgb()
+            })
+            .collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+
+    /// Loads an 8 or 16-bit RGB or gray PNG file, with or without alpha,
+    /// converting from sRGB. Alpha is ignored.
+    pub fn load_png<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        let mut reader = decoder.read_info().map_err(|_| ImageError::InvalidHeader)?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut data)
+            .map_err(|_| ImageError::InvalidData)?;
+
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => return Err(ImageError::UnsupportedFormat),
+        };
+        let samples: Vec<f64> = match info.bit_depth {
+            png::BitDepth::Eight => data[..info.buffer_size()]
+                .iter()
+                .map(|&b| b as f64 / 255.)
+                .collect(),
+            png::BitDepth::Sixteen => data[..info.buffer_size()]
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as f64 / 65535.)
+                .collect(),
+            _ => return Err(ImageError::UnsupportedFormat),
+        };
+
+        let pixels = samples
+            .chunks_exact(channels)
+            .map(|p| {
+                let srgb = match channels {
+                    1 | 2 => Color::new(p[0], p[0], p[0]),
+                    _ => Color::new(p[0], p[1], p[2]),
+                };
+                srgb.from_srgb()
+            })
+            .collect();
+        Ok(Self::new(info.width as usize, info.height as usize, pixels))
+    }
+
+    /// Loads a color (PF) or gray (Pf) PFM file of linear floats.
+    pub fn load_pfm<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let data = fs::read(path)?;
+        let mut pos = 0;
+
+        let channels = match next_token(&data, &mut pos).ok_or(ImageError::Truncated)? {
+            b"PF" => 3,
+            b"Pf" => 1,
+            _ => return Err(ImageError::UnsupportedFormat),
+        };
+        let mut size = [0; 2];
+        for value in size.iter_mut() {
+            let token = next_token(&data, &mut pos).ok_or(ImageError::Truncated)?;
+            *value = parse_number(token).ok_or(ImageError::InvalidHeader)?;
+        }
+        let [width, height] = size;
+        let scale: f64 = next_token(&data, &mut pos)
+            .and_then(|token| std::str::from_utf8(token).ok()?.parse().ok())
+            .ok_or(ImageError::InvalidHeader)?;
+        if width == 0 || height == 0 || scale == 0. {
+            return Err(ImageError::InvalidHeader);
+        }
+
+        // A negative scale marks the data as little-endian
+        let start = pos + 1;
+        let bytes = data
+            .get(start..start + width * height * channels * 4)
+            .ok_or(ImageError::Truncated)?;
+        let values: Vec<f64> = bytes
+            .chunks_exact(4)
+            .map(|b| {
+                let b = [b[0], b[1], b[2], b[3]];
+                let value = if scale < 0. {
+                    f32::from_le_bytes(b)
+                } else {
+                    f32::from_be_bytes(b)
+                };
+                value as f64 * scale.abs()
+            })
+            .collect();
+
+        // Rows run from the bottom of the image to the top
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in values.chunks_exact(width * channels).rev() {
+            pixels.extend(row.chunks_exact(channels).map(|p| match channels {
+                1 => Color::new(p[0], p[0], p[0]),
+                _ => Color::new(p[0], p[1], p[2]),
+            }));
+        }
+        Ok(Self::new(width, height, pixels))
+    }
+
+    /// Loads the R, G and B channels, or the Y channel of a gray image, of
+    /// an uncompressed OpenEXR file; see `exr::read`.
+    pub fn load_exr<P: AsRef<Path>>(path: P) -> Result<Self, ImageError> {
+        let (width, height, channels) = exr::read(&mut BufReader::new(File::open(path)?))?;
+        let channel = |name: &str| {
+            channels
+                .iter()
+                .find(|channel| channel.name == name)
+                .map(|channel| &channel.values)
+        };
+
+        let pixels = match (channel("R"), channel("G"), channel("B"), channel("Y")) {
+            (Some(r), Some(g), Some(b), _) => (0..width * height)
+                .map(|i| Color::new(r[i] as f64, g[i] as f64, b[i] as f64))
+                .collect(),
+            (_, _, _, Some(y)) => y
+                .iter()
+                .map(|&y| Color::new(y as f64, y as f64, y as f64))
+                .collect(),
+            _ => return Err(ImageError::InvalidData),
+        };
+        Ok(Self::new(width, height, pixels))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Memory taken up by the pixels.
+    pub fn size_in_bytes(&self) -> usize {
+        self.pixels.len() * std::mem::size_of::<Color>()
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+/// Skips whitespace and comments, then returns the next token in a PPM header.
+fn next_token<'a>(data: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    loop {
+        match data.get(*pos)? {
+            b'#' => {
+                while *data.get(*pos)? != b'\n' {
+                    *pos += 1;
+                }
+            }
+            c if c.is_ascii_whitespace() => *pos += 1,
+            _ => break,
+        }
+    }
+
+    let start = *pos;
+    while data.get(*pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+
+    Some(&data[start..*pos])
+}
+
+fn parse_number(token: &[u8]) -> Option<usize> {
+    std::str::from_utf8(token).ok()?.parse().ok()
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    UnsupportedFormat,
+    InvalidHeader,
+    InvalidData,
+    Truncated,
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(err) => write!(f, "{}", err),
+            ImageError::UnsupportedFormat => write!(f, "unsupported image format"),
+            ImageError::InvalidHeader => write!(f, "invalid image header"),
+            ImageError::InvalidData => write!(f, "invalid pixel value"),
+            ImageError::Truncated => write!(f, "file ends before the image data does"),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<io::Error> for ImageError {
+    fn from(err: io::Error) -> Self {
+        ImageError::Io(err)
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use crate::bounds::AABB;
+use crate::export::Tessellation;
+use crate::ray::{Hit, HitRecord, Ray, SurfaceDerivatives};
+use crate::scene::SceneStats;
+use crate::transform::Transform;
+
+/// A transformed reference to shared geometry.
+///
+/// The referenced object is usually a `Mesh` or a `BVH`, which acts as a
+/// bottom-level acceleration structure built once in object space. Building a
+/// `BVH` over instances gives the top level, which stays small and cheap to
+/// rebuild however many times the geometry is reused.
+pub struct Instance {
+    object: Arc<dyn Hit>,
+    transform: Transform,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hit>, transform: Transform) -> Self {
+        Self { object, transform }
+    }
+
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+}
+
+impl Hit for Instance {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        transform_hit(self.object.as_ref(), self.transform, r, t_min, t_max)
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        let local = to_object(self.transform, r);
+        self.object.occluded(local, t_min, t_max)
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        let local = to_object(self.transform, r);
+        self.object.transmittance(local, t_min, t_max)
+    }
+
+    fn bounds(&self, time: (f64, f64)) -> Option<AABB> {
+        self.object
+            .bounds(time)
+            .map(|bounds| self.transform.bounds(bounds))
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add("instance", std::mem::size_of::<Self>());
+        stats.add_shared(&self.object);
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        let mut local = Tessellation::new(mesh.tolerance);
+        self.object.tessellate(time, &mut local);
+        mesh.add_transformed(local, &self.transform);
+    }
+}
+
+/// Intersects `r` with `object` placed by `transform`, returning the hit in
+/// world space.
+pub(crate) fn transform_hit(
+    object: &dyn Hit,
+    transform: Transform,
+    r: Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord<'_>> {
+    let mut hit = object.hit(to_object(transform, r), t_min, t_max)?;
+    let d = hit.derivatives;
+    hit.p = transform.point(hit.p);
+    hit.normal = transform.normal(hit.normal).unit_vector();
+    hit.velocity = transform.vector(hit.velocity);
+    hit.derivatives = SurfaceDerivatives {
+        dpdu: transform.vector(d.dpdu),
+        dpdv: transform.vector(d.dpdv),
+        dndu: transform.normal(d.dndu),
+        dndv: transform.normal(d.dndv),
+    };
+
+    Some(hit)
+}
+
+/// `r` in the object space of an object placed by `transform`.
+pub(crate) fn to_object(transform: Transform, r: Ray) -> Ray {
+    // The direction is not renormalized, so t means the same in both spaces
+    let inverse = transform.inverse();
+    Ray::new(inverse.point(r.origin), inverse.vector(r.direction), r.time)
+}
@@ -0,0 +1,175 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::film::Film;
+use crate::render::{render_film, Pause, RenderConfig};
+use crate::scene::Scene;
+
+/// A render running on a background thread, which can be watched, cancelled
+/// and waited for without blocking the thread that started it.
+///
+/// Waiting can be done either by blocking in `wait` or by awaiting the job,
+/// which works with any async executor.
+pub struct RenderJob {
+    state: Arc<JobState>,
+    handle: Option<JoinHandle<RenderResult>>,
+}
+
+/// What the job and its thread share.
+struct JobState {
+    config: RenderConfig,
+    start: Instant,
+    cancel: AtomicBool,
+    pause: Pause,
+    samples: AtomicU32,
+    finished: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+/// How far a render has got.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    /// Samples per pixel completed so far.
+    pub samples: u32,
+    /// Time spent rendering, not counting any pauses.
+    pub elapsed: Duration,
+    pub paused: bool,
+    /// Estimated fraction of the render done, from the sample count or the
+    /// time limit, whichever is further along. None for renders without
+    /// either limit, which run until cancelled.
+    pub fraction: Option<f64>,
+}
+
+pub struct RenderResult {
+    pub film: Film,
+    /// Whether the render was cancelled before its budget ran out.
+    pub cancelled: bool,
+}
+
+impl RenderJob {
+    /// Starts rendering `scene` on a new thread.
+    pub fn spawn(scene: Arc<Scene>, config: RenderConfig) -> Self {
+        let state = Arc::new(JobState {
+            config,
+            start: Instant::now(),
+            cancel: AtomicBool::new(false),
+            pause: Pause::new(),
+            samples: AtomicU32::new(0),
+            finished: AtomicBool::new(false),
+            waker: Mutex::new(None),
+        });
+
+        let thread_state = Arc::clone(&state);
+        let handle = thread::spawn(move || {
+            let state = thread_state;
+            let (film, finished) = render_film(
+                &scene,
+                &state.config,
+                Some(&state.cancel),
+                Some(&state.pause),
+                Some(&state.samples),
+            );
+
+            state.finished.store(true, Ordering::Release);
+            if let Some(waker) = state.waker.lock().unwrap().take() {
+                waker.wake();
+            }
+
+            RenderResult {
+                film,
+                cancelled: !finished,
+            }
+        });
+
+        Self {
+            state,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn progress(&self) -> Progress {
+        let samples = self.state.samples.load(Ordering::Relaxed);
+        let elapsed = self
+            .state
+            .start
+            .elapsed()
+            .saturating_sub(self.state.pause.paused_time());
+        let budget = self.state.config.budget;
+
+        Progress {
+            samples,
+            elapsed,
+            paused: self.state.pause.is_paused(),
+            fraction: budget.fraction(samples as f64, elapsed),
+        }
+    }
+
+    /// Asks the render to stop. The pass in progress is dropped, and the
+    /// result holds the passes completed before it.
+    pub fn cancel(&self) {
+        self.state.cancel.store(true, Ordering::Relaxed);
+    }
+
+    /// Stops new tiles being started, keeping what has been rendered so
+    /// far, until `resume` is called. Tiles already started are finished.
+    pub fn pause(&self) {
+        self.state.pause.pause();
+    }
+
+    pub fn resume(&self) {
+        self.state.pause.resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.pause.is_paused()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.finished.load(Ordering::Acquire)
+    }
+
+    /// Blocks until the render is done.
+    pub fn wait(mut self) -> RenderResult {
+        self.join()
+    }
+
+    fn join(&mut self) -> RenderResult {
+        let handle = self.handle.take().expect("render job already joined");
+        match handle.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+impl Future for RenderJob {
+    type Output = RenderResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if !self.is_finished() {
+            *self.state.waker.lock().unwrap() = Some(cx.waker().clone());
+
+            // The render may have finished before the waker was stored
+            if !self.is_finished() {
+                return Poll::Pending;
+            }
+        }
+
+        Poll::Ready(self.join())
+    }
+}
+
+impl Drop for RenderJob {
+    /// Dropping a job that is still running cancels it rather than leaving
+    /// it to render in the background.
+    fn drop(&mut self) {
+        if self.handle.is_some() {
+            self.cancel();
+        }
+    }
+}
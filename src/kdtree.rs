@@ -0,0 +1,427 @@
+use crate::accel::{partition_bounded, with_unbounded, AccelNode, AccelStats, Accelerator};
+use crate::bounds::AABB;
+use crate::export::Tessellation;
+use crate::ray::{Hit, HitRecord, Ray};
+use crate::scene::SceneStats;
+
+/// Nodes with this many objects or fewer become leaves.
+const MAX_LEAF_SIZE: usize = 2;
+
+/// Relative costs of stepping through an interior node and of testing an
+/// object, for the surface area heuristic.
+const TRAVERSAL_COST: f64 = 1.;
+const INTERSECTION_COST: f64 = 80.;
+
+/// How much cheaper a split leaving one side empty is than it looks, since
+/// rays crossing the empty side skip it for free.
+const EMPTY_BONUS: f64 = 0.5;
+
+/// Splits that cost more than a leaf allowed along any path, in case later
+/// ones pay off.
+const MAX_BAD_REFINES: usize = 3;
+
+/// Deepest a tree is built, which bounds the traversal stack.
+const MAX_DEPTH: usize = 64;
+
+/// A kd-tree: space is recursively split by axis-aligned planes, and objects
+/// straddling a plane are referenced from both sides. Planes are placed by
+/// the surface area heuristic, which weighs the chance of a ray entering
+/// each side against the objects it would then test.
+///
+/// Unlike a BVH, traversal visits space front to back and can stop at the
+/// first cell containing a hit.
+pub struct KdTree {
+    objects: Vec<Box<dyn Hit>>,
+    nodes: Vec<Node>,
+    bounds: AABB,
+    time: (f64, f64),
+}
+
+enum Node {
+    Interior {
+        axis: usize,
+        split: f64,
+        /// Index of the child above the split; the one below follows this node.
+        above: usize,
+    },
+    Leaf {
+        objects: Vec<usize>,
+    },
+}
+
+impl Accelerator for KdTree {
+    fn build_with_stats(
+        objects: Vec<Box<dyn Hit>>,
+        time: (f64, f64),
+    ) -> (Box<dyn Hit>, AccelStats) {
+        let (bounded, unbounded) = partition_bounded(objects, time);
+
+        let (tree, stats) = if bounded.is_empty() {
+            (None, AccelStats::default())
+        } else {
+            let (bounds, objects): (Vec<_>, Vec<_>) = bounded.into_iter().unzip();
+            let tree = KdTree::new(objects, &bounds, time);
+            let stats = AccelStats {
+                objects: tree.objects.len(),
+                ..tree.node_stats(0)
+            };
+            (Some(Box::new(tree) as Box<dyn Hit>), stats)
+        };
+        let stats = AccelStats {
+            unbounded: unbounded.len(),
+            ..stats
+        };
+
+        (with_unbounded(tree, unbounded), stats)
+    }
+}
+
+impl KdTree {
+    fn new(objects: Vec<Box<dyn Hit>>, object_bounds: &[AABB], time: (f64, f64)) -> Self {
+        let bounds = object_bounds
+            .iter()
+            .copied()
+            .reduce(|a, b| a + b)
+            .expect("kd-tree needs at least one object");
+
+        let max_depth =
+            ((8. + 1.3 * (objects.len() as f64).log2()).round() as usize).min(MAX_DEPTH);
+
+        let mut tree = Self {
+            objects,
+            nodes: vec![],
+            bounds,
+            time,
+        };
+
+        let all = (0..object_bounds.len()).collect();
+        tree.build_node(all, object_bounds, bounds, max_depth, 0);
+        tree
+    }
+
+    fn build_node(
+        &mut self,
+        objects: Vec<usize>,
+        object_bounds: &[AABB],
+        node_bounds: AABB,
+        depth: usize,
+        bad_refines: usize,
+    ) {
+        if objects.len() <= MAX_LEAF_SIZE || depth == 0 {
+            self.nodes.push(Node::Leaf { objects });
+            return;
+        }
+
+        let Some((axis, split, cost)) = best_split(&objects, object_bounds, node_bounds) else {
+            self.nodes.push(Node::Leaf { objects });
+            return;
+        };
+
+        // Keep going past a split that costs more than a leaf only a few
+        // times, and never when it's far worse
+        let leaf_cost = INTERSECTION_COST * objects.len() as f64;
+        let bad_refines = bad_refines + usize::from(cost > leaf_cost);
+        if (cost > 4. * leaf_cost && objects.len() < 16) || bad_refines == MAX_BAD_REFINES {
+            self.nodes.push(Node::Leaf { objects });
+            return;
+        }
+
+        // Objects lying in the plane itself go below it
+        let below: Vec<usize> = objects
+            .iter()
+            .copied()
+            .filter(|&i| {
+                let b = object_bounds[i];
+                b.min.axis(axis) < split || b.max.axis(axis) <= split
+            })
+            .collect();
+        let above: Vec<usize> = objects
+            .iter()
+            .copied()
+            .filter(|&i| object_bounds[i].max.axis(axis) > split)
+            .collect();
+
+        let mut below_bounds = node_bounds;
+        below_bounds.max = below_bounds.max.with_axis(axis, split);
+        let mut above_bounds = node_bounds;
+        above_bounds.min = above_bounds.min.with_axis(axis, split);
+
+        let index = self.nodes.len();
+        self.nodes.push(Node::Interior {
+            axis,
+            split,
+            above: 0,
+        });
+
+        self.build_node(below, object_bounds, below_bounds, depth - 1, bad_refines);
+
+        let above_index = self.nodes.len();
+        if let Node::Interior { above, .. } = &mut self.nodes[index] {
+            *above = above_index;
+        }
+
+        self.build_node(above, object_bounds, above_bounds, depth - 1, bad_refines);
+    }
+
+    /// The shape of the subtree at node `index`. Objects are counted once per
+    /// leaf that refers to them.
+    fn node_stats(&self, index: usize) -> AccelStats {
+        match &self.nodes[index] {
+            Node::Leaf { objects } => AccelStats::leaf(objects.len()),
+            Node::Interior { above, .. } => {
+                AccelStats::join(self.node_stats(index + 1), self.node_stats(*above))
+            }
+        }
+    }
+
+    /// Calls `visit` with the objects in each leaf the ray passes through
+    /// between `t_min` and `t_max`, nearest first, and where the ray is
+    /// inside the leaf's cell, until `visit` returns true. Cells still to
+    /// visit are kept on a stack rather than recursed into.
+    fn walk<F>(&self, r: Ray, t_min: f64, t_max: f64, mut visit: F)
+    where
+        F: FnMut(&[usize], f64, f64) -> bool,
+    {
+        let Some((mut t_near, mut t_far)) = self.bounds.hit(r, t_min, t_max) else {
+            return;
+        };
+
+        let mut stack = [(0, 0., 0.); MAX_DEPTH];
+        let mut top = 0;
+        let mut index = 0;
+        loop {
+            match &self.nodes[index] {
+                &Node::Interior { axis, split, above } => {
+                    let origin = r.origin.axis(axis);
+                    let direction = r.direction.axis(axis);
+
+                    let below = index + 1;
+                    let below_first = origin < split || (origin == split && direction <= 0.);
+                    let (near, far) = if below_first {
+                        (below, above)
+                    } else {
+                        (above, below)
+                    };
+
+                    if direction == 0. {
+                        index = near;
+                        continue;
+                    }
+
+                    let t_split = (split - origin) * r.inv_direction().axis(axis);
+
+                    if t_split > t_far || t_split <= 0. {
+                        index = near;
+                    } else if t_split < t_near {
+                        index = far;
+                    } else {
+                        stack[top] = (far, t_split, t_far);
+                        top += 1;
+                        index = near;
+                        t_far = t_split;
+                    }
+                }
+                Node::Leaf { objects } => {
+                    if visit(objects, t_near, t_far) || top == 0 {
+                        return;
+                    }
+                    top -= 1;
+                    (index, t_near, t_far) = stack[top];
+                }
+            }
+        }
+    }
+}
+
+/// The cheapest plane to split a node with `node_bounds` holding `objects`
+/// at, by the surface area heuristic, as its axis, position and cost.
+fn best_split(
+    objects: &[usize],
+    object_bounds: &[AABB],
+    node_bounds: AABB,
+) -> Option<(usize, f64, f64)> {
+    let extent = node_bounds.max - node_bounds.min;
+    let area = 2. * (extent.x() * extent.y() + extent.y() * extent.z() + extent.z() * extent.x());
+    if area <= 0. {
+        return None;
+    }
+
+    let mut best: Option<(usize, f64, f64)> = None;
+    for axis in 0..3 {
+        // Where each object starts and ends along the axis, with starts
+        // before ends at the same position
+        let mut edges: Vec<(f64, bool)> = objects
+            .iter()
+            .flat_map(|&i| {
+                let b = object_bounds[i];
+                [(b.min.axis(axis), false), (b.max.axis(axis), true)]
+            })
+            .collect();
+        edges.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+        let (min, max) = (node_bounds.min.axis(axis), node_bounds.max.axis(axis));
+        let (d1, d2) = (extent.axis((axis + 1) % 3), extent.axis((axis + 2) % 3));
+        let mut below = 0;
+        let mut above = objects.len();
+        for (position, end) in edges {
+            if end {
+                above -= 1;
+            }
+            if position > min && position < max {
+                let area_below = 2. * (d1 * d2 + (position - min) * (d1 + d2));
+                let area_above = 2. * (d1 * d2 + (max - position) * (d1 + d2));
+                let bonus = if below == 0 || above == 0 {
+                    EMPTY_BONUS
+                } else {
+                    0.
+                };
+                let cost = TRAVERSAL_COST
+                    + INTERSECTION_COST
+                        * (1. - bonus)
+                        * (area_below * below as f64 + area_above * above as f64)
+                        / area;
+                if best.is_none_or(|(_, _, best_cost)| cost < best_cost) {
+                    best = Some((axis, position, cost));
+                }
+            }
+            if !end {
+                below += 1;
+            }
+        }
+    }
+
+    best
+}
+
+impl Hit for KdTree {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest: Option<HitRecord> = None;
+        self.walk(r, t_min, t_max, |objects, _, t_far| {
+            for &i in objects {
+                let t_max = closest.map_or(t_max, |hit| hit.t);
+                if let Some(hit) = self.objects[i].hit(r, t_min, t_max) {
+                    closest = Some(hit);
+                }
+            }
+            // Objects can straddle the split, so a hit found on the near
+            // side may still lie beyond it
+            closest.is_some_and(|hit| hit.t <= t_far)
+        });
+
+        closest
+    }
+
+    /// Any hit will do, so cells further along are only visited when the
+    /// nearer ones turn up nothing.
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        let mut occluded = false;
+        self.walk(r, t_min, t_max, |objects, _, _| {
+            occluded = objects
+                .iter()
+                .any(|&i| self.objects[i].occluded(r, t_min, t_max));
+            occluded
+        });
+
+        occluded
+    }
+
+    /// Objects straddling a split are only crossed within each cell, so
+    /// media referenced from both sides aren't counted twice.
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut transmittance = 1.;
+        self.walk(r, t_min, t_max, |objects, t_near, t_far| {
+            if t_near < t_far {
+                for &i in objects {
+                    transmittance *= self.objects[i].transmittance(r, t_near, t_far);
+                    if transmittance <= 0. {
+                        return true;
+                    }
+                }
+            }
+            false
+        });
+
+        transmittance
+    }
+
+    /// Objects can be in several cells, so each one the ray passes is only
+    /// asked once.
+    fn hits<'a>(&'a self, r: Ray, t_min: f64, t_max: f64, hits: &mut Vec<HitRecord<'a>>) {
+        let mut passed = vec![];
+        self.walk(r, t_min, t_max, |objects, _, _| {
+            passed.extend_from_slice(objects);
+            false
+        });
+        passed.sort_unstable();
+        passed.dedup();
+        for i in passed {
+            self.objects[i].hits(r, t_min, t_max, hits);
+        }
+    }
+
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
+        Some(self.bounds)
+    }
+
+    /// A kd-tree partitions space rather than objects, so it can't be refit
+    /// and is rebuilt from scratch instead.
+    fn refit(&mut self, time: (f64, f64)) -> Option<AABB> {
+        if time != self.time {
+            let objects = std::mem::take(&mut self.objects);
+            let object_bounds: Option<Vec<AABB>> = objects.iter().map(|o| o.bounds(time)).collect();
+            let object_bounds = object_bounds.expect("objects in a kd-tree must stay bounded");
+            *self = KdTree::new(objects, &object_bounds, time);
+        }
+
+        Some(self.bounds)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        let leaves: usize = self
+            .nodes
+            .iter()
+            .map(|node| match node {
+                Node::Leaf { objects } => objects.capacity() * std::mem::size_of::<usize>(),
+                Node::Interior { .. } => 0,
+            })
+            .sum();
+        stats.memory += std::mem::size_of::<Self>()
+            + self.nodes.capacity() * std::mem::size_of::<Node>()
+            + self.objects.capacity() * std::mem::size_of::<Box<dyn Hit>>()
+            + leaves;
+        for object in &self.objects {
+            object.count(stats);
+        }
+    }
+
+    fn accel_nodes(&self, depth: usize, _time: (f64, f64), nodes: &mut Vec<AccelNode>) {
+        // Each node's cell is its parent's, cut at the parent's split
+        let mut stack = vec![(0, self.bounds, depth)];
+        while let Some((index, bounds, depth)) = stack.pop() {
+            match &self.nodes[index] {
+                Node::Interior { axis, split, above } => {
+                    nodes.push(AccelNode {
+                        bounds,
+                        depth,
+                        leaf: false,
+                    });
+                    let below = AABB::new(bounds.min, bounds.max.with_axis(*axis, *split));
+                    let upper = AABB::new(bounds.min.with_axis(*axis, *split), bounds.max);
+                    stack.push((*above, upper, depth + 1));
+                    stack.push((index + 1, below, depth + 1));
+                }
+                Node::Leaf { .. } => nodes.push(AccelNode {
+                    bounds,
+                    depth,
+                    leaf: true,
+                }),
+            }
+        }
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        for object in &self.objects {
+            object.tessellate(time, mesh);
+        }
+    }
+}
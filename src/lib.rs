@@ -0,0 +1,55 @@
+//! A path tracer that grew out of "Ray Tracing in One Weekend".
+//!
+//! Scenes are built from objects implementing [`ray::Hit`] and rendered with
+//! a [`render::Renderer`] into a [`film::Film`], or straight into a pixel
+//! buffer with [`render::render_to_rgba8`] and [`render::render_to_f32`].
+
+pub mod accel;
+pub mod animation;
+pub mod background;
+pub mod bloom;
+pub mod bounds;
+pub mod cam;
+pub mod color;
+pub mod compare;
+pub mod depth_fog;
+pub mod export;
+pub mod exr;
+pub mod film;
+pub mod filter;
+pub mod glare;
+pub mod grid;
+pub mod guide;
+pub mod holdout;
+pub mod ies;
+pub mod image;
+pub mod instance;
+pub mod job;
+pub mod kdtree;
+pub mod light;
+pub mod lint;
+pub mod lod;
+pub mod lut;
+pub mod material_library;
+pub mod mesh;
+pub mod metaball;
+pub mod pdf;
+pub mod perlin;
+pub mod points;
+pub mod progress;
+pub mod ray;
+pub mod render;
+pub mod rng;
+pub mod sampling;
+pub mod scene;
+pub mod sun;
+pub mod texture;
+pub mod texture_cache;
+pub mod tile;
+pub mod timing;
+pub mod transform;
+pub mod vector;
+pub mod visibility;
+pub mod volume;
+pub mod world;
+pub mod worley;
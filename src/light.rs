@@ -0,0 +1,763 @@
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::bounds::AABB;
+use crate::color::{self, Color};
+use crate::ies::IesProfile;
+use crate::ray::{Hit, Ray};
+use crate::sampling::{uniform_cone, uniform_cone_pdf};
+use crate::texture::TexCoords;
+use crate::vector::{Onb, Point3, Vec3};
+use crate::world::{sphere_root, sphere_uv, Disk, Rect, Sphere};
+
+/// The light group of lights that weren't given one, and of the background.
+pub const DEFAULT_LIGHT_GROUP: usize = 0;
+
+/// Lumens per watt of light at 555nm, the green the eye is most sensitive
+/// to, which defines the lumen.
+const LUMINOUS_EFFICACY: f64 = 683.;
+
+/// The total light an emitter gives off, so a light keeps its brightness
+/// whatever its size and whatever scene it's put in.
+///
+/// Radiance from it is in candelas per square meter, as a camera's
+/// `Exposure` expects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightPower {
+    /// Radiant flux, in watts of the green light the eye is most sensitive
+    /// to, at 683 lumens each. Watts of light rather than of electricity,
+    /// so 1.2W is as bright as 800lm, or a 60W incandescent bulb.
+    Watts(f64),
+    /// Luminous flux, as bulbs are rated in.
+    Lumens(f64),
+}
+
+impl LightPower {
+    pub fn lumens(self) -> f64 {
+        match self {
+            LightPower::Watts(watts) => watts * LUMINOUS_EFFICACY,
+            LightPower::Lumens(lumens) => lumens,
+        }
+    }
+
+    /// Radiance of `color` from a diffuse emitter with surface `area`
+    /// giving off this much light in total, from the side it faces.
+    pub fn radiance(self, color: Color, area: f64) -> Color {
+        let luminance = color.luminance();
+        if luminance <= 0. || area <= 0. {
+            return color::BLACK;
+        }
+        // A Lambertian emitter gives off π times its radiance per unit area
+        color * (self.lumens() / (PI * area * luminance))
+    }
+}
+
+impl FromStr for LightPower {
+    type Err = String;
+
+    /// Parses a number with its unit, such as `1.2W` or `800lm`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid light power {:?}, expected e.g. 1.2W or 800lm", s);
+        let s = s.trim();
+        let (number, unit) = s.split_at(
+            s.find(|c: char| c.is_ascii_alphabetic())
+                .ok_or_else(invalid)?,
+        );
+        let number = number.trim().parse::<f64>().map_err(|_| invalid())?;
+        if number < 0. {
+            return Err(invalid());
+        }
+
+        match unit.to_ascii_lowercase().as_str() {
+            "w" => Ok(LightPower::Watts(number)),
+            "lm" => Ok(LightPower::Lumens(number)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for LightPower {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LightPower::Watts(watts) => write!(f, "{}W", watts),
+            LightPower::Lumens(lumens) => write!(f, "{}lm", lumens),
+        }
+    }
+}
+
+/// An emitter that can be sampled directly, rather than waiting for paths to
+/// hit it by chance.
+///
+/// The light's geometry, if it has any, must also be in the scene, so that
+/// it blocks other lights and is seen by camera rays and specular bounces.
+pub trait Light: Send + Sync {
+    fn bounds(&self) -> AABB;
+
+    /// Total emitted power, or an estimate of it. Only used to decide how
+    /// often to sample the light, so it needn't be exact.
+    fn power(&self) -> f64;
+
+    /// Picks a point on the light as seen from `p`, using the uniform random
+    /// numbers `u`.
+    fn sample(&self, p: Point3, u: (f64, f64)) -> Option<LightSample>;
+
+    /// Probability density, per unit solid angle, of `sample` picking a
+    /// point along `direction` from `p`; zero where it never would. Lets
+    /// directions found some other way, such as by a bounce, be weighted
+    /// against sampling the light.
+    fn pdf(&self, p: Point3, direction: Vec3) -> f64;
+
+    /// Whether the light is at a single point, so that nothing can find it
+    /// but sampling it.
+    fn is_delta(&self) -> bool {
+        false
+    }
+
+    /// Index of the light group the light's contribution is credited to.
+    fn group(&self) -> usize {
+        DEFAULT_LIGHT_GROUP
+    }
+}
+
+pub struct LightSample {
+    pub point: Point3,
+    /// Unit vector from the shaded point towards the light.
+    pub direction: Vec3,
+    pub distance: f64,
+    /// Probability density of the sample, per unit solid angle. Lights at
+    /// a single point give 1, with their intensity over the squared
+    /// distance as the radiance.
+    pub pdf: f64,
+    pub radiance: Color,
+}
+
+impl Light for Sphere {
+    fn bounds(&self) -> AABB {
+        let octant = Vec3::new(self.radius, self.radius, self.radius);
+        AABB::new(self.center - octant, self.center + octant)
+    }
+
+    fn power(&self) -> f64 {
+        // Textured emission is estimated from a single point
+        let area = self.area();
+        let p = self.center + Vec3::new(0., self.radius, 0.);
+        let radiance = self
+            .material
+            .emission(&TexCoords::point(sphere_uv(Vec3::new(0., 1., 0.), 1.).0, p));
+        PI * area * radiance.luminance()
+    }
+
+    fn sample(&self, p: Point3, u: (f64, f64)) -> Option<LightSample> {
+        // Within the cone of directions the sphere covers, seen from outside
+        if let Some(cone) = Cone::around(p, self.center, self.radius) {
+            let direction = cone.sample(u);
+            // The nearer crossing, which rounding can leave just off the
+            // sphere at its edge
+            let to_center = self.center - p;
+            let along = direction.dot_product(to_center);
+            let off_axis = to_center.length_squared() - along * along;
+            let distance = along - (self.radius * self.radius - off_axis).max(0.).sqrt();
+
+            let point = p + direction * distance;
+            let normal = (point - self.center) / self.radius;
+            let (uv, _) = sphere_uv(normal, self.radius);
+
+            return Some(LightSample {
+                point,
+                direction,
+                distance,
+                pdf: cone.pdf(),
+                radiance: self.material.emission(&TexCoords::point(uv, point)),
+            });
+        }
+
+        // Uniform over the surface; points facing away from `p` are hidden
+        // by the sphere itself and contribute nothing
+        let z = 1. - 2. * u.0;
+        let r = (1. - z * z).max(0.).sqrt();
+        let phi = 2. * PI * u.1;
+        let normal = Vec3::new(r * phi.cos(), r * phi.sin(), z);
+
+        let point = self.center + normal * self.radius;
+        let to_light = point - p;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+
+        let cos_light = -direction.dot_product(normal);
+        if cos_light <= 0. {
+            return None;
+        }
+
+        let (uv, _) = sphere_uv(normal, self.radius);
+
+        Some(LightSample {
+            point,
+            direction,
+            distance,
+            pdf: distance * distance / (cos_light * self.area()),
+            radiance: self.material.emission(&TexCoords::point(uv, point)),
+        })
+    }
+
+    fn pdf(&self, p: Point3, direction: Vec3) -> f64 {
+        let r = Ray::new(p, direction, 0.);
+        let Some(t) = sphere_root(self.center, self.radius, r, 0., f64::INFINITY) else {
+            return 0.;
+        };
+
+        if let Some(cone) = Cone::around(p, self.center, self.radius) {
+            return cone.pdf();
+        }
+
+        let point = r.at(t);
+        let normal = (point - self.center) / self.radius;
+        let distance = t * direction.length();
+        let cos_light = -direction.unit_vector().dot_product(normal);
+        if cos_light <= 0. {
+            return 0.;
+        }
+
+        distance * distance / (cos_light * self.area())
+    }
+
+    fn group(&self) -> usize {
+        self.material.light_group()
+    }
+}
+
+/// Gives off light from the side it faces.
+impl Light for Rect {
+    fn bounds(&self) -> AABB {
+        Hit::bounds(self, (0., 0.)).unwrap()
+    }
+
+    fn power(&self) -> f64 {
+        // Textured emission is estimated from the center
+        let p = self.corner + self.u / 2. + self.v / 2.;
+        let radiance = self.material.emission(&TexCoords::point((0.5, 0.5), p));
+        PI * self.area() * radiance.luminance()
+    }
+
+    fn sample(&self, p: Point3, u: (f64, f64)) -> Option<LightSample> {
+        // Uniform over the area
+        let point = self.corner + self.u * u.0 + self.v * u.1;
+        let to_light = point - p;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+
+        let cos_light = -direction.dot_product(self.normal());
+        if cos_light <= 0. {
+            return None;
+        }
+
+        Some(LightSample {
+            point,
+            direction,
+            distance,
+            pdf: distance * distance / (cos_light * self.area()),
+            radiance: self.material.emission(&TexCoords::point(u, point)),
+        })
+    }
+
+    fn pdf(&self, p: Point3, direction: Vec3) -> f64 {
+        let Some((t, _, _)) = self.intersect(Ray::new(p, direction, 0.), 0., f64::INFINITY) else {
+            return 0.;
+        };
+
+        let distance = t * direction.length();
+        let cos_light = -direction.unit_vector().dot_product(self.normal());
+        if cos_light <= 0. {
+            return 0.;
+        }
+
+        distance * distance / (cos_light * self.area())
+    }
+
+    fn group(&self) -> usize {
+        self.material.light_group()
+    }
+}
+
+/// Light from a single point, such as a bare bulb or, given a cone, a
+/// spotlight. Its brightness in each direction can follow a light fixture's
+/// measured IES profile.
+///
+/// Nothing in the scene stands for it, so it's only found by sampling it
+/// and not seen by camera rays or specular bounces.
+#[derive(Clone)]
+pub struct PointLight {
+    pub position: Point3,
+    /// Color and brightness in candelas, in the brightest direction.
+    pub intensity: Color,
+    /// Which way the light points: the middle of a spot's cone, and
+    /// straight down for a profile.
+    pub axis: Vec3,
+    /// Cosines of the angles from `axis` a spot is at full brightness
+    /// within and dark beyond.
+    pub cone: Option<(f64, f64)>,
+    pub profile: Option<Arc<IesProfile>>,
+    /// Index of the light group its light is credited to.
+    pub group: usize,
+}
+
+impl PointLight {
+    /// A light shining `intensity` equally in every direction.
+    pub fn new(position: Point3, intensity: Color) -> Self {
+        Self {
+            position,
+            intensity,
+            axis: Vec3::new(0., -1., 0.),
+            cone: None,
+            profile: None,
+            group: DEFAULT_LIGHT_GROUP,
+        }
+    }
+
+    /// The light as a spot pointing along `axis`, at full brightness within
+    /// `inner` degrees of it and fading out by `outer`.
+    pub fn spot(self, axis: Vec3, inner: f64, outer: f64) -> Self {
+        Self {
+            axis,
+            cone: Some((inner.to_radians().cos(), outer.to_radians().cos())),
+            ..self
+        }
+    }
+
+    /// The light shaped by the fixture `profile`, hung pointing down along
+    /// `axis`. `intensity` is in its brightest direction, so
+    /// `IesProfile::max_candela` gives the fixture's own brightness.
+    pub fn with_profile(self, profile: Arc<IesProfile>, axis: Vec3) -> Self {
+        Self {
+            axis,
+            profile: Some(profile),
+            ..self
+        }
+    }
+
+    /// The fraction of `intensity` shone along the unit vector `direction`.
+    fn falloff(&self, direction: Vec3) -> f64 {
+        let axis = self.axis.unit_vector();
+        let cos_axis = direction.dot_product(axis).clamp(-1., 1.);
+
+        let cone = match self.cone {
+            Some((inner, outer)) if inner > outer => {
+                // Smoothstep between the edges
+                let t = ((cos_axis - outer) / (inner - outer)).clamp(0., 1.);
+                t * t * (3. - 2. * t)
+            }
+            Some((_, outer)) if cos_axis < outer => 0.,
+            _ => 1.,
+        };
+
+        let shape = match &self.profile {
+            Some(profile) if profile.max_candela() > 0. => {
+                let basis = Onb::new(axis);
+                let vertical = cos_axis.acos().to_degrees();
+                let horizontal = direction
+                    .dot_product(basis.v)
+                    .atan2(direction.dot_product(basis.u))
+                    .to_degrees();
+                profile.candela(vertical, horizontal) / profile.max_candela()
+            }
+            Some(_) => 0.,
+            None => 1.,
+        };
+
+        cone * shape
+    }
+}
+
+impl Light for PointLight {
+    fn bounds(&self) -> AABB {
+        AABB::new(self.position, self.position)
+    }
+
+    fn power(&self) -> f64 {
+        // The solid angle lit, ignoring the profile and the cone's soft edge
+        let solid_angle = match self.cone {
+            Some((_, outer)) => 2. * PI * (1. - outer),
+            None => 4. * PI,
+        };
+        self.intensity.luminance() * solid_angle
+    }
+
+    fn sample(&self, p: Point3, _u: (f64, f64)) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        let direction = to_light / distance;
+
+        let falloff = self.falloff(-direction);
+        if falloff <= 0. {
+            return None;
+        }
+
+        Some(LightSample {
+            point: self.position,
+            direction,
+            distance,
+            pdf: 1.,
+            radiance: self.intensity * (falloff / (distance * distance)),
+        })
+    }
+
+    fn pdf(&self, _p: Point3, _direction: Vec3) -> f64 {
+        0.
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+
+    fn group(&self) -> usize {
+        self.group
+    }
+}
+
+/// Gives off light from the side it faces.
+impl Light for Disk {
+    fn bounds(&self) -> AABB {
+        Hit::bounds(self, (0., 0.)).unwrap()
+    }
+
+    fn power(&self) -> f64 {
+        // Textured emission is estimated from the center
+        let radiance = self
+            .material
+            .emission(&TexCoords::point((0.5, 0.5), self.center));
+        PI * self.area() * radiance.luminance()
+    }
+
+    fn sample(&self, p: Point3, u: (f64, f64)) -> Option<LightSample> {
+        // Within the cone around the sphere holding the disk, keeping the
+        // directions that reach it. Close up, where that cone is most of the
+        // sky, the area is sampled instead
+        let (direction, pdf) = match Cone::around(p, self.center, self.radius) {
+            Some(cone) => (cone.sample(u), cone.pdf()),
+            None => {
+                let r = self.radius * u.0.sqrt();
+                let phi = 2. * PI * u.1;
+                let basis = self.basis();
+                let point = self.center + basis.local(Vec3::new(r * phi.cos(), r * phi.sin(), 0.));
+                let to_light = point - p;
+                let distance = to_light.length();
+                let direction = to_light / distance;
+                let cos_light = -direction.dot_product(self.normal);
+                if cos_light <= 0. {
+                    return None;
+                }
+                (direction, distance * distance / (cos_light * self.area()))
+            }
+        };
+
+        let (distance, a, b) = self.intersect(Ray::new(p, direction, 0.), 0., f64::INFINITY)?;
+        if direction.dot_product(self.normal) >= 0. {
+            return None;
+        }
+
+        let point = p + direction * distance;
+        Some(LightSample {
+            point,
+            direction,
+            distance,
+            pdf,
+            radiance: self.material.emission(&TexCoords::point((a, b), point)),
+        })
+    }
+
+    fn pdf(&self, p: Point3, direction: Vec3) -> f64 {
+        let Some((t, _, _)) = self.intersect(Ray::new(p, direction, 0.), 0., f64::INFINITY) else {
+            return 0.;
+        };
+
+        let cos_light = -direction.unit_vector().dot_product(self.normal);
+        if cos_light <= 0. {
+            return 0.;
+        }
+
+        match Cone::around(p, self.center, self.radius) {
+            Some(cone) => cone.pdf(),
+            None => {
+                let distance = t * direction.length();
+                distance * distance / (cos_light * self.area())
+            }
+        }
+    }
+
+    fn group(&self) -> usize {
+        self.material.light_group()
+    }
+}
+
+/// The directions from a point that fall within a sphere around a light,
+/// which sampling in proportion to solid angle picks among evenly. For
+/// small or distant lights this wastes far fewer samples than picking
+/// points on their surface, which are mostly seen at a glancing angle or
+/// bunched into a tiny patch of sky.
+struct Cone {
+    basis: Onb,
+    /// One minus the cosine of the angle from the axis to the edge, kept
+    /// accurate for narrow cones.
+    one_minus_cos_max: f64,
+}
+
+impl Cone {
+    /// The cone from `p` around the sphere at `center`, or None from inside
+    /// it.
+    fn around(p: Point3, center: Point3, radius: f64) -> Option<Self> {
+        let to_center = center - p;
+        let distance_squared = to_center.length_squared();
+        let sin2_max = radius * radius / distance_squared;
+        if sin2_max >= 1. {
+            return None;
+        }
+
+        let cos_max = (1. - sin2_max).sqrt();
+        Some(Self {
+            basis: Onb::new(to_center),
+            one_minus_cos_max: sin2_max / (1. + cos_max),
+        })
+    }
+
+    /// A direction picked evenly from the cone with the uniform random
+    /// numbers `u`.
+    fn sample(&self, u: (f64, f64)) -> Vec3 {
+        self.basis.local(uniform_cone(self.one_minus_cos_max, u))
+    }
+
+    /// Probability density of `sample`, per unit solid angle.
+    fn pdf(&self) -> f64 {
+        uniform_cone_pdf(self.one_minus_cos_max)
+    }
+}
+
+/// A hierarchy over many lights for picking one that is likely to matter at a
+/// given point, in time proportional to the log of the number of lights.
+///
+/// At each level the child to descend into is chosen at random, in
+/// proportion to how bright its lights could appear from the point.
+pub struct LightTree {
+    lights: Vec<Arc<dyn Light>>,
+    nodes: Vec<Node>,
+}
+
+struct Node {
+    bounds: AABB,
+    power: f64,
+    kind: NodeKind,
+}
+
+enum NodeKind {
+    /// The first child follows this node; this is the index of the second.
+    Interior {
+        second: usize,
+    },
+    Leaf {
+        light: usize,
+    },
+}
+
+impl LightTree {
+    pub fn new(lights: Vec<Arc<dyn Light>>) -> Self {
+        let mut lights: Vec<_> = lights
+            .into_iter()
+            .filter(|light| light.power() > 0.)
+            .collect();
+
+        let mut tree = Self {
+            lights: vec![],
+            nodes: vec![],
+        };
+
+        if !lights.is_empty() {
+            tree.build_node(&mut lights, 0);
+        }
+
+        tree.lights = lights;
+        tree
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lights.len()
+    }
+
+    /// Builds the subtree over `lights`, which start at index `first` in the
+    /// tree's list.
+    fn build_node(&mut self, lights: &mut [Arc<dyn Light>], first: usize) {
+        let bounds = lights
+            .iter()
+            .map(|light| light.bounds())
+            .reduce(|a, b| a + b)
+            .unwrap();
+        let power = lights.iter().map(|light| light.power()).sum();
+
+        let index = self.nodes.len();
+
+        if lights.len() == 1 {
+            self.nodes.push(Node {
+                bounds,
+                power,
+                kind: NodeKind::Leaf { light: first },
+            });
+            return;
+        }
+
+        // Split at the median along the axis the lights are most spread out on
+        let centroids = lights
+            .iter()
+            .map(|light| light.bounds().centroid())
+            .fold(None, |acc: Option<AABB>, c| match acc {
+                Some(acc) => Some(acc.include(c)),
+                None => Some(AABB::new(c, c)),
+            })
+            .unwrap();
+        let extent = centroids.max - centroids.min;
+        let axis = (0..3)
+            .max_by(|&a, &b| extent.axis(a).total_cmp(&extent.axis(b)))
+            .unwrap();
+
+        let mid = lights.len() / 2;
+        lights.select_nth_unstable_by(mid, |a, b| {
+            let a = a.bounds().centroid().axis(axis);
+            let b = b.bounds().centroid().axis(axis);
+            a.total_cmp(&b)
+        });
+
+        self.nodes.push(Node {
+            bounds,
+            power,
+            kind: NodeKind::Interior { second: 0 },
+        });
+
+        let (below, above) = lights.split_at_mut(mid);
+        self.build_node(below, first);
+
+        let second = self.nodes.len();
+        self.nodes[index].kind = NodeKind::Interior { second };
+
+        self.build_node(above, first + mid);
+    }
+
+    /// Picks a light to sample from `p` using the uniform random number `u`,
+    /// along with the probability of having picked it.
+    pub fn pick(&self, p: Point3, u: f64) -> Option<(&dyn Light, f64)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut u = u;
+        let mut pmf = 1.;
+        let mut index = 0;
+
+        loop {
+            match self.nodes[index].kind {
+                NodeKind::Interior { second } => {
+                    let first_importance = self.nodes[index + 1].importance(p);
+                    let second_importance = self.nodes[second].importance(p);
+                    let total = first_importance + second_importance;
+                    if total <= 0. {
+                        return None;
+                    }
+
+                    // Reuse the random number by rescaling it into the
+                    // chosen child's share of the interval
+                    let p_first = first_importance / total;
+                    if u < p_first {
+                        u /= p_first;
+                        pmf *= p_first;
+                        index += 1;
+                    } else {
+                        u = (u - p_first) / (1. - p_first);
+                        pmf *= 1. - p_first;
+                        index = second;
+                    }
+                    u = u.min(1. - f64::EPSILON);
+                }
+                NodeKind::Leaf { light } => return Some((self.lights[light].as_ref(), pmf)),
+            }
+        }
+    }
+
+    /// Probability density, per unit solid angle, of picking a light from
+    /// `p` and then sampling a point on it along `direction`, summed over
+    /// every light along it.
+    pub fn pdf(&self, p: Point3, direction: Vec3) -> f64 {
+        if self.is_empty() {
+            return 0.;
+        }
+        self.node_pdf(0, p, Ray::new(p, direction, 0.))
+    }
+
+    /// Like `pdf`, for the lights under node `index`, given it was reached.
+    fn node_pdf(&self, index: usize, p: Point3, r: Ray) -> f64 {
+        // Lights the direction doesn't point at can't have been sampled
+        if self.nodes[index].bounds.hit(r, 0., f64::INFINITY).is_none() {
+            return 0.;
+        }
+
+        match self.nodes[index].kind {
+            NodeKind::Interior { second } => {
+                let first_importance = self.nodes[index + 1].importance(p);
+                let second_importance = self.nodes[second].importance(p);
+                let total = first_importance + second_importance;
+                if total <= 0. {
+                    return 0.;
+                }
+
+                let p_first = first_importance / total;
+                p_first * self.node_pdf(index + 1, p, r)
+                    + (1. - p_first) * self.node_pdf(second, p, r)
+            }
+            NodeKind::Leaf { light } => self.lights[light].pdf(p, r.direction),
+        }
+    }
+}
+
+impl Node {
+    /// How bright the node's lights could appear from `p`. Points inside the
+    /// bounds are treated as being at a distance of half the diagonal.
+    fn importance(&self, p: Point3) -> f64 {
+        let half_diagonal = (self.bounds.max - self.bounds.min).length() / 2.;
+        let distance_squared = (self.bounds.centroid() - p).length_squared();
+        self.power / distance_squared.max(half_diagonal * half_diagonal)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn power_parses_watts_and_lumens() {
+        assert_eq!("800lm".parse(), Ok(LightPower::Lumens(800.)));
+        assert_eq!(" 1.2 W".parse(), Ok(LightPower::Watts(1.2)));
+        for power in ["800", "lm", "-5W", "60kW"] {
+            assert!(
+                power.parse::<LightPower>().is_err(),
+                "{} was accepted",
+                power
+            );
+        }
+    }
+
+    #[test]
+    fn help_examples_are_equally_bright() {
+        let watts: LightPower = "1.2W".parse().unwrap();
+        let lumens: LightPower = "800lm".parse().unwrap();
+        assert!((watts.lumens() / lumens.lumens() - 1.).abs() < 0.05);
+    }
+
+    #[test]
+    fn power_spreads_over_the_area() {
+        let color = Color::new(1., 1., 1.);
+        let small = LightPower::Lumens(800.).radiance(color, 1.);
+        let large = LightPower::Lumens(800.).radiance(color, 4.);
+        assert!((small.luminance() / large.luminance() - 4.).abs() < 1e-9);
+        assert!((small.luminance() * PI - 800.).abs() < 1e-9);
+    }
+}
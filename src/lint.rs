@@ -0,0 +1,273 @@
+use std::fmt;
+use std::path::Path;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::color::Color;
+use crate::image::Image;
+use crate::material_library::MaterialLibrary;
+use crate::ray::{Material, Ray};
+use crate::scene::Scene;
+use crate::texture::Texture;
+use crate::vector::{Point3, Vec3};
+
+/// Something wrong with a scene that would stop it rendering, or make it
+/// render other than was meant.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    /// What the problem is with, as the dotted path it's changed by with
+    /// `--set` where there is one, e.g. `materials.mirror.fuzz`.
+    pub subject: String,
+    pub message: String,
+}
+
+impl Problem {
+    fn new(subject: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            subject: subject.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.subject, self.message)
+    }
+}
+
+/// Directions to look for the camera being inside something in: along the
+/// axes and the diagonals between them.
+const PROBE_DIRECTIONS: [(f64, f64, f64); 14] = [
+    (1., 0., 0.),
+    (-1., 0., 0.),
+    (0., 1., 0.),
+    (0., -1., 0.),
+    (0., 0., 1.),
+    (0., 0., -1.),
+    (1., 1., 1.),
+    (1., 1., -1.),
+    (1., -1., 1.),
+    (1., -1., -1.),
+    (-1., 1., 1.),
+    (-1., 1., -1.),
+    (-1., -1., 1.),
+    (-1., -1., -1.),
+];
+
+/// Problems with each material in `library`, by name.
+pub fn materials(library: &MaterialLibrary) -> Vec<Problem> {
+    library
+        .names()
+        .flat_map(|name| {
+            let material = library.get(name).expect("named material");
+            material_problems(material)
+                .into_iter()
+                .map(move |(property, message)| {
+                    Problem::new(format!("materials.{}.{}", name, property), message)
+                })
+        })
+        .collect()
+}
+
+/// Problems with a material's properties, by property.
+fn material_problems(material: &Material) -> Vec<(&'static str, String)> {
+    let mut problems = vec![];
+    let mut fraction = |property: &'static str, value: f64| {
+        if !(0. ..=1.).contains(&value) {
+            problems.push((property, format!("{} must be from 0 to 1", value)));
+        }
+    };
+
+    match material {
+        Material::Dialectric {
+            index_of_refraction,
+            medium,
+            reflection_roughness,
+            transmission_roughness,
+            ..
+        } => {
+            fraction("reflection_roughness", *reflection_roughness);
+            fraction("transmission_roughness", *transmission_roughness);
+            if index_of_refraction.is_nan() || *index_of_refraction <= 0. {
+                problems.push((
+                    "ior",
+                    format!(
+                        "{} must be above 0, e.g. 1.5 for glass or 1.33 for water",
+                        index_of_refraction
+                    ),
+                ));
+            } else if !index_of_refraction.is_finite() {
+                problems.push(("ior", format!("{} must be finite", index_of_refraction)));
+            }
+            if let Some(medium) = medium {
+                if !non_negative(medium.absorption) {
+                    problems.push((
+                        "absorption",
+                        format!("{} can't be negative", rgb(medium.absorption)),
+                    ));
+                }
+                if medium.scattering.is_nan() || medium.scattering < 0. {
+                    problems.push((
+                        "scattering",
+                        format!("{} can't be negative", medium.scattering),
+                    ));
+                }
+            }
+        }
+        Material::DiffuseLight { emit, .. } => {
+            if let Texture::Solid(c) = emit {
+                if !non_negative(*c) {
+                    problems.push(("emit", format!("{} can't be negative", rgb(*c))));
+                }
+            }
+            texture_problems("emit", emit, &mut problems);
+        }
+        Material::Isotropic { albedo }
+        | Material::Lambertian { albedo }
+        | Material::ShadowCatcher { albedo } => {
+            if let Texture::Solid(c) = albedo {
+                albedo_problems(*c, &mut problems);
+            }
+            texture_problems("albedo", albedo, &mut problems);
+        }
+        Material::Metal { albedo, fuzz } => {
+            albedo_problems(*albedo, &mut problems);
+            if !(0. ..=1.).contains(fuzz) {
+                problems.push(("fuzz", format!("{} must be from 0 for a mirror to 1", fuzz)));
+            }
+        }
+    }
+
+    problems
+}
+
+/// A color as it's given on the command line.
+fn rgb(c: Color) -> String {
+    format!("{},{},{}", c.r(), c.g(), c.b())
+}
+
+/// A point as it's given on the command line.
+fn xyz(p: Point3) -> String {
+    format!("{},{},{}", p.x(), p.y(), p.z())
+}
+
+fn non_negative(c: Color) -> bool {
+    [c.r(), c.g(), c.b()].iter().all(|&v| v >= 0.)
+}
+
+/// An albedo outside 0 to 1 reflects light that was never there, or takes
+/// away light that was.
+fn albedo_problems(c: Color, problems: &mut Vec<(&'static str, String)>) {
+    if ![c.r(), c.g(), c.b()].iter().all(|v| (0. ..=1.).contains(v)) {
+        problems.push((
+            "albedo",
+            format!("{} must have every channel from 0 to 1", rgb(c)),
+        ));
+    }
+}
+
+/// Image files a texture refers to that can't be loaded. Those would
+/// otherwise only show up as magenta in the render.
+fn texture_problems(
+    property: &'static str,
+    texture: &Texture,
+    problems: &mut Vec<(&'static str, String)>,
+) {
+    match texture {
+        Texture::CachedImage { path, .. } => {
+            if let Err(err) = Image::load(path) {
+                problems.push((property, missing_image(path, err)));
+            }
+        }
+        Texture::Checker { even, odd, .. } => {
+            texture_problems(property, even, problems);
+            texture_problems(property, odd, problems);
+        }
+        Texture::Transformed { texture, .. } => texture_problems(property, texture, problems),
+        _ => {}
+    }
+}
+
+fn missing_image(path: &Path, err: impl fmt::Display) -> String {
+    if path.exists() {
+        format!("texture {} can't be loaded: {}", path.display(), err)
+    } else {
+        format!(
+            "texture {} doesn't exist; paths are relative to the working directory",
+            path.display()
+        )
+    }
+}
+
+/// Problems with a built scene: objects too large or misplaced to bound,
+/// a camera that can't see anything, and emitters with no lights sampled.
+pub fn scene(scene: &Scene) -> Vec<Problem> {
+    let mut problems = vec![];
+    let time = scene.camera.time();
+
+    if let Some(bounds) = scene.world.bounds(time) {
+        let corners = [bounds.min, bounds.max];
+        let finite = corners
+            .iter()
+            .all(|p| p.x().is_finite() && p.y().is_finite() && p.z().is_finite());
+        if !finite {
+            problems.push(Problem::new(
+                "scene",
+                format!(
+                    "objects reach from {} to {}; every object needs a finite position and size",
+                    xyz(bounds.min),
+                    xyz(bounds.max)
+                ),
+            ));
+        }
+    }
+
+    let mut rng = StdRng::seed_from_u64(0);
+    let center = scene.camera.get_ray(&mut rng, 0.5, 0.5);
+    let direction = center.direction;
+    let degenerate =
+        !(direction.x().is_finite() && direction.y().is_finite() && direction.z().is_finite())
+            || direction.length_squared() == 0.;
+    if degenerate {
+        problems.push(Problem::new(
+            "camera",
+            "the camera has no direction to look in; make sure look_from and look_at differ \
+             and fov is between 0 and 180",
+        ));
+    }
+
+    // A camera inside something closed sees only its inside, from behind
+    let origin = scene.camera.origin();
+    let epsilon = scene.epsilon();
+    let inside = PROBE_DIRECTIONS.iter().all(|&(x, y, z)| {
+        let r = Ray::new(origin, Vec3::new(x, y, z), time.0);
+        matches!(scene.world.hit(r, epsilon, f64::INFINITY), Some(hit) if !hit.front_face)
+    });
+    if inside {
+        problems.push(Problem::new(
+            "camera.look_from",
+            format!(
+                "the camera at {} is inside an object, so it can't see out; move it",
+                xyz(origin)
+            ),
+        ));
+    }
+
+    // Emitters are only sampled directly when they're in the light tree;
+    // otherwise only the paths that happen to hit them carry their light
+    let emitters = scene.stats().emitters;
+    if emitters > 0 && scene.lights.is_empty() {
+        problems.push(Problem::new(
+            "lights",
+            format!(
+                "{} object(s) give off light but none are sampled as lights, so the \
+                 light they cast will be noisy",
+                emitters
+            ),
+        ));
+    }
+
+    problems
+}
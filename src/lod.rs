@@ -0,0 +1,123 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::cam::Camera;
+use crate::instance::Instance;
+use crate::ray::Hit;
+use crate::transform::Transform;
+
+/// What decides which level of detail an instance gets.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LodMetric {
+    /// Distance from the camera to the center of the instance.
+    #[default]
+    Distance,
+    /// The fraction of the image height the instance's bounds cover, which
+    /// also accounts for the field of view and the size of the object.
+    ScreenSize,
+}
+
+impl FromStr for LodMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "distance" => Ok(LodMetric::Distance),
+            "screen-size" => Ok(LodMetric::ScreenSize),
+            _ => Err(format!("unknown LOD metric {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for LodMetric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            LodMetric::Distance => "distance",
+            LodMetric::ScreenSize => "screen-size",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Versions of the same object at decreasing levels of detail, such as a
+/// `Mesh` and simplified copies of it, from which each instance is given the
+/// simplest one that will still look right from the camera.
+///
+/// Levels are picked once, when an instance is made, so pick again for each
+/// frame of a moving camera.
+pub struct LodSet {
+    levels: Vec<Arc<dyn Hit>>,
+    metric: LodMetric,
+    thresholds: Vec<f64>,
+}
+
+impl LodSet {
+    /// A set with just the full detail object; add the simpler ones with
+    /// `with_level`.
+    pub fn new(object: Arc<dyn Hit>, metric: LodMetric) -> Self {
+        Self {
+            levels: vec![object],
+            metric,
+            thresholds: vec![],
+        }
+    }
+
+    /// Adds a simpler level, to use from `threshold` onwards: at distances
+    /// of at least `threshold` with `LodMetric::Distance`, or at screen sizes
+    /// of at most `threshold` with `LodMetric::ScreenSize`.
+    ///
+    /// # Panics
+    ///
+    /// If the threshold doesn't come after the previous level's, as the
+    /// levels must go from most to least detailed.
+    pub fn with_level(mut self, object: Arc<dyn Hit>, threshold: f64) -> Self {
+        if let Some(&previous) = self.thresholds.last() {
+            let ordered = match self.metric {
+                LodMetric::Distance => threshold > previous,
+                LodMetric::ScreenSize => threshold < previous,
+            };
+            assert!(ordered, "LOD levels must get less detailed");
+        }
+
+        self.levels.push(object);
+        self.thresholds.push(threshold);
+        self
+    }
+
+    pub fn metric(&self) -> LodMetric {
+        self.metric
+    }
+
+    pub fn levels(&self) -> &[Arc<dyn Hit>] {
+        &self.levels
+    }
+
+    /// The index of the level to use for the full detail object placed by
+    /// `transform`, as seen by `camera`.
+    pub fn select(&self, camera: &Camera, transform: Transform) -> usize {
+        let Some(bounds) = self.levels[0].bounds(camera.time()) else {
+            return 0;
+        };
+        let bounds = transform.bounds(bounds);
+        let center = bounds.centroid();
+
+        match self.metric {
+            LodMetric::Distance => {
+                let distance = (center - camera.origin()).length();
+                self.thresholds.partition_point(|&t| t <= distance)
+            }
+            LodMetric::ScreenSize => {
+                let radius = (bounds.max - bounds.min).length() / 2.;
+                let size = camera.screen_size(center, radius);
+                self.thresholds.partition_point(|&t| t >= size)
+            }
+        }
+    }
+
+    /// An instance of the level `select` picks.
+    pub fn instance(&self, camera: &Camera, transform: Transform) -> Instance {
+        let level = self.select(camera, transform);
+        Instance::new(self.levels[level].clone(), transform)
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::color::Color;
+
+/// A color lookup table from an Adobe/Resolve `.cube` file, for matching a
+/// production's color pipeline or applying a creative grade.
+///
+/// Both 1D tables, which map each channel separately, and 3D tables are
+/// supported. Like most grading LUTs, the table is taken to map sRGB-encoded
+/// colors to sRGB-encoded colors.
+pub struct Lut {
+    size: usize,
+    table: Table,
+    domain_min: Color,
+    domain_max: Color,
+}
+
+enum Table {
+    /// Output colors for evenly spaced input values of every channel.
+    OneD(Vec<Color>),
+    /// Output colors on an evenly spaced grid, with red changing fastest,
+    /// then green, then blue.
+    ThreeD(Vec<Color>),
+}
+
+impl Lut {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, LutError> {
+        let data = fs::read(path)?;
+        Self::parse(&String::from_utf8_lossy(&data))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, LutError> {
+        let invalid = |line: &str| LutError::Invalid(format!("{:?}", line));
+
+        let mut size_1d = None;
+        let mut size_3d = None;
+        let mut domain_min = Color::zero();
+        let mut domain_max = Color::new(1., 1., 1.);
+        let mut values = vec![];
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some(keyword) = line.split_whitespace().next() else {
+                continue;
+            };
+            let numbers = || {
+                line.split_whitespace()
+                    .skip(1)
+                    .map(|word| word.parse::<f64>().map_err(|_| invalid(line)))
+                    .collect::<Result<Vec<_>, _>>()
+            };
+            let triple = || match numbers()?[..] {
+                [r, g, b] => Ok(Color::new(r, g, b)),
+                _ => Err(invalid(line)),
+            };
+            let size = || match numbers()?[..] {
+                [n] if n >= 2. && n.fract() == 0. => Ok(n as usize),
+                _ => Err(invalid(line)),
+            };
+
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => size_1d = Some(size()?),
+                "LUT_3D_SIZE" => size_3d = Some(size()?),
+                "DOMAIN_MIN" => domain_min = triple()?,
+                "DOMAIN_MAX" => domain_max = triple()?,
+                // Resolve's way of giving the same domain for every channel
+                "LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE" => match numbers()?[..] {
+                    [min, max] => {
+                        domain_min = Color::new(min, min, min);
+                        domain_max = Color::new(max, max, max);
+                    }
+                    _ => return Err(invalid(line)),
+                },
+                // Anything else is a row of the table
+                _ => {
+                    let row = line
+                        .split_whitespace()
+                        .map(|word| word.parse::<f64>().map_err(|_| invalid(line)))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    match row[..] {
+                        [r, g, b] => values.push(Color::new(r, g, b)),
+                        _ => return Err(invalid(line)),
+                    }
+                }
+            }
+        }
+
+        let (size, table) = match (size_1d, size_3d) {
+            (Some(size), None) => (size, Table::OneD(values)),
+            (None, Some(size)) => (size, Table::ThreeD(values)),
+            (None, None) => return Err(LutError::Invalid("no table size".to_string())),
+            (Some(_), Some(_)) => {
+                return Err(LutError::Invalid("both 1D and 3D sizes".to_string()))
+            }
+        };
+        let expected = match &table {
+            Table::OneD(_) => size,
+            Table::ThreeD(_) => size * size * size,
+        };
+        let (Table::OneD(values) | Table::ThreeD(values)) = &table;
+        if values.len() < expected {
+            return Err(LutError::Truncated);
+        }
+        if values.len() > expected {
+            return Err(LutError::Invalid(format!(
+                "{} entries for a table of {}",
+                values.len(),
+                expected
+            )));
+        }
+
+        let ordered = |min: f64, max: f64| min < max;
+        if !(ordered(domain_min.r(), domain_max.r())
+            && ordered(domain_min.g(), domain_max.g())
+            && ordered(domain_min.b(), domain_max.b()))
+        {
+            return Err(LutError::Invalid("empty domain".to_string()));
+        }
+
+        Ok(Self {
+            size,
+            table,
+            domain_min,
+            domain_max,
+        })
+    }
+
+    /// The linear color `c` graded by the table, as a linear color.
+    /// Colors outside the table's domain are clamped to it.
+    pub fn apply(&self, c: Color) -> Color {
+        let c = c.to_srgb();
+
+        // Positions in the table, from 0 to size - 1
+        let last = (self.size - 1) as f64;
+        let position =
+            |value: f64, min: f64, max: f64| ((value - min) / (max - min)).clamp(0., 1.) * last;
+        let r = position(c.r(), self.domain_min.r(), self.domain_max.r());
+        let g = position(c.g(), self.domain_min.g(), self.domain_max.g());
+        let b = position(c.b(), self.domain_min.b(), self.domain_max.b());
+
+        let graded = match &self.table {
+            Table::OneD(values) => {
+                let channel = |p: f64, f: fn(Color) -> f64| {
+                    let (i, t) = split(p, self.size);
+                    f(values[i]) * (1. - t) + f(values[i + 1]) * t
+                };
+                Color::new(
+                    channel(r, Color::r),
+                    channel(g, Color::g),
+                    channel(b, Color::b),
+                )
+            }
+            Table::ThreeD(values) => {
+                let n = self.size;
+                let at = |i: usize, j: usize, k: usize| values[(k * n + j) * n + i];
+                let (i, ti) = split(r, n);
+                let (j, tj) = split(g, n);
+                let (k, tk) = split(b, n);
+
+                // Trilinear interpolation between the eight entries around
+                let along_r = |j: usize, k: usize| at(i, j, k).lerp(at(i + 1, j, k), ti);
+                let along_g = |k: usize| along_r(j, k).lerp(along_r(j + 1, k), tj);
+                along_g(k).lerp(along_g(k + 1), tk)
+            }
+        };
+
+        graded.from_srgb()
+    }
+}
+
+/// The index of the table entry at or before `position` and how far it is
+/// towards the next, leaving room for the next in a table of `size`.
+fn split(position: f64, size: usize) -> (usize, f64) {
+    let i = (position.floor() as usize).min(size - 2);
+    (i, position - i as f64)
+}
+
+#[derive(Debug)]
+pub enum LutError {
+    Io(io::Error),
+    Invalid(String),
+    Truncated,
+}
+
+impl fmt::Display for LutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LutError::Io(err) => write!(f, "{}", err),
+            LutError::Invalid(message) => write!(f, "invalid LUT: {}", message),
+            LutError::Truncated => write!(f, "LUT file ends before its table does"),
+        }
+    }
+}
+
+impl std::error::Error for LutError {}
+
+impl From<io::Error> for LutError {
+    fn from(err: io::Error) -> Self {
+        LutError::Io(err)
+    }
+}
@@ -1,145 +1,1087 @@
-pub mod bounds;
-pub mod cam;
-pub mod color;
-pub mod ray;
-pub mod vector;
-pub mod world;
-
-use crate::cam::Camera;
-use crate::color::{BLACK, WHITE};
-use crate::ray::{Hit, Material, Ray, ScatterResult};
-use crate::vector::{Color, Point3, Vec3};
-use crate::world::{Sphere, World};
+mod cli;
+mod report;
+
+use raytracing::accel;
+use raytracing::background::{Atmosphere, Background, Sky, VerticalGradient};
+use raytracing::cam::{Camera, Exposure, Lens, Projection, ShutterCurve};
+use raytracing::color::{Color, BLACK};
+use raytracing::compare::Comparison;
+use raytracing::export::{self, Tessellation};
+use raytracing::film::{Film, ImageFormat, ImageStats};
+use raytracing::glare::Glare;
+use raytracing::guide::Guide;
+use raytracing::ies::IesProfile;
+use raytracing::image::Image;
+use raytracing::light::{Light, LightTree, PointLight};
+use raytracing::lint;
+use raytracing::lut::Lut;
+use raytracing::material_library::MaterialLibrary;
+use raytracing::ray::{Hit, Material};
+use raytracing::render::{Budget, Renderer};
+use raytracing::scene::Scene;
+use raytracing::texture::ColorRamp;
+use raytracing::timing::Timings;
+use raytracing::vector::{Point3, Vec3};
+use raytracing::volume::Medium;
+use raytracing::world::{Disk, MovingSphere, Sphere};
+
+use crate::cli::{Args, CliError, LightSpec};
+use crate::report::Report;
 
 use rand::prelude::*;
-use rayon::prelude::*;
-use std::io::{stderr, Write};
-use world::MovingSphere;
+use rand::rngs::StdRng;
+use std::fs::{self, File};
+use std::io::{stdout, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Seed for placing the spheres, so every render is of the same scene and
+/// renders with different `--seed`s can be merged.
+const SCENE_SEED: u64 = 0x5eed;
 
 fn main() {
-    let mut rng = thread_rng();
+    let mut argv = std::env::args().skip(1).peekable();
+    if argv.next_if(|arg| arg == "merge").is_some() {
+        merge(argv);
+        return;
+    }
+    if argv.next_if(|arg| arg == "compare").is_some() {
+        compare(argv);
+        return;
+    }
+    if argv.next_if(|arg| arg == "convert").is_some() {
+        convert(argv);
+        return;
+    }
+
+    // Checking a scene or summing it up takes the same options as rendering
+    // it
+    let task = if argv.next_if(|arg| arg == "check").is_some() {
+        Task::Check
+    } else if argv.next_if(|arg| arg == "stats").is_some() {
+        Task::Stats
+    } else {
+        Task::Render
+    };
+
+    let args = match cli::parse_args(argv) {
+        Ok(args) => args,
+        Err(CliError::Help) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // A first Ctrl-C stops the render and writes what we have so far, a
+    // second one exits immediately.
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        eprintln!("Interrupted, writing partial image...");
+    })
+    .expect("failed to install Ctrl-C handler");
+
+    // Everything parallel, from building the BVH to rendering tiles, runs
+    // on the global pool
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .expect("failed to start worker threads");
+    }
+
+    if let (true, Some(path)) = (args.watch, &args.material_library) {
+        if task != Task::Render {
+            eprintln!("error: --watch only works when rendering, not with check or stats");
+            std::process::exit(2);
+        }
+        watch(&args, path);
+    }
+
+    let result = load_material_library(&args)
+        .and_then(|(library, light_groups)| run(&args, &library, light_groups, task));
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        std::process::exit(2);
+    }
+}
+
+/// What to do with the scene the options describe.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Task {
+    Render,
+    Check,
+    Stats,
+}
+
+/// Builds the scene `args` describe, with the materials in `library`
+/// replaced before those given on the command line, and carries out `task`
+/// with it. `light_groups` names the groups the lights are in. Errors are
+/// in the options or the library, for `watch` to wait on being fixed.
+fn run(
+    args: &Args,
+    library: &[(String, Material)],
+    light_groups: Vec<String>,
+    task: Task,
+) -> Result<(), String> {
+    let mut rng = StdRng::seed_from_u64(SCENE_SEED);
 
     // Image
 
-    let aspect_ratio = 16. / 9.;
-    let image_width = 400;
-    let image_height = (image_width as f64 / aspect_ratio) as i32;
-    let samples_per_pixel = 100;
-    let max_depth = 50;
+    let aspect_ratio = args.resolution.aspect_ratio();
+    let image_width = args.resolution.width as usize;
+    let image_height = args.resolution.height as usize;
 
     // World
 
-    let world = random_scene(&mut rng);
+    // Loaded up front so a bad file is reported before rendering
+    let lut = args.lut.as_deref().map(load_lut);
+
+    let mut timings = Timings::new();
+    let scene_start = Instant::now();
+
+    let time = frame_time(0, args.frames);
+    let mut materials = default_materials();
+    for (name, material) in library.iter().chain(&args.materials) {
+        if !materials.contains(name) {
+            let names: Vec<_> = materials.names().collect();
+            return Err(format!(
+                "unknown material {:?}, expected one of: {}",
+                name,
+                names.join(", ")
+            ));
+        }
+        materials.insert(name, material.clone());
+    }
+    let mut view = CameraSettings::default();
+
+    for (path, value) in &args.settings {
+        if let Err(err) = apply_setting(path, value, &mut view, &mut materials) {
+            return Err(format!("--set {}={}: {}", path, value, err));
+        }
+    }
+    // Image textures otherwise load during the render, on whichever
+    // thread first needs them
+    timings.time("textures", || preload_textures(&materials));
+    for (path, err) in args.textures.failures() {
+        eprintln!(
+            "warning: failed to load texture {}: {}",
+            path.display(),
+            err
+        );
+    }
+
+    let (near, far) = view.clip;
+    if near >= far {
+        return Err(format!(
+            "--set camera.near={} must be less than camera.far={}",
+            near, far
+        ));
+    }
+
+    let mut contents = random_scene(&mut rng, &materials, args.shadow_catcher);
+    add_lights(&mut contents, &args.lights);
+    contents.light_groups = light_groups;
+    let material_override = args.material_override.map(|kind| kind.material());
 
     // Camera
 
-    let look_from = Point3::new(13., 2., 3.);
-    let look_at = Point3::zero();
     let view_up = Vec3::new(0., 1., 0.);
-    let vertical_fov = 20.;
-    let distance_to_focus = 10.;
-    let aperture = 0.1;
-    let time = (0., 1.);
-
-    let camera = Camera::new(
-        look_from,
-        look_at,
+
+    // A physical camera's depth of field follows from its f-stop
+    let aperture = match view.exposure {
+        Some(exposure) => exposure.aperture(view.vertical_fov),
+        None => view.aperture,
+    };
+    let mut camera = Camera::new(
+        view.look_from,
+        view.look_at,
         view_up,
-        vertical_fov,
+        view.vertical_fov,
         aspect_ratio,
         aperture,
-        distance_to_focus,
+        view.focus_distance,
         time,
-    );
+    )
+    .with_shutter_curve(view.shutter_curve)
+    .with_projection(view.projection)
+    .with_lens(view.lens)
+    .with_clip(near, far);
+    if let Some(readout) = view.rolling_shutter {
+        camera = camera.with_rolling_shutter(readout);
+    }
+    if let Some(exposure) = view.exposure {
+        camera = camera.with_exposure(exposure);
+    }
+    if let Some(glare) = view.glare {
+        camera = camera.with_glare(glare);
+    }
+    timings.add("scene", scene_start.elapsed() - timings.get("textures"));
+
+    let (world, accel_stats) = timings.time("bvh", || {
+        args.accelerator.build_with_stats(contents.objects, time)
+    });
+    if let Some(path) = &args.accel_obj {
+        let nodes = accel::accel_nodes(world.as_ref(), time);
+        let result = File::create(path)
+            .and_then(|f| accel::write_obj(&nodes, args.accel_obj_nodes, &mut BufWriter::new(f)));
+        if let Err(err) = result {
+            eprintln!("error: failed to write {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+    let background: Box<dyn Background + Send + Sync> = match (&args.sun, args.atmosphere) {
+        (_, Some(altitude)) => Box::new(Atmosphere {
+            altitude,
+            ..args
+                .sun
+                .as_ref()
+                .map_or_else(Atmosphere::default, Atmosphere::at)
+        }),
+        (Some(time), None) => Box::new(Sky::at(time)),
+        (None, None) => Box::new(VerticalGradient::default()),
+    };
+    let mut scene = Scene {
+        world,
+        lights: LightTree::new(contents.lights),
+        light_groups: contents.light_groups,
+        camera,
+        background,
+        fog: args.fog.map(|scattering| Medium::new(BLACK, scattering)),
+    };
+
+    if let Some(path) = &args.export_obj {
+        export_obj(&scene, path);
+    }
+
+    match task {
+        Task::Check => {
+            check(&scene, &materials);
+            return Ok(());
+        }
+        Task::Stats => {
+            println!("Scene statistics:\n{}", scene.stats());
+            return Ok(());
+        }
+        Task::Render => {}
+    }
 
     // Render
 
-    println!("P3\n{} {}\n255", image_width, image_height);
+    let budget = Budget {
+        samples_per_pixel: args.samples_per_pixel,
+        time_limit: args.time_limit,
+        noise_threshold: args.noise_threshold,
+    };
+
+    let rays = AtomicU64::new(0);
+    let mut outputs = vec![];
+    let report = |outputs: &[PathBuf],
+                  timings: &Timings,
+                  samples_per_pixel,
+                  noise,
+                  stats: Option<&ImageStats>,
+                  completed| {
+        let Some(path) = &args.report else {
+            return;
+        };
+        let report = Report {
+            width: image_width,
+            height: image_height,
+            frames: args.frames,
+            samples_per_pixel,
+            completed,
+            noise,
+            timings,
+            rays: rays.load(Ordering::Relaxed),
+            accelerator: args.accelerator,
+            accel_stats,
+            outputs,
+            stats,
+        };
+        if let Err(err) = std::fs::write(path, report.to_json()) {
+            eprintln!("error: failed to write report: {}", err);
+        }
+    };
+
+    // The last frame rendered, when its samples are reused in the next
+    let mut previous: Option<Film> = None;
 
-    for j in (0..image_height).rev() {
-        eprintln!("Scanlines remaining: {} ", j);
-        stderr().flush().expect("failed to flush stderr");
+    for frame in 0..args.frames {
+        if frame > 0 {
+            // Only the objects' positions change between frames, so keeping
+            // the existing hierarchy is much cheaper than building a new one
+            let time = frame_time(frame, args.frames);
+            timings.time("bvh", || scene.world.refit(time));
+            scene.camera.set_time(time);
+        }
 
-        for i in 0..image_width {
-            let j = j as f64;
-            let i = i as f64;
+        // What the guide learned is specific to this frame's geometry
+        let guide = match scene.world.bounds(frame_time(frame, args.frames)) {
+            Some(bounds) if args.guiding => Some(Guide::new(bounds)),
+            _ => None,
+        };
 
-            let pixel_color: Color = (0..samples_per_pixel)
-                .into_par_iter()
-                .map(|_| {
-                    let mut rng = thread_rng();
+        let renderer = Renderer {
+            guide: guide.as_ref(),
+            material_override: material_override.as_ref(),
+            max_depth: args.max_depth,
+            bounce_limits: args.bounce_limits,
+            indirect_clamp: args.indirect_clamp,
+            // Reused samples only help if they're different ones
+            seed: match args.temporal_reuse {
+                Some(_) => args.seed.wrapping_add(frame as u64),
+                None => args.seed,
+            },
+            sampler: args.sampler,
+            light_samples: args.light_samples,
+            epsilon: args.epsilon.unwrap_or_else(|| scene.epsilon()),
+            transparent_background: args.transparent,
+            transparent_shadows: args.transparent_shadows,
+            fog_sampling: args.fog_sampling,
+            cancel: Some(&INTERRUPTED),
+            rays: Some(&rays),
+            verbose: true,
+            ..Renderer::new(&scene)
+        };
 
-                    let u = (i + rng.gen::<f64>()) / (image_width - 1) as f64;
-                    let v = (j + rng.gen::<f64>()) / (image_height - 1) as f64;
+        if args.frames > 1 {
+            eprintln!("Frame {}/{}", frame + 1, args.frames);
+        }
 
-                    let r = camera.get_ray(&mut rng, u, v);
+        let mut film = Film::with_filter(image_width, image_height, args.filter);
+        if args.format == ImageFormat::Exr {
+            film = film
+                .with_aovs()
+                .with_light_groups(scene.light_groups.clone());
+        } else if args.temporal_reuse.is_some() || args.depth_fog.is_some() {
+            // Reuse needs the AOVs to tell where the same surfaces are, and
+            // depth fog how far away they are
+            film = film.with_aovs();
+        }
+        if args.transparent {
+            film = film.with_alpha();
+        }
+        if let Some(white_balance) = args.white_balance {
+            film = film.with_white_balance(white_balance);
+        }
+        if let Some(lut) = &lut {
+            film = film.with_lut(lut.clone());
+        }
+        film = film.with_color_space(args.color_space);
+        film = film.with_tile_order(args.tile_order);
+        if let Some(region) = args.region {
+            film = film.with_region(region);
+        }
+        let finished = timings.time("render", || renderer.render(&mut film, budget));
+        if let (Some(_), Some(noise)) = (args.noise_threshold, film.noise()) {
+            eprintln!(
+                "Estimated noise {:.2}% after {} samples per pixel.",
+                noise * 100.,
+                film.samples()
+            );
+        }
+
+        if let (Some(keep), Some(previous), true) = (args.temporal_reuse, &previous, finished) {
+            let (open, close) = frame_time(frame - 1, args.frames);
+            renderer.reuse_frame(&mut film, previous, (open + close) / 2., keep);
+        }
 
-                    return ray_color(&mut rng, r, &world, max_depth);
-                })
-                .reduce(|| Color::zero(), |a, b| a + b);
+        let output_start = Instant::now();
 
-            write_color(pixel_color, samples_per_pixel);
+        if let Some(path) = &args.checkpoint {
+            let path = frame_path(path, frame, args.frames);
+            let result =
+                File::create(&path).and_then(|f| film.write_checkpoint(&mut BufWriter::new(f)));
+            match result {
+                Ok(()) => outputs.push(path),
+                Err(err) => eprintln!("error: failed to write checkpoint: {}", err),
+            }
+        }
+
+        if let Some(path) = &args.heatmap {
+            let path = frame_path(path, frame, args.frames);
+            let format = ImageFormat::from_path(&path).unwrap_or_default();
+            let result = File::create(&path)
+                .and_then(|f| film.heatmap().write(format, &mut BufWriter::new(f)));
+            match result {
+                Ok(()) => outputs.push(path),
+                Err(err) => eprintln!("error: failed to write heat map: {}", err),
+            }
         }
 
-        println!();
+        let path = match &args.output {
+            Some(path) => frame_path(path, frame, args.frames),
+            None => PathBuf::from("-"),
+        };
+        let fogged = args.depth_fog.map(|fog| film.depth_fog(&fog));
+        let base = fogged.as_ref().unwrap_or(&film);
+        let aberration = scene.camera.lens().chromatic_aberration;
+        let fringed = (aberration != 0.).then(|| base.chromatic_aberration(aberration));
+        let base = fringed.as_ref().unwrap_or(base);
+        let bloomed = args.bloom.map(|bloom| base.bloom(&bloom));
+        let base = bloomed.as_ref().unwrap_or(base);
+        let glared = scene.camera.glare().map(|glare| base.glare(&glare));
+        let image = glared.as_ref().unwrap_or(base);
+        let result = match &args.output {
+            Some(_) => {
+                File::create(&path).and_then(|f| image.write(args.format, &mut BufWriter::new(f)))
+            }
+            None => image.write(args.format, &mut stdout().lock()),
+        };
+
+        if let Err(err) = result {
+            eprintln!("error: failed to write image: {}", err);
+            std::process::exit(1);
+        }
+        outputs.push(path);
+
+        let stats = (args.stats || args.report.is_some()).then(|| image.stats());
+        timings.add("output", output_start.elapsed());
+        if let (true, Some(stats)) = (args.stats, &stats) {
+            eprintln!("Image statistics:\n{}", stats);
+        }
+
+        if !finished {
+            eprintln!("Stopped after {} samples per pixel.", film.samples());
+            eprintln!("Time breakdown:\n{}", timings);
+            report(
+                &outputs,
+                &timings,
+                film.samples(),
+                film.noise(),
+                stats.as_ref(),
+                false,
+            );
+            std::process::exit(130);
+        }
+
+        if frame + 1 == args.frames {
+            report(
+                &outputs,
+                &timings,
+                film.samples(),
+                film.noise(),
+                stats.as_ref(),
+                true,
+            );
+        }
+
+        if args.temporal_reuse.is_some() {
+            previous = Some(film);
+        }
     }
 
     eprintln!("Done.");
+    eprintln!("Time breakdown:\n{}", timings);
+    Ok(())
+}
+
+/// How often `watch` looks for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Renders the scene `args` describe, and again each time the material
+/// library at `path` changes, until interrupted. A library that can't be
+/// loaded or used, such as one naming a material the scene doesn't have, is
+/// reported and waited on to be fixed.
+fn watch(args: &Args, path: &Path) -> ! {
+    let modified = || fs::metadata(path).and_then(|m| m.modified()).ok();
+    let mut rendered = None;
+    loop {
+        if INTERRUPTED.load(Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+
+        let now = modified();
+        if rendered.is_some() && now == rendered {
+            thread::sleep(WATCH_INTERVAL);
+            continue;
+        }
+        rendered = now;
+        let result = load_material_library(args)
+            .and_then(|(library, light_groups)| run(args, &library, light_groups, Task::Render));
+        if let Err(err) = result {
+            eprintln!("error: {}", err);
+        }
+        eprintln!("Watching {} for changes...", path.display());
+    }
+}
+
+/// Named materials to replace, in order.
+type NamedMaterials = Vec<(String, Material)>;
+
+/// The materials in the `--material-library` file, if any, and the light
+/// groups named there and on the command line.
+fn load_material_library(args: &Args) -> Result<(NamedMaterials, Vec<String>), String> {
+    let mut light_groups = args.light_groups.clone();
+    let Some(path) = &args.material_library else {
+        return Ok((vec![], light_groups));
+    };
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let library = cli::parse_material_library(&text, &args.textures, &mut light_groups)
+        .map_err(|err| format!("{}:{}", path.display(), err))?;
+    Ok((library, light_groups))
+}
+
+/// Averages checkpoints rendered separately into one image.
+fn merge<I: IntoIterator<Item = String>>(argv: I) {
+    let args = match cli::parse_merge_args(argv) {
+        Ok(args) => args,
+        Err(CliError::Help) => {
+            println!("{}", cli::MERGE_USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::MERGE_USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let mut film: Option<Film> = None;
+    for path in &args.inputs {
+        let result = File::open(path)
+            .and_then(|f| Film::read_checkpoint(&mut BufReader::new(f)))
+            .and_then(|checkpoint| match &mut film {
+                Some(film) => film.merge(&checkpoint),
+                None => {
+                    film = Some(checkpoint);
+                    Ok(())
+                }
+            });
+
+        if let Err(err) = result {
+            eprintln!("error: failed to merge {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+
+    // There is at least one input, so there is a film
+    let mut film = film.unwrap();
+    if let Some(white_balance) = args.white_balance {
+        film = film.with_white_balance(white_balance);
+    }
+    if let Some(path) = &args.lut {
+        film = film.with_lut(load_lut(path));
+    }
+    film = film.with_color_space(args.color_space);
+    eprintln!(
+        "Merged {} checkpoints, {} samples per pixel.",
+        args.inputs.len(),
+        film.samples()
+    );
+
+    if let Some(path) = &args.checkpoint {
+        let result = File::create(path).and_then(|f| film.write_checkpoint(&mut BufWriter::new(f)));
+        if let Err(err) = result {
+            eprintln!("error: failed to write checkpoint: {}", err);
+        }
+    }
+
+    if let Some(bloom) = args.bloom {
+        film = film.bloom(&bloom);
+    }
+    let result = match &args.output {
+        Some(path) => {
+            File::create(path).and_then(|f| film.write(args.format, &mut BufWriter::new(f)))
+        }
+        None => film.write(args.format, &mut stdout().lock()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: failed to write image: {}", err);
+        std::process::exit(1);
+    }
+}
+
+/// Measures how an image differs from a reference render.
+fn compare<I: IntoIterator<Item = String>>(argv: I) {
+    let args = match cli::parse_compare_args(argv) {
+        Ok(args) => args,
+        Err(CliError::Help) => {
+            println!("{}", cli::COMPARE_USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::COMPARE_USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let load = |path: &Path| match Image::load(path) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("error: failed to load {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    };
+    let comparison = match Comparison::new(&load(&args.image), &load(&args.reference)) {
+        Ok(comparison) => comparison,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    };
+    println!("{}", comparison);
+
+    let ramp = ColorRamp::heat();
+    let (width, height) = (comparison.width, comparison.height);
+    let write_map = |path: &Path, values: Vec<f64>| {
+        let pixels = values.into_iter().map(|t| ramp.at(t)).collect();
+        let format = ImageFormat::from_path(path).unwrap_or_default();
+        let result = File::create(path).and_then(|f| {
+            Film::from_pixels(width, height, pixels).write(format, &mut BufWriter::new(f))
+        });
+        if let Err(err) = result {
+            eprintln!("error: failed to write {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(path) = &args.error_map {
+        let mut sorted = comparison.error_map.clone();
+        sorted.sort_unstable_by(f64::total_cmp);
+        let top = sorted[(sorted.len() - 1) * 99 / 100];
+        let scaled = comparison
+            .error_map
+            .iter()
+            .map(|&error| if top > 0. { error / top } else { 0. })
+            .collect();
+        write_map(path, scaled);
+    }
+    if let Some(path) = &args.ssim_map {
+        let dissimilarity = comparison.ssim_map.iter().map(|&ssim| 1. - ssim).collect();
+        write_map(path, dissimilarity);
+    }
+}
+
+/// Writes the scene's geometry to `path` as OBJ, and its materials beside
+/// it, exiting if they can't be written.
+fn export_obj(scene: &Scene, path: &Path) {
+    let mut mesh = Tessellation::new(export::DEFAULT_TOLERANCE);
+    scene.world.tessellate(scene.camera.time().0, &mut mesh);
+
+    let mtl_path = path.with_extension("mtl");
+    let mtl_name = mtl_path
+        .file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let result = File::create(path)
+        .and_then(|f| mesh.write_obj(&mtl_name, &mut BufWriter::new(f)))
+        .and_then(|()| File::create(&mtl_path))
+        .and_then(|f| mesh.write_mtl(&mut BufWriter::new(f)));
+    if let Err(err) = result {
+        eprintln!("error: failed to write {}: {}", path.display(), err);
+        std::process::exit(1);
+    }
+
+    eprintln!(
+        "Exported {} triangles to {}.",
+        mesh.triangle_count(),
+        path.display()
+    );
+    if mesh.skipped() > 0 {
+        eprintln!(
+            "{} objects couldn't be tessellated and were left out.",
+            mesh.skipped()
+        );
+    }
+}
+
+/// Lists the problems with a scene, exiting with an error if there are any.
+fn check(scene: &Scene, materials: &MaterialLibrary) {
+    let mut problems = lint::materials(materials);
+    problems.extend(lint::scene(scene));
+    if problems.is_empty() {
+        println!("No problems found.");
+        return;
+    }
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    eprintln!(
+        "error: found {} problem{} with the scene",
+        problems.len(),
+        if problems.len() == 1 { "" } else { "s" }
+    );
+    std::process::exit(1);
+}
+
+/// Converts an image from one format to another.
+fn convert<I: IntoIterator<Item = String>>(argv: I) {
+    let args = match cli::parse_convert_args(argv) {
+        Ok(args) => args,
+        Err(CliError::Help) => {
+            println!("{}", cli::CONVERT_USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("error: {}\n\n{}", err, cli::CONVERT_USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let image = match Image::load(&args.input) {
+        Ok(image) => image,
+        Err(err) => {
+            eprintln!("error: failed to load {}: {}", args.input.display(), err);
+            std::process::exit(1);
+        }
+    };
+
+    let (width, height) = (image.width(), image.height());
+    let scale = args.exposure.exp2();
+    let pixels = (0..width * height)
+        .map(|i| {
+            args.tone_map
+                .apply(image.pixel(i % width, i / width) * scale)
+        })
+        .collect();
+    let film = Film::from_pixels(width, height, pixels);
+
+    let result =
+        File::create(&args.output).and_then(|f| film.write(args.format, &mut BufWriter::new(f)));
+    if let Err(err) = result {
+        eprintln!("error: failed to write {}: {}", args.output.display(), err);
+        std::process::exit(1);
+    }
+}
+
+/// Reads a `.cube` file, exiting if it can't be used.
+fn load_lut(path: &Path) -> Arc<Lut> {
+    match Lut::load(path) {
+        Ok(lut) => Arc::new(lut),
+        Err(err) => {
+            eprintln!("error: failed to load {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The shutter interval of an animation frame. The whole animation spans the
+/// scene's time range from 0 to 1.
+fn frame_time(frame: u32, frames: u32) -> (f64, f64) {
+    let frames = frames as f64;
+    let frame = frame as f64;
+    (frame / frames, (frame + 1.) / frames)
+}
+
+/// Replaces a run of '#' in `path` with the zero-padded frame number. When
+/// rendering several frames into a path without one, the number is appended
+/// to the file name.
+fn frame_path(path: &Path, frame: u32, frames: u32) -> PathBuf {
+    if frames == 1 {
+        return path.to_path_buf();
+    }
+
+    let path = path.to_string_lossy();
+    match path.find('#') {
+        Some(start) => {
+            let width = path[start..].chars().take_while(|&c| c == '#').count();
+            let number = format!("{:0width$}", frame, width = width);
+            PathBuf::from(path.replacen(&"#".repeat(width), &number, 1))
+        }
+        None => {
+            let path = Path::new(path.as_ref());
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let name = match path.extension() {
+                Some(ext) => format!("{}_{:04}.{}", stem, frame, ext.to_string_lossy()),
+                None => format!("{}_{:04}", stem, frame),
+            };
+            path.with_file_name(name)
+        }
+    }
+}
+
+/// The camera parameters of the random scene, which can be changed with
+/// `--set camera.<NAME>=<VALUE>`.
+struct CameraSettings {
+    look_from: Point3,
+    look_at: Point3,
+    vertical_fov: f64,
+    aperture: f64,
+    focus_distance: f64,
+    /// Set by any of the exposure parameters, which then also decide the
+    /// aperture.
+    exposure: Option<Exposure>,
+    shutter_curve: ShutterCurve,
+    rolling_shutter: Option<f64>,
+    projection: Projection,
+    glare: Option<Glare>,
+    lens: Lens,
+    /// Near and far clipping distances.
+    clip: (f64, f64),
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            look_from: Point3::new(13., 2., 3.),
+            look_at: Point3::zero(),
+            vertical_fov: 20.,
+            aperture: 0.1,
+            focus_distance: 10.,
+            exposure: None,
+            shutter_curve: ShutterCurve::default(),
+            rolling_shutter: None,
+            projection: Projection::default(),
+            glare: None,
+            lens: Lens::default(),
+            clip: (0., f64::INFINITY),
+        }
+    }
 }
 
-fn write_color(pixel_color: Color, samples_per_pixel: i32) {
-    let r = pixel_color.x();
-    let g = pixel_color.y();
-    let b = pixel_color.z();
+/// Changes the scene parameter at a dotted `path`, such as `camera.fov` or
+/// `materials.mirror.roughness`.
+fn apply_setting(
+    path: &str,
+    value: &str,
+    camera: &mut CameraSettings,
+    materials: &mut MaterialLibrary,
+) -> Result<(), String> {
+    let invalid = || format!("invalid value {:?}", value);
+    let number = || value.trim().parse::<f64>().map_err(|_| invalid());
+    let point = || {
+        let coords = value
+            .split(',')
+            .map(|c| c.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| invalid())?;
+        match coords[..] {
+            [x, y, z] => Ok(Point3::new(x, y, z)),
+            _ => Err(invalid()),
+        }
+    };
 
-    // Divide the color by the number of samples and gamma-correct for gamma=2.0
-    let samples_per_pixel = samples_per_pixel as f64;
-    let scale = 1. / samples_per_pixel;
-    let r = (scale * r).sqrt();
-    let g = (scale * g).sqrt();
-    let b = (scale * b).sqrt();
+    let positive = |n: f64| if n > 0. { Ok(n) } else { Err(invalid()) };
 
-    let ir = (256. * r.clamp(0., 0.999)) as i32;
-    let ig = (256. * g.clamp(0., 0.999)) as i32;
-    let ib = (256. * b.clamp(0., 0.999)) as i32;
+    let parts: Vec<&str> = path.split('.').collect();
+    match parts[..] {
+        ["camera", "look_from"] => camera.look_from = point()?,
+        ["camera", "look_at"] => camera.look_at = point()?,
+        ["camera", "fov"] => camera.vertical_fov = number()?,
+        ["camera", "aperture"] => camera.aperture = number()?,
+        ["camera", "focus_distance"] => camera.focus_distance = number()?,
+        ["camera", "shutter_curve"] => camera.shutter_curve = value.parse()?,
+        ["camera", "projection"] => camera.projection = value.parse()?,
+        ["camera", "glare"] => camera.glare = Some(value.parse()?),
+        ["camera", "distortion"] => camera.lens.distortion = number()?,
+        ["camera", "vignetting"] => match number()? {
+            amount if (0. ..=1.).contains(&amount) => camera.lens.vignetting = amount,
+            _ => return Err(invalid()),
+        },
+        ["camera", "chromatic_aberration"] => match number()? {
+            amount if amount.abs() < 0.5 => camera.lens.chromatic_aberration = amount,
+            _ => return Err(invalid()),
+        },
+        ["camera", "near"] => match number()? {
+            near if near >= 0. => camera.clip.0 = near,
+            _ => return Err(invalid()),
+        },
+        ["camera", "far"] => camera.clip.1 = positive(number()?)?,
+        ["camera", "rolling_shutter"] => match number()? {
+            readout if (0. ..=1.).contains(&readout) => camera.rolling_shutter = Some(readout),
+            _ => return Err(invalid()),
+        },
+        ["camera", name @ ("iso" | "f_stop" | "shutter")] => {
+            let exposure = camera.exposure.get_or_insert_with(Exposure::default);
+            match name {
+                "iso" => exposure.iso = positive(number()?)?,
+                "f_stop" => exposure.f_number = positive(number()?)?,
+                _ => {
+                    // Shutter speeds are usually written as fractions, e.g.
+                    // 1/125
+                    let seconds = match value.split_once('/') {
+                        Some((n, d)) => {
+                            let n = n.trim().parse::<f64>().map_err(|_| invalid())?;
+                            let d = d.trim().parse::<f64>().map_err(|_| invalid())?;
+                            n / d
+                        }
+                        None => number()?,
+                    };
+                    exposure.shutter = positive(seconds)?;
+                }
+            }
+        }
+        ["materials", name, property] => materials.set(name, property, value)?,
+        _ => return Err(format!("unknown scene parameter {:?}", path)),
+    }
+
+    Ok(())
+}
 
-    print!("{} {} {} ", ir, ig, ib);
+/// What goes into a scene, before the objects are built into an
+/// acceleration structure.
+struct SceneObjects {
+    objects: Vec<Box<dyn Hit>>,
+    /// Emissive objects to sample directly. Each is also among `objects`.
+    lights: Vec<Arc<dyn Light>>,
+    /// Names of the light groups, by index. The first is the default group,
+    /// which also holds the background.
+    light_groups: Vec<String>,
 }
 
-fn ray_color<T: Rng>(rng: &mut T, r: Ray, world: &World, depth: i32) -> Color {
-    if depth <= 0 {
-        return BLACK;
+impl SceneObjects {
+    fn new() -> Self {
+        Self {
+            objects: vec![],
+            lights: vec![],
+            light_groups: vec!["default".to_string()],
+        }
     }
 
-    if let Some(hit) = world.hit(r, 0.001, std::f64::INFINITY) {
-        if let Some(ScatterResult {
-            scattered,
-            attenuation,
-        }) = hit.material.scatter(rng, r, hit)
-        {
-            return attenuation * ray_color(rng, scattered, world, depth - 1);
+    fn add(&mut self, object: Box<dyn Hit>) {
+        self.objects.push(object);
+    }
+
+    /// Adds a sphere, which is also sampled as a light if it gives off any.
+    fn add_sphere(&mut self, sphere: Sphere) {
+        if matches!(sphere.material, Material::DiffuseLight { .. }) {
+            self.add_light(sphere);
+        } else {
+            self.add(Box::new(sphere));
         }
+    }
 
-        return BLACK;
+    /// Adds an object that's also sampled as a light.
+    fn add_light<L: Hit + Light + Clone + 'static>(&mut self, light: L) {
+        self.lights.push(Arc::new(light.clone()));
+        self.add(Box::new(light));
     }
+}
+
+/// Loads the image textures `materials` look up through a cache.
+fn preload_textures(materials: &MaterialLibrary) {
+    for name in materials.names() {
+        match materials.get(name) {
+            Some(
+                Material::Isotropic { albedo }
+                | Material::Lambertian { albedo }
+                | Material::ShadowCatcher { albedo },
+            ) => albedo.preload(),
+            Some(Material::DiffuseLight { emit, .. }) => emit.preload(),
+            _ => {}
+        }
+    }
+}
+
+/// Adds the lights given on the command line.
+fn add_lights(contents: &mut SceneObjects, lights: &[LightSpec]) {
+    for light in lights {
+        match light.clone() {
+            LightSpec::Sphere {
+                center,
+                radius,
+                material,
+            } => contents.add_light(Sphere::new(center, radius, material)),
+            LightSpec::Disk {
+                center,
+                radius,
+                material,
+            } => contents.add_light(Disk::new(center, Vec3::new(0., -1., 0.), radius, material)),
+            LightSpec::Point {
+                position,
+                intensity,
+                group,
+            } => contents.lights.push(Arc::new(PointLight {
+                group,
+                ..PointLight::new(position, intensity)
+            })),
+            LightSpec::Ies {
+                position,
+                path,
+                group,
+            } => {
+                let profile = match IesProfile::load(&path) {
+                    Ok(profile) => Arc::new(profile),
+                    Err(err) => {
+                        eprintln!("error: failed to load {}: {}", path.display(), err);
+                        std::process::exit(1);
+                    }
+                };
+                let brightest = profile.max_candela();
+                let light = PointLight::new(position, Color::new(brightest, brightest, brightest))
+                    .with_profile(profile, Vec3::new(0., -1., 0.));
+                contents
+                    .lights
+                    .push(Arc::new(PointLight { group, ..light }));
+            }
+        }
+    }
+}
+
+/// The materials the random scene refers to by name, which can be replaced
+/// from the command line.
+fn default_materials() -> MaterialLibrary {
+    let mut materials = MaterialLibrary::new();
+
+    materials.insert(
+        "ground",
+        Material::Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5).into(),
+        },
+    );
+    materials.insert(
+        "glass",
+        Material::Dialectric {
+            index_of_refraction: 1.5,
+            medium: None,
+            priority: 0,
+            reflection_roughness: 0.,
+            transmission_roughness: 0.,
+        },
+    );
+    materials.insert(
+        "matte",
+        Material::Lambertian {
+            albedo: Color::new(0.4, 0.2, 0.1).into(),
+        },
+    );
+    materials.insert(
+        "mirror",
+        Material::Metal {
+            albedo: Color::new(0.7, 0.6, 0.5),
+            fuzz: 0.0,
+        },
+    );
 
-    let unit_direction = r.direction.unit_vector();
-    let t = 0.5 * (unit_direction.y() + 1.);
-    WHITE * (1. - t) + Color::new(0.5, 0.7, 1.) * t
+    materials
 }
 
-fn random_scene<T: Rng>(rng: &mut T) -> World {
-    let mut objects: Vec<Box<dyn Hit + Sync>> = vec![];
+fn random_scene<T: Rng>(
+    rng: &mut T,
+    materials: &MaterialLibrary,
+    shadow_catcher: bool,
+) -> SceneObjects {
+    let mut contents = SceneObjects::new();
+
+    // Every material the scene uses by name is in the default library
+    let material = |name: &str| materials.material(name).unwrap();
 
-    let ground_material = Material::Lambertian {
-        albedo: Color::new(0.5, 0.5, 0.5),
+    let ground_material = if shadow_catcher {
+        Material::ShadowCatcher {
+            albedo: Color::new(0.5, 0.5, 0.5).into(),
+        }
+    } else {
+        material("ground")
     };
 
-    objects.push(Box::new(Sphere::new(
+    contents.add_sphere(Sphere::new(
         Point3::new(0., -1000., 0.),
         1000.,
         ground_material,
-    )));
+    ));
 
     for a in -11..11 {
         for b in -11..11 {
@@ -156,58 +1098,158 @@ fn random_scene<T: Rng>(rng: &mut T) -> World {
             let p = Point3::new(4., 0.2, 0.);
 
             if (center - p).length() > 0.9 {
-                let object: Box<dyn Hit + Sync> = if choose_mat < 0.8 {
+                if choose_mat < 0.8 {
                     // diffuse
                     let albedo = Color::random(rng) * Color::random(rng);
-                    let material = Material::Lambertian { albedo };
+                    let material = Material::Lambertian {
+                        albedo: albedo.into(),
+                    };
                     let center = (center, center + Vec3::new(0., rng.gen_range(0.0..0.5), 0.));
                     let time = (0., 1.);
 
-                    Box::new(MovingSphere::new(time, center, 0.2, material))
+                    contents.add(Box::new(MovingSphere::new(time, center, 0.2, material)));
                 } else if choose_mat < 0.95 {
                     // metal
                     let albedo = Color::random_range(rng, 0.5, 1.0);
                     let fuzz = rng.gen_range(0.0..0.5);
                     let material = Material::Metal { albedo, fuzz };
 
-                    Box::new(Sphere::new(center, 0.2, material))
+                    contents.add_sphere(Sphere::new(center, 0.2, material));
                 } else {
                     // glass
-                    let material = Material::Dialectric {
-                        index_of_refraction: 1.5,
-                    };
+                    contents.add_sphere(Sphere::new(center, 0.2, material("glass")));
+                }
+            }
+        }
+    }
 
-                    Box::new(Sphere::new(center, 0.2, material))
-                };
+    contents.add_sphere(Sphere::new(Point3::new(0., 1., 0.), 1., material("glass")));
+    contents.add_sphere(Sphere::new(Point3::new(-4., 1., 0.), 1., material("matte")));
+    contents.add_sphere(Sphere::new(Point3::new(4., 1., 0.), 1., material("mirror")));
 
-                objects.push(object);
-            }
+    contents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use raytracing::accel::AcceleratorKind;
+    use raytracing::material_library::parse_material;
+    use raytracing::render::{render_to_f32, RenderConfig};
+
+    /// The random scene built with `materials`, as a render would see it.
+    fn scene(materials: &MaterialLibrary) -> Scene {
+        let contents = random_scene(&mut StdRng::seed_from_u64(SCENE_SEED), materials, false);
+        let view = CameraSettings::default();
+        let camera = Camera::new(
+            view.look_from,
+            view.look_at,
+            Vec3::new(0., 1., 0.),
+            view.vertical_fov,
+            2.,
+            view.aperture,
+            view.focus_distance,
+            (0., 1.),
+        );
+        Scene {
+            world: AcceleratorKind::default()
+                .build_with_stats(contents.objects, (0., 1.))
+                .0,
+            lights: LightTree::new(contents.lights),
+            light_groups: contents.light_groups,
+            camera,
+            background: Box::new(VerticalGradient::default()),
+            fog: None,
         }
     }
 
-    let dialectric = Material::Dialectric {
-        index_of_refraction: 1.5,
-    };
-    objects.push(Box::new(Sphere::new(
-        Point3::new(0., 1., 0.),
-        1.,
-        dialectric,
-    )));
+    #[test]
+    fn default_scene_has_no_lights() {
+        assert!(scene(&default_materials()).lights.is_empty());
+    }
 
-    let lambertian = Material::Lambertian {
-        albedo: Color::new(0.4, 0.2, 0.1),
-    };
-    objects.push(Box::new(Sphere::new(
-        Point3::new(-4., 1., 0.),
-        1.,
-        lambertian,
-    )));
+    #[test]
+    fn emissive_spheres_are_sampled_as_lights() {
+        let mut materials = default_materials();
+        materials.insert("matte", parse_material("light:4,4,4").unwrap());
+        let scene = scene(&materials);
+        assert_eq!(scene.lights.len(), 1);
 
-    let metal = Material::Metal {
-        albedo: Color::new(0.7, 0.6, 0.5),
-        fuzz: 0.0,
-    };
-    objects.push(Box::new(Sphere::new(Point3::new(4., 1., 0.), 1., metal)));
+        let config = RenderConfig {
+            width: 8,
+            height: 4,
+            budget: Budget {
+                samples_per_pixel: Some(1),
+                ..Budget::default()
+            },
+            ..RenderConfig::default()
+        };
+        let pixels = render_to_f32(&scene, &config);
+        assert_eq!(pixels.len(), 8 * 4 * 4);
+        assert!(pixels.iter().all(|v| v.is_finite()));
+    }
+
+    #[test]
+    fn emitters_are_counted_whether_sampled_or_not() {
+        let mut materials = default_materials();
+        materials.insert("matte", parse_material("light:4,4,4").unwrap());
+        let mut scene = scene(&materials);
+        let stats = scene.stats();
+        assert_eq!((stats.emitters, stats.lights), (1, 1));
+
+        scene.lights = LightTree::new(vec![]);
+        let stats = scene.stats();
+        assert_eq!((stats.emitters, stats.lights), (1, 0));
+    }
+
+    #[test]
+    fn check_flags_emitters_that_arent_sampled() {
+        let mut materials = default_materials();
+        materials.insert("matte", parse_material("light:4,4,4").unwrap());
+        let mut scene = scene(&materials);
+        let subjects = |scene: &Scene| -> Vec<_> {
+            lint::scene(scene)
+                .into_iter()
+                .map(|problem| problem.subject)
+                .collect()
+        };
+        assert!(!subjects(&scene).contains(&"lights".to_string()));
+
+        scene.lights = LightTree::new(vec![]);
+        assert!(subjects(&scene).contains(&"lights".to_string()));
+    }
 
-    World::new(objects)
+    #[test]
+    fn bad_library_is_an_error_to_wait_on() {
+        let args = cli::parse_args(["--set".to_string(), "camera.fov=30".to_string()]).unwrap();
+        let library = [(
+            "nope".to_string(),
+            parse_material("lambertian:1,1,1").unwrap(),
+        )];
+        let err = run(&args, &library, args.light_groups.clone(), Task::Stats).unwrap_err();
+        assert!(err.starts_with("unknown material \"nope\""), "{}", err);
+    }
+
+    #[test]
+    fn lights_given_are_sampled() {
+        let args = cli::parse_args(
+            [
+                "--light",
+                "sphere:0,4,0:0.5:4,4,4",
+                "--light",
+                "disk:0,5,0:0.5:4,4,4",
+            ]
+            .map(String::from),
+        )
+        .unwrap();
+        let mut contents = random_scene(
+            &mut StdRng::seed_from_u64(SCENE_SEED),
+            &default_materials(),
+            false,
+        );
+        let objects = contents.objects.len();
+        add_lights(&mut contents, &args.lights);
+        assert_eq!(contents.lights.len(), 2);
+        assert_eq!(contents.objects.len(), objects + 2);
+    }
 }
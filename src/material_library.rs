@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::color::{Color, BLACK};
+use crate::light::{LightPower, DEFAULT_LIGHT_GROUP};
+use crate::ray::Material;
+use crate::texture::{Mapping, Texture, Wrap};
+use crate::texture_cache::TextureCache;
+use crate::volume::Medium;
+
+/// Materials by name, so that a material can be defined once and given to
+/// any number of objects, and swapped out in one place.
+///
+/// Objects get their own copy of a material when they're built, so changes
+/// to the library only affect objects built afterwards.
+#[derive(Clone, Default)]
+pub struct MaterialLibrary {
+    materials: BTreeMap<String, Material>,
+}
+
+/// The error for a name with no material in the library.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownMaterial(pub String);
+
+impl fmt::Display for UnknownMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown material {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownMaterial {}
+
+impl MaterialLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a material, replacing and returning any already called `name`.
+    pub fn insert(&mut self, name: impl Into<String>, material: Material) -> Option<Material> {
+        self.materials.insert(name.into(), material)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Material> {
+        self.materials.get(name)
+    }
+
+    /// A copy of the material called `name`, for giving to an object.
+    pub fn material(&self, name: &str) -> Result<Material, UnknownMaterial> {
+        self.get(name)
+            .cloned()
+            .ok_or_else(|| UnknownMaterial(name.to_string()))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.materials.contains_key(name)
+    }
+
+    /// Changes one property of the material called `name`, parsed from
+    /// `value`. Colors are given as `R,G,B` or an sRGB hex code.
+    ///
+    /// Properties are `albedo` for diffuse, metal and medium materials,
+    /// `fuzz` (or `roughness`) for metal, `ior`, `absorption`, `scattering`,
+    /// `priority`, `reflection_roughness`, `transmission_roughness` and
+    /// `roughness` (both at once) for glass and `emit` and `power` (e.g.
+    /// `800lm`) for lights. Setting absorption or scattering on clear glass
+    /// fills it with a medium.
+    pub fn set(&mut self, name: &str, property: &str, value: &str) -> Result<(), String> {
+        let material = self
+            .materials
+            .get_mut(name)
+            .ok_or_else(|| UnknownMaterial(name.to_string()).to_string())?;
+        let invalid = || format!("invalid value {:?} for {}", value, property);
+        let color = || parse_color(value).ok_or_else(invalid);
+        let number = || value.trim().parse::<f64>().map_err(|_| invalid());
+
+        match (material, property) {
+            (
+                Material::Isotropic { albedo }
+                | Material::Lambertian { albedo }
+                | Material::ShadowCatcher { albedo },
+                "albedo",
+            ) => *albedo = color()?.into(),
+            (Material::Metal { albedo, .. }, "albedo") => *albedo = color()?,
+            (Material::Metal { fuzz, .. }, "fuzz" | "roughness") => *fuzz = number()?,
+            (
+                Material::Dialectric {
+                    index_of_refraction,
+                    ..
+                },
+                "ior",
+            ) => *index_of_refraction = number()?,
+            (Material::Dialectric { medium, .. }, "absorption") => {
+                medium.get_or_insert(Medium::new(BLACK, 0.)).absorption = color()?
+            }
+            (Material::Dialectric { medium, .. }, "scattering") => {
+                medium.get_or_insert(Medium::new(BLACK, 0.)).scattering = number()?
+            }
+            (Material::Dialectric { priority, .. }, "priority") => {
+                *priority = value.trim().parse().map_err(|_| invalid())?
+            }
+            (
+                Material::Dialectric {
+                    reflection_roughness,
+                    transmission_roughness,
+                    ..
+                },
+                "roughness",
+            ) => {
+                *reflection_roughness = number()?;
+                *transmission_roughness = *reflection_roughness;
+            }
+            (
+                Material::Dialectric {
+                    reflection_roughness,
+                    ..
+                },
+                "reflection_roughness",
+            ) => *reflection_roughness = number()?,
+            (
+                Material::Dialectric {
+                    transmission_roughness,
+                    ..
+                },
+                "transmission_roughness",
+            ) => *transmission_roughness = number()?,
+            (Material::DiffuseLight { emit, .. }, "emit") => *emit = color()?.into(),
+            (Material::DiffuseLight { power, .. }, "power") => *power = Some(value.parse()?),
+            _ => {
+                return Err(format!(
+                    "material {:?} has no property {:?}",
+                    name, property
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The names of all the materials, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.materials.keys().map(String::as_str)
+    }
+}
+
+/// Parses a short material description, as given on the command line:
+///
+/// - `lambertian:R,G,B`
+/// - `checker:R,G,B:R,G,B` or `checker:R,G,B:R,G,B:SCALE`, a diffuse
+///   surface in a 3D checkerboard of the two colors, with cells `SCALE`
+///   across (default 1), filtered where it's too fine to see
+/// - `metal:R,G,B` or `metal:R,G,B:FUZZ`
+/// - `glass`, `glass:IOR` or `glass:IOR:ABSORPTION[:SCATTERING]`, with a
+///   medium inside absorbing `ABSORPTION` (`R,G,B`) and scattering
+///   `SCATTERING` per unit distance
+/// - `frosted:IOR:ROUGHNESS` or
+///   `frosted:IOR:REFLECTION_ROUGHNESS:TRANSMISSION_ROUGHNESS`, rough glass
+/// - `light:R,G,B` or `light:R,G,B:POWER`, with the light's total `POWER`
+///   in watts or lumens, e.g. `1.2W` or `800lm`, setting its brightness
+pub fn parse_material(spec: &str) -> Result<Material, String> {
+    let mut parts = spec.split(':');
+    let kind = parts.next().unwrap_or_default();
+    let params: Vec<&str> = parts.collect();
+    let invalid = || format!("invalid material {:?}", spec);
+
+    let color = |s: &str| parse_color(s).ok_or_else(invalid);
+    let number = |s: &str| s.trim().parse::<f64>().map_err(|_| invalid());
+
+    match (kind, &params[..]) {
+        ("lambertian", [albedo]) => Ok(Material::Lambertian {
+            albedo: color(albedo)?.into(),
+        }),
+        ("checker", [even, odd]) => Ok(Material::Lambertian {
+            albedo: Texture::checker(color(even)?.into(), color(odd)?.into(), 1., Mapping::Solid),
+        }),
+        ("checker", [even, odd, scale]) => Ok(Material::Lambertian {
+            albedo: Texture::checker(
+                color(even)?.into(),
+                color(odd)?.into(),
+                number(scale)?,
+                Mapping::Solid,
+            ),
+        }),
+        ("metal", [albedo]) => Ok(Material::Metal {
+            albedo: color(albedo)?,
+            fuzz: 0.,
+        }),
+        ("metal", [albedo, fuzz]) => Ok(Material::Metal {
+            albedo: color(albedo)?,
+            fuzz: number(fuzz)?,
+        }),
+        ("glass", []) => Ok(Material::Dialectric {
+            index_of_refraction: 1.5,
+            medium: None,
+            priority: 0,
+            reflection_roughness: 0.,
+            transmission_roughness: 0.,
+        }),
+        ("glass", [index_of_refraction]) => Ok(Material::Dialectric {
+            index_of_refraction: number(index_of_refraction)?,
+            medium: None,
+            priority: 0,
+            reflection_roughness: 0.,
+            transmission_roughness: 0.,
+        }),
+        ("glass", [index_of_refraction, absorption]) => Ok(Material::Dialectric {
+            index_of_refraction: number(index_of_refraction)?,
+            medium: Some(Medium::new(color(absorption)?, 0.)),
+            priority: 0,
+            reflection_roughness: 0.,
+            transmission_roughness: 0.,
+        }),
+        ("glass", [index_of_refraction, absorption, scattering]) => Ok(Material::Dialectric {
+            index_of_refraction: number(index_of_refraction)?,
+            medium: Some(Medium::new(color(absorption)?, number(scattering)?)),
+            priority: 0,
+            reflection_roughness: 0.,
+            transmission_roughness: 0.,
+        }),
+        ("frosted", [index_of_refraction, roughness]) => Ok(Material::Dialectric {
+            index_of_refraction: number(index_of_refraction)?,
+            medium: None,
+            priority: 0,
+            reflection_roughness: number(roughness)?,
+            transmission_roughness: number(roughness)?,
+        }),
+        ("frosted", [index_of_refraction, reflection_roughness, transmission_roughness]) => {
+            Ok(Material::Dialectric {
+                index_of_refraction: number(index_of_refraction)?,
+                medium: None,
+                priority: 0,
+                reflection_roughness: number(reflection_roughness)?,
+                transmission_roughness: number(transmission_roughness)?,
+            })
+        }
+        ("light", [emit]) => Ok(Material::DiffuseLight {
+            emit: color(emit)?.into(),
+            group: DEFAULT_LIGHT_GROUP,
+            power: None,
+        }),
+        ("light", [emit, power]) => Ok(Material::DiffuseLight {
+            emit: color(emit)?.into(),
+            group: DEFAULT_LIGHT_GROUP,
+            power: Some(power.parse::<LightPower>()?),
+        }),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses a material as `parse_material` does, or `image:FILE`, a diffuse
+/// surface colored by the image in `FILE` stretched over its texture
+/// coordinates, loaded through `textures` when it's first looked up.
+pub fn parse_material_with_textures(
+    spec: &str,
+    textures: &Arc<TextureCache>,
+) -> Result<Material, String> {
+    match spec.split_once(':') {
+        Some(("image", path)) if !path.is_empty() => Ok(Material::Lambertian {
+            albedo: Texture::cached_image(path, Arc::clone(textures), Wrap::Repeat),
+        }),
+        Some(("image", _)) => Err(format!("invalid material {:?}", spec)),
+        _ => parse_material(spec),
+    }
+}
+
+/// Parses a linear `R,G,B` triple or an sRGB hex code such as `#80c0ff`.
+pub fn parse_color(s: &str) -> Option<Color> {
+    if let Ok(color) = Color::from_hex(s) {
+        return Some(color);
+    }
+
+    let channels = s
+        .split(',')
+        .map(|c| c.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    match channels[..] {
+        [r, g, b] => Some(Color::new(r, g, b)),
+        _ => None,
+    }
+}
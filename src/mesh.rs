@@ -0,0 +1,247 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::bounds::{AABB, BVH};
+use crate::export::Tessellation;
+use crate::ray::{Hit, HitRecord, Material, Ray, SurfaceDerivatives};
+use crate::scene::SceneStats;
+use crate::vector::{Point3, Vec3};
+
+/// Triangles thinner than this along an axis get their bounds padded, since
+/// the slab test never hits a box with no volume.
+const BOUNDS_PADDING: f64 = 1e-4;
+
+pub struct Triangle {
+    pub vertices: [Point3; 3],
+    pub material: Material,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Point3; 3], material: Material) -> Self {
+        Self { vertices, material }
+    }
+}
+
+impl Triangle {
+    /// Where `r` crosses the triangle between `t_min` and `t_max`, as the
+    /// distance along it and the barycentric coordinates of the crossing.
+    fn intersect(&self, r: Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        // Möller–Trumbore intersection
+        let [v0, v1, v2] = self.vertices;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+
+        let p = r.direction.cross_product(edge2);
+        let det = edge1.dot_product(p);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+
+        let inv_det = det.recip();
+        let s = r.origin - v0;
+        let u = s.dot_product(p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross_product(edge1);
+        let v = r.direction.dot_product(q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+
+        let t = edge2.dot_product(q) * inv_det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        Some((t, u, v))
+    }
+}
+
+impl Hit for Triangle {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, u, v) = self.intersect(r, t_min, t_max)?;
+        let [v0, v1, v2] = self.vertices;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+
+        let outward_normal = edge1.cross_product(edge2).unit_vector();
+
+        // Barycentric coordinates double as texture coordinates
+        Some(HitRecord::new(
+            t,
+            r,
+            outward_normal,
+            (u, v),
+            SurfaceDerivatives::flat(edge1, edge2),
+            &self.material,
+        ))
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
+        let [v0, v1, v2] = self.vertices;
+        let bounds = AABB::new(v0, v0).include(v1).include(v2);
+        let padding = Vec3::new(BOUNDS_PADDING, BOUNDS_PADDING, BOUNDS_PADDING);
+
+        Some(AABB::new(bounds.min - padding, bounds.max + padding))
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add_surface("triangle", std::mem::size_of::<Self>(), &self.material);
+    }
+
+    fn tessellate(&self, _time: f64, mesh: &mut Tessellation) {
+        mesh.add_triangle(self.vertices, &self.material);
+    }
+}
+
+/// A triangle mesh with its own BVH, built once when the mesh is created.
+///
+/// Share a mesh between several `Instance`s to place copies of it in a scene
+/// without duplicating the triangles or rebuilding the hierarchy.
+pub struct Mesh {
+    triangle_count: usize,
+    bvh: BVH<Triangle>,
+}
+
+impl Mesh {
+    /// Creates a mesh from a vertex list and triples of indices into it.
+    pub fn new(vertices: &[Point3], indices: &[[usize; 3]], material: Material) -> Self {
+        let triangles: Vec<Triangle> = indices
+            .iter()
+            .map(|&[a, b, c]| {
+                Triangle::new([vertices[a], vertices[b], vertices[c]], material.clone())
+            })
+            .collect();
+
+        Self {
+            triangle_count: triangles.len(),
+            bvh: BVH::new(triangles, (0., 0.)),
+        }
+    }
+
+    /// Loads the vertices and faces of a Wavefront OBJ file. Polygons are
+    /// split into triangle fans; normals, texture coordinates and material
+    /// libraries are ignored.
+    pub fn load_obj<P: AsRef<Path>>(path: P, material: Material) -> Result<Self, ObjError> {
+        let source = fs::read_to_string(path)?;
+
+        let mut vertices = vec![];
+        let mut indices = vec![];
+
+        for (n, line) in source.lines().enumerate() {
+            let error = |message: &str| ObjError::Parse {
+                line: n + 1,
+                message: message.to_string(),
+            };
+
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some("v") => {
+                    let coords: Vec<f64> = fields
+                        .take(3)
+                        .map(|f| f.parse().map_err(|_| error("invalid vertex coordinate")))
+                        .collect::<Result<_, _>>()?;
+                    if coords.len() != 3 {
+                        return Err(error("vertex needs three coordinates"));
+                    }
+                    vertices.push(Point3::new(coords[0], coords[1], coords[2]));
+                }
+                Some("f") => {
+                    let face: Vec<usize> = fields
+                        .map(|f| {
+                            // Faces may be written as v, v/vt, v//vn or v/vt/vn
+                            let index = f.split('/').next().unwrap_or_default();
+                            let index: i64 = index.parse().map_err(|_| error("invalid index"))?;
+                            let resolved = if index < 0 {
+                                vertices.len() as i64 + index
+                            } else {
+                                index - 1
+                            };
+                            if resolved < 0 || resolved >= vertices.len() as i64 {
+                                return Err(error("vertex index out of range"));
+                            }
+                            Ok(resolved as usize)
+                        })
+                        .collect::<Result<_, _>>()?;
+                    if face.len() < 3 {
+                        return Err(error("face needs at least three vertices"));
+                    }
+                    for i in 1..face.len() - 1 {
+                        indices.push([face[0], face[i], face[i + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if indices.is_empty() {
+            return Err(ObjError::Empty);
+        }
+
+        Ok(Self::new(&vertices, &indices, material))
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangle_count
+    }
+}
+
+impl Hit for Mesh {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.bvh.hit(r, t_min, t_max)
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.bvh.occluded(r, t_min, t_max)
+    }
+
+    fn hits<'a>(&'a self, r: Ray, t_min: f64, t_max: f64, hits: &mut Vec<HitRecord<'a>>) {
+        self.bvh.hits(r, t_min, t_max, hits);
+    }
+
+    fn bounds(&self, time: (f64, f64)) -> Option<AABB> {
+        self.bvh.bounds(time)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add("mesh", std::mem::size_of::<Self>());
+        self.bvh.count(stats);
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        self.bvh.tessellate(time, mesh);
+    }
+}
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Empty,
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io(err) => write!(f, "{}", err),
+            ObjError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            ObjError::Empty => write!(f, "no faces found"),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+impl From<io::Error> for ObjError {
+    fn from(err: io::Error) -> Self {
+        ObjError::Io(err)
+    }
+}
@@ -0,0 +1,211 @@
+use crate::bounds::AABB;
+use crate::ray::{Hit, HitRecord, Material, Ray};
+use crate::scene::SceneStats;
+use crate::vector::{Point3, Vec3};
+use crate::world::{sphere_roots, sphere_uv};
+
+/// Steps taken across the smallest ball's radius while marching a ray, which
+/// is enough not to step over thin necks between balls.
+const STEPS_PER_RADIUS: f64 = 16.;
+
+/// Halvings of the step in which the surface was found.
+const REFINE_STEPS: usize = 24;
+
+/// One point of influence in a `Metaballs` object.
+#[derive(Clone, Copy)]
+pub struct Ball {
+    pub center: Point3,
+    /// Distance at which the ball's influence falls to zero.
+    pub radius: f64,
+    /// Influence at the center. Negative strengths carve dents and holes out
+    /// of the other balls.
+    pub strength: f64,
+}
+
+impl Ball {
+    pub fn new(center: Point3, radius: f64, strength: f64) -> Self {
+        Self {
+            center,
+            radius,
+            strength,
+        }
+    }
+
+    /// This ball's influence at `p`, and how it changes with `p`.
+    ///
+    /// The falloff is `(1 - d²/R²)³`, which is smooth everywhere and reaches
+    /// zero at the radius, so distant balls can be skipped.
+    fn field(&self, p: Point3) -> (f64, Vec3) {
+        let offset = p - self.center;
+        let x = offset.length_squared() / (self.radius * self.radius);
+        if x >= 1. {
+            return (0., Vec3::zero());
+        }
+
+        let falloff = 1. - x;
+        let value = self.strength * falloff * falloff * falloff;
+        let gradient =
+            offset * (-6. * self.strength * falloff * falloff / (self.radius * self.radius));
+        (value, gradient)
+    }
+}
+
+/// A blobby surface around a group of balls whose influences add up, so
+/// balls close together melt into one another.
+///
+/// The surface is where the total influence equals `threshold`, found by
+/// marching along each ray through the balls it passes. With the default
+/// threshold of 0.5 a lone ball of strength 1 looks like a sphere of about
+/// 0.45 times its radius.
+#[derive(Clone)]
+pub struct Metaballs {
+    balls: Vec<Ball>,
+    pub threshold: f64,
+    pub material: Material,
+    /// Radius of the smallest ball, which sets the marching step.
+    smallest_radius: f64,
+}
+
+impl Metaballs {
+    pub fn new(balls: Vec<Ball>, material: Material) -> Self {
+        let smallest_radius = balls
+            .iter()
+            .map(|ball| ball.radius)
+            .fold(f64::INFINITY, f64::min);
+
+        Self {
+            balls,
+            threshold: 0.5,
+            material,
+            smallest_radius,
+        }
+    }
+
+    pub fn with_threshold(self, threshold: f64) -> Self {
+        Self { threshold, ..self }
+    }
+
+    pub fn balls(&self) -> &[Ball] {
+        &self.balls
+    }
+
+    /// The total influence at `p` less the threshold, which is positive
+    /// inside the surface, and its gradient.
+    fn field(&self, p: Point3) -> (f64, Vec3) {
+        self.balls.iter().fold(
+            (-self.threshold, Vec3::zero()),
+            |(value, gradient), ball| {
+                let (v, g) = ball.field(p);
+                (value + v, gradient + g)
+            },
+        )
+    }
+
+    /// The stretches of `r` within `t_min` and `t_max` that pass through at
+    /// least one ball, in order. Outside of them the influence is zero.
+    fn spans(&self, r: Ray, t_min: f64, t_max: f64) -> Vec<(f64, f64)> {
+        let mut spans: Vec<(f64, f64)> = self
+            .balls
+            .iter()
+            .filter_map(|ball| sphere_roots(ball.center, ball.radius, r))
+            .map(|[t0, t1]| (t0.max(t_min), t1.min(t_max)))
+            .filter(|(t0, t1)| t0 < t1)
+            .collect();
+        spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut merged: Vec<(f64, f64)> = Vec::with_capacity(spans.len());
+        for (t0, t1) in spans {
+            match merged.last_mut() {
+                Some(last) if t0 <= last.1 => last.1 = last.1.max(t1),
+                _ => merged.push((t0, t1)),
+            }
+        }
+        merged
+    }
+
+    /// The nearest distance along `r` within `t_min` and `t_max` where it
+    /// crosses the surface.
+    fn intersect(&self, r: Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        if self.threshold <= 0. {
+            return None;
+        }
+
+        let dt = self.smallest_radius / STEPS_PER_RADIUS / r.direction.length();
+        let value = |t: f64| self.field(r.at(t)).0;
+
+        for (start, end) in self.spans(r, t_min, t_max) {
+            let mut t0 = start;
+            let mut v0 = value(t0);
+            while t0 < end {
+                let t1 = (t0 + dt).min(end);
+                let v1 = value(t1);
+
+                if (v0 > 0.) != (v1 > 0.) {
+                    // Narrow down the crossing between t0 and t1
+                    let (mut lo, mut hi) = (t0, t1);
+                    for _ in 0..REFINE_STEPS {
+                        let mid = 0.5 * (lo + hi);
+                        if (value(mid) > 0.) == (v0 > 0.) {
+                            lo = mid;
+                        } else {
+                            hi = mid;
+                        }
+                    }
+                    let t = 0.5 * (lo + hi);
+                    if t_min < t && t < t_max {
+                        return Some(t);
+                    }
+                }
+
+                t0 = t1;
+                v0 = v1;
+            }
+        }
+
+        None
+    }
+}
+
+impl Hit for Metaballs {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = self.intersect(r, t_min, t_max)?;
+        let (_, gradient) = self.field(r.at(t));
+        // The influence falls off outwards
+        let outward_normal = (-gradient).unit_vector();
+
+        // Textures wrap around the blob as they would around a sphere with
+        // the same normal
+        let (uv, derivatives) = sphere_uv(outward_normal, self.smallest_radius);
+
+        Some(HitRecord::new(
+            t,
+            r,
+            outward_normal,
+            uv,
+            derivatives,
+            &self.material,
+        ))
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    /// Encloses every ball's reach, which contains the whole surface.
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
+        self.balls
+            .iter()
+            .map(|ball| {
+                let octant = Vec3::new(ball.radius, ball.radius, ball.radius);
+                AABB::new(ball.center - octant, ball.center + octant)
+            })
+            .reduce(|a, b| a + b)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add(
+            "metaballs",
+            std::mem::size_of::<Self>() + self.balls.capacity() * std::mem::size_of::<Ball>(),
+        );
+    }
+}
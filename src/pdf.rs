@@ -0,0 +1,138 @@
+use std::f64::consts::PI;
+
+use rand::{Rng, RngCore};
+
+use crate::guide::GuideRegion;
+use crate::light::LightTree;
+use crate::sampling::random_cosine_direction;
+use crate::vector::{Onb, Point3, Vec3};
+
+/// A distribution of directions leaving a point, for importance sampling:
+/// directions are picked from it and weighted by how likely it was to pick
+/// them.
+pub trait Pdf {
+    /// Probability density, per unit solid angle, of `sample` picking
+    /// `direction`.
+    fn value(&self, direction: Vec3) -> f64;
+
+    /// Picks a direction, or None if there is none to pick.
+    fn sample(&self, rng: &mut dyn RngCore) -> Option<Vec3>;
+}
+
+/// Directions around a surface normal in proportion to the cosine of their
+/// angle to it, as a Lambertian surface reflects light.
+pub struct CosinePdf {
+    basis: Onb,
+}
+
+impl CosinePdf {
+    pub fn new(normal: Vec3) -> Self {
+        Self {
+            basis: Onb::new(normal),
+        }
+    }
+}
+
+impl Pdf for CosinePdf {
+    fn value(&self, direction: Vec3) -> f64 {
+        let cos_theta = direction.unit_vector().dot_product(self.basis.w);
+        cos_theta.max(0.) / PI
+    }
+
+    fn sample(&self, rng: &mut dyn RngCore) -> Option<Vec3> {
+        Some(self.basis.local(random_cosine_direction(rng)))
+    }
+}
+
+/// Directions from `p` towards the lights, picked the same way direct
+/// lighting picks them. Lights at a single point are left out, having no
+/// density to weigh their directions by.
+pub struct LightPdf<'a> {
+    lights: &'a LightTree,
+    p: Point3,
+}
+
+impl<'a> LightPdf<'a> {
+    pub fn new(lights: &'a LightTree, p: Point3) -> Self {
+        Self { lights, p }
+    }
+}
+
+impl Pdf for LightPdf<'_> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.lights.pdf(self.p, direction)
+    }
+
+    fn sample(&self, rng: &mut dyn RngCore) -> Option<Vec3> {
+        let (light, _) = self.lights.pick(self.p, rng.gen())?;
+        if light.is_delta() {
+            return None;
+        }
+        let sample = light.sample(self.p, (rng.gen(), rng.gen()))?;
+        Some(sample.direction)
+    }
+}
+
+impl Pdf for GuideRegion {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.pdf(direction)
+    }
+
+    fn sample(&self, rng: &mut dyn RngCore) -> Option<Vec3> {
+        GuideRegion::sample(self, (rng.gen(), rng.gen(), rng.gen()))
+    }
+}
+
+/// Picks each direction from one of several distributions, chosen at
+/// random by weight, such as the material's and the lights', so each covers
+/// the directions the others sample poorly.
+pub struct MixturePdf<'a> {
+    /// Each distribution with its share of the samples, summing to 1.
+    components: Vec<(f64, &'a dyn Pdf)>,
+}
+
+impl<'a> MixturePdf<'a> {
+    /// An even mix of `a` and `b`.
+    pub fn new(a: &'a dyn Pdf, b: &'a dyn Pdf) -> Self {
+        Self::weighted(vec![(1., a), (1., b)])
+    }
+
+    /// A mix with each distribution picked in proportion to its weight.
+    /// Weights that aren't positive leave their distribution out.
+    pub fn weighted(components: Vec<(f64, &'a dyn Pdf)>) -> Self {
+        let components: Vec<_> = components
+            .into_iter()
+            .filter(|&(weight, _)| weight > 0.)
+            .collect();
+        let total: f64 = components.iter().map(|&(weight, _)| weight).sum();
+
+        Self {
+            components: components
+                .into_iter()
+                .map(|(weight, pdf)| (weight / total, pdf))
+                .collect(),
+        }
+    }
+}
+
+impl Pdf for MixturePdf<'_> {
+    fn value(&self, direction: Vec3) -> f64 {
+        self.components
+            .iter()
+            .map(|&(weight, pdf)| weight * pdf.value(direction))
+            .sum()
+    }
+
+    fn sample(&self, rng: &mut dyn RngCore) -> Option<Vec3> {
+        let mut u = rng.gen::<f64>();
+        for &(weight, pdf) in &self.components {
+            if u < weight {
+                return pdf.sample(rng);
+            }
+            u -= weight;
+        }
+        // Rounding can leave `u` just past the last share
+        let &(_, pdf) = self.components.last()?;
+        pdf.sample(rng)
+    }
+}
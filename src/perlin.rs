@@ -0,0 +1,115 @@
+use std::sync::OnceLock;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::vector::{Point3, Vec3};
+
+/// Size of the lattice tables; noise repeats every this many units.
+pub(crate) const POINT_COUNT: usize = 256;
+
+/// Seed for the noise shared by the built-in textures, so a scene looks the
+/// same from one render to the next.
+const SHARED_SEED: u64 = 0x5eed;
+
+/// Gradient noise: smooth pseudo-random values between -1 and 1 that vary on
+/// the scale of one unit.
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new<T: Rng>(rng: &mut T) -> Self {
+        let gradients = (0..POINT_COUNT)
+            .map(|_| Vec3::random_range(rng, -1., 1.).unit_vector())
+            .collect();
+
+        Self {
+            gradients,
+            perm_x: permutation(rng),
+            perm_y: permutation(rng),
+            perm_z: permutation(rng),
+        }
+    }
+
+    /// The noise used by textures.
+    pub fn shared() -> &'static Perlin {
+        static SHARED: OnceLock<Perlin> = OnceLock::new();
+        SHARED.get_or_init(|| Perlin::new(&mut StdRng::seed_from_u64(SHARED_SEED)))
+    }
+
+    pub fn noise(&self, p: Point3) -> f64 {
+        let (i, u) = split(p.x());
+        let (j, v) = split(p.y());
+        let (k, w) = split(p.z());
+
+        let mut c = [[[Vec3::zero(); 2]; 2]; 2];
+        for (di, plane) in c.iter_mut().enumerate() {
+            for (dj, row) in plane.iter_mut().enumerate() {
+                for (dk, gradient) in row.iter_mut().enumerate() {
+                    let index = self.perm_x[(i + di as i64) as usize & (POINT_COUNT - 1)]
+                        ^ self.perm_y[(j + dj as i64) as usize & (POINT_COUNT - 1)]
+                        ^ self.perm_z[(k + dk as i64) as usize & (POINT_COUNT - 1)];
+                    *gradient = self.gradients[index];
+                }
+            }
+        }
+
+        interpolate(&c, u, v, w)
+    }
+
+    /// Sums `octaves` layers of noise, each at twice the frequency and half
+    /// the weight of the one before.
+    pub fn turbulence(&self, p: Point3, octaves: u32) -> f64 {
+        let mut sum = 0.;
+        let mut p = p;
+        let mut weight = 1.;
+
+        for _ in 0..octaves {
+            sum += weight * self.noise(p);
+            weight *= 0.5;
+            p *= 2.;
+        }
+
+        sum.abs()
+    }
+}
+
+/// A random ordering of the lattice table indices.
+pub(crate) fn permutation<T: Rng>(rng: &mut T) -> Vec<usize> {
+    let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+    p.shuffle(rng);
+    p
+}
+
+/// The lattice cell containing `x` and the position within it.
+fn split(x: f64) -> (i64, f64) {
+    let floor = x.floor();
+    (floor as i64, x - floor)
+}
+
+fn interpolate(c: &[[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    // Hermite smoothing hides the lattice
+    let uu = u * u * (3. - 2. * u);
+    let vv = v * v * (3. - 2. * v);
+    let ww = w * w * (3. - 2. * w);
+
+    let mut sum = 0.;
+    for (i, plane) in c.iter().enumerate() {
+        for (j, row) in plane.iter().enumerate() {
+            for (k, gradient) in row.iter().enumerate() {
+                let (i, j, k) = (i as f64, j as f64, k as f64);
+                let weight = Vec3::new(u - i, v - j, w - k);
+                sum += (i * uu + (1. - i) * (1. - uu))
+                    * (j * vv + (1. - j) * (1. - vv))
+                    * (k * ww + (1. - k) * (1. - ww))
+                    * gradient.dot_product(weight);
+            }
+        }
+    }
+
+    sum
+}
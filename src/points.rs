@@ -0,0 +1,286 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::bounds::{AABB, BVH};
+use crate::color::Color;
+use crate::export::Tessellation;
+use crate::ray::{Hit, HitRecord, Material, Ray, SurfaceDerivatives};
+use crate::scene::SceneStats;
+use crate::vector::{Point3, Vec3};
+use crate::world::Sphere;
+
+/// What each point of a `PointInstancer` is drawn as.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PointShape {
+    #[default]
+    Sphere,
+    /// A flat round splat that always faces the ray looking at it, which
+    /// reads as a point from any side and is cheaper than a sphere.
+    Disk,
+}
+
+impl FromStr for PointShape {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sphere" => Ok(PointShape::Sphere),
+            "disk" => Ok(PointShape::Disk),
+            _ => Err(format!("unknown point shape {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for PointShape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            PointShape::Sphere => "sphere",
+            PointShape::Disk => "disk",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One point of a `PointInstancer`.
+#[derive(Clone, Copy)]
+pub struct Point {
+    pub center: Point3,
+    pub radius: f64,
+    /// Replaces the instancer's material color for this point.
+    pub color: Option<Color>,
+}
+
+/// A disk facing the ray that hits it.
+struct Splat {
+    center: Point3,
+    radius: f64,
+    material: Material,
+}
+
+impl Splat {
+    /// Where `r` passes within the radius of the center, measured in the
+    /// plane through the center that faces the ray.
+    ///
+    /// The disk turns to face every ray, so rays leaving it would hit it
+    /// again; rays starting within its radius never see it.
+    fn intersect(&self, r: Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let radius_squared = self.radius * self.radius;
+        if (r.origin - self.center).length_squared() <= radius_squared {
+            return None;
+        }
+
+        let t = (self.center - r.origin).dot_product(r.direction) / r.direction.length_squared();
+        let inside = (r.at(t) - self.center).length_squared() <= radius_squared;
+        (inside && t_min <= t && t <= t_max).then_some(t)
+    }
+}
+
+impl Hit for Splat {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = self.intersect(r, t_min, t_max)?;
+
+        // Polar coordinates across the disk, with u out from the center
+        let offset = r.at(t) - self.center;
+        let normal = -r.direction.unit_vector();
+        let uv = (offset.length() / self.radius, 0.);
+        let derivatives = SurfaceDerivatives::flat(Vec3::zero(), Vec3::zero());
+
+        Some(HitRecord::new(
+            t,
+            r,
+            normal,
+            uv,
+            derivatives,
+            &self.material,
+        ))
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
+        let octant = Vec3::new(self.radius, self.radius, self.radius);
+
+        Some(AABB::new(self.center - octant, self.center + octant))
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add("splat", std::mem::size_of::<Self>());
+    }
+}
+
+/// A large set of points drawn as small spheres or disks, such as the
+/// particles of a simulation or a scanned point cloud, with its own BVH
+/// built once when it is created.
+pub struct PointInstancer {
+    point_count: usize,
+    shape: PointShape,
+    bvh: Box<dyn Hit>,
+}
+
+impl PointInstancer {
+    /// Every point gets a copy of `material`, with the point's color in
+    /// place of its albedo (or emission, for lights) where it has one.
+    pub fn new(points: &[Point], shape: PointShape, material: Material) -> Self {
+        let material = |point: &Point| match point.color {
+            Some(color) => recolor(&material, color),
+            None => material.clone(),
+        };
+        // Points of one shape are stored in place in the BVH
+        let bvh: Box<dyn Hit> = match shape {
+            PointShape::Sphere => {
+                let spheres = points
+                    .iter()
+                    .map(|point| Sphere::new(point.center, point.radius, material(point)))
+                    .collect();
+                Box::new(BVH::new(spheres, (0., 0.)))
+            }
+            PointShape::Disk => {
+                let splats = points
+                    .iter()
+                    .map(|point| Splat {
+                        center: point.center,
+                        radius: point.radius,
+                        material: material(point),
+                    })
+                    .collect();
+                Box::new(BVH::new(splats, (0., 0.)))
+            }
+        };
+
+        Self {
+            point_count: points.len(),
+            shape,
+            bvh,
+        }
+    }
+
+    /// Loads points from a text file with one point per line, given as
+    /// `x y z radius` or `x y z radius r g b` with linear colors from 0 to
+    /// 1. Blank lines and lines starting with `#` are skipped.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        shape: PointShape,
+        material: Material,
+    ) -> Result<Self, PointsError> {
+        let source = fs::read_to_string(path)?;
+
+        let mut points = vec![];
+        for (n, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: &str| PointsError::Parse {
+                line: n + 1,
+                message: message.to_string(),
+            };
+            let values: Vec<f64> = line
+                .split_whitespace()
+                .map(|f| f.parse().map_err(|_| error("invalid number")))
+                .collect::<Result<_, _>>()?;
+
+            let (center, radius, color) = match values[..] {
+                [x, y, z, radius] => (Point3::new(x, y, z), radius, None),
+                [x, y, z, radius, r, g, b] => {
+                    (Point3::new(x, y, z), radius, Some(Color::new(r, g, b)))
+                }
+                _ => return Err(error("point needs 4 or 7 values")),
+            };
+            if radius <= 0. || radius.is_nan() {
+                return Err(error("radius must be positive"));
+            }
+
+            points.push(Point {
+                center,
+                radius,
+                color,
+            });
+        }
+
+        if points.is_empty() {
+            return Err(PointsError::Empty);
+        }
+
+        Ok(Self::new(&points, shape, material))
+    }
+
+    pub fn point_count(&self) -> usize {
+        self.point_count
+    }
+
+    pub fn shape(&self) -> PointShape {
+        self.shape
+    }
+}
+
+impl Hit for PointInstancer {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.bvh.hit(r, t_min, t_max)
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.bvh.occluded(r, t_min, t_max)
+    }
+
+    fn hits<'a>(&'a self, r: Ray, t_min: f64, t_max: f64, hits: &mut Vec<HitRecord<'a>>) {
+        self.bvh.hits(r, t_min, t_max, hits);
+    }
+
+    fn bounds(&self, time: (f64, f64)) -> Option<AABB> {
+        self.bvh.bounds(time)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add("point instancer", std::mem::size_of::<Self>());
+        self.bvh.count(stats);
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        self.bvh.tessellate(time, mesh);
+    }
+}
+
+/// A copy of `material` in `color`. Glass has no color, so it is unchanged.
+fn recolor(material: &Material, color: Color) -> Material {
+    let mut material = material.clone();
+    match &mut material {
+        Material::Isotropic { albedo }
+        | Material::Lambertian { albedo }
+        | Material::ShadowCatcher { albedo } => *albedo = color.into(),
+        Material::Metal { albedo, .. } => *albedo = color,
+        Material::DiffuseLight { emit, .. } => *emit = color.into(),
+        Material::Dialectric { .. } => {}
+    }
+    material
+}
+
+#[derive(Debug)]
+pub enum PointsError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Empty,
+}
+
+impl fmt::Display for PointsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PointsError::Io(err) => write!(f, "{}", err),
+            PointsError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            PointsError::Empty => write!(f, "no points found"),
+        }
+    }
+}
+
+impl std::error::Error for PointsError {}
+
+impl From<io::Error> for PointsError {
+    fn from(err: io::Error) -> Self {
+        PointsError::Io(err)
+    }
+}
@@ -0,0 +1,140 @@
+use std::io::{self, IsTerminal};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::render::Budget;
+
+/// Shortest time between redraws, so threads finishing tiles together don't
+/// flood the terminal.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Width of the bar in characters.
+const BAR_WIDTH: usize = 30;
+
+/// A line on stderr showing how far a render has got, how fast it is taking
+/// samples and how long it has left, redrawn in place as tiles finish.
+///
+/// When stderr isn't a terminal only the final state is written, to keep
+/// logs readable.
+pub struct ProgressBar {
+    budget: Budget,
+    pixels: usize,
+    interactive: bool,
+    last_draw: Mutex<Option<Instant>>,
+}
+
+impl ProgressBar {
+    /// A progress bar for rendering `pixels` pixels within `budget`.
+    pub fn new(budget: Budget, pixels: usize) -> Self {
+        Self {
+            budget,
+            pixels,
+            interactive: io::stderr().is_terminal(),
+            last_draw: Mutex::new(None),
+        }
+    }
+
+    /// Redraws the line for a render `samples` samples per pixel in, with
+    /// the finished part of the current pass as a fraction, after `elapsed`
+    /// time spent rendering. Does nothing if it was redrawn very recently.
+    pub fn update(&self, samples: f64, elapsed: Duration) {
+        if !self.interactive {
+            return;
+        }
+
+        let Ok(mut last_draw) = self.last_draw.try_lock() else {
+            // Another thread is drawing
+            return;
+        };
+        if last_draw.is_some_and(|last| last.elapsed() < REDRAW_INTERVAL) {
+            return;
+        }
+        *last_draw = Some(Instant::now());
+
+        // Clears what's left of a longer previous line
+        eprint!("\r{}\x1b[K", self.line(samples, elapsed, None));
+    }
+
+    /// Draws the line one last time, for a render that ran to the end of its
+    /// budget or was `cancelled`, and ends it.
+    pub fn finish(&self, samples: f64, elapsed: Duration, cancelled: bool) {
+        let line = self.line(samples, elapsed, Some(cancelled));
+        if self.interactive {
+            eprintln!("\r{}\x1b[K", line);
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+
+    /// The progress line, with `finished` set to whether the render was
+    /// cancelled once it is over.
+    fn line(&self, samples: f64, elapsed: Duration, finished: Option<bool>) -> String {
+        let seconds = elapsed.as_secs_f64();
+        let rate = if seconds > 0. {
+            samples * self.pixels as f64 / seconds
+        } else {
+            0.
+        };
+        let rate = format!("{} samples/s", format_count(rate));
+
+        let fraction = match finished {
+            // A time limit stops short of the deadline rather than overshoot
+            Some(false) => Some(1.),
+            _ => self.budget.fraction(samples, elapsed),
+        };
+        let Some(fraction) = fraction else {
+            // Without a budget there is no end to measure against
+            return format!(
+                "{:.1} samples per pixel  {}  {} elapsed",
+                samples,
+                rate,
+                format_duration(elapsed)
+            );
+        };
+
+        let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+        let bar = format!("{}{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled));
+        let eta = if finished == Some(true) {
+            format!("stopped after {}", format_duration(elapsed))
+        } else if fraction >= 1. {
+            format!("done in {}", format_duration(elapsed))
+        } else if fraction > 0. {
+            let left = seconds * (1. - fraction) / fraction;
+            format!("ETA {}", format_duration(Duration::from_secs_f64(left)))
+        } else {
+            "ETA --".to_string()
+        };
+
+        format!("[{}] {:5.1}%  {}  {}", bar, fraction * 100., rate, eta)
+    }
+}
+
+/// A count with a k, M or G suffix, to three significant figures.
+fn format_count(count: f64) -> String {
+    let (value, suffix) = match count {
+        c if c >= 1e9 => (c / 1e9, "G"),
+        c if c >= 1e6 => (c / 1e6, "M"),
+        c if c >= 1e3 => (c / 1e3, "k"),
+        c => return format!("{:.0}", c),
+    };
+    let precision = match value {
+        v if v >= 100. => 0,
+        v if v >= 10. => 1,
+        _ => 2,
+    };
+    format!("{:.*}{}", precision, value, suffix)
+}
+
+/// A duration to the second, in the largest units that fit, e.g. `1h02m`,
+/// `3m05s` or `12s`.
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64().round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h{:02}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m{:02}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
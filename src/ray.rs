@@ -1,15 +1,45 @@
 use rand::Rng;
+use std::f64::consts::PI;
+use std::fmt;
 use std::ops::Neg;
+use std::str::FromStr;
 
+use crate::accel::AccelNode;
 use crate::bounds::AABB;
-use crate::color;
-use crate::vector::{random_in_unit_sphere, random_unit_vector, Color, Point3, Vec3};
+use crate::color::{self, Color};
+use crate::export::Tessellation;
+use crate::light::{LightPower, DEFAULT_LIGHT_GROUP};
+use crate::sampling::{
+    random_cosine_direction, random_ggx_normal, random_in_unit_sphere, random_unit_vector,
+};
+use crate::scene::SceneStats;
+use crate::texture::{TexCoords, Texture};
+use crate::vector::{Onb, Point3, Vec3};
+use crate::visibility::Visibility;
+use crate::volume::Medium;
 
 #[derive(Clone, Copy)]
 pub struct Ray {
     pub origin: Point3,
+    /// Changing the direction of an existing ray leaves `inv_direction`
+    /// stale, so make a new one with `Ray::new` instead.
     pub direction: Vec3,
     pub time: f64,
+    /// Rays through the neighbouring pixels, used to find how much of a
+    /// surface a pixel covers.
+    pub differential: Option<RayDifferential>,
+    /// The reciprocal of each component of `direction`, computed once so the
+    /// many bounding box tests along the ray don't each have to divide.
+    inv_direction: Vec3,
+}
+
+/// Offset rays one pixel over in x and one pixel over in y.
+#[derive(Clone, Copy)]
+pub struct RayDifferential {
+    pub rx_origin: Point3,
+    pub rx_direction: Vec3,
+    pub ry_origin: Point3,
+    pub ry_direction: Vec3,
 }
 
 impl Ray {
@@ -18,6 +48,24 @@ impl Ray {
             origin,
             direction,
             time,
+            differential: None,
+            inv_direction: Vec3::new(
+                direction.x().recip(),
+                direction.y().recip(),
+                direction.z().recip(),
+            ),
+        }
+    }
+
+    /// `1 / direction` for each axis, infinite where the direction is zero.
+    pub fn inv_direction(&self) -> Vec3 {
+        self.inv_direction
+    }
+
+    pub fn with_differential(self, differential: RayDifferential) -> Self {
+        Self {
+            differential: Some(differential),
+            ..self
         }
     }
 
@@ -26,24 +74,68 @@ impl Ray {
     }
 }
 
+/// How the surface position and normal change with the texture coordinates.
+#[derive(Clone, Copy)]
+pub struct SurfaceDerivatives {
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub dndu: Vec3,
+    pub dndv: Vec3,
+}
+
+impl SurfaceDerivatives {
+    /// Derivatives of a surface whose normal doesn't change, such as a plane.
+    pub fn flat(dpdu: Vec3, dpdv: Vec3) -> Self {
+        Self {
+            dpdu,
+            dpdv,
+            dndu: Vec3::zero(),
+            dndv: Vec3::zero(),
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct HitRecord<'a> {
     pub p: Point3,
     pub t: f64,
     pub normal: Vec3,
     pub front_face: bool,
+    pub uv: (f64, f64),
+    pub derivatives: SurfaceDerivatives,
     pub material: &'a Material,
+    /// Set on hits with holdout objects, which camera rays see as a
+    /// transparent hole.
+    pub holdout: bool,
+    /// The kinds of rays that see the surface hit; rays of other kinds
+    /// carry on through it.
+    pub visibility: Visibility,
+    /// How fast the surface at the hit is moving, per unit of time, for
+    /// motion vectors. Zero for objects that don't move.
+    pub velocity: Vec3,
 }
 
 impl<'a> HitRecord<'a> {
-    pub fn new(t: f64, r: Ray, outward_normal: Vec3, material: &'a Material) -> Self {
+    pub fn new(
+        t: f64,
+        r: Ray,
+        outward_normal: Vec3,
+        uv: (f64, f64),
+        derivatives: SurfaceDerivatives,
+        material: &'a Material,
+    ) -> Self {
         let p = r.at(t);
         let front_face = r.direction.dot_product(outward_normal) < 0.;
 
-        let normal = if front_face {
-            outward_normal
+        let (normal, derivatives) = if front_face {
+            (outward_normal, derivatives)
         } else {
-            -outward_normal
+            let flipped = SurfaceDerivatives {
+                dndu: -derivatives.dndu,
+                dndv: -derivatives.dndv,
+                ..derivatives
+            };
+            (-outward_normal, flipped)
         };
 
         Self {
@@ -51,35 +143,433 @@ impl<'a> HitRecord<'a> {
             t,
             normal,
             front_face,
+            uv,
+            derivatives,
             material,
+            holdout: false,
+            visibility: Visibility::ALL,
+            velocity: Vec3::zero(),
         }
     }
+
+    /// Texture coordinates at the hit, with the pixel footprint estimated
+    /// from where the ray's differentials meet the tangent plane.
+    pub fn tex_coords(&self, r: &Ray) -> TexCoords {
+        let point = TexCoords::point(self.uv, self.p);
+
+        let rd = match r.differential {
+            Some(rd) => rd,
+            None => return point,
+        };
+
+        let n = self.normal;
+        let d = n.dot_product(self.p);
+        let tx = (d - n.dot_product(rd.rx_origin)) / n.dot_product(rd.rx_direction);
+        let ty = (d - n.dot_product(rd.ry_origin)) / n.dot_product(rd.ry_direction);
+        if !tx.is_finite() || !ty.is_finite() {
+            return point;
+        }
+
+        let dpdx = rd.rx_origin + rd.rx_direction * tx - self.p;
+        let dpdy = rd.ry_origin + rd.ry_direction * ty - self.p;
+
+        // Least-squares solution of dpdx = dpdu * dudx + dpdv * dvdx
+        let SurfaceDerivatives { dpdu, dpdv, .. } = self.derivatives;
+        let a11 = dpdu.dot_product(dpdu);
+        let a12 = dpdu.dot_product(dpdv);
+        let a22 = dpdv.dot_product(dpdv);
+        let det = a11 * a22 - a12 * a12;
+
+        let solve = |dp: Vec3| {
+            if det.abs() < 1e-12 {
+                return (0., 0.);
+            }
+            let b1 = dpdu.dot_product(dp);
+            let b2 = dpdv.dot_product(dp);
+            ((a22 * b1 - a12 * b2) / det, (a11 * b2 - a12 * b1) / det)
+        };
+
+        TexCoords {
+            dpdx,
+            dpdy,
+            duvdx: solve(dpdx),
+            duvdy: solve(dpdy),
+            ..point
+        }
+    }
+
+    /// How the normal changes from one pixel to the next in x and y.
+    fn normal_differentials(&self, tc: &TexCoords) -> (Vec3, Vec3) {
+        let SurfaceDerivatives { dndu, dndv, .. } = self.derivatives;
+        let dndx = dndu * tc.duvdx.0 + dndv * tc.duvdx.1;
+        let dndy = dndu * tc.duvdy.0 + dndv * tc.duvdy.1;
+        (dndx, dndy)
+    }
+
+    /// Differentials of a perfectly reflected ray leaving in direction `wi`.
+    fn reflected_differential(&self, r: &Ray, tc: &TexCoords, wi: Vec3) -> Option<RayDifferential> {
+        let rd = r.differential?;
+        let n = self.normal;
+        let wo = -r.direction.unit_vector();
+        let (dndx, dndy) = self.normal_differentials(tc);
+
+        let direction = |offset_direction: Vec3, dndx: Vec3| {
+            let dwodx = -offset_direction.unit_vector() - wo;
+            let ddndx = dwodx.dot_product(n) + wo.dot_product(dndx);
+            wi - dwodx + (dndx * wo.dot_product(n) + n * ddndx) * 2.
+        };
+
+        Some(RayDifferential {
+            rx_origin: self.p + tc.dpdx,
+            rx_direction: direction(rd.rx_direction, dndx),
+            ry_origin: self.p + tc.dpdy,
+            ry_direction: direction(rd.ry_direction, dndy),
+        })
+    }
+
+    /// Differentials of a ray refracted into direction `wi` with relative
+    /// index of refraction `eta`.
+    fn refracted_differential(
+        &self,
+        r: &Ray,
+        tc: &TexCoords,
+        wi: Vec3,
+        eta: f64,
+    ) -> Option<RayDifferential> {
+        let rd = r.differential?;
+        let n = self.normal;
+        let wo = -r.direction.unit_vector();
+        let (dndx, dndy) = self.normal_differentials(tc);
+
+        let cos_o = wo.dot_product(n);
+        let cos_i = wi.dot_product(n).abs();
+        let mu = eta * cos_o - cos_i;
+
+        let direction = |offset_direction: Vec3, dndx: Vec3| {
+            let dwodx = -offset_direction.unit_vector() - wo;
+            let ddndx = dwodx.dot_product(n) + wo.dot_product(dndx);
+            let dmudx = (eta - (eta * eta * cos_o) / cos_i) * ddndx;
+            wi - dwodx * eta + dndx * mu + n * dmudx
+        };
+
+        Some(RayDifferential {
+            rx_origin: self.p + tc.dpdx,
+            rx_direction: direction(rd.rx_direction, dndx),
+            ry_origin: self.p + tc.dpdy,
+            ry_direction: direction(rd.ry_direction, dndy),
+        })
+    }
 }
 
-pub trait Hit {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+pub trait Hit: Send + Sync {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
     fn bounds(&self, time: (f64, f64)) -> Option<AABB>;
+
+    /// Whether anything lies along `r` between `t_min` and `t_max`, for
+    /// shadow rays. Unlike `hit` this can stop at the first hit found, in any
+    /// order, and never builds a `HitRecord`.
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.hit(r, t_min, t_max).is_some()
+    }
+
+    /// The fraction of light that makes it along `r` between `t_min` and
+    /// `t_max`, for shadow rays: none past anything solid, and some through
+    /// a participating medium, which may estimate it.
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        if self.occluded(r, t_min, t_max) {
+            0.
+        } else {
+            1.
+        }
+    }
+
+    /// Adds every hit along `r` between `t_min` and `t_max` to `hits`, in
+    /// any order, for queries that need more than the closest one, such as
+    /// CSG or tracking nested media. By default `hit` is asked again from
+    /// just past each hit it finds.
+    fn hits<'a>(&'a self, r: Ray, t_min: f64, t_max: f64, hits: &mut Vec<HitRecord<'a>>) {
+        let mut t_min = t_min;
+        while let Some(hit) = self.hit(r, t_min, t_max) {
+            hits.push(hit);
+            t_min = hit.t.next_up();
+        }
+    }
+
+    /// Updates any cached bounds for a new time interval, e.g. the next frame
+    /// of an animation, and returns the new bounds.
+    fn refit(&mut self, time: (f64, f64)) -> Option<AABB> {
+        self.bounds(time)
+    }
+
+    /// Adds this object, and any it's made of, to `stats`.
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add("other", std::mem::size_of_val(self));
+    }
+
+    /// Adds the nodes of the acceleration structure this object is, if it
+    /// is one, to `nodes`, with the root `depth` levels down. Objects that
+    /// aren't acceleration structures add nothing.
+    fn accel_nodes(&self, _depth: usize, _time: (f64, f64), _nodes: &mut Vec<AccelNode>) {}
+
+    /// Adds triangles approximating this object as it is at `time` to
+    /// `mesh`, for exporting the scene. Objects that can't be tessellated,
+    /// such as volumes, are counted as left out.
+    fn tessellate(&self, _time: f64, mesh: &mut Tessellation) {
+        mesh.skip();
+    }
 }
 
-#[derive(Clone, Copy)]
+/// A boxed object is hit like the object itself, so structures holding
+/// objects of one kind, such as `BVH`, can hold boxes of any kind.
+impl<T: Hit + ?Sized> Hit for Box<T> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        (**self).hit(r, t_min, t_max)
+    }
+
+    fn bounds(&self, time: (f64, f64)) -> Option<AABB> {
+        (**self).bounds(time)
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        (**self).occluded(r, t_min, t_max)
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        (**self).transmittance(r, t_min, t_max)
+    }
+
+    fn hits<'a>(&'a self, r: Ray, t_min: f64, t_max: f64, hits: &mut Vec<HitRecord<'a>>) {
+        (**self).hits(r, t_min, t_max, hits)
+    }
+
+    fn refit(&mut self, time: (f64, f64)) -> Option<AABB> {
+        (**self).refit(time)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        (**self).count(stats)
+    }
+
+    fn accel_nodes(&self, depth: usize, time: (f64, f64), nodes: &mut Vec<AccelNode>) {
+        (**self).accel_nodes(depth, time, nodes)
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        (**self).tessellate(time, mesh)
+    }
+}
+
+/// The first `n` hits along `r` between `t_min` and `t_max`, nearest first,
+/// or all of them if there are fewer.
+pub fn closest_hits(
+    object: &dyn Hit,
+    r: Ray,
+    t_min: f64,
+    t_max: f64,
+    n: usize,
+) -> Vec<HitRecord<'_>> {
+    let mut hits = vec![];
+    object.hits(r, t_min, t_max, &mut hits);
+    hits.sort_by(|a, b| a.t.total_cmp(&b.t));
+    hits.truncate(n);
+    hits
+}
+
+#[derive(Clone)]
 pub enum Material {
-    Dialectric { index_of_refraction: f64 },
-    Lambertian { albedo: Color },
-    Metal { albedo: Color, fuzz: f64 },
+    Dialectric {
+        index_of_refraction: f64,
+        /// What fills the inside, if it isn't clear.
+        medium: Option<Medium>,
+        /// Where dielectrics overlap, e.g. water poured into a glass whose
+        /// surface sits just inside the glass's walls, the one with the
+        /// highest priority fills the overlap and the others' surfaces
+        /// there are ignored.
+        priority: u32,
+        /// How rough the surface is to light reflecting off it, from 0 for
+        /// polished to 1, and to light passing through it, which frosting
+        /// roughens without dulling reflections.
+        reflection_roughness: f64,
+        transmission_roughness: f64,
+    },
+    /// Gives off light from its front face and doesn't reflect any.
+    DiffuseLight {
+        emit: Texture,
+        /// Index of the light group its light is credited to, so groups can
+        /// be rebalanced after rendering.
+        group: usize,
+        /// Total light to give off, in which case `emit` only sets its
+        /// color. Converted to radiance by the sphere or rectangle it's
+        /// given to, over its area; other objects ignore it.
+        power: Option<LightPower>,
+    },
+    /// Scatters light equally in every direction, for the inside of a
+    /// participating medium such as a `Volume`.
+    Isotropic {
+        albedo: Texture,
+    },
+    Lambertian {
+        albedo: Texture,
+    },
+    Metal {
+        albedo: Color,
+        fuzz: f64,
+    },
+    /// A matte stand-in for the ground of a photographic backplate. Camera
+    /// rays see through it, except for the shadows and bounce light that
+    /// other objects cast onto it; to everything else it is diffuse.
+    ShadowCatcher {
+        albedo: Texture,
+    },
 }
 
 impl Material {
-    pub fn scatter<T: Rng>(&self, rng: &mut T, r: Ray, hit: HitRecord) -> Option<ScatterResult> {
+    /// Radiance given off towards the origin of `r`.
+    pub fn emitted(&self, r: &Ray, hit: &HitRecord) -> Color {
+        if !hit.front_face {
+            return color::BLACK;
+        }
+
+        self.emission(&hit.tex_coords(r))
+    }
+
+    /// Radiance given off by the front face, looked up at `tc`.
+    pub fn emission(&self, tc: &TexCoords) -> Color {
+        match self {
+            Material::DiffuseLight { emit, .. } => emit.value(tc),
+            _ => color::BLACK,
+        }
+    }
+
+    /// The material on an object with surface `area`, with the total light
+    /// it gives off, if set, turned into radiance. Only solid colors can be.
+    pub fn emitting_over(self, area: f64) -> Self {
+        match self {
+            Material::DiffuseLight {
+                emit: Texture::Solid(color),
+                group,
+                power: Some(power),
+            } => Material::DiffuseLight {
+                emit: Texture::Solid(power.radiance(color, area)),
+                group,
+                power: None,
+            },
+            material => material,
+        }
+    }
+
+    /// The medium filling the inside of the surface, which rays leaving
+    /// through its back face have crossed.
+    pub fn medium(&self) -> Option<&Medium> {
         match self {
-            &Material::Dialectric {
+            Material::Dialectric { medium, .. } => medium.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The priority of a dielectric, zero for anything else.
+    pub fn priority(&self) -> u32 {
+        match self {
+            Material::Dialectric { priority, .. } => *priority,
+            _ => 0,
+        }
+    }
+
+    pub fn index_of_refraction(&self) -> Option<f64> {
+        match self {
+            Material::Dialectric {
                 index_of_refraction,
+                ..
+            } => Some(*index_of_refraction),
+            _ => None,
+        }
+    }
+
+    pub fn light_group(&self) -> usize {
+        match self {
+            Material::DiffuseLight { group, .. } => *group,
+            _ => DEFAULT_LIGHT_GROUP,
+        }
+    }
+
+    /// The fraction of light the surface reflects, ignoring direction.
+    pub fn albedo(&self, tc: &TexCoords) -> Color {
+        match self {
+            Material::Dialectric { .. } => color::WHITE,
+            Material::DiffuseLight { .. } => color::BLACK,
+            Material::Isotropic { albedo } => albedo.value(tc),
+            Material::Lambertian { albedo } => albedo.value(tc),
+            Material::Metal { albedo, .. } => *albedo,
+            Material::ShadowCatcher { albedo } => albedo.value(tc),
+        }
+    }
+
+    /// The albedo of materials that reflect light equally in all
+    /// directions, which can be lit by sampling lights directly.
+    pub fn diffuse_albedo(&self, tc: &TexCoords) -> Option<Color> {
+        match self {
+            Material::Lambertian { albedo } | Material::ShadowCatcher { albedo } => {
+                Some(albedo.value(tc))
+            }
+            _ => None,
+        }
+    }
+
+    /// Probability density, per unit solid angle, of `scatter` sending light
+    /// arriving at `hit` off along `direction`. Zero for mirrors and glass,
+    /// whose single direction has no density.
+    pub fn scattering_pdf(&self, hit: &HitRecord, direction: Vec3) -> f64 {
+        match self {
+            Material::Isotropic { .. } => 1. / (4. * PI),
+            Material::Lambertian { .. } | Material::ShadowCatcher { .. } => {
+                let cos_theta = direction.unit_vector().dot_product(hit.normal);
+                cos_theta.max(0.) / PI
+            }
+            _ => 0.,
+        }
+    }
+
+    /// Scatters light arriving along `r` at `hit`, with only empty space
+    /// surrounding the object.
+    pub fn scatter<T: Rng>(&self, rng: &mut T, r: Ray, hit: HitRecord) -> Option<ScatterResult> {
+        self.scatter_within(rng, r, hit, 1.)
+    }
+
+    /// Like `scatter`, with the object surrounded by a medium with index of
+    /// refraction `surrounding_ior`, which bends light less at its surface.
+    pub fn scatter_within<T: Rng>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        hit: HitRecord,
+        surrounding_ior: f64,
+    ) -> Option<ScatterResult> {
+        let tc = hit.tex_coords(&r);
+
+        match *self {
+            Material::Dialectric {
+                index_of_refraction,
+                reflection_roughness,
+                transmission_roughness,
+                ..
             } => {
                 let refraction_ratio = if hit.front_face {
-                    index_of_refraction.recip()
+                    surrounding_ior / index_of_refraction
                 } else {
-                    index_of_refraction
+                    index_of_refraction / surrounding_ior
                 };
 
+                if reflection_roughness > 0. || transmission_roughness > 0. {
+                    return scatter_rough_dielectric(
+                        rng,
+                        r,
+                        &hit,
+                        refraction_ratio,
+                        reflection_roughness,
+                        transmission_roughness,
+                    );
+                }
+
                 let unit_direction = r.direction.unit_vector();
                 let cos_theta = unit_direction.neg().dot_product(hit.normal).min(1.);
                 let sin_theta = (1. - cos_theta * cos_theta).sqrt();
@@ -87,44 +577,66 @@ impl Material {
                 let cannot_refract = refraction_ratio * sin_theta > 1.;
                 let reflectance = reflectance(cos_theta, refraction_ratio);
 
-                let direction = if cannot_refract || reflectance > rng.gen() {
-                    unit_direction.reflect(hit.normal)
+                let (direction, differential) = if cannot_refract || reflectance > rng.gen() {
+                    let direction = unit_direction.reflect(hit.normal);
+                    (direction, hit.reflected_differential(&r, &tc, direction))
                 } else {
-                    unit_direction.refract(hit.normal, refraction_ratio)
+                    let direction = unit_direction
+                        .refract(hit.normal, refraction_ratio)
+                        .unit_vector();
+                    let differential =
+                        hit.refracted_differential(&r, &tc, direction, refraction_ratio);
+                    (direction, differential)
                 };
 
-                let scattered = Ray::new(hit.p, direction, r.time);
+                let scattered = Ray {
+                    differential,
+                    ..Ray::new(hit.p, direction, r.time)
+                };
                 let attenuation = color::WHITE;
 
                 Some(ScatterResult {
                     scattered,
                     attenuation,
+                    specular: true,
                 })
             }
 
-            &Material::Lambertian { albedo } => {
-                let scatter_direction = hit.normal + random_unit_vector(rng);
+            Material::DiffuseLight { .. } => None,
 
-                // Catch degenerate scatter direction
-                let scatter_direction = if scatter_direction.near_zero(1e-8) {
-                    hit.normal
-                } else {
-                    scatter_direction
-                };
+            Material::Isotropic { ref albedo } => {
+                let scattered = Ray::new(hit.p, random_unit_vector(rng), r.time);
+                let attenuation = albedo.value(&tc);
 
+                Some(ScatterResult {
+                    scattered,
+                    attenuation,
+                    specular: false,
+                })
+            }
+
+            Material::Lambertian { ref albedo } | Material::ShadowCatcher { ref albedo } => {
+                let scatter_direction = Onb::new(hit.normal).local(random_cosine_direction(rng));
+
+                // Diffuse bounces spread out too much for differentials to
+                // be meaningful, so they are dropped
                 let scattered = Ray::new(hit.p, scatter_direction, r.time);
-                let attenuation = albedo;
+                let attenuation = albedo.value(&tc);
 
                 Some(ScatterResult {
                     scattered,
                     attenuation,
+                    specular: false,
                 })
             }
 
-            &Material::Metal { albedo, fuzz } => {
+            Material::Metal { albedo, fuzz } => {
                 let reflected = r.direction.unit_vector().reflect(hit.normal);
                 let fuzz_offset = random_in_unit_sphere(rng) * fuzz;
-                let scattered = Ray::new(hit.p, reflected + fuzz_offset, r.time);
+                let scattered = Ray {
+                    differential: hit.reflected_differential(&r, &tc, reflected),
+                    ..Ray::new(hit.p, reflected + fuzz_offset, r.time)
+                };
                 let attenuation = albedo;
 
                 if scattered.direction.dot_product(hit.normal) <= 0. {
@@ -134,20 +646,245 @@ impl Material {
                 Some(ScatterResult {
                     scattered,
                     attenuation,
+                    specular: true,
                 })
             }
         }
     }
 }
 
+/// A material to render every surface with in place of its own, to judge
+/// lighting and geometry on their own. Lights and media keep their
+/// materials.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MaterialOverride {
+    /// Neutral gray diffuse.
+    Clay,
+}
+
+impl MaterialOverride {
+    pub fn material(self) -> Material {
+        match self {
+            MaterialOverride::Clay => Material::Lambertian {
+                albedo: Color::new(0.5, 0.5, 0.5).into(),
+            },
+        }
+    }
+}
+
+impl FromStr for MaterialOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clay" => Ok(MaterialOverride::Clay),
+            _ => Err(format!("unknown material override {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for MaterialOverride {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            MaterialOverride::Clay => "clay",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 pub struct ScatterResult {
     pub scattered: Ray,
     pub attenuation: Color,
+    /// Whether the direction was picked without a density to weigh it by,
+    /// as by mirrors and glass. Lights can't usefully be sampled from such
+    /// surfaces, so light they reflect is only found by following the ray.
+    pub specular: bool,
 }
 
-fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
+/// Scatters light off or through a rough dielectric surface, made of tiny
+/// facets facing every which way with the GGX distribution (Walter et al.,
+/// "Microfacet Models for Refraction through Rough Surfaces").
+///
+/// Reflection and transmission each have their own roughness. One is picked
+/// by the Fresnel reflectance of the surface as a whole, then a facet from
+/// that one's distribution, so the weight of each sample stays close to 1.
+fn scatter_rough_dielectric<T: Rng>(
+    rng: &mut T,
+    r: Ray,
+    hit: &HitRecord,
+    refraction_ratio: f64,
+    reflection_roughness: f64,
+    transmission_roughness: f64,
+) -> Option<ScatterResult> {
+    let tc = hit.tex_coords(&r);
+    let unit_direction = r.direction.unit_vector();
+    let incoming = -unit_direction;
+    let cos_incoming = incoming.dot_product(hit.normal);
+    if cos_incoming <= 0. {
+        return None;
+    }
+
+    let reflect_probability = reflectance(cos_incoming, refraction_ratio);
+    let reflecting = rng.gen::<f64>() < reflect_probability;
+    // Roughness is squared so that it looks about as rough as it says
+    let alpha = if reflecting {
+        reflection_roughness
+    } else {
+        transmission_roughness
+    }
+    .powi(2);
+
+    let facet = Onb::new(hit.normal).local(random_ggx_normal(rng, alpha));
+    let cos_facet = incoming.dot_product(facet);
+    if cos_facet <= 0. {
+        return None;
+    }
+
+    // Light a facet can't refract through is all reflected. Such facets
+    // belong to the transmission lobe, which reflects off them instead, so
+    // the reflection lobe leaves them out rather than count them twice
+    let sin_facet = (1. - cos_facet * cos_facet).sqrt();
+    let refracts = refraction_ratio * sin_facet <= 1.;
+    let reflects = reflecting || !refracts;
+
+    let (direction, differential, lobe_weight) = if reflects {
+        let direction = unit_direction.reflect(facet);
+        let differential = hit.reflected_differential(&r, &tc, direction);
+        let lobe_weight = match (reflecting, refracts) {
+            (true, true) => reflectance(cos_facet, refraction_ratio) / reflect_probability,
+            (true, false) => 0.,
+            (false, _) => 1. / (1. - reflect_probability),
+        };
+        (direction, differential, lobe_weight)
+    } else {
+        let direction = unit_direction
+            .refract(facet, refraction_ratio)
+            .unit_vector();
+        let differential = hit.refracted_differential(&r, &tc, direction, refraction_ratio);
+        let fresnel = reflectance(cos_facet, refraction_ratio);
+        (
+            direction,
+            differential,
+            (1. - fresnel) / (1. - reflect_probability),
+        )
+    };
+
+    // Facets can send light back into the surface, where it's lost
+    let cos_outgoing = direction.dot_product(hit.normal);
+    if lobe_weight <= 0. || reflects != (cos_outgoing > 0.) {
+        return None;
+    }
+
+    let shadowing = smith_g1(incoming, facet, hit.normal, alpha)
+        * smith_g1(direction, facet, hit.normal, alpha);
+    let weight =
+        lobe_weight * cos_facet * shadowing / (cos_incoming * facet.dot_product(hit.normal));
+
+    Some(ScatterResult {
+        scattered: Ray {
+            differential,
+            ..Ray::new(hit.p, direction, r.time)
+        },
+        attenuation: color::WHITE * weight,
+        specular: true,
+    })
+}
+
+/// The fraction of `facet` visible along `v` on a GGX surface with normal
+/// `normal` and roughness `alpha`, using Smith's approximation.
+fn smith_g1(v: Vec3, facet: Vec3, normal: Vec3, alpha: f64) -> f64 {
+    let cos_v = v.dot_product(normal);
+    if v.dot_product(facet) * cos_v <= 0. {
+        return 0.;
+    }
+
+    let tan2_v = (1. - cos_v * cos_v).max(0.) / (cos_v * cos_v);
+    2. / (1. + (1. + alpha * alpha * tan2_v).sqrt())
+}
+
+pub(crate) fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
     // Schlick's approximation for reflectance
     let r0 = (1. - ref_idx) / (1. + ref_idx);
     let r0 = r0 * r0;
     r0 + (1. - r0) * (1. - cosine).powi(5)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::background::SolidColor;
+    use crate::cam::Camera;
+    use crate::light::LightTree;
+    use crate::render::{render_to_f32, Budget, RenderConfig};
+    use crate::scene::Scene;
+    use crate::world::{Sphere, World};
+
+    /// A sphere of `material` filling most of the view, in a uniform white
+    /// environment.
+    fn furnace(material: Material) -> Scene {
+        Scene {
+            world: Box::new(World::new(vec![Box::new(Sphere::new(
+                Point3::new(0., 0., 0.),
+                1.,
+                material,
+            ))])),
+            lights: LightTree::new(vec![]),
+            light_groups: vec!["default".to_string()],
+            camera: Camera::new(
+                Point3::new(0., 0., 4.),
+                Point3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                30.,
+                1.,
+                0.,
+                4.,
+                (0., 1.),
+            ),
+            background: Box::new(SolidColor::new(color::WHITE)),
+            fog: None,
+        }
+    }
+
+    /// The average luminance of `scene` rendered with `samples_per_pixel`.
+    fn mean(scene: &Scene, samples_per_pixel: u32) -> f64 {
+        let config = RenderConfig {
+            width: 16,
+            height: 16,
+            budget: Budget {
+                samples_per_pixel: Some(samples_per_pixel),
+                ..Budget::default()
+            },
+            ..RenderConfig::default()
+        };
+        let pixels = render_to_f32(scene, &config);
+        let total: f64 = pixels
+            .chunks(4)
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64).luminance())
+            .sum();
+        total / (pixels.len() / 4) as f64
+    }
+
+    #[test]
+    fn rough_glass_keeps_the_light_of_a_white_furnace() {
+        // Smooth and rough lobes together, where facets the transmission
+        // lobe picks past the critical angle used to lose their light
+        for (reflection_roughness, transmission_roughness) in [(0.05, 0.3), (0.3, 0.05), (0.3, 0.3)]
+        {
+            let glass = Material::Dialectric {
+                index_of_refraction: 1.5,
+                medium: None,
+                priority: 0,
+                reflection_roughness,
+                transmission_roughness,
+            };
+            let mean = mean(&furnace(glass), 64);
+            assert!(
+                (mean - 1.).abs() < 0.04,
+                "roughness {}/{}: {}",
+                reflection_roughness,
+                transmission_roughness,
+                mean
+            );
+        }
+    }
+}
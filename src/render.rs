@@ -0,0 +1,1889 @@
+use std::cell::Cell;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::prelude::*;
+
+use crate::background::Background;
+use crate::cam::Camera;
+use crate::color::{Color, BLACK, WHITE};
+use crate::film::{Film, Sample, Surface};
+use crate::filter::Filter;
+use crate::guide::{Guide, GuideRegion};
+use crate::light::{LightTree, DEFAULT_LIGHT_GROUP};
+use crate::pdf::{CosinePdf, MixturePdf, Pdf};
+use crate::progress::ProgressBar;
+use crate::ray::{
+    reflectance, Hit, HitRecord, Material, MaterialOverride, Ray, ScatterResult, SurfaceDerivatives,
+};
+use crate::rng::{Sampler, SamplerFactory};
+use crate::sampling::random_unit_vector;
+use crate::scene::Scene;
+use crate::tile::TileOrder;
+use crate::vector::{Point3, Vec3};
+use crate::visibility::RayKind;
+use crate::volume::{FogSampling, Medium};
+
+/// When a progressive render should stop. Whichever limit is reached first
+/// ends the render; with none set it runs forever.
+#[derive(Clone, Copy, Default)]
+pub struct Budget {
+    pub samples_per_pixel: Option<u32>,
+    pub time_limit: Option<Duration>,
+    /// The noise to stop at, as a fraction of pixel values; see
+    /// `Film::noise_against`. It's estimated whenever the samples double,
+    /// from 8 on, and in between the render stops once the last estimate,
+    /// falling with the square root of the samples, says it's reached.
+    pub noise_threshold: Option<f64>,
+}
+
+impl Budget {
+    /// How much of the budget a render `samples` samples per pixel in after
+    /// `elapsed` has used, from 0 to 1, by whichever limit is further along.
+    /// None without either limit, when a render runs until cancelled.
+    pub fn fraction(&self, samples: f64, elapsed: Duration) -> Option<f64> {
+        let by_samples = self
+            .samples_per_pixel
+            .map(|target| samples / target.max(1) as f64);
+        let by_time = self
+            .time_limit
+            .map(|limit| elapsed.as_secs_f64() / limit.as_secs_f64().max(f64::EPSILON));
+        let fraction = match (by_samples, by_time) {
+            (Some(a), Some(b)) => a.max(b),
+            (a, b) => a.or(b)?,
+        };
+        Some(fraction.min(1.))
+    }
+}
+
+thread_local! {
+    /// Rays traced on this thread that haven't yet been added to
+    /// `Renderer::rays`.
+    static RAYS_TRACED: Cell<u64> = const { Cell::new(0) };
+}
+
+fn count_ray() {
+    RAYS_TRACED.with(|count| count.set(count.get() + 1));
+}
+
+/// Samples per pixel to size texture filters for when the budget doesn't say.
+const EXPECTED_SAMPLES: u32 = 64;
+
+/// How often a diffuse bounce follows the guide rather than the material,
+/// once the guide has learned something.
+const GUIDED_FRACTION: f64 = 0.5;
+
+/// Settings for rendering a scene straight into a pixel buffer.
+#[derive(Clone, Copy)]
+pub struct RenderConfig {
+    /// Image size in pixels, which should match the camera's aspect ratio.
+    pub width: usize,
+    pub height: usize,
+    pub budget: Budget,
+    pub max_depth: i32,
+    pub bounce_limits: BounceLimits,
+    /// See `Renderer::indirect_clamp`.
+    pub indirect_clamp: Option<f64>,
+    /// Seed for the sampling pattern; see `Renderer::seed`.
+    pub seed: u64,
+    /// See `Renderer::sampler`.
+    pub sampler: SamplerFactory,
+    /// Paths traced from each camera ray's hit on a diffuse surface.
+    pub light_samples: u32,
+    /// Self-intersection offset, or None to scale it with the scene.
+    pub epsilon: Option<f64>,
+    pub filter: Filter,
+    pub tile_order: TileOrder,
+    pub guiding: bool,
+    pub transparent_background: bool,
+    /// See `Renderer::transparent_shadows`.
+    pub transparent_shadows: bool,
+    pub material_override: Option<MaterialOverride>,
+    pub fog_sampling: FogSampling,
+    /// Threads to render with, or None to use the current rayon pool (by
+    /// default one thread per logical core).
+    pub threads: Option<usize>,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        Self {
+            width: 400,
+            height: 225,
+            budget: Budget {
+                samples_per_pixel: Some(100),
+                time_limit: None,
+                noise_threshold: None,
+            },
+            max_depth: 50,
+            bounce_limits: BounceLimits::default(),
+            indirect_clamp: None,
+            seed: 0,
+            sampler: SamplerFactory::default(),
+            light_samples: 1,
+            epsilon: None,
+            filter: Filter::default(),
+            tile_order: TileOrder::default(),
+            guiding: false,
+            transparent_background: false,
+            transparent_shadows: false,
+            material_override: None,
+            fog_sampling: FogSampling::default(),
+            threads: None,
+        }
+    }
+}
+
+/// Renders `scene` without any file I/O, returning linear RGBA floats in rows
+/// from the top, with the color premultiplied by alpha.
+pub fn render_to_f32(scene: &Scene, config: &RenderConfig) -> Vec<f32> {
+    render_film(scene, config, None, None, None).0.to_rgba_f32()
+}
+
+/// Renders `scene` without any file I/O, returning 8-bit sRGB RGBA in rows
+/// from the top, with straight alpha.
+pub fn render_to_rgba8(scene: &Scene, config: &RenderConfig) -> Vec<u8> {
+    render_film(scene, config, None, None, None).0.to_rgba8()
+}
+
+/// Renders `scene` into a new film, returning it along with whether the
+/// render ran to completion rather than being cancelled.
+pub(crate) fn render_film(
+    scene: &Scene,
+    config: &RenderConfig,
+    cancel: Option<&AtomicBool>,
+    pause: Option<&Pause>,
+    progress: Option<&AtomicU32>,
+) -> (Film, bool) {
+    let guide = match scene.world.bounds(scene.camera.time()) {
+        Some(bounds) if config.guiding => Some(Guide::new(bounds)),
+        _ => None,
+    };
+    let material_override = config.material_override.map(|kind| kind.material());
+
+    let renderer = Renderer {
+        guide: guide.as_ref(),
+        material_override: material_override.as_ref(),
+        max_depth: config.max_depth,
+        bounce_limits: config.bounce_limits,
+        indirect_clamp: config.indirect_clamp,
+        seed: config.seed,
+        sampler: config.sampler,
+        light_samples: config.light_samples,
+        epsilon: config.epsilon.unwrap_or_else(|| scene.epsilon()),
+        transparent_background: config.transparent_background,
+        transparent_shadows: config.transparent_shadows,
+        fog_sampling: config.fog_sampling,
+        cancel,
+        pause,
+        progress,
+        ..Renderer::new(scene)
+    };
+
+    let mut film = Film::with_filter(config.width, config.height, config.filter)
+        .with_tile_order(config.tile_order);
+    if config.transparent_background {
+        film = film.with_alpha();
+    }
+
+    let finished = match config.threads {
+        Some(threads) => rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .expect("failed to start render threads")
+            .install(|| renderer.render(&mut film, config.budget)),
+        None => renderer.render(&mut film, config.budget),
+    };
+    (film, finished)
+}
+
+/// Holds a render up between tiles while paused, keeping everything rendered
+/// so far.
+#[derive(Default)]
+pub struct Pause {
+    state: Mutex<PauseState>,
+    resumed: Condvar,
+}
+
+#[derive(Default)]
+struct PauseState {
+    /// When the current pause began.
+    since: Option<Instant>,
+    /// Length of all earlier pauses.
+    total: Duration,
+}
+
+impl Pause {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn pause(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.since.get_or_insert_with(Instant::now);
+    }
+
+    pub fn resume(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(since) = state.since.take() {
+            state.total += since.elapsed();
+        }
+        self.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().since.is_some()
+    }
+
+    /// How long the render has spent paused in total.
+    pub fn paused_time(&self) -> Duration {
+        let state = self.state.lock().unwrap();
+        state.total + state.since.map_or(Duration::ZERO, |since| since.elapsed())
+    }
+
+    /// Blocks while paused, waking up every so often to check `cancelled`.
+    /// Returns false if it was cancelled.
+    fn wait(&self, cancelled: impl Fn() -> bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while state.since.is_some() {
+            if cancelled() {
+                return false;
+            }
+            state = self
+                .resumed
+                .wait_timeout(state, Duration::from_millis(50))
+                .unwrap()
+                .0;
+        }
+        true
+    }
+}
+
+pub struct Renderer<'a> {
+    pub camera: &'a Camera,
+    pub world: &'a dyn Hit,
+    pub background: &'a (dyn Background + Sync),
+    /// The medium outside every object, if any, which lights are sampled
+    /// from as `fog_sampling` says.
+    pub fog: Option<&'a Medium>,
+    /// How points in the fog are picked to sample the lights from.
+    pub fog_sampling: FogSampling,
+    /// Lights to sample directly from diffuse surfaces, weighted by multiple
+    /// importance sampling against bounces that find them. Emissive objects
+    /// left out are only found by bounces, which is noisier for small ones.
+    pub lights: Option<&'a LightTree>,
+    /// Learns where light comes from during the render and steers diffuse
+    /// bounces towards it. Refined after every pass.
+    pub guide: Option<&'a Guide>,
+    /// Replaces the material of every surface that doesn't give off light.
+    pub material_override: Option<&'a Material>,
+    /// The most vertices a path can have, whatever kind of bounces it takes.
+    pub max_depth: i32,
+    /// The most bounces of each kind a path can take, within `max_depth`.
+    pub bounce_limits: BounceLimits,
+    /// The highest luminance, in pixel values, of the light a path brings
+    /// back from beyond its first diffuse bounce. Clamping it removes the
+    /// fireflies from rare paths to small bright lights, at the cost of
+    /// some indirect light, while lights and their mirror images seen
+    /// directly or through glass keep their full brightness, as does the
+    /// light they shine straight onto the first diffuse surface.
+    pub indirect_clamp: Option<f64>,
+    /// Seeds the random numbers each sample is taken with, which depend only
+    /// on the seed, the pixel and how many samples it already has. Renders
+    /// with the same seed come out the same whatever the thread count or tile
+    /// order, and ones with different seeds can be merged. Path guiding
+    /// learns from samples in whatever order they finish, so with a guide
+    /// renders only come out nearly the same.
+    pub seed: u64,
+    /// The random number generator samples are taken with, and whether
+    /// it's seeded for each sample, pixel or tile. Seeding less often than
+    /// each sample gives images that depend on how the samples are split
+    /// into passes, so they can't be extended or merged with the same seed.
+    pub sampler: SamplerFactory,
+    /// Paths traced onwards from where each camera ray hits a diffuse
+    /// surface, averaged together. Raising this rather than the samples per
+    /// pixel spends more of the time on lighting and less on antialiasing,
+    /// depth of field and motion blur, which often need fewer samples.
+    /// Specular surfaces, whose paths vary much less, only ever trace one.
+    pub light_samples: u32,
+    /// How far along each ray to start looking for hits, so rays leaving a
+    /// surface don't hit it again. See `Scene::epsilon`.
+    pub epsilon: f64,
+    /// Leaves the background out of the image where nothing was hit, with
+    /// zero alpha, so it can be composited over another. The background still
+    /// lights the scene.
+    pub transparent_background: bool,
+    /// Lets light sampled from a surface through glass on its way, tinted
+    /// by what fills the glass, so glass casts colored shadows rather than
+    /// black ones. The light still travels in a straight line, rather than
+    /// being bent and focused as it really is; caustics are left to
+    /// bounces.
+    pub transparent_shadows: bool,
+    /// Checked between pixels; once set the current pass is dropped and
+    /// rendering stops.
+    pub cancel: Option<&'a AtomicBool>,
+    /// Set to the number of samples per pixel completed after every pass.
+    pub progress: Option<&'a AtomicU32>,
+    /// Counts every ray traced, from the camera, bounces and shadow rays
+    /// alike, updated after each pixel.
+    pub rays: Option<&'a AtomicU64>,
+    /// Stops new tiles being started while paused. Time spent paused doesn't
+    /// count towards the budget's time limit.
+    pub pause: Option<&'a Pause>,
+    /// Shows a progress bar with the sampling rate and an estimate of the
+    /// time left on stderr.
+    pub verbose: bool,
+}
+
+impl<'a> Renderer<'a> {
+    /// A renderer for `scene` with its lights sampled directly, an epsilon
+    /// suited to its size and every other option off.
+    pub fn new(scene: &'a Scene) -> Self {
+        Self {
+            camera: &scene.camera,
+            world: scene.world.as_ref(),
+            background: scene.background.as_ref(),
+            fog: scene.fog.as_ref(),
+            fog_sampling: FogSampling::default(),
+            lights: Some(&scene.lights),
+            guide: None,
+            material_override: None,
+            max_depth: 50,
+            bounce_limits: BounceLimits::default(),
+            indirect_clamp: None,
+            seed: 0,
+            sampler: SamplerFactory::default(),
+            light_samples: 1,
+            epsilon: scene.epsilon(),
+            transparent_background: false,
+            transparent_shadows: false,
+            cancel: None,
+            progress: None,
+            rays: None,
+            pause: None,
+            verbose: false,
+        }
+    }
+
+    /// Renders in passes of one sample per pixel until the budget runs out or
+    /// the render is cancelled. Returns false if it was cancelled.
+    ///
+    /// A new pass is only started if the previous one suggests it will finish
+    /// within the time limit, so the deadline is not overshot by a full pass.
+    pub fn render(&self, film: &mut Film, budget: Budget) -> bool {
+        // Time spent rendering since `since`, leaving out any pauses
+        let active_since = |since: Instant, paused_before: Duration| {
+            since
+                .elapsed()
+                .saturating_sub(self.paused_time() - paused_before)
+        };
+        let start = (Instant::now(), self.paused_time());
+        let mut last_pass = Duration::ZERO;
+
+        // Each sample only needs to filter textures over its share of the
+        // pixel. Without a sample count, assume a moderately converged image.
+        let expected_samples = budget.samples_per_pixel.unwrap_or(EXPECTED_SAMPLES);
+        let footprint = (expected_samples.max(1) as f64).sqrt().recip();
+
+        let progress_bar = self
+            .verbose
+            .then(|| ProgressBar::new(budget, film.width() * film.height()));
+        let finish_progress = |samples: u32, cancelled: bool| {
+            if let Some(bar) = &progress_bar {
+                bar.finish(samples as f64, active_since(start.0, start.1), cancelled);
+            }
+        };
+
+        let mut convergence = Convergence::default();
+
+        loop {
+            if let Some(target) = budget.samples_per_pixel {
+                if film.samples() >= target {
+                    break;
+                }
+            }
+
+            if let Some(limit) = budget.time_limit {
+                if film.samples() > 0 && active_since(start.0, start.1) + last_pass > limit {
+                    break;
+                }
+            }
+
+            let pass_start = (Instant::now(), self.paused_time());
+            let samples_before = film.samples() as f64;
+            let tile_done = |pass_fraction: f64| {
+                if let Some(bar) = &progress_bar {
+                    bar.update(
+                        samples_before + pass_fraction,
+                        active_since(start.0, start.1),
+                    );
+                }
+            };
+            if !self.sample_pass(film, 1, footprint, tile_done) {
+                finish_progress(film.samples(), true);
+                return false;
+            }
+            if let Some(guide) = self.guide {
+                guide.refine();
+            }
+            last_pass = active_since(pass_start.0, pass_start.1);
+
+            if let Some(progress) = self.progress {
+                progress.store(film.samples(), Ordering::Relaxed);
+            }
+
+            if let Some(threshold) = budget.noise_threshold {
+                if convergence.update(film) <= threshold {
+                    break;
+                }
+            }
+        }
+
+        finish_progress(film.samples(), false);
+        true
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.is_some_and(|c| c.load(Ordering::Relaxed))
+    }
+
+    /// Time spent paused, which doesn't count as rendering time.
+    fn paused_time(&self) -> Duration {
+        self.pause.map_or(Duration::ZERO, Pause::paused_time)
+    }
+
+    /// Reuses the samples of `previous`, the frame before `film` of an
+    /// animation, wherever it saw the same surface as `film` does, keeping
+    /// `keep` of their weight; see `Film::reuse`. Surfaces are looked for
+    /// where the camera saw them at `previous_time`, so they're only found
+    /// if they haven't moved.
+    pub fn reuse_frame(&self, film: &mut Film, previous: &Film, previous_time: f64, keep: f64) {
+        let width = previous.width();
+        let height = previous.height();
+        film.reuse(previous, keep, |p| {
+            let (s, t) = self.camera.project(p, previous_time)?;
+            let x = (s * (width - 1) as f64).floor();
+            let y = (height - 1) as f64 - (t * (height - 1) as f64).floor();
+            (x >= 0. && y >= 0.).then_some((x as usize, y as usize))
+        });
+    }
+
+    /// How far the point a camera ray `r` hits moves across an image of
+    /// `width` by `height` pixels from shutter open to close, following the
+    /// surface's velocity and the camera. Zero where that can't be worked
+    /// out, such as when the point is behind the camera at either end.
+    fn motion_vector(&self, r: Ray, hit: &HitRecord, width: usize, height: usize) -> (f64, f64) {
+        let (open, close) = self.camera.time();
+        let at = |time: f64| {
+            let p = hit.p + hit.velocity * (time - r.time);
+            self.camera.project(p, time)
+        };
+
+        match (at(open), at(close)) {
+            (Some((s0, t0)), Some((s1, t1))) => (
+                (s1 - s0) * (width - 1) as f64,
+                (t1 - t0) * (height - 1) as f64,
+            ),
+            _ => (0., 0.),
+        }
+    }
+
+    /// The first surface along `r` that rays of `kind` see, as it should be
+    /// shaded.
+    fn trace(&self, r: Ray, kind: RayKind) -> Option<HitRecord<'a>> {
+        self.trace_between(r, kind, 0., f64::INFINITY)
+    }
+
+    /// Like `trace`, only looking between `t_min` and `t_max` along `r`.
+    fn trace_between(
+        &self,
+        r: Ray,
+        kind: RayKind,
+        t_min: f64,
+        t_max: f64,
+    ) -> Option<HitRecord<'a>> {
+        count_ray();
+        let mut t_min = t_min.max(self.epsilon);
+        let hit = loop {
+            let hit = self.world.hit(r, t_min, t_max)?;
+            if hit.visibility.sees(kind) {
+                break hit;
+            }
+            t_min = hit.t + self.epsilon;
+        };
+        match self.material_override {
+            Some(material)
+                if !matches!(
+                    hit.material,
+                    Material::DiffuseLight { .. } | Material::Isotropic { .. }
+                ) =>
+            {
+                Some(HitRecord { material, ..hit })
+            }
+            _ => Some(hit),
+        }
+    }
+
+    /// Adds `samples` samples to every pixel of the film, or nothing at all
+    /// if the render is cancelled part way through.
+    ///
+    /// Ray differentials span `footprint` pixels, which is where textures
+    /// are filtered over.
+    pub fn render_pass(&self, film: &mut Film, samples: u32, footprint: f64) -> bool {
+        self.sample_pass(film, samples, footprint, |_| {})
+    }
+
+    /// Like `render_pass`, calling `tile_done` with the fraction of the pass
+    /// finished as each tile is done.
+    fn sample_pass<D>(&self, film: &mut Film, samples: u32, footprint: f64, tile_done: D) -> bool
+    where
+        D: Fn(f64) + Sync,
+    {
+        let image_width = film.width();
+        let image_height = film.height();
+        let ds = footprint / (image_width - 1) as f64;
+        let dt = footprint / (image_height - 1) as f64;
+        let aovs = film.has_aovs();
+        let group_count = film.light_group_count();
+        let first_sample = film.samples();
+        let exposure = self.camera.exposure_scale();
+
+        let start_tile = |tile| match self.pause {
+            Some(pause) if !pause.wait(|| self.cancelled()) => None,
+            _ => Some(self.sampler.tile(self.seed, tile, first_sample)),
+        };
+
+        let sample_pixel = |tile_rng: &mut Option<Sampler>, x, y| {
+            if self.cancelled() {
+                return None;
+            }
+
+            let i = x as f64;
+            let j = (image_height - 1 - y) as f64;
+
+            let mut pixel_rng = self.sampler.pixel(self.seed, x, y, first_sample);
+            let samples = (first_sample..first_sample + samples)
+                .map(|index| {
+                    let mut sample_rng;
+                    let rng = match tile_rng.as_mut().or(pixel_rng.as_mut()) {
+                        Some(rng) => rng,
+                        None => {
+                            sample_rng = self.sampler.sample(self.seed, x, y, index);
+                            &mut sample_rng
+                        }
+                    };
+                    let (dx, dy) = (rng.gen::<f64>(), rng.gen::<f64>());
+                    let u = (i + dx) / (image_width - 1) as f64;
+                    let v = (j + dy) / (image_height - 1) as f64;
+
+                    let r = self.camera.get_ray_differential(rng, u, v, ds, dt);
+                    let (near, far) = self.camera.clip_range(&r);
+                    let hit = self.trace_between(r, RayKind::Camera, near, far);
+
+                    let surface = hit.filter(|_| aovs).map(|hit| {
+                        let motion = self.motion_vector(r, &hit, image_width, image_height);
+                        surface(r, &hit, motion)
+                    });
+
+                    let mut light_groups = vec![BLACK; group_count];
+                    let mut split = GroupSplit::new(&mut light_groups);
+
+                    // Light the fog scatters towards the camera, and what
+                    // it lets through from beyond
+                    let distance = hit
+                        .as_ref()
+                        .map_or(f64::INFINITY, |hit| hit.t * r.direction.length());
+                    let crossing = match self.fog {
+                        Some(fog) => self.cross(rng, r, fog, true, distance, &mut split),
+                        None => Crossing::clear(),
+                    };
+
+                    let mut split = split.bounce(crossing.transmittance);
+                    let (color, alpha) = match (crossing.scattered, hit) {
+                        (Some((scattered, emission)), _) => {
+                            let path =
+                                Path::new(self.max_depth, self.bounce_limits).bounce(emission);
+                            (self.ray_color(rng, scattered, path, &mut split), 1.)
+                        }
+                        (None, Some(hit)) if hit.holdout => (BLACK, 0.),
+                        (None, Some(hit))
+                            if matches!(hit.material, Material::ShadowCatcher { .. }) =>
+                        {
+                            self.catch_shadow(rng, r, hit, &mut split)
+                        }
+                        (None, Some(hit)) => (self.shade_camera_hit(rng, r, hit, &mut split), 1.),
+                        (None, None) if self.transparent_background => (BLACK, 0.),
+                        (None, None) => (self.background(r, &mut split), 1.),
+                    };
+                    let color = crossing.in_scattered + crossing.transmittance * color;
+                    let exposure = exposure * self.camera.vignetting(u, v);
+                    for group in &mut light_groups {
+                        *group *= exposure;
+                    }
+
+                    // The film counts rows from the top
+                    Sample {
+                        x: i + dx,
+                        y: y as f64 + 1. - dy,
+                        color: color * exposure,
+                        alpha,
+                        surface,
+                        light_groups,
+                    }
+                })
+                .collect();
+
+            let traced = RAYS_TRACED.with(Cell::take);
+            if let Some(rays) = self.rays {
+                rays.fetch_add(traced, Ordering::Relaxed);
+            }
+
+            Some(samples)
+        };
+
+        film.accumulate(samples, start_tile, sample_pixel, |done, total| {
+            tile_done(done as f64 / total as f64)
+        })
+    }
+
+    /// Radiance arriving along `r` at the next vertex of `path`.
+    fn ray_color<T: Rng>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        path: Path<'a>,
+        split: &mut GroupSplit,
+    ) -> Color {
+        if path.depth <= 0 {
+            return BLACK;
+        }
+
+        let hit = self.trace(r, path.ray);
+
+        // The dielectric the path is inside fills the space with its medium,
+        // if it has one, and otherwise the fog does
+        let (medium, in_fog) = match path.media.current() {
+            Some(material) => (material.medium(), false),
+            None => (self.fog, true),
+        };
+        let Some(medium) = medium else {
+            return match hit {
+                Some(hit) => self.pass_or_shade(rng, r, hit, path, split),
+                None => self.background(r, split),
+            };
+        };
+
+        let length = r.direction.length();
+        let distance = hit.as_ref().map_or(f64::INFINITY, |hit| hit.t * length);
+        // Light scattered by the medium is as indirect as what surfaces
+        // beyond reflect
+        let mut crossing = Crossing::clear();
+        let in_scattered = self.clamp_indirect(path.clamp, split, |split| {
+            crossing = self.cross(rng, r, medium, in_fog, distance, split);
+            crossing.in_scattered
+        });
+
+        let mut split = split.bounce(crossing.transmittance);
+        let color = match (crossing.scattered, hit) {
+            (Some((scattered, emission)), _) => {
+                self.clamp_indirect(path.clamp, &mut split, |split| {
+                    self.ray_color(rng, scattered, path.bounce(emission), split)
+                })
+            }
+            (None, Some(hit)) => self.pass_or_shade(rng, r, hit, path, &mut split),
+            (None, None) => self.background(r, &mut split),
+        };
+        in_scattered + crossing.transmittance * color
+    }
+
+    /// Where `r` scatters crossing `distance` of `medium`, if it does, and
+    /// how much light gets through. Rays that hit nothing cross an endless
+    /// stretch of it.
+    fn cross<T: Rng>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        medium: &Medium,
+        in_fog: bool,
+        distance: f64,
+        split: &mut GroupSplit,
+    ) -> Crossing {
+        // Light inside a dielectric is mostly cut off from the lights by its
+        // surface, so only the fog is worth sampling them from
+        let sampling = in_fog.then_some(self.fog_sampling);
+        let in_scattered = match sampling {
+            Some(FogSampling::Equiangular) => self.light_in_fog(rng, r, medium, distance, split),
+            _ => None,
+        };
+
+        // Scattering closer than `epsilon` to either end would start the next
+        // ray past the surface, leaving the medium unnoticed
+        let scatter = medium
+            .scatter_distance(rng, distance)
+            .filter(|&d| d > self.epsilon && d < distance - self.epsilon);
+        let transmittance = medium.transmittance(scatter.unwrap_or(distance));
+
+        let mut crossing = Crossing {
+            in_scattered: in_scattered.unwrap_or(BLACK),
+            transmittance,
+            scattered: None,
+        };
+        let Some(d) = scatter else {
+            return crossing;
+        };
+
+        let t = d / r.direction.length();
+        let p = r.at(t);
+        let emission = match (sampling, in_scattered) {
+            // Lights the scattered ray finds were sampled all along it
+            (_, Some(_)) => Emission::Sampled { from: p },
+            (Some(FogSampling::Distance), None) => {
+                let mut split = split.bounce(transmittance);
+                match self.light_at_scatter(rng, r, t, medium, &mut split) {
+                    Some(direct) => {
+                        crossing.in_scattered += transmittance * direct;
+                        Emission::Weighted {
+                            from: p,
+                            pdf: 1. / (4. * PI),
+                        }
+                    }
+                    None => Emission::Full,
+                }
+            }
+            _ => Emission::Full,
+        };
+        crossing.scattered = Some((Ray::new(p, random_unit_vector(rng), r.time), emission));
+        crossing
+    }
+
+    /// Shades `hit`, unless it's on the surface of a dielectric inside a
+    /// higher priority one, where the path carries straight on as if it
+    /// weren't there.
+    fn pass_or_shade<T: Rng>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        hit: HitRecord<'a>,
+        path: Path<'a>,
+        split: &mut GroupSplit,
+    ) -> Color {
+        if !path.media.is_hidden(hit.material) {
+            return self.shade(rng, r, hit, path, split);
+        }
+
+        let media = path.media.crossing(hit.material, hit.front_face);
+        let path = Path { media, ..path };
+        self.ray_color(rng, Ray::new(hit.p, r.direction, r.time), path, split)
+    }
+
+    fn background(&self, r: Ray, split: &mut GroupSplit) -> Color {
+        let color = self.background.color(r);
+        split.add(DEFAULT_LIGHT_GROUP, color);
+        color
+    }
+
+    /// Radiance leaving `hit` back along `r`, the vertex `path` has reached.
+    fn shade<T: Rng>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        hit: HitRecord<'a>,
+        path: Path<'a>,
+        split: &mut GroupSplit,
+    ) -> Color {
+        let mut emitted = hit.material.emitted(&r, &hit);
+        if let (Some(lights), Material::DiffuseLight { .. }) = (self.lights, hit.material) {
+            match path.emission {
+                Emission::Full => {}
+                Emission::Weighted { from, pdf } => {
+                    emitted *= power_heuristic(pdf, lights.pdf(from, r.direction));
+                }
+                Emission::Sampled { from } if lights.pdf(from, r.direction) > 0. => {
+                    emitted = BLACK;
+                }
+                Emission::Sampled { .. } => {}
+            }
+        }
+        split.add(hit.material.light_group(), emitted);
+
+        let clamp = path.clamp;
+        let reflected =
+            self.clamp_indirect(clamp, split, |split| self.reflect(rng, r, hit, path, split));
+        emitted + reflected
+    }
+
+    /// Radiance `hit` reflects or refracts back along `r`, from the light
+    /// reaching it.
+    fn reflect<T: Rng>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        hit: HitRecord<'a>,
+        path: Path<'a>,
+        split: &mut GroupSplit,
+    ) -> Color {
+        let surrounding_ior = path.media.surrounding_ior(hit.material);
+        let Some(ScatterResult {
+            scattered,
+            attenuation,
+            specular,
+        }) = hit.material.scatter_within(rng, r, hit, surrounding_ior)
+        else {
+            return BLACK;
+        };
+
+        if specular {
+            // Light refracted into or out of a dielectric
+            let refracted = scattered.direction.dot_product(hit.normal) < 0.;
+            let kind = if refracted {
+                Bounce::Transmission
+            } else {
+                Bounce::Glossy
+            };
+            let Some(mut next) = path.bounce_kind(kind, Emission::Full) else {
+                return BLACK;
+            };
+            if refracted {
+                next.media = next.media.crossing(hit.material, hit.front_face);
+            }
+
+            let mut split = split.bounce(attenuation);
+            return attenuation * self.ray_color(rng, scattered, next, &mut split);
+        }
+
+        let region = self
+            .guide
+            .filter(|_| hit.material.diffuse_albedo(&hit.tex_coords(&r)).is_some())
+            .map(|guide| guide.at(hit.p));
+
+        // Light leaving the surface without bouncing off anything else
+        let bounce_pdf = |direction| self.bounce_pdf(region.as_ref(), &hit, direction);
+        let fog = self.fog.filter(|_| path.media.current().is_none());
+        let direct = self.direct_light(rng, r, &hit, attenuation, bounce_pdf, fog, split);
+        let local = direct.unwrap_or(BLACK);
+        let Some(bounced) = path.bounce_kind(Bounce::Diffuse, Emission::Full) else {
+            return local;
+        };
+
+        // Lights found by the bounce were also sampled directly, if lights
+        // are sampled here at all
+        let next = |pdf| Path {
+            emission: match direct {
+                Some(_) => Emission::Weighted { from: hit.p, pdf },
+                None => Emission::Full,
+            },
+            ..bounced.clone()
+        };
+
+        let Some(region) = region else {
+            let pdf = hit.material.scattering_pdf(&hit, scattered.direction);
+            let mut split = split.bounce(attenuation);
+            let indirect = self.ray_color(rng, scattered, next(pdf), &mut split);
+            return local + attenuation * indirect;
+        };
+
+        let Some((scattered, pdf)) = self.guided_bounce(rng, &region, &hit, scattered) else {
+            return local;
+        };
+
+        let weight = attenuation * (hit.material.scattering_pdf(&hit, scattered.direction) / pdf);
+
+        let mut split = split.bounce(weight);
+        let indirect = self.ray_color(rng, scattered, next(pdf), &mut split);
+        region.record(scattered.direction, indirect.luminance() / pdf);
+
+        local + weight * indirect
+    }
+
+    /// The light `trace` brings back to the current vertex, with what it
+    /// adds to the pixel clamped to `indirect_clamp` if `clamp` is set. The
+    /// light groups it added to are scaled down with it.
+    fn clamp_indirect<F>(&self, clamp: bool, split: &mut GroupSplit, trace: F) -> Color
+    where
+        F: FnOnce(&mut GroupSplit) -> Color,
+    {
+        let Some(max) = self.indirect_clamp.filter(|_| clamp) else {
+            return trace(split);
+        };
+
+        let before = split.groups.to_vec();
+        let light = trace(split);
+        // The clamp is on pixel values, before the camera's exposure
+        let max = max / self.camera.exposure_scale();
+        let luminance = (split.throughput * light).luminance();
+        if luminance <= max {
+            return light;
+        }
+
+        let scale = max / luminance;
+        split.scale_since(&before, scale);
+        light * scale
+    }
+
+    /// Radiance leaving the first surface a camera ray hits, averaged over
+    /// `light_samples` paths where the surface is diffuse.
+    fn shade_camera_hit<T: Rng>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        hit: HitRecord,
+        split: &mut GroupSplit,
+    ) -> Color {
+        let diffuse = hit.material.diffuse_albedo(&hit.tex_coords(&r)).is_some();
+        let paths = if diffuse {
+            self.light_samples.max(1)
+        } else {
+            1
+        };
+        let weight = (paths as f64).recip();
+
+        let mut split = split.bounce(WHITE * weight);
+        let total: Color = (0..paths)
+            .map(|_| {
+                self.shade(
+                    rng,
+                    r,
+                    hit,
+                    Path::new(self.max_depth, self.bounce_limits),
+                    &mut split,
+                )
+            })
+            .sum();
+        total * weight
+    }
+
+    /// What a camera ray sees where it hits a shadow catcher: the background
+    /// behind it, darkened where other objects hide the catcher from the
+    /// background's light, plus the light they bounce onto it. With a
+    /// transparent background, alpha is the strength of the shadow.
+    fn catch_shadow<T: Rng>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        hit: HitRecord,
+        split: &mut GroupSplit,
+    ) -> (Color, f64) {
+        let albedo = hit.material.albedo(&hit.tex_coords(&r));
+
+        // Follow one diffuse bounce; if it reaches another object, that
+        // object both casts shadow and lights the catcher
+        let blocker = hit
+            .material
+            .scatter(rng, r, hit)
+            .and_then(|ScatterResult { scattered, .. }| {
+                let blocker = self.trace(scattered, RayKind::Diffuse)?;
+                Some((scattered, blocker))
+            })
+            .filter(|(_, blocker)| !matches!(blocker.material, Material::ShadowCatcher { .. }));
+
+        let (bounced, shadow) = match blocker {
+            Some((scattered, blocker)) => {
+                let mut split = split.bounce(albedo);
+                let path = Path::new(self.max_depth, self.bounce_limits).bounce(Emission::Full);
+                let color = self.shade(rng, scattered, blocker, path, &mut split);
+                (albedo * color, 1.)
+            }
+            None => (BLACK, 0.),
+        };
+
+        if self.transparent_background {
+            return (bounced, shadow);
+        }
+
+        let mut split = split.bounce(WHITE * (1. - shadow));
+        let behind = self.background(r, &mut split);
+        (bounced + behind * (1. - shadow), 1.)
+    }
+
+    /// Picks the direction of a diffuse bounce from either the material or
+    /// the guide, returning the ray along with its density under the
+    /// combination of both. The material's own sample is `scattered`.
+    fn guided_bounce<T: Rng>(
+        &self,
+        rng: &mut T,
+        region: &GuideRegion,
+        hit: &HitRecord,
+        scattered: Ray,
+    ) -> Option<(Ray, f64)> {
+        if !region.is_trained() {
+            let pdf = hit.material.scattering_pdf(hit, scattered.direction);
+            return Some((scattered, pdf));
+        }
+
+        let cosine = CosinePdf::new(hit.normal);
+        let mixture = guided_mixture(region, &cosine);
+        let direction = mixture.sample(rng)?;
+
+        // Guided directions can point into the surface, which reflects no
+        // light that way
+        if cosine.value(direction) <= 0. {
+            return None;
+        }
+
+        let pdf = mixture.value(direction);
+        Some((Ray::new(hit.p, direction, scattered.time), pdf))
+    }
+
+    /// The density of a bounce from `hit` going along `direction`, as picked
+    /// by `guided_bounce` if there is a guide `region` and by the material
+    /// if not.
+    fn bounce_pdf(&self, region: Option<&GuideRegion>, hit: &HitRecord, direction: Vec3) -> f64 {
+        match region.filter(|region| region.is_trained()) {
+            Some(region) => {
+                let cosine = CosinePdf::new(hit.normal);
+                guided_mixture(region, &cosine).value(direction)
+            }
+            None => hit.material.scattering_pdf(hit, direction),
+        }
+    }
+
+    /// The fraction of light, in each channel, making it along `shadow_ray`
+    /// from a light `distance` along it. See `transparent_shadows`.
+    fn shadow(&self, shadow_ray: Ray, distance: f64) -> Color {
+        count_ray();
+        let (t_min, t_max) = (self.epsilon, distance - self.epsilon);
+        if !self.transparent_shadows {
+            return WHITE * self.world.transmittance(shadow_ray, t_min, t_max);
+        }
+
+        let mut hits = vec![];
+        self.world.hits(shadow_ray, t_min, t_max, &mut hits);
+        hits.sort_by(|a, b| a.t.total_cmp(&b.t));
+
+        let mut transmittance = WHITE;
+        // The glass the ray is inside, and where it went in
+        let mut inside: Vec<(&Material, f64)> = vec![];
+        for hit in hits.iter().filter(|hit| hit.visibility.shadow) {
+            let material = match self.material_override {
+                Some(material) if !matches!(hit.material, Material::DiffuseLight { .. }) => {
+                    material
+                }
+                _ => hit.material,
+            };
+            let Material::Dialectric {
+                index_of_refraction,
+                medium,
+                ..
+            } = material
+            else {
+                return BLACK;
+            };
+
+            // Some light is reflected off each surface crossed
+            let cosine = shadow_ray.direction.dot_product(hit.normal).abs();
+            transmittance *= 1. - reflectance(cosine, *index_of_refraction);
+
+            if hit.front_face {
+                inside.push((hit.material, hit.t));
+            } else {
+                // Leaving glass the ray started in crosses it from the start
+                let entered = inside
+                    .iter()
+                    .rposition(|&(material, _)| std::ptr::eq(material, hit.material))
+                    .map_or(t_min, |i| inside.remove(i).1);
+                if let Some(medium) = medium {
+                    transmittance *= medium.transmittance(hit.t - entered);
+                }
+            }
+        }
+
+        transmittance
+    }
+
+    /// Light reaching a diffuse surface straight from a randomly picked
+    /// light and scattered back along `r`, where the material's attenuation
+    /// is `attenuation`. Weighted against the chance of the bounce, picked
+    /// with density `bounce_pdf`, finding the same light. Returns None where
+    /// lights aren't sampled, and must instead be found by the next bounce.
+    /// Light crossing `fog` on its way is dimmed by it.
+    #[allow(clippy::too_many_arguments)]
+    fn direct_light<T: Rng, P: Fn(Vec3) -> f64>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        hit: &HitRecord,
+        attenuation: Color,
+        bounce_pdf: P,
+        fog: Option<&Medium>,
+        split: &mut GroupSplit,
+    ) -> Option<Color> {
+        let lights = self.lights.filter(|lights| !lights.is_empty())?;
+
+        let mut sample_light = || {
+            let (light, pick_probability) = lights.pick(hit.p, rng.gen())?;
+            let sample = light.sample(hit.p, (rng.gen(), rng.gen()))?;
+
+            let scattering_pdf = hit.material.scattering_pdf(hit, sample.direction);
+            if scattering_pdf <= 0. {
+                return None;
+            }
+
+            let shadow_ray = Ray::new(hit.p, sample.direction, r.time);
+            let visibility = self.shadow(shadow_ray, sample.distance);
+            if visibility == BLACK {
+                return None;
+            }
+
+            let pdf = sample.pdf * pick_probability;
+            // Bounces never find lights at a single point, so sampling them
+            // gets all the weight
+            let weight = if light.is_delta() {
+                1.
+            } else {
+                power_heuristic(pdf, bounce_pdf(sample.direction))
+            };
+            let mut color =
+                attenuation * visibility * sample.radiance * (scattering_pdf * weight / pdf);
+            if let Some(fog) = fog {
+                color *= fog.attenuation(sample.distance);
+            }
+            split.add(light.group(), color);
+            Some(color)
+        };
+
+        Some(sample_light().unwrap_or(BLACK))
+    }
+
+    /// Light from a randomly picked light scattered back along `r` by the
+    /// fog `medium` at `t` along it, where it was picked to scatter. Returns
+    /// None where lights aren't sampled.
+    fn light_at_scatter<T: Rng>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        t: f64,
+        medium: &Medium,
+        split: &mut GroupSplit,
+    ) -> Option<Color> {
+        // Fog scatters light like an isotropic volume of the same color
+        let material = Material::Isotropic {
+            albedo: WHITE.into(),
+        };
+        let derivatives = SurfaceDerivatives::flat(Vec3::zero(), Vec3::zero());
+        let normal = -r.direction.unit_vector();
+        let hit = HitRecord::new(t, r, normal, (0., 0.), derivatives, &material);
+
+        let bounce_pdf = |_| 1. / (4. * PI);
+        self.direct_light(rng, r, &hit, WHITE, bounce_pdf, Some(medium), split)
+    }
+
+    /// Light from a randomly picked light scattered back along `r` by the
+    /// fog `medium`, from somewhere in the first `distance` of it. Returns
+    /// None where lights aren't sampled, and must instead be found by rays
+    /// scattered in the fog.
+    ///
+    /// The point is picked by equiangular sampling: in proportion to how
+    /// much light from the light's center reaches each point, which is
+    /// mostly where the ray passes closest to it. Picking it by distance
+    /// into the fog, as scattered rays are, wastes most samples away from
+    /// a beam or spotlight cone, leaving it speckled.
+    fn light_in_fog<T: Rng>(
+        &self,
+        rng: &mut T,
+        r: Ray,
+        medium: &Medium,
+        distance: f64,
+        split: &mut GroupSplit,
+    ) -> Option<Color> {
+        let lights = self.lights.filter(|lights| !lights.is_empty())?;
+        if medium.scattering <= 0. {
+            return None;
+        }
+
+        let mut sample_light = || {
+            let (light, pick_probability) = lights.pick(r.origin, rng.gen())?;
+            let direction = r.direction.unit_vector();
+            let target = light.bounds().centroid();
+            let (d, d_pdf) = equiangular(r.origin, direction, target, distance, rng.gen())?;
+            if d <= self.epsilon || d >= distance - self.epsilon {
+                return None;
+            }
+
+            let p = r.origin + direction * d;
+            let sample = light.sample(p, (rng.gen(), rng.gen()))?;
+            let shadow_ray = Ray::new(p, sample.direction, r.time);
+            let visibility = self.shadow(shadow_ray, sample.distance);
+            if visibility == BLACK {
+                return None;
+            }
+
+            // Scattered evenly in every direction
+            let phase = 1. / (4. * PI);
+            let reach = medium.attenuation(d) * medium.attenuation(sample.distance);
+            let pdf = pick_probability * sample.pdf * d_pdf;
+            let color = reach * visibility * sample.radiance * (medium.scattering * phase / pdf);
+            split.add(light.group(), color);
+            Some(color)
+        };
+
+        Some(sample_light().unwrap_or(BLACK))
+    }
+}
+
+/// What becomes of a ray crossing a medium.
+struct Crossing {
+    /// Light from sampling the lights along the way.
+    in_scattered: Color,
+    /// The fraction of the light from where the ray ends, or scatters, that
+    /// reaches its start.
+    transmittance: Color,
+    /// The ray onwards from where it scattered, and how much of the
+    /// emission it finds counts.
+    scattered: Option<(Ray, Emission)>,
+}
+
+impl Crossing {
+    /// A crossing of empty space.
+    fn clear() -> Self {
+        Self {
+            in_scattered: BLACK,
+            transmittance: WHITE,
+            scattered: None,
+        }
+    }
+}
+
+/// Follows the noise left in a film as it renders, for
+/// `Budget::noise_threshold`.
+#[derive(Default)]
+struct Convergence {
+    /// The samples and pixel averages when the noise is next estimated
+    /// against, once the samples have doubled.
+    half: Option<(u32, Vec<Color>)>,
+    /// The samples when the noise was last estimated, and the estimate.
+    estimate: Option<(u32, f64)>,
+}
+
+impl Convergence {
+    /// The samples to first estimate the noise against, when each pixel's
+    /// average is at least somewhat settled.
+    const FIRST_SAMPLES: u32 = 4;
+
+    /// The noise in `film` after a pass, estimated again if its samples have
+    /// doubled and otherwise projected from the last estimate. Infinite
+    /// until there's been an estimate.
+    fn update(&mut self, film: &mut Film) -> f64 {
+        let samples = film.samples();
+        match &self.half {
+            Some((half, averages)) if samples >= 2 * half => {
+                let noise = film.noise_against(averages);
+                film.set_noise(noise);
+                self.estimate = Some((samples, noise));
+                self.half = Some((samples, film.averages()));
+                noise
+            }
+            Some(_) => match self.estimate {
+                Some((at, noise)) => {
+                    // Noise falls with the square root of the samples
+                    let noise = noise * (at as f64 / samples as f64).sqrt();
+                    film.set_noise(noise);
+                    noise
+                }
+                None => f64::INFINITY,
+            },
+            None => {
+                if samples >= Self::FIRST_SAMPLES {
+                    self.half = Some((samples, film.averages()));
+                }
+                f64::INFINITY
+            }
+        }
+    }
+}
+
+/// What a path carries from one vertex to the next.
+#[derive(Clone)]
+struct Path<'a> {
+    /// Vertices left, including the next.
+    depth: i32,
+    /// Bounces of each kind left.
+    left: BounceLimits,
+    /// Whether the path has bounced off a diffuse surface, so the light it
+    /// finds from here on is indirect.
+    indirect: bool,
+    /// Whether the light the next vertex reflects or scatters, as opposed
+    /// to what it gives off, is clamped to `Renderer::indirect_clamp`. Set
+    /// just after the first diffuse bounce, so the light that bounce finds
+    /// directly keeps its full brightness.
+    clamp: bool,
+    /// How much of the emission found at the next vertex counts.
+    emission: Emission,
+    media: Media<'a>,
+    /// The kind of ray reaching the next vertex.
+    ray: RayKind,
+}
+
+impl<'a> Path<'a> {
+    /// A path from the camera, with up to `depth` vertices and `limits`
+    /// bounces of each kind.
+    fn new(depth: i32, limits: BounceLimits) -> Self {
+        Self {
+            depth,
+            left: limits,
+            indirect: false,
+            clamp: false,
+            emission: Emission::Full,
+            media: Media::default(),
+            ray: RayKind::Camera,
+        }
+    }
+
+    /// The path after a bounce, counting the emission the bounce finds as
+    /// `emission` says. Bounces are diffuse unless `bounce_kind` says
+    /// otherwise, as scattering in volumes is.
+    fn bounce(&self, emission: Emission) -> Self {
+        Self {
+            depth: self.depth - 1,
+            left: self.left,
+            indirect: self.indirect,
+            clamp: false,
+            emission,
+            media: self.media.clone(),
+            ray: RayKind::Diffuse,
+        }
+    }
+
+    /// The path after a bounce off a surface of `kind`, if it has any of
+    /// those left.
+    fn bounce_kind(&self, kind: Bounce, emission: Emission) -> Option<Self> {
+        let mut next = self.bounce(emission);
+        let left = match kind {
+            Bounce::Diffuse => &mut next.left.diffuse,
+            Bounce::Glossy => &mut next.left.glossy,
+            Bounce::Transmission => &mut next.left.transmission,
+        };
+        if *left <= 0 {
+            return None;
+        }
+        *left -= 1;
+        next.clamp = matches!(kind, Bounce::Diffuse) && !self.indirect;
+        next.indirect |= matches!(kind, Bounce::Diffuse);
+        next.ray = match kind {
+            Bounce::Diffuse => RayKind::Diffuse,
+            Bounce::Glossy | Bounce::Transmission => RayKind::Specular,
+        };
+        Some(next)
+    }
+}
+
+/// The most bounces of each kind of surface a path can take, like the
+/// separate limits of production renderers. Glass-heavy scenes can allow
+/// many transmission bounces, so light gets through stacks of panes,
+/// without paying for as many diffuse ones, which rarely add much after
+/// the first few. Scattering in fog counts only towards the overall depth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BounceLimits {
+    /// Off surfaces that scatter light in every direction, like the
+    /// Lambertian ones, and in volumes.
+    pub diffuse: i32,
+    /// Mirror-like reflections off metals and dielectrics.
+    pub glossy: i32,
+    /// Refraction into or out of dielectrics.
+    pub transmission: i32,
+}
+
+impl Default for BounceLimits {
+    /// No limits beyond the overall depth.
+    fn default() -> Self {
+        Self {
+            diffuse: i32::MAX,
+            glossy: i32::MAX,
+            transmission: i32::MAX,
+        }
+    }
+}
+
+/// The kinds of bounce `BounceLimits` counts.
+#[derive(Clone, Copy)]
+enum Bounce {
+    Diffuse,
+    Glossy,
+    Transmission,
+}
+
+/// The dielectrics a path is inside, in the order it entered them.
+///
+/// Where they overlap, such as water filling a glass up to slightly inside
+/// its walls, the one with the highest priority fills the space, and the
+/// others' surfaces within it are passed straight through. Light is bent at
+/// each surface by the media on both sides of it rather than by the surface
+/// alone, as if surrounded by empty space.
+///
+/// Objects are told apart by their materials, so all instances of an object
+/// count as one.
+#[derive(Clone, Default)]
+struct Media<'a> {
+    inside: Vec<&'a Material>,
+}
+
+impl<'a> Media<'a> {
+    /// The dielectric filling the space the path is in, if any: the highest
+    /// priority one, or the last entered of those tied.
+    fn current(&self) -> Option<&'a Material> {
+        self.inside
+            .iter()
+            .copied()
+            .max_by_key(|material| material.priority())
+    }
+
+    /// The media after entering or leaving the surface of `material` through
+    /// its `front_face` or back.
+    fn crossing(&self, material: &'a Material, front_face: bool) -> Self {
+        let mut inside = self.inside.clone();
+        if front_face {
+            inside.push(material);
+        } else if let Some(i) = inside.iter().rposition(|&m| std::ptr::eq(m, material)) {
+            inside.remove(i);
+        }
+        Self { inside }
+    }
+
+    /// The media but for `material`, as on the other side of its surface.
+    fn without(&self, material: &Material) -> Self {
+        Self {
+            inside: self
+                .inside
+                .iter()
+                .copied()
+                .filter(|&m| !std::ptr::eq(m, material))
+                .collect(),
+        }
+    }
+
+    /// Whether `material` is a dielectric whose surface here is within a
+    /// higher priority one.
+    fn is_hidden(&self, material: &Material) -> bool {
+        material.index_of_refraction().is_some()
+            && self
+                .without(material)
+                .current()
+                .is_some_and(|other| other.priority() > material.priority())
+    }
+
+    /// The index of refraction on the other side of `material`'s surface
+    /// from its inside, 1 for empty space.
+    fn surrounding_ior(&self, material: &Material) -> f64 {
+        if self.inside.is_empty() {
+            return 1.;
+        }
+        self.without(material)
+            .current()
+            .and_then(Material::index_of_refraction)
+            .unwrap_or(1.)
+    }
+}
+
+/// How much of the emission a ray finds to count.
+#[derive(Clone, Copy)]
+enum Emission {
+    /// All of it, for rays from the camera or a specular surface, or from
+    /// a surface where lights aren't sampled.
+    Full,
+    /// The share left by sampling the lights directly from `from`, where
+    /// the ray was picked with density `pdf`.
+    Weighted { from: Point3, pdf: f64 },
+    /// None of it from lights that could be sampled from `from`, which
+    /// already were, from every point along the ray before it.
+    Sampled { from: Point3 },
+}
+
+/// The MIS weight, by the power heuristic, of a sample picked with density
+/// `pdf` by one strategy, where the other would have picked it with density
+/// `other_pdf`.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0. {
+        a / (a + b)
+    } else {
+        0.
+    }
+}
+
+/// Picks a distance along the unit vector `direction` from `origin`, no
+/// further than `distance`, with density in proportion to the inverse
+/// square of the distance to `target`. Returns it with its density.
+fn equiangular(
+    origin: Point3,
+    direction: Vec3,
+    target: Point3,
+    distance: f64,
+    u: f64,
+) -> Option<(f64, f64)> {
+    // Distances are measured from the point closest to `target`, where the
+    // ray passes `offset` from it, and picked evenly by the angle they're
+    // seen at from there
+    let closest = (target - origin).dot_product(direction);
+    let offset = (origin + direction * closest - target).length();
+    if offset <= 0. {
+        return None;
+    }
+    let start = (-closest).atan2(offset);
+    let end = (distance - closest).atan2(offset);
+    if end <= start {
+        return None;
+    }
+
+    let along = offset * (start + u * (end - start)).tan();
+    let pdf = offset / ((end - start) * (offset * offset + along * along));
+    Some((closest + along, pdf))
+}
+
+/// The guide's distribution mixed with the material's.
+fn guided_mixture<'a>(region: &'a GuideRegion, cosine: &'a CosinePdf) -> MixturePdf<'a> {
+    MixturePdf::weighted(vec![
+        (GUIDED_FRACTION, region as &dyn Pdf),
+        (1. - GUIDED_FRACTION, cosine),
+    ])
+}
+
+/// Credits the light a path picks up to the light groups it came from.
+struct GroupSplit<'a> {
+    /// How much of the light leaving the current vertex reaches the camera.
+    throughput: Color,
+    groups: &'a mut [Color],
+}
+
+impl<'a> GroupSplit<'a> {
+    fn new(groups: &'a mut [Color]) -> Self {
+        Self {
+            throughput: WHITE,
+            groups,
+        }
+    }
+
+    /// Adds `radiance` leaving the current vertex to `group`, if the film
+    /// has it.
+    fn add(&mut self, group: usize, radiance: Color) {
+        if let Some(total) = self.groups.get_mut(group) {
+            *total += self.throughput * radiance;
+        }
+    }
+
+    /// Scales everything added to the groups since they were `before` by
+    /// `scale`.
+    fn scale_since(&mut self, before: &[Color], scale: f64) {
+        for (total, &before) in self.groups.iter_mut().zip(before) {
+            *total = before + (*total - before) * scale;
+        }
+    }
+
+    /// The split for the next vertex, whose light is scaled by `weight` on
+    /// its way to this one.
+    fn bounce(&mut self, weight: Color) -> GroupSplit<'_> {
+        GroupSplit {
+            throughput: self.throughput * weight,
+            groups: self.groups,
+        }
+    }
+}
+
+/// What a camera ray sees, for the film's AOVs.
+fn surface(r: Ray, hit: &HitRecord, motion: (f64, f64)) -> Surface {
+    Surface {
+        position: hit.p,
+        normal: hit.normal,
+        depth: hit.t * r.direction.length(),
+        albedo: hit.material.albedo(&hit.tex_coords(&r)),
+        id: material_id(hit.material),
+        motion,
+    }
+}
+
+/// Identifies a surface by the address of its material, which every object
+/// in a scene usually has its own copy of. Hashed so neighbouring objects get
+/// very different IDs, and kept below 2^24 to survive conversion to `f32`.
+fn material_id(material: &Material) -> u32 {
+    let mut x = material as *const Material as u64;
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^= x >> 31;
+    (x & 0xff_ffff) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::background::SolidColor;
+    use crate::light::Light;
+    use crate::volume::Medium;
+    use crate::world::{Rect, Sphere, World};
+
+    fn gray() -> Material {
+        Material::Lambertian {
+            albedo: Color::new(0.5, 0.5, 0.5).into(),
+        }
+    }
+
+    fn lamp() -> Material {
+        Material::DiffuseLight {
+            emit: Color::new(40., 40., 40.).into(),
+            group: DEFAULT_LIGHT_GROUP,
+            power: None,
+        }
+    }
+
+    fn glass() -> Material {
+        Material::Dialectric {
+            index_of_refraction: 1.5,
+            medium: None,
+            priority: 0,
+            reflection_roughness: 0.,
+            transmission_roughness: 0.,
+        }
+    }
+
+    /// A gray floor lit only by a small lamp above it, with `between` in
+    /// the way if given, seen from above and to one side.
+    fn lamp_scene(between: Option<Material>) -> Scene {
+        let lamp = Sphere::new(Point3::new(0., 4., 0.), 0.3, lamp());
+        let mut objects: Vec<Box<dyn Hit>> = vec![
+            Box::new(Rect::new(
+                Point3::new(-5., 0., -5.),
+                Vec3::new(0., 0., 10.),
+                Vec3::new(10., 0., 0.),
+                gray(),
+            )),
+            Box::new(lamp.clone()),
+        ];
+        if let Some(material) = between {
+            objects.push(Box::new(Sphere::new(
+                Point3::new(0., 2., 0.),
+                0.8,
+                material,
+            )));
+        }
+        let lights: Vec<Arc<dyn Light>> = vec![Arc::new(lamp)];
+        Scene {
+            world: Box::new(World::new(objects)),
+            lights: LightTree::new(lights),
+            light_groups: vec!["default".to_string()],
+            camera: Camera::new(
+                Point3::new(4., 5., 4.),
+                Point3::new(0., 0., 0.),
+                Vec3::new(0., 1., 0.),
+                40.,
+                1.,
+                0.,
+                1.,
+                (0., 1.),
+            ),
+            background: Box::new(SolidColor::new(BLACK)),
+            fog: None,
+        }
+    }
+
+    fn config(samples_per_pixel: u32) -> RenderConfig {
+        RenderConfig {
+            width: 16,
+            height: 16,
+            budget: Budget {
+                samples_per_pixel: Some(samples_per_pixel),
+                ..Budget::default()
+            },
+            ..RenderConfig::default()
+        }
+    }
+
+    /// The average luminance of an image from `render_to_f32`.
+    fn mean(pixels: &[f32]) -> f64 {
+        let total: f64 = pixels
+            .chunks(4)
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64).luminance())
+            .sum();
+        total / (pixels.len() / 4) as f64
+    }
+
+    #[test]
+    fn transparent_shadows_let_light_through_glass() {
+        let scene = lamp_scene(Some(glass()));
+        let opaque = mean(&render_to_f32(&scene, &config(16)));
+        let transparent = mean(&render_to_f32(
+            &scene,
+            &RenderConfig {
+                transparent_shadows: true,
+                ..config(16)
+            },
+        ));
+        assert!(
+            transparent > opaque * 1.05,
+            "{} isn't brighter than {}",
+            transparent,
+            opaque
+        );
+    }
+
+    #[test]
+    fn fog_sampling_modes_differ_but_agree_on_average() {
+        let scene = Scene {
+            fog: Some(Medium::new(BLACK, 0.05)),
+            ..lamp_scene(None)
+        };
+        let render = |fog_sampling| {
+            render_to_f32(
+                &scene,
+                &RenderConfig {
+                    fog_sampling,
+                    ..config(8)
+                },
+            )
+        };
+        let equiangular = render(FogSampling::Equiangular);
+        let distance = render(FogSampling::Distance);
+        assert_ne!(equiangular, distance);
+
+        let (equiangular, distance) = (mean(&equiangular), mean(&distance));
+        assert!(
+            (equiangular / distance - 1.).abs() < 0.1,
+            "{} is far from {}",
+            equiangular,
+            distance
+        );
+    }
+
+    #[test]
+    fn sampling_lights_lowers_noise() {
+        // Noise shows up as the difference between renders with different
+        // seeds, with the lamp only found by bounces in the second scene
+        let noise = |scene: &Scene| {
+            let first = render_to_f32(scene, &config(4));
+            let second = render_to_f32(
+                scene,
+                &RenderConfig {
+                    seed: 1,
+                    ..config(4)
+                },
+            );
+            let total: f64 = first
+                .iter()
+                .zip(&second)
+                .map(|(a, b)| (*a as f64 - *b as f64).powi(2))
+                .sum();
+            total / first.len() as f64
+        };
+        let sampled = noise(&lamp_scene(None));
+        let unsampled = noise(&Scene {
+            lights: LightTree::new(Vec::new()),
+            ..lamp_scene(None)
+        });
+        assert!(
+            sampled * 10. < unsampled,
+            "{} isn't much less than {}",
+            sampled,
+            unsampled
+        );
+    }
+
+    #[test]
+    fn clamp_keeps_lights_seen_directly() {
+        // Straight down onto the lamp
+        let scene = Scene {
+            camera: Camera::new(
+                Point3::new(0., 10., 0.),
+                Point3::new(0., 4., 0.),
+                Vec3::new(1., 0., 0.),
+                1.,
+                1.,
+                0.,
+                1.,
+                (0., 1.),
+            ),
+            ..lamp_scene(None)
+        };
+        let pixels = render_to_f32(
+            &scene,
+            &RenderConfig {
+                indirect_clamp: Some(0.01),
+                ..config(1)
+            },
+        );
+        for pixel in pixels.chunks(4) {
+            assert_eq!(pixel, [40., 40., 40., 1.]);
+        }
+    }
+
+    #[test]
+    fn clamp_keeps_direct_light() {
+        // With nothing but the floor to bounce off, everything the lamp
+        // lights it with comes straight from the lamp, whether it's found
+        // by sampling the lamp or by bounces
+        let unsampled = Scene {
+            lights: LightTree::new(Vec::new()),
+            ..lamp_scene(None)
+        };
+        for scene in [lamp_scene(None), unsampled] {
+            let unclamped = render_to_f32(&scene, &config(4));
+            let clamped = render_to_f32(
+                &scene,
+                &RenderConfig {
+                    indirect_clamp: Some(0.01),
+                    ..config(4)
+                },
+            );
+            assert_eq!(unclamped, clamped);
+        }
+    }
+
+    #[test]
+    fn clamp_dims_bright_indirect_light() {
+        let scene = lamp_scene(Some(glass()));
+        let unclamped = mean(&render_to_f32(&scene, &config(4)));
+        let clamped = mean(&render_to_f32(
+            &scene,
+            &RenderConfig {
+                indirect_clamp: Some(0.01),
+                ..config(4)
+            },
+        ));
+        assert!(clamped < unclamped, "{} isn't below {}", clamped, unclamped);
+    }
+
+    #[test]
+    fn light_groups_add_up_to_the_image() {
+        let lamp = |x, group| {
+            let material = Material::DiffuseLight {
+                emit: Color::new(40., 30., 20.).into(),
+                group,
+                power: None,
+            };
+            Sphere::new(Point3::new(x, 4., 0.), 0.3, material)
+        };
+        let (key, fill) = (lamp(-1., 1), lamp(1., 2));
+        let floor = Rect::new(
+            Point3::new(-5., 0., -5.),
+            Vec3::new(0., 0., 10.),
+            Vec3::new(10., 0., 0.),
+            gray(),
+        );
+        let lights: Vec<Arc<dyn Light>> = vec![Arc::new(key.clone()), Arc::new(fill.clone())];
+        let scene = Scene {
+            world: Box::new(World::new(vec![
+                Box::new(floor),
+                Box::new(key),
+                Box::new(fill),
+            ])),
+            lights: LightTree::new(lights),
+            light_groups: vec!["default".to_string(), "key".to_string(), "fill".to_string()],
+            // The background is in the default group
+            background: Box::new(SolidColor::new(Color::new(0.2, 0.3, 0.4))),
+            ..lamp_scene(None)
+        };
+
+        let mut film = Film::new(16, 16).with_light_groups(scene.light_groups.clone());
+        Renderer::new(&scene).render(&mut film, config(4).budget);
+        for y in 0..16 {
+            for x in 0..16 {
+                let groups: Vec<Color> = (0..3).map(|g| film.light_group(g, x, y)).collect();
+                let difference = groups[0] + groups[1] + groups[2] - film.pixel(x, y);
+                for channel in [difference.r(), difference.g(), difference.b()] {
+                    assert!(channel.abs() < 1e-9);
+                }
+            }
+        }
+        let lit_by = |g| (0..16 * 16).any(|i| film.light_group(g, i % 16, i / 16).luminance() > 0.);
+        assert!(lit_by(0) && lit_by(1) && lit_by(2));
+    }
+}
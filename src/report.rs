@@ -0,0 +1,153 @@
+use std::path::PathBuf;
+
+use raytracing::accel::{AccelStats, AcceleratorKind};
+use raytracing::film::ImageStats;
+use raytracing::timing::Timings;
+
+/// A summary of a finished render, written as JSON for render farm
+/// bookkeeping and benchmarking scripts.
+pub struct Report<'a> {
+    pub width: usize,
+    pub height: usize,
+    pub frames: u32,
+    /// Samples per pixel reached in the last frame rendered.
+    pub samples_per_pixel: u32,
+    /// False if the render was interrupted.
+    pub completed: bool,
+    /// The noise estimated to be left in the last frame rendered, if it was
+    /// rendered to a noise threshold.
+    pub noise: Option<f64>,
+    pub timings: &'a Timings,
+    pub rays: u64,
+    pub accelerator: AcceleratorKind,
+    pub accel_stats: AccelStats,
+    /// Every file written, with `-` for the image when written to stdout.
+    pub outputs: &'a [PathBuf],
+    /// How bright the last frame rendered is.
+    pub stats: Option<&'a ImageStats>,
+}
+
+impl Report<'_> {
+    pub fn to_json(&self) -> String {
+        let render_time = self.timings.get("render").as_secs_f64();
+        let rays_per_second = if render_time > 0. {
+            self.rays as f64 / render_time
+        } else {
+            0.
+        };
+
+        let stages: Vec<String> = self
+            .timings
+            .stages()
+            .map(|(name, duration)| format!("{}: {}", string(name), duration.as_secs_f64()))
+            .collect();
+        let outputs: Vec<String> = self
+            .outputs
+            .iter()
+            .map(|path| string(&path.to_string_lossy()))
+            .collect();
+        let stats = self.accel_stats;
+        let image = match self.stats {
+            Some(image) => {
+                let (first_stop, _) = image.histogram_stops();
+                let histogram: Vec<String> = image
+                    .histogram
+                    .iter()
+                    .map(|count| count.to_string())
+                    .collect();
+                format!(
+                    r#"
+  "image": {{
+    "average_luminance": {},
+    "log_average_luminance": {},
+    "max_luminance": {},
+    "clipped_fraction": {},
+    "exposure_to_middle_gray": {},
+    "histogram": {{ "first_stop": {}, "counts": [{}] }}
+  }},"#,
+                    json_number(image.average),
+                    json_number(image.log_average),
+                    json_number(image.max),
+                    json_number(image.clipped),
+                    json_number(image.exposure_to_middle_gray()),
+                    first_stop,
+                    histogram.join(", "),
+                )
+            }
+            None => String::new(),
+        };
+
+        format!(
+            r#"{{
+  "resolution": {{ "width": {}, "height": {} }},
+  "frames": {},
+  "samples_per_pixel": {},
+  "completed": {},
+  "noise": {},
+  "time": {{
+    "total": {},
+    "stages": {{ {} }}
+  }},
+  "rays": {},
+  "rays_per_second": {:.0},
+  "accelerator": {{
+    "kind": {},
+    "objects": {},
+    "unbounded": {},
+    "interior_nodes": {},
+    "leaves": {},
+    "max_depth": {}
+  }},{}
+  "outputs": [{}]
+}}
+"#,
+            self.width,
+            self.height,
+            self.frames,
+            self.samples_per_pixel,
+            self.completed,
+            self.noise.map_or_else(|| "null".to_string(), json_number),
+            self.timings.total().as_secs_f64(),
+            stages.join(", "),
+            self.rays,
+            rays_per_second,
+            string(&self.accelerator.to_string()),
+            stats.objects,
+            stats.unbounded,
+            stats.interior_nodes,
+            stats.leaves,
+            stats.max_depth,
+            image,
+            outputs.join(", "),
+        )
+    }
+}
+
+/// `x` as a JSON number, which can't be infinite or NaN, so those are
+/// written as null.
+fn json_number(x: f64) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+/// `s` as a quoted JSON string.
+fn string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,250 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::rngs::{SmallRng, StdRng};
+use rand::{RngCore, SeedableRng};
+
+use crate::tile::Tile;
+
+/// Mixes `values` into a single well-scrambled number, so nearby inputs
+/// such as neighbouring pixels give unrelated results.
+pub fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |h, &v| {
+        // The SplitMix64 finalizer over the running hash and each value
+        let mut z = (h ^ v).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+/// A random number generator that always gives the same numbers for the
+/// same `values`, e.g. a seed with the pixel and sample being taken.
+pub fn seeded(values: &[u64]) -> StdRng {
+    StdRng::seed_from_u64(hash(values))
+}
+
+/// The PCG32 generator (XSH RR, with 64 bits of state): very small and fast
+/// to seed, with good enough statistics for sampling.
+#[derive(Clone, Debug)]
+pub struct Pcg32 {
+    state: u64,
+    /// Selects one of 2^63 different streams; always odd.
+    increment: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+
+    fn step(&mut self) {
+        self.state = self
+            .state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(self.increment);
+    }
+}
+
+impl RngCore for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.step();
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        xorshifted.rotate_right((old >> 59) as u32)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let low = self.next_u32() as u64;
+        (self.next_u32() as u64) << 32 | low
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            chunk.copy_from_slice(&self.next_u32().to_le_bytes()[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl SeedableRng for Pcg32 {
+    /// The starting state, then the stream.
+    type Seed = [u8; 16];
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        let (state, stream) = seed.split_at(8);
+        let state = u64::from_le_bytes(state.try_into().unwrap());
+        let stream = u64::from_le_bytes(stream.try_into().unwrap());
+
+        // As the reference implementation seeds it
+        let mut rng = Pcg32 {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.step();
+        rng.state = rng.state.wrapping_add(state);
+        rng.step();
+        rng
+    }
+}
+
+/// The random number generators samples can be taken with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Generator {
+    /// ChaCha with 12 rounds, a cryptographic generator giving the same
+    /// numbers on every platform and version of this program.
+    #[default]
+    ChaCha,
+    /// Xoshiro256++, much faster to run and to seed.
+    Xoshiro,
+    /// PCG32, the smallest and fastest to seed.
+    Pcg,
+}
+
+impl FromStr for Generator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chacha" => Ok(Generator::ChaCha),
+            "xoshiro" => Ok(Generator::Xoshiro),
+            "pcg" => Ok(Generator::Pcg),
+            _ => Err(format!("unknown random number generator {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Generator::ChaCha => write!(f, "chacha"),
+            Generator::Xoshiro => write!(f, "xoshiro"),
+            Generator::Pcg => write!(f, "pcg"),
+        }
+    }
+}
+
+/// How often a new generator is seeded while rendering, each from the seed
+/// and where it's used.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Seeding {
+    /// For every sample, so a pixel's samples come out the same however
+    /// they're split into passes, and renders can be resumed or extended.
+    #[default]
+    Sample,
+    /// For each pixel in each pass, shared by its samples in the pass.
+    Pixel,
+    /// For each tile in each pass, shared by all its pixels. Seeding the
+    /// least often, but the image then depends on the tile size too.
+    Tile,
+}
+
+impl FromStr for Seeding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sample" => Ok(Seeding::Sample),
+            "pixel" => Ok(Seeding::Pixel),
+            "tile" => Ok(Seeding::Tile),
+            _ => Err(format!("unknown seeding {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Seeding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Seeding::Sample => write!(f, "sample"),
+            Seeding::Pixel => write!(f, "pixel"),
+            Seeding::Tile => write!(f, "tile"),
+        }
+    }
+}
+
+/// Random numbers for sampling, from whichever generator was chosen.
+// Kept unboxed, as one is seeded for every sample by default
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum Sampler {
+    ChaCha(StdRng),
+    Xoshiro(SmallRng),
+    Pcg(Pcg32),
+}
+
+impl RngCore for Sampler {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Sampler::ChaCha(rng) => rng.next_u32(),
+            Sampler::Xoshiro(rng) => rng.next_u32(),
+            Sampler::Pcg(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Sampler::ChaCha(rng) => rng.next_u64(),
+            Sampler::Xoshiro(rng) => rng.next_u64(),
+            Sampler::Pcg(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Sampler::ChaCha(rng) => rng.fill_bytes(dest),
+            Sampler::Xoshiro(rng) => rng.fill_bytes(dest),
+            Sampler::Pcg(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            Sampler::ChaCha(rng) => rng.try_fill_bytes(dest),
+            Sampler::Xoshiro(rng) => rng.try_fill_bytes(dest),
+            Sampler::Pcg(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+/// Creates the random number generators a render takes its samples with,
+/// seeded from the render's seed and where they're used, so the same seed
+/// always gives the same image whatever the thread count or tile order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SamplerFactory {
+    pub generator: Generator,
+    pub seeding: Seeding,
+}
+
+impl SamplerFactory {
+    /// A generator always giving the same numbers for the same `values`,
+    /// as `seeded` does.
+    pub fn seeded(&self, values: &[u64]) -> Sampler {
+        let seed = hash(values);
+        match self.generator {
+            Generator::ChaCha => Sampler::ChaCha(StdRng::seed_from_u64(seed)),
+            Generator::Xoshiro => Sampler::Xoshiro(SmallRng::seed_from_u64(seed)),
+            Generator::Pcg => Sampler::Pcg(Pcg32::seed_from_u64(seed)),
+        }
+    }
+
+    /// The generator shared by a tile's pixels in the pass starting with
+    /// sample `first_sample`, if seeded per tile.
+    pub fn tile(&self, seed: u64, tile: Tile, first_sample: u32) -> Option<Sampler> {
+        (self.seeding == Seeding::Tile)
+            .then(|| self.seeded(&[seed, tile.x0 as u64, tile.y0 as u64, first_sample as u64]))
+    }
+
+    /// The generator shared by a pixel's samples in the pass starting with
+    /// sample `first_sample`, if seeded per pixel.
+    pub fn pixel(&self, seed: u64, x: usize, y: usize, first_sample: u32) -> Option<Sampler> {
+        (self.seeding == Seeding::Pixel)
+            .then(|| self.seeded(&[seed, x as u64, y as u64, first_sample as u64]))
+    }
+
+    /// The generator for a single sample of a pixel, when seeded per sample.
+    pub fn sample(&self, seed: u64, x: usize, y: usize, index: u32) -> Sampler {
+        self.seeded(&[seed, x as u64, y as u64, index as u64])
+    }
+}
@@ -0,0 +1,100 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use crate::vector::Vec3;
+
+// Each distribution is a mapping from uniform random numbers `u` in [0, 1),
+// with no rejection loop, so every sample takes the same few numbers and
+// the same time, and well spread numbers give well spread samples. The
+// `random_*` functions draw the numbers from an `Rng`.
+
+/// A point in the unit disk in the xy plane, spread evenly. The concentric
+/// mapping used keeps nearby `u` nearby, without squashing squares of `u`
+/// into slivers as polar coordinates do near the center.
+pub fn unit_disk(u: (f64, f64)) -> Vec3 {
+    let (a, b) = (2. * u.0 - 1., 2. * u.1 - 1.);
+    if a == 0. && b == 0. {
+        return Vec3::zero();
+    }
+
+    // Squares around the center map to circles around it
+    let (r, theta) = if a.abs() > b.abs() {
+        (a, PI / 4. * (b / a))
+    } else {
+        (b, PI / 2. - PI / 4. * (a / b))
+    };
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    Vec3::new(r * cos_theta, r * sin_theta, 0.)
+}
+
+/// A direction picked evenly from the whole sphere.
+pub fn unit_sphere(u: (f64, f64)) -> Vec3 {
+    let z = 1. - 2. * u.0;
+    let r = (1. - z * z).max(0.).sqrt();
+    let (sin_phi, cos_phi) = (2. * PI * u.1).sin_cos();
+    Vec3::new(r * cos_phi, r * sin_phi, z)
+}
+
+/// A point picked evenly from inside the unit ball.
+pub fn unit_ball(u: (f64, f64, f64)) -> Vec3 {
+    // The volume within radius r grows as r^3
+    unit_sphere((u.0, u.1)) * u.2.cbrt()
+}
+
+/// A direction in the hemisphere around +z, picked in proportion to the
+/// cosine of its angle to z: a point in the unit disk lifted up onto the
+/// hemisphere. Place it around a normal with `Onb::local`.
+pub fn cosine_hemisphere(u: (f64, f64)) -> Vec3 {
+    let d = unit_disk(u);
+    let z = (1. - d.length_squared()).max(0.).sqrt();
+    Vec3::new(d.x(), d.y(), z)
+}
+
+/// A direction picked evenly from the cone around +z whose edge is at an
+/// angle with a cosine of `1 - one_minus_cos_max`, which is passed that way
+/// to stay accurate for narrow cones.
+pub fn uniform_cone(one_minus_cos_max: f64, u: (f64, f64)) -> Vec3 {
+    let cos_theta = 1. - u.0 * one_minus_cos_max;
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let (sin_phi, cos_phi) = (2. * PI * u.1).sin_cos();
+    Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+}
+
+/// Probability density of `uniform_cone`, per unit solid angle.
+pub fn uniform_cone_pdf(one_minus_cos_max: f64) -> f64 {
+    1. / (2. * PI * one_minus_cos_max)
+}
+
+/// A microfacet normal around +z from the GGX distribution with roughness
+/// `alpha`, picked in proportion to its projected area. Place it around a
+/// normal with `Onb::local`.
+pub fn ggx_normal(alpha: f64, u: (f64, f64)) -> Vec3 {
+    let tan2_theta = alpha * alpha * u.0 / (1. - u.0);
+    let cos_theta = 1. / (1. + tan2_theta).sqrt();
+    let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+    let (sin_phi, cos_phi) = (2. * PI * u.1).sin_cos();
+    Vec3::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+}
+
+pub fn random_in_unit_sphere<T: Rng + ?Sized>(rng: &mut T) -> Vec3 {
+    unit_ball((rng.gen(), rng.gen(), rng.gen()))
+}
+
+pub fn random_unit_vector<T: Rng + ?Sized>(rng: &mut T) -> Vec3 {
+    unit_sphere((rng.gen(), rng.gen()))
+}
+
+pub fn random_in_unit_disk<T: Rng + ?Sized>(rng: &mut T) -> Vec3 {
+    unit_disk((rng.gen(), rng.gen()))
+}
+
+/// See `cosine_hemisphere`.
+pub fn random_cosine_direction<T: Rng + ?Sized>(rng: &mut T) -> Vec3 {
+    cosine_hemisphere((rng.gen(), rng.gen()))
+}
+
+/// See `ggx_normal`.
+pub fn random_ggx_normal<T: Rng + ?Sized>(rng: &mut T, alpha: f64) -> Vec3 {
+    ggx_normal(alpha, (rng.gen(), rng.gen()))
+}
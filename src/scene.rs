@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::background::Background;
+use crate::bounds::AABB;
+use crate::cam::Camera;
+use crate::light::LightTree;
+use crate::ray::{Hit, Material};
+use crate::volume::Medium;
+
+/// Offset along a ray before it can hit anything, for scenes with no bounds
+/// to scale it by.
+pub const DEFAULT_EPSILON: f64 = 0.001;
+
+/// The self-intersection offset as a fraction of the scene's size. Rounding
+/// errors in hit points grow with their distance from the origin, so large
+/// scenes need a bigger offset and small ones a smaller one to keep detail.
+const EPSILON_SCALE: f64 = 5e-7;
+
+/// Everything needed to render an image, ready to be rendered any number of
+/// times.
+pub struct Scene {
+    /// The objects, usually built into an acceleration structure.
+    pub world: Box<dyn Hit>,
+    /// The lights to sample directly: the emissive objects in `world`, and
+    /// lights such as `PointLight` with nothing there to hit.
+    pub lights: LightTree,
+    /// Names of the light groups, by index. The first is the default group,
+    /// which also holds the background.
+    pub light_groups: Vec<String>,
+    pub camera: Camera,
+    pub background: Box<dyn Background + Send + Sync>,
+    /// Haze or dust filling the space around the objects, which shows up
+    /// the beams of light crossing it. Its absorption and scattering are
+    /// per unit of the scene's length.
+    pub fog: Option<Medium>,
+}
+
+impl Scene {
+    /// What the scene is made of, at the camera's time.
+    pub fn stats(&self) -> SceneStats {
+        let mut stats = SceneStats::new();
+        self.world.count(&mut stats);
+        stats.bounds = self.world.bounds(self.camera.time());
+        stats.lights = self.lights.len();
+        stats.light_groups = self.light_groups.len();
+        stats
+    }
+
+    /// How far along a ray to start looking for hits, so that rays leaving a
+    /// surface don't hit it again through rounding errors ("shadow acne").
+    /// Scaled with the size of the scene; too large an offset lets light
+    /// leak through thin objects and into corners.
+    pub fn epsilon(&self) -> f64 {
+        match self.world.bounds(self.camera.time()) {
+            Some(bounds) => {
+                let size = bounds.max - bounds.min;
+                let largest = size.x().max(size.y()).max(size.z());
+                if largest.is_finite() && largest > 0. {
+                    largest * EPSILON_SCALE
+                } else {
+                    DEFAULT_EPSILON
+                }
+            }
+            None => DEFAULT_EPSILON,
+        }
+    }
+}
+
+/// What a scene is made of, for judging how long it will take to render and
+/// how much memory it needs before starting.
+#[derive(Clone, Default)]
+pub struct SceneStats {
+    /// How many objects there are of each kind, by kind. Objects shared
+    /// between instances are counted once.
+    pub objects: BTreeMap<&'static str, usize>,
+    /// A rough estimate of the memory the objects and acceleration
+    /// structures take, in bytes. Textures aren't included.
+    pub memory: usize,
+    pub bounds: Option<AABB>,
+    /// Objects with a light material, whether they're sampled directly or
+    /// only found by paths hitting them.
+    pub emitters: usize,
+    /// Lights sampled directly.
+    pub lights: usize,
+    pub light_groups: usize,
+    /// Addresses of the shared objects counted so far.
+    shared: HashSet<usize>,
+}
+
+impl SceneStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts an object of `kind` taking `memory` bytes.
+    pub fn add(&mut self, kind: &'static str, memory: usize) {
+        *self.objects.entry(kind).or_default() += 1;
+        self.memory += memory;
+    }
+
+    /// Counts an object of `kind` taking `memory` bytes with the surface
+    /// `material`, as an emitter too if it's a light.
+    pub fn add_surface(&mut self, kind: &'static str, memory: usize, material: &Material) {
+        self.add(kind, memory);
+        if matches!(material, Material::DiffuseLight { .. }) {
+            self.emitters += 1;
+        }
+    }
+
+    /// Counts an object shared between instances, unless it's been counted
+    /// already.
+    pub fn add_shared(&mut self, object: &Arc<dyn Hit>) {
+        if self
+            .shared
+            .insert(Arc::as_ptr(object) as *const () as usize)
+        {
+            object.count(self);
+        }
+    }
+
+    /// The number of objects of every kind.
+    pub fn total(&self) -> usize {
+        self.objects.values().sum()
+    }
+}
+
+impl fmt::Display for SceneStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "  {:<24} {}", "objects", self.total())?;
+        for (kind, count) in &self.objects {
+            writeln!(f, "    {:<22} {}", kind, count)?;
+        }
+
+        let bounds = match self.bounds {
+            Some(AABB { min, max }) => format!(
+                "{:.3},{:.3},{:.3} to {:.3},{:.3},{:.3}",
+                min.x(),
+                min.y(),
+                min.z(),
+                max.x(),
+                max.y(),
+                max.z()
+            ),
+            None => "unbounded".to_string(),
+        };
+        let rows = [
+            ("emitters", self.emitters.to_string()),
+            ("lights", self.lights.to_string()),
+            ("light groups", self.light_groups.to_string()),
+            ("bounds", bounds),
+            ("memory", bytes(self.memory)),
+        ];
+        for (i, (name, value)) in rows.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "  {:<24} {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A number of bytes in the largest binary unit it makes at least one of.
+fn bytes(n: usize) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if n < 1024 {
+        return format!("{} B", n);
+    }
+    let mut size = n as f64 / 1024.;
+    let mut unit = 0;
+    while size >= 1024. && unit + 1 < UNITS.len() {
+        size /= 1024.;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,170 @@
+use std::str::FromStr;
+
+use crate::vector::Vec3;
+
+/// A place and moment on Earth, for working out where the sun is in its
+/// sky, e.g. to light a building as it would be on a given afternoon.
+///
+/// Scenes are taken to be laid out with +y up, -z north and +x east.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SolarTime {
+    /// Degrees north of the equator, negative to the south.
+    pub latitude: f64,
+    /// Degrees east of Greenwich, negative to the west.
+    pub longitude: f64,
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    /// Hours since midnight UTC at the start of the day, e.g. 14.5 for half
+    /// past two, running past 0 or 24 into the neighbouring days.
+    pub hour: f64,
+}
+
+impl SolarTime {
+    /// The sun's elevation above the horizon and its azimuth clockwise from
+    /// north, in degrees.
+    ///
+    /// Uses NOAA's solar position equations, after Meeus, "Astronomical
+    /// Algorithms", which are accurate to well under a degree for dates
+    /// within a few centuries of 2000. Refraction by the atmosphere, which
+    /// lifts the sun slightly near the horizon, is ignored.
+    pub fn sun_angles(&self) -> (f64, f64) {
+        // Julian centuries since noon on 1 January 2000
+        let t = (self.julian_day() - 2_451_545.) / 36_525.;
+
+        let mean_longitude = (280.46646 + t * (36000.76983 + t * 0.0003032)).rem_euclid(360.);
+        let mean_anomaly = 357.52911 + t * (35999.05029 - 0.0001537 * t);
+        let eccentricity = 0.016708634 - t * (0.000042037 + 0.0000001267 * t);
+
+        let m = mean_anomaly.to_radians();
+        let center = m.sin() * (1.914602 - t * (0.004817 + 0.000014 * t))
+            + (2. * m).sin() * (0.019993 - 0.000101 * t)
+            + (3. * m).sin() * 0.000289;
+        let node = (125.04 - 1934.136 * t).to_radians();
+        let apparent_longitude =
+            (mean_longitude + center - 0.00569 - 0.00478 * node.sin()).to_radians();
+
+        let mean_obliquity =
+            23. + (26. + (21.448 - t * (46.815 + t * (0.00059 - t * 0.001813))) / 60.) / 60.;
+        let obliquity = (mean_obliquity + 0.00256 * node.cos()).to_radians();
+        let declination = (obliquity.sin() * apparent_longitude.sin()).asin();
+
+        // How far the sun runs ahead of or behind clock time, in minutes
+        let y = (obliquity / 2.).tan().powi(2);
+        let l0 = mean_longitude.to_radians();
+        let e = eccentricity;
+        let equation_of_time = 4.
+            * (y * (2. * l0).sin() - 2. * e * m.sin() + 4. * e * y * m.sin() * (2. * l0).cos()
+                - 0.5 * y * y * (4. * l0).sin()
+                - 1.25 * e * e * (2. * m).sin())
+            .to_degrees();
+
+        let solar_minutes = self.hour * 60. + equation_of_time + 4. * self.longitude;
+        let hour_angle = (solar_minutes / 4. - 180.).to_radians();
+
+        let latitude = self.latitude.to_radians();
+        let cos_zenith = latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos();
+        let elevation = 90. - cos_zenith.clamp(-1., 1.).acos().to_degrees();
+        let azimuth = hour_angle
+            .sin()
+            .atan2(hour_angle.cos() * latitude.sin() - declination.tan() * latitude.cos())
+            .to_degrees()
+            + 180.;
+
+        (elevation, azimuth.rem_euclid(360.))
+    }
+
+    /// Unit vector towards the sun, which is below the horizon when its y is
+    /// negative.
+    pub fn sun_direction(&self) -> Vec3 {
+        let (elevation, azimuth) = self.sun_angles();
+        let (sin_elevation, cos_elevation) = elevation.to_radians().sin_cos();
+        let (sin_azimuth, cos_azimuth) = azimuth.to_radians().sin_cos();
+        Vec3::new(
+            cos_elevation * sin_azimuth,
+            sin_elevation,
+            -cos_elevation * cos_azimuth,
+        )
+    }
+
+    fn julian_day(&self) -> f64 {
+        // Days from a date in March 4801 BC, where leap days fall at the end
+        // of each year
+        let a = (14 - self.month as i64) / 12;
+        let y = self.year as i64 + 4800 - a;
+        let m = self.month as i64 + 12 * a - 3;
+        let day_number =
+            self.day as i64 + (153 * m + 2) / 5 + 365 * y + y / 4 - y / 100 + y / 400 - 32_045;
+
+        day_number as f64 + (self.hour - 12.) / 24.
+    }
+}
+
+impl FromStr for SolarTime {
+    type Err = String;
+
+    /// Parses `LATITUDE,LONGITUDE,DATE` with an ISO 8601 date and time such
+    /// as `2024-06-21T14:30`, in UTC unless it ends with an offset such as
+    /// `+02:00`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid place and time {:?}", s);
+        let number = |s: &str| s.trim().parse::<f64>().map_err(|_| invalid());
+
+        let [latitude, longitude, when] = s.split(',').collect::<Vec<_>>()[..] else {
+            return Err(invalid());
+        };
+        let latitude = number(latitude)?;
+        let longitude = number(longitude)?;
+        if !(-90. ..=90.).contains(&latitude) || !(-180. ..=180.).contains(&longitude) {
+            return Err(invalid());
+        }
+
+        let (date, time) = when.trim().split_once('T').ok_or_else(invalid)?;
+        let [year, month, day] = date.split('-').collect::<Vec<_>>()[..] else {
+            return Err(invalid());
+        };
+        let year = year.parse::<i32>().map_err(|_| invalid())?;
+        let month = month.parse::<u32>().map_err(|_| invalid())?;
+        let day = day.parse::<u32>().map_err(|_| invalid())?;
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return Err(invalid());
+        }
+
+        // Hours and minutes, with optional seconds, then the zone
+        let hours = |clock: &str| -> Result<f64, String> {
+            let parts = clock
+                .split(':')
+                .map(number)
+                .collect::<Result<Vec<_>, _>>()?;
+            match parts[..] {
+                [h, m] => Ok(h + m / 60.),
+                [h, m, s] => Ok(h + m / 60. + s / 3600.),
+                _ => Err(invalid()),
+            }
+        };
+        let (clock, offset) = match time.find(['Z', '+', '-']) {
+            Some(i) => match &time[i..] {
+                "Z" => (&time[..i], 0.),
+                zone => {
+                    let sign = if zone.starts_with('-') { -1. } else { 1. };
+                    (&time[..i], sign * hours(&zone[1..])?)
+                }
+            },
+            None => (time, 0.),
+        };
+        let local = hours(clock)?;
+        if !(0. ..24.).contains(&local) {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            latitude,
+            longitude,
+            year,
+            month,
+            day,
+            hour: local - offset,
+        })
+    }
+}
@@ -0,0 +1,445 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::color::Color;
+use crate::image::Image;
+use crate::perlin::Perlin;
+use crate::texture_cache::TextureCache;
+use crate::vector::{Point3, Vec3};
+use crate::worley::{Feature, Metric, Worley};
+
+/// Octaves of turbulence used when the footprint doesn't limit them.
+const MAX_OCTAVES: u32 = 7;
+
+/// Shown in place of images that couldn't be loaded, to stand out.
+const MISSING_TEXTURE: Color = Color::new(1., 0., 1.);
+
+/// Where on a surface a texture is being looked up, and how large an area of
+/// the surface the current pixel covers there.
+///
+/// The derivatives are zero when the footprint is unknown, in which case
+/// textures are point sampled.
+#[derive(Clone, Copy)]
+pub struct TexCoords {
+    pub uv: (f64, f64),
+    pub p: Point3,
+    /// Change in position from one pixel to the next in x and y.
+    pub dpdx: Vec3,
+    pub dpdy: Vec3,
+    /// Change in texture coordinates from one pixel to the next in x and y.
+    pub duvdx: (f64, f64),
+    pub duvdy: (f64, f64),
+}
+
+impl TexCoords {
+    pub fn point(uv: (f64, f64), p: Point3) -> Self {
+        Self {
+            uv,
+            p,
+            dpdx: Vec3::zero(),
+            dpdy: Vec3::zero(),
+            duvdx: (0., 0.),
+            duvdy: (0., 0.),
+        }
+    }
+}
+
+/// How a pattern is placed on a surface.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mapping {
+    /// Follows the surface's texture coordinates.
+    Uv,
+    /// Fills space, so the surface is carved out of a solid block of pattern.
+    Solid,
+}
+
+#[derive(Clone)]
+pub enum Texture {
+    Solid(Color),
+    /// Alternates between two textures in squares (or cubes, with solid
+    /// mapping) `1 / scale` units across.
+    Checker {
+        even: Box<Texture>,
+        odd: Box<Texture>,
+        scale: f64,
+        mapping: Mapping,
+    },
+    /// Veins of turbulence running across layers perpendicular to z.
+    Marble {
+        ramp: ColorRamp,
+        scale: f64,
+        turbulence: f64,
+    },
+    /// Growth rings `1 / scale` units apart around the y axis, warped by
+    /// noise.
+    Wood {
+        ramp: ColorRamp,
+        scale: f64,
+        turbulence: f64,
+    },
+    /// Cells around points scattered `1 / scale` units apart, colored by
+    /// the distance to them.
+    Worley {
+        ramp: ColorRamp,
+        scale: f64,
+        metric: Metric,
+        feature: Feature,
+    },
+    /// An image stretched over the unit square of texture coordinates, with
+    /// v pointing up the image.
+    Image {
+        image: Arc<Image>,
+        wrap: Wrap,
+    },
+    /// An image texture loaded through a cache the first time it is looked
+    /// up. Shows as magenta if the image can't be loaded.
+    CachedImage {
+        path: Arc<Path>,
+        cache: Arc<TextureCache>,
+        wrap: Wrap,
+    },
+    /// Another texture with its texture coordinates transformed. Textures
+    /// with solid mapping are unaffected.
+    Transformed {
+        texture: Box<Texture>,
+        transform: UvTransform,
+    },
+}
+
+impl Texture {
+    pub fn checker(even: Texture, odd: Texture, scale: f64, mapping: Mapping) -> Self {
+        Texture::Checker {
+            even: Box::new(even),
+            odd: Box::new(odd),
+            scale,
+            mapping,
+        }
+    }
+
+    pub fn marble(ramp: ColorRamp, scale: f64, turbulence: f64) -> Self {
+        Texture::Marble {
+            ramp,
+            scale,
+            turbulence,
+        }
+    }
+
+    pub fn wood(ramp: ColorRamp, scale: f64, turbulence: f64) -> Self {
+        Texture::Wood {
+            ramp,
+            scale,
+            turbulence,
+        }
+    }
+
+    pub fn worley(ramp: ColorRamp, scale: f64, metric: Metric, feature: Feature) -> Self {
+        Texture::Worley {
+            ramp,
+            scale,
+            metric,
+            feature,
+        }
+    }
+
+    pub fn image(image: Arc<Image>, wrap: Wrap) -> Self {
+        Texture::Image { image, wrap }
+    }
+
+    pub fn cached_image<P: AsRef<Path>>(path: P, cache: Arc<TextureCache>, wrap: Wrap) -> Self {
+        Texture::CachedImage {
+            path: Arc::from(path.as_ref()),
+            cache,
+            wrap,
+        }
+    }
+
+    /// Loads the images the texture looks up through a cache, so they're
+    /// ready before rendering rather than loaded by whichever thread first
+    /// needs them.
+    pub fn preload(&self) {
+        match self {
+            Texture::CachedImage { path, cache, .. } => {
+                cache.get(path);
+            }
+            Texture::Checker { even, odd, .. } => {
+                even.preload();
+                odd.preload();
+            }
+            Texture::Transformed { texture, .. } => texture.preload(),
+            _ => {}
+        }
+    }
+
+    /// Wraps the texture to look it up through `transform`.
+    pub fn transformed(self, transform: UvTransform) -> Self {
+        Texture::Transformed {
+            texture: Box::new(self),
+            transform,
+        }
+    }
+
+    pub fn value(&self, tc: &TexCoords) -> Color {
+        match self {
+            Texture::Solid(color) => *color,
+            Texture::Checker {
+                even,
+                odd,
+                scale,
+                mapping,
+            } => {
+                // Each axis is box filtered over the footprint separately. A
+                // cell is odd when an odd number of axes are, which for
+                // independent axes combines as a XOR of probabilities.
+                let fraction = |x: f64, width: f64| odd_fraction(x * scale, width * scale);
+                let xor = |a: f64, b: f64| a + b - 2. * a * b;
+
+                let odd_fraction = match mapping {
+                    Mapping::Uv => {
+                        let du = tc.duvdx.0.abs().max(tc.duvdy.0.abs());
+                        let dv = tc.duvdx.1.abs().max(tc.duvdy.1.abs());
+                        xor(fraction(tc.uv.0, du), fraction(tc.uv.1, dv))
+                    }
+                    Mapping::Solid => (0..3)
+                        .map(|axis| {
+                            let width = tc.dpdx.axis(axis).abs().max(tc.dpdy.axis(axis).abs());
+                            fraction(tc.p.axis(axis), width)
+                        })
+                        .fold(0., xor),
+                };
+
+                if odd_fraction <= 0. {
+                    even.value(tc)
+                } else if odd_fraction >= 1. {
+                    odd.value(tc)
+                } else {
+                    even.value(tc).lerp(odd.value(tc), odd_fraction)
+                }
+            }
+            Texture::Marble {
+                ramp,
+                scale,
+                turbulence,
+            } => {
+                let p = tc.p * *scale;
+                let octaves = octaves(tc, *scale);
+                let phase = p.z() + turbulence * Perlin::shared().turbulence(p, octaves);
+                ramp.at(0.5 * (1. + (phase * std::f64::consts::PI).sin()))
+            }
+            Texture::Wood {
+                ramp,
+                scale,
+                turbulence,
+            } => {
+                let p = tc.p * *scale;
+                let distance = p.x().hypot(p.z()) + turbulence * Perlin::shared().noise(p);
+                ramp.at(distance.rem_euclid(1.))
+            }
+            Texture::Worley {
+                ramp,
+                scale,
+                metric,
+                feature,
+            } => ramp.at(Worley::shared().value(tc.p * *scale, *metric, *feature)),
+            Texture::Image { image, wrap } => sample_bilinear(image, tc.uv, *wrap),
+            Texture::CachedImage { path, cache, wrap } => match cache.get(path) {
+                Some(image) => sample_bilinear(&image, tc.uv, *wrap),
+                None => MISSING_TEXTURE,
+            },
+            Texture::Transformed { texture, transform } => texture.value(&transform.apply(tc)),
+        }
+    }
+}
+
+impl From<Color> for Texture {
+    fn from(color: Color) -> Self {
+        Texture::Solid(color)
+    }
+}
+
+/// What happens to texture coordinates outside the unit square.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Wrap {
+    /// Tile the texture.
+    #[default]
+    Repeat,
+    /// Stretch the edge pixels outwards.
+    Clamp,
+    /// Tile the texture, flipping every other copy so the edges meet.
+    Mirror,
+}
+
+impl Wrap {
+    /// Maps a pixel index, possibly outside the image, to one inside it.
+    fn index(self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Clamp => i.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+/// Scales, rotates and then offsets texture coordinates, all about the
+/// center of the texture.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UvTransform {
+    /// How many times the texture fits across the surface in u and v.
+    pub scale: (f64, f64),
+    /// Counterclockwise, in degrees.
+    pub rotation: f64,
+    pub offset: (f64, f64),
+}
+
+impl UvTransform {
+    pub fn apply(&self, tc: &TexCoords) -> TexCoords {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let linear = |(u, v): (f64, f64)| {
+            let (u, v) = (u * self.scale.0, v * self.scale.1);
+            (u * cos - v * sin, u * sin + v * cos)
+        };
+
+        let (u, v) = linear((tc.uv.0 - 0.5, tc.uv.1 - 0.5));
+
+        TexCoords {
+            uv: (u + 0.5 + self.offset.0, v + 0.5 + self.offset.1),
+            duvdx: linear(tc.duvdx),
+            duvdy: linear(tc.duvdy),
+            ..*tc
+        }
+    }
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        Self {
+            scale: (1., 1.),
+            rotation: 0.,
+            offset: (0., 0.),
+        }
+    }
+}
+
+/// Colors picked by position between 0 and 1, blending linearly between
+/// stops.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColorRamp {
+    stops: Vec<(f64, Color)>,
+}
+
+impl ColorRamp {
+    /// Creates a ramp from `(position, color)` pairs, in any order.
+    pub fn new(stops: Vec<(f64, Color)>) -> Self {
+        assert!(!stops.is_empty(), "color ramp needs at least one stop");
+
+        let mut stops = stops;
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { stops }
+    }
+
+    pub fn marble() -> Self {
+        Self::new(vec![
+            (0., Color::new(0.25, 0.25, 0.28)),
+            (0.4, Color::new(0.75, 0.75, 0.75)),
+            (1., Color::new(0.95, 0.95, 0.92)),
+        ])
+    }
+
+    pub fn wood() -> Self {
+        Self::new(vec![
+            (0., Color::new(0.55, 0.33, 0.15)),
+            (0.7, Color::new(0.45, 0.25, 0.1)),
+            (0.85, Color::new(0.25, 0.12, 0.04)),
+            (1., Color::new(0.55, 0.33, 0.15)),
+        ])
+    }
+
+    /// Black through purple, red and yellow to white, for false-color
+    /// images.
+    pub fn heat() -> Self {
+        Self::new(vec![
+            (0., Color::new(0., 0., 0.)),
+            (0.25, Color::new(0.12, 0.01, 0.25)),
+            (0.5, Color::new(0.6, 0.05, 0.1)),
+            (0.75, Color::new(0.95, 0.45, 0.02)),
+            (1., Color::new(1., 1., 0.8)),
+        ])
+    }
+
+    pub fn at(&self, t: f64) -> Color {
+        let next = self.stops.partition_point(|&(position, _)| position < t);
+
+        if next == 0 {
+            return self.stops[0].1;
+        }
+        if next == self.stops.len() {
+            return self.stops[next - 1].1;
+        }
+
+        let (p0, c0) = self.stops[next - 1];
+        let (p1, c1) = self.stops[next];
+        c0.lerp(c1, (t - p0) / (p1 - p0))
+    }
+}
+
+fn sample_bilinear(image: &Image, (u, v): (f64, f64), wrap: Wrap) -> Color {
+    // Pixel centers lie at half-integer coordinates
+    let x = u * image.width() as f64 - 0.5;
+    let y = (1. - v) * image.height() as f64 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let pixel = |dx: i64, dy: i64| {
+        image.pixel(
+            wrap.index(x0 as i64 + dx, image.width()),
+            wrap.index(y0 as i64 + dy, image.height()),
+        )
+    };
+
+    let top = pixel(0, 0).lerp(pixel(1, 0), fx);
+    let bottom = pixel(0, 1).lerp(pixel(1, 1), fx);
+    top.lerp(bottom, fy)
+}
+
+/// How many octaves of turbulence are finer than the footprint allows, since
+/// those would only add noise to the image.
+fn octaves(tc: &TexCoords, scale: f64) -> u32 {
+    let width = tc.dpdx.length().max(tc.dpdy.length()) * scale;
+    if width <= 0. {
+        return MAX_OCTAVES;
+    }
+
+    // Octave n varies over 1 / 2^n units
+    let finest = (0.5 / width).log2().floor() + 1.;
+    finest.clamp(1., MAX_OCTAVES as f64) as u32
+}
+
+/// The fraction of `[x - width, x + width]` lying in odd unit cells.
+fn odd_fraction(x: f64, width: f64) -> f64 {
+    let point = || (x.floor() as i64).rem_euclid(2) as f64;
+
+    if width <= 0. || (x - width).floor() == (x + width).floor() {
+        return point();
+    }
+
+    // Wide enough to cover many cells, which average out
+    if width > 1. {
+        return 0.5;
+    }
+
+    // Integral of the odd-cell indicator from 0 to x
+    let integral = |x: f64| {
+        let half = x / 2.;
+        half.floor() + (half - half.floor() - 0.5).max(0.) * 2.
+    };
+
+    (integral(x + width) - integral(x - width)) / (2. * width)
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::image::Image;
+
+/// Loads image textures when they are first looked up rather than when the
+/// scene is built, keeping the most recently used ones in memory up to a
+/// budget.
+///
+/// Once the budget is full, loading another image evicts the least recently
+/// used ones. An evicted image stays alive while lookups are still using it,
+/// and is loaded again if it is needed later. An image larger than the whole
+/// budget is still loaded, on its own.
+pub struct TextureCache {
+    budget: usize,
+    entries: RwLock<Entries>,
+    /// Images that couldn't be loaded, with why, which aren't tried again.
+    failed: Mutex<HashMap<PathBuf, String>>,
+    /// Counts lookups, to order entries by when they were last used.
+    clock: AtomicU64,
+    /// Nanoseconds spent reading and decoding images, summed over threads.
+    load_time: AtomicU64,
+}
+
+#[derive(Default)]
+struct Entries {
+    images: HashMap<PathBuf, Entry>,
+    /// Total size of `images`, in bytes.
+    used: usize,
+}
+
+struct Entry {
+    image: Arc<Image>,
+    last_used: AtomicU64,
+}
+
+impl TextureCache {
+    /// Creates an empty cache that holds up to `budget` bytes of images.
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            entries: RwLock::new(Entries::default()),
+            failed: Mutex::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+            load_time: AtomicU64::new(0),
+        }
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Bytes of images currently held.
+    pub fn memory_used(&self) -> usize {
+        self.entries.read().unwrap().used
+    }
+
+    /// Time spent loading images so far. Images load while rendering, on
+    /// whichever threads first need them, so this is the sum over all
+    /// threads rather than time the render was held up by.
+    pub fn load_time(&self) -> Duration {
+        Duration::from_nanos(self.load_time.load(Ordering::Relaxed))
+    }
+
+    /// The images that couldn't be loaded so far, with why, by path.
+    pub fn failures(&self) -> Vec<(PathBuf, String)> {
+        let mut failures: Vec<_> = self
+            .failed
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, err)| (path.clone(), err.clone()))
+            .collect();
+        failures.sort();
+        failures
+    }
+
+    /// The image at `path`, loading it if it isn't in the cache. Returns None
+    /// if it can't be loaded; `failures` says why.
+    pub fn get(&self, path: &Path) -> Option<Arc<Image>> {
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(entry) = self.entries.read().unwrap().images.get(path) {
+            entry.last_used.store(now, Ordering::Relaxed);
+            return Some(Arc::clone(&entry.image));
+        }
+
+        self.load(path, now)
+    }
+
+    fn load(&self, path: &Path, now: u64) -> Option<Arc<Image>> {
+        if self.failed.lock().unwrap().contains_key(path) {
+            return None;
+        }
+
+        // Loading happens outside the lock so other lookups can go on
+        let start = Instant::now();
+        let loaded = Image::load(path);
+        self.load_time
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+
+        let image = match loaded {
+            Ok(image) => Arc::new(image),
+            Err(err) => {
+                let mut failed = self.failed.lock().unwrap();
+                failed.insert(path.to_path_buf(), err.to_string());
+                return None;
+            }
+        };
+
+        let mut entries = self.entries.write().unwrap();
+
+        // Another thread may have loaded it in the meantime
+        if let Some(entry) = entries.images.get(path) {
+            entry.last_used.store(now, Ordering::Relaxed);
+            return Some(Arc::clone(&entry.image));
+        }
+
+        let size = image.size_in_bytes();
+        while entries.used + size > self.budget {
+            let oldest = entries
+                .images
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+                .map(|(path, _)| path.clone());
+            let Some(oldest) = oldest else {
+                break;
+            };
+
+            let evicted = entries.images.remove(&oldest).unwrap();
+            entries.used -= evicted.image.size_in_bytes();
+        }
+
+        entries.used += size;
+        entries.images.insert(
+            path.to_path_buf(),
+            Entry {
+                image: Arc::clone(&image),
+                last_used: AtomicU64::new(now),
+            },
+        );
+
+        Some(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// A file in the temporary directory unique to this test run.
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("texture_cache_{}_{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn images_are_loaded_once() {
+        let path = temp_path("gray.ppm");
+        fs::write(&path, "P3 2 1 255 128 128 128 255 255 255").unwrap();
+
+        let cache = TextureCache::new(1 << 20);
+        let first = cache.get(&path).unwrap();
+        let second = cache.get(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.memory_used(), first.size_in_bytes());
+        assert!(cache.failures().is_empty());
+    }
+
+    #[test]
+    fn missing_images_are_failures() {
+        let path = temp_path("missing.ppm");
+        let cache = TextureCache::new(1 << 20);
+        assert!(cache.get(&path).is_none());
+        assert!(cache.get(&path).is_none());
+
+        let failures = cache.failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, path);
+    }
+}
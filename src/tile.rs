@@ -0,0 +1,163 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::seq::SliceRandom;
+
+/// Width and height of a tile in pixels. Tiles at the right and bottom edges
+/// of the image may be smaller.
+pub const TILE_SIZE: usize = 16;
+
+/// A rectangle of pixels rendered together, from `(x0, y0)` up to but not
+/// including `(x1, y1)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn pixels(self) -> impl Iterator<Item = (usize, usize)> {
+        (self.y0..self.y1).flat_map(move |y| (self.x0..self.x1).map(move |x| (x, y)))
+    }
+
+    /// The pixels in both `self` and `other`, if there are any.
+    pub fn intersection(self, other: Tile) -> Option<Tile> {
+        let tile = Tile {
+            x0: self.x0.max(other.x0),
+            y0: self.y0.max(other.y0),
+            x1: self.x1.min(other.x1),
+            y1: self.y1.min(other.y1),
+        };
+        (tile.x0 < tile.x1 && tile.y0 < tile.y1).then_some(tile)
+    }
+}
+
+/// The order tiles are handed out to render threads in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Row by row from the top left.
+    #[default]
+    Scanline,
+    /// Along a Hilbert curve, which keeps consecutive tiles next to each
+    /// other for better cache coherence.
+    Hilbert,
+    /// Outwards from the center of the image, where the subject usually is.
+    Spiral,
+    /// Shuffled, which spreads early results over the whole image.
+    Random,
+}
+
+impl TileOrder {
+    /// Divides a `width` by `height` image into tiles, in this order.
+    pub fn tiles(self, width: usize, height: usize) -> Vec<Tile> {
+        let columns = width.div_ceil(TILE_SIZE);
+        let rows = height.div_ceil(TILE_SIZE);
+
+        let cells: Vec<(usize, usize)> = match self {
+            TileOrder::Scanline => (0..rows)
+                .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                .collect(),
+            TileOrder::Hilbert => {
+                // Walk the smallest power of two square covering the grid and
+                // skip the cells outside it
+                let n = columns.max(rows).next_power_of_two();
+                (0..n * n)
+                    .map(|d| hilbert_cell(n, d))
+                    .filter(|&(column, row)| column < columns && row < rows)
+                    .collect()
+            }
+            TileOrder::Spiral => {
+                let center = (columns as f64 / 2., rows as f64 / 2.);
+                let mut cells: Vec<_> = (0..rows)
+                    .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                    .collect();
+
+                // Ring by ring, going around each ring by angle
+                let key = |&(column, row): &(usize, usize)| {
+                    let dx = column as f64 + 0.5 - center.0;
+                    let dy = row as f64 + 0.5 - center.1;
+                    (dx.abs().max(dy.abs()).floor(), dy.atan2(dx))
+                };
+                cells.sort_by(|a, b| {
+                    let (a, b) = (key(a), key(b));
+                    a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+                });
+                cells
+            }
+            TileOrder::Random => {
+                let mut cells: Vec<_> = (0..rows)
+                    .flat_map(|row| (0..columns).map(move |column| (column, row)))
+                    .collect();
+                cells.shuffle(&mut rand::thread_rng());
+                cells
+            }
+        };
+
+        cells
+            .into_iter()
+            .map(|(column, row)| Tile {
+                x0: column * TILE_SIZE,
+                y0: row * TILE_SIZE,
+                x1: ((column + 1) * TILE_SIZE).min(width),
+                y1: ((row + 1) * TILE_SIZE).min(height),
+            })
+            .collect()
+    }
+}
+
+/// The cell at distance `d` along a Hilbert curve filling an `n` by `n`
+/// grid, where `n` is a power of two.
+fn hilbert_cell(n: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+
+        // Rotate the quadrant so the curve joins up with its neighbours
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+
+    (x, y)
+}
+
+impl FromStr for TileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scanline" => Ok(TileOrder::Scanline),
+            "hilbert" => Ok(TileOrder::Hilbert),
+            "spiral" => Ok(TileOrder::Spiral),
+            "random" => Ok(TileOrder::Random),
+            _ => Err(format!("unknown tile order {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for TileOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TileOrder::Scanline => "scanline",
+            TileOrder::Hilbert => "hilbert",
+            TileOrder::Spiral => "spiral",
+            TileOrder::Random => "random",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Time spent in each stage of a render, such as building the scene or
+/// writing the output, to show where the time goes.
+#[derive(Clone, Debug, Default)]
+pub struct Timings {
+    /// Stages in the order they were first timed.
+    stages: Vec<(String, Duration)>,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `f`, adding the time it takes to `stage`.
+    pub fn time<T>(&mut self, stage: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(stage, start.elapsed());
+        result
+    }
+
+    /// Adds `duration` to `stage`, which stages timed more than once (e.g.
+    /// once per frame) accumulate.
+    pub fn add(&mut self, stage: &str, duration: Duration) {
+        match self.stages.iter_mut().find(|(name, _)| name == stage) {
+            Some((_, total)) => *total += duration,
+            None => self.stages.push((stage.to_string(), duration)),
+        }
+    }
+
+    /// Time spent in `stage`, zero if it was never timed.
+    pub fn get(&self, stage: &str) -> Duration {
+        self.stages
+            .iter()
+            .find(|(name, _)| name == stage)
+            .map_or(Duration::ZERO, |&(_, duration)| duration)
+    }
+
+    pub fn stages(&self) -> impl Iterator<Item = (&str, Duration)> {
+        self.stages
+            .iter()
+            .map(|(name, duration)| (name.as_str(), *duration))
+    }
+
+    pub fn total(&self) -> Duration {
+        self.stages.iter().map(|&(_, duration)| duration).sum()
+    }
+}
+
+/// A table of the stages with their times and shares of the total.
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+        let width = self
+            .stages()
+            .map(|(name, _)| name.len())
+            .chain(["total".len()])
+            .max()
+            .unwrap_or_default();
+
+        for (name, duration) in self.stages() {
+            let share = if total.is_zero() {
+                0.
+            } else {
+                100. * duration.as_secs_f64() / total.as_secs_f64()
+            };
+            writeln!(
+                f,
+                "  {:width$}  {:>9.3}s  {:>3.0}%",
+                name,
+                duration.as_secs_f64(),
+                share
+            )?;
+        }
+        write!(f, "  {:width$}  {:>9.3}s", "total", total.as_secs_f64())
+    }
+}
@@ -0,0 +1,185 @@
+use crate::bounds::AABB;
+use crate::vector::{Point3, Vec3};
+
+type Matrix = [[f64; 4]; 4];
+
+const IDENTITY: Matrix = [
+    [1., 0., 0., 0.],
+    [0., 1., 0., 0.],
+    [0., 0., 1., 0.],
+    [0., 0., 0., 1.],
+];
+
+/// An affine transformation, stored along with its inverse.
+///
+/// Transforms are built from simple operations whose inverses are known, so
+/// no general matrix inversion is ever needed.
+#[derive(Clone, Copy)]
+pub struct Transform {
+    m: Matrix,
+    inv: Matrix,
+}
+
+impl Transform {
+    pub const fn identity() -> Self {
+        Self {
+            m: IDENTITY,
+            inv: IDENTITY,
+        }
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        let mut m = IDENTITY;
+        let mut inv = IDENTITY;
+        for axis in 0..3 {
+            m[axis][3] = offset.axis(axis);
+            inv[axis][3] = -offset.axis(axis);
+        }
+
+        Self { m, inv }
+    }
+
+    pub fn scale(factors: Vec3) -> Self {
+        let mut m = IDENTITY;
+        let mut inv = IDENTITY;
+        for axis in 0..3 {
+            m[axis][axis] = factors.axis(axis);
+            inv[axis][axis] = factors.axis(axis).recip();
+        }
+
+        Self { m, inv }
+    }
+
+    /// Rotation by `degrees` counterclockwise around `axis`.
+    pub fn rotate(axis: Vec3, degrees: f64) -> Self {
+        let a = axis.unit_vector();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (x, y, z) = (a.x(), a.y(), a.z());
+
+        let m = [
+            [
+                cos + x * x * (1. - cos),
+                x * y * (1. - cos) - z * sin,
+                x * z * (1. - cos) + y * sin,
+                0.,
+            ],
+            [
+                y * x * (1. - cos) + z * sin,
+                cos + y * y * (1. - cos),
+                y * z * (1. - cos) - x * sin,
+                0.,
+            ],
+            [
+                z * x * (1. - cos) - y * sin,
+                z * y * (1. - cos) + x * sin,
+                cos + z * z * (1. - cos),
+                0.,
+            ],
+            [0., 0., 0., 1.],
+        ];
+
+        // Rotations are orthogonal, so the inverse is the transpose
+        Self {
+            m,
+            inv: transpose(&m),
+        }
+    }
+
+    pub fn rotate_x(degrees: f64) -> Self {
+        Self::rotate(Vec3::new(1., 0., 0.), degrees)
+    }
+
+    pub fn rotate_y(degrees: f64) -> Self {
+        Self::rotate(Vec3::new(0., 1., 0.), degrees)
+    }
+
+    pub fn rotate_z(degrees: f64) -> Self {
+        Self::rotate(Vec3::new(0., 0., 1.), degrees)
+    }
+
+    /// Applies `self` first and then `next`.
+    pub fn then(self, next: Self) -> Self {
+        Self {
+            m: multiply(&next.m, &self.m),
+            inv: multiply(&self.inv, &next.inv),
+        }
+    }
+
+    pub fn inverse(self) -> Self {
+        Self {
+            m: self.inv,
+            inv: self.m,
+        }
+    }
+
+    pub fn point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        Point3::new(
+            m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3],
+            m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3],
+            m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3],
+        )
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+
+    /// Transforms a surface normal, which needs the inverse transpose to stay
+    /// perpendicular to the surface under non-uniform scaling.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        let inv = &self.inv;
+        Vec3::new(
+            inv[0][0] * n.x() + inv[1][0] * n.y() + inv[2][0] * n.z(),
+            inv[0][1] * n.x() + inv[1][1] * n.y() + inv[2][1] * n.z(),
+            inv[0][2] * n.x() + inv[1][2] * n.y() + inv[2][2] * n.z(),
+        )
+    }
+
+    /// The axis-aligned box enclosing the transformed corners of `bounds`.
+    pub fn bounds(&self, bounds: AABB) -> AABB {
+        let corner = |i: usize| {
+            let pick = |axis: usize| {
+                if i & (1 << axis) == 0 {
+                    bounds.min.axis(axis)
+                } else {
+                    bounds.max.axis(axis)
+                }
+            };
+            self.point(Point3::new(pick(0), pick(1), pick(2)))
+        };
+
+        (1..8).fold(AABB::new(corner(0), corner(0)), |b, i| b.include(corner(i)))
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut m = [[0.; 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..4).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    m
+}
+
+fn transpose(a: &Matrix) -> Matrix {
+    let mut m = [[0.; 4]; 4];
+    for (i, row) in m.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = a[j][i];
+        }
+    }
+    m
+}
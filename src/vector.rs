@@ -6,7 +6,6 @@ use rand::Rng;
 pub struct Vec3(pub f64, pub f64, pub f64);
 
 pub type Point3 = Vec3;
-pub type Color = Vec3;
 
 impl Vec3 {
     pub const fn new(x: f64, y: f64, z: f64) -> Self {
@@ -17,11 +16,11 @@ impl Vec3 {
         Self(0., 0., 0.)
     }
 
-    pub fn random<T: Rng>(rng: &mut T) -> Self {
+    pub fn random<T: Rng + ?Sized>(rng: &mut T) -> Self {
         Self(rng.gen(), rng.gen(), rng.gen())
     }
 
-    pub fn random_range<T: Rng>(rng: &mut T, min: f64, max: f64) -> Self {
+    pub fn random_range<T: Rng + ?Sized>(rng: &mut T, min: f64, max: f64) -> Self {
         let x = rng.gen_range(min..max);
         let y = rng.gen_range(min..max);
         let z = rng.gen_range(min..max);
@@ -40,6 +39,23 @@ impl Vec3 {
         self.2
     }
 
+    /// The component along axis 0 (x), 1 (y) or 2 (z).
+    pub fn axis(self, axis: usize) -> f64 {
+        match axis {
+            0 => self.0,
+            1 => self.1,
+            2 => self.2,
+            _ => panic!("invalid axis {}", axis),
+        }
+    }
+
+    /// A copy of the vector with component `axis` replaced by `value`.
+    pub fn with_axis(self, axis: usize, value: f64) -> Self {
+        let mut components = [self.0, self.1, self.2];
+        components[axis] = value;
+        Self(components[0], components[1], components[2])
+    }
+
     pub fn length(self) -> f64 {
         self.length_squared().sqrt()
     }
@@ -162,29 +178,32 @@ impl std::ops::DivAssign<f64> for Vec3 {
     }
 }
 
-pub fn random_in_unit_sphere<T: Rng>(rng: &mut T) -> Vec3 {
-    loop {
-        let p = Vec3::random_range(rng, -1., 1.);
-        if p.length_squared() < 1.0 {
-            return p;
-        }
-    }
-}
-
-pub fn random_unit_vector<T: Rng>(rng: &mut T) -> Vec3 {
-    random_in_unit_sphere(rng).unit_vector()
+/// An orthonormal basis with `w` along a given direction, usually a surface
+/// normal, for turning directions sampled around +z into world space.
+#[derive(Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
 }
 
-pub fn random_in_unit_disk<T: Rng>(rng: &mut T) -> Vec3 {
-    loop {
-        let x = rng.gen_range(-1.0..1.0);
-        let y = rng.gen_range(-1.0..1.0);
-        let z = 0.0;
+impl Onb {
+    pub fn new(w: Vec3) -> Self {
+        // Duff et al., "Building an Orthonormal Basis, Revisited", which
+        // has no branch to flip at and no cross product to degenerate
+        let w = w.unit_vector();
+        let sign = 1f64.copysign(w.z());
+        let a = -1. / (sign + w.z());
+        let b = w.x() * w.y() * a;
+        let u = Vec3::new(1. + sign * w.x() * w.x() * a, sign * b, -sign * w.x());
+        let v = Vec3::new(b, sign + w.y() * w.y() * a, -w.y());
 
-        let p = Vec3::new(x, y, z);
+        Self { u, v, w }
+    }
 
-        if p.length_squared() < 1.0 {
-            return p;
-        }
+    /// The world space direction with components `a` along `u`, `v` and
+    /// `w`.
+    pub fn local(&self, a: Vec3) -> Vec3 {
+        self.u * a.x() + self.v * a.y() + self.w * a.z()
     }
 }
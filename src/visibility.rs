@@ -0,0 +1,188 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::accel::AccelNode;
+use crate::bounds::AABB;
+use crate::export::Tessellation;
+use crate::ray::{Hit, HitRecord, Ray};
+use crate::scene::SceneStats;
+
+/// The kinds of rays a renderer traces, by what they're looking for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RayKind {
+    /// From the camera, finding what each pixel sees.
+    Camera,
+    /// Towards a light, finding whether anything is in the way.
+    Shadow,
+    /// Bounced off a diffuse surface or scattered in a volume.
+    Diffuse,
+    /// Reflected off a mirror-like surface or refracted through glass.
+    Specular,
+}
+
+/// Which kinds of rays see an object. Others pass straight through it, as if
+/// it weren't there.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Visibility {
+    pub camera: bool,
+    pub shadow: bool,
+    pub diffuse: bool,
+    pub specular: bool,
+}
+
+impl Visibility {
+    /// Seen by every kind of ray, like any ordinary object.
+    pub const ALL: Visibility = Visibility {
+        camera: true,
+        shadow: true,
+        diffuse: true,
+        specular: true,
+    };
+
+    pub fn sees(self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera,
+            RayKind::Shadow => self.shadow,
+            RayKind::Diffuse => self.diffuse,
+            RayKind::Specular => self.specular,
+        }
+    }
+
+    /// Seen only by the rays both `self` and `other` are seen by.
+    pub fn and(self, other: Visibility) -> Visibility {
+        Visibility {
+            camera: self.camera && other.camera,
+            shadow: self.shadow && other.shadow,
+            diffuse: self.diffuse && other.diffuse,
+            specular: self.specular && other.specular,
+        }
+    }
+}
+
+impl Default for Visibility {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+impl FromStr for Visibility {
+    type Err = String;
+
+    /// Parses the kinds of rays that see an object, separated by commas,
+    /// e.g. `camera` for a backdrop or `shadow,diffuse,specular` for a light
+    /// blocker; `none` hides the object from all of them.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut visibility = Visibility {
+            camera: false,
+            shadow: false,
+            diffuse: false,
+            specular: false,
+        };
+        if s == "none" {
+            return Ok(visibility);
+        }
+
+        for kind in s.split(',') {
+            match kind.trim() {
+                "camera" => visibility.camera = true,
+                "shadow" => visibility.shadow = true,
+                "diffuse" => visibility.diffuse = true,
+                "specular" => visibility.specular = true,
+                _ => return Err(format!("unknown ray kind {:?}", kind)),
+            }
+        }
+        Ok(visibility)
+    }
+}
+
+impl fmt::Display for Visibility {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds: Vec<&str> = [
+            (self.camera, "camera"),
+            (self.shadow, "shadow"),
+            (self.diffuse, "diffuse"),
+            (self.specular, "specular"),
+        ]
+        .iter()
+        .filter(|(seen, _)| *seen)
+        .map(|&(_, name)| name)
+        .collect();
+
+        if kinds.is_empty() {
+            write!(f, "none")
+        } else {
+            write!(f, "{}", kinds.join(","))
+        }
+    }
+}
+
+/// An object seen only by some kinds of rays, such as a light blocker that
+/// casts shadows but doesn't show up in the image, or a backdrop that only
+/// the camera sees, which lights nothing else and shows in no reflections.
+pub struct Restricted {
+    object: Box<dyn Hit>,
+    visibility: Visibility,
+}
+
+impl Restricted {
+    pub fn new(object: Box<dyn Hit>, visibility: Visibility) -> Self {
+        Self { object, visibility }
+    }
+
+    pub fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+}
+
+impl Hit for Restricted {
+    /// Hits with the object, marked with the rays that see it. The renderer
+    /// looks past hits the ray it's tracing can't see.
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let hit = self.object.hit(r, t_min, t_max)?;
+        Some(HitRecord {
+            visibility: hit.visibility.and(self.visibility),
+            ..hit
+        })
+    }
+
+    fn hits<'a>(&'a self, r: Ray, t_min: f64, t_max: f64, hits: &mut Vec<HitRecord<'a>>) {
+        let first = hits.len();
+        self.object.hits(r, t_min, t_max, hits);
+        for hit in &mut hits[first..] {
+            hit.visibility = hit.visibility.and(self.visibility);
+        }
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.visibility.shadow && self.object.occluded(r, t_min, t_max)
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        if self.visibility.shadow {
+            self.object.transmittance(r, t_min, t_max)
+        } else {
+            1.
+        }
+    }
+
+    fn bounds(&self, time: (f64, f64)) -> Option<AABB> {
+        self.object.bounds(time)
+    }
+
+    fn refit(&mut self, time: (f64, f64)) -> Option<AABB> {
+        self.object.refit(time)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add("restricted visibility", std::mem::size_of::<Self>());
+        self.object.count(stats);
+    }
+
+    fn accel_nodes(&self, depth: usize, time: (f64, f64), nodes: &mut Vec<AccelNode>) {
+        self.object.accel_nodes(depth, time, nodes);
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        self.object.tessellate(time, mesh);
+    }
+}
@@ -0,0 +1,573 @@
+use rand::Rng;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::bounds::AABB;
+use crate::color::Color;
+use crate::ray::{Hit, HitRecord, Material, Ray, SurfaceDerivatives};
+use crate::rng;
+use crate::scene::SceneStats;
+use crate::vector::{Point3, Vec3};
+
+/// How each voxel is stored in a raw grid file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoxelType {
+    /// Bytes, read as 0 to 1.
+    U8,
+    /// Unsigned 16-bit integers, read as 0 to 1.
+    U16,
+    F32,
+    F64,
+}
+
+impl VoxelType {
+    fn size(self) -> usize {
+        match self {
+            VoxelType::U8 => 1,
+            VoxelType::U16 => 2,
+            VoxelType::F32 => 4,
+            VoxelType::F64 => 8,
+        }
+    }
+
+    fn decode(self, bytes: &[u8], big_endian: bool) -> f32 {
+        macro_rules! read {
+            ($t:ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                if big_endian {
+                    <$t>::from_be_bytes(bytes)
+                } else {
+                    <$t>::from_le_bytes(bytes)
+                }
+            }};
+        }
+
+        match self {
+            VoxelType::U8 => bytes[0] as f32 / u8::MAX as f32,
+            VoxelType::U16 => read!(u16) as f32 / u16::MAX as f32,
+            VoxelType::F32 => read!(f32),
+            VoxelType::F64 => read!(f64) as f32,
+        }
+    }
+}
+
+impl FromStr for VoxelType {
+    type Err = String;
+
+    /// Accepts the short names and the NRRD type names.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "u8" | "uchar" | "unsigned char" | "uint8" | "uint8_t" => Ok(VoxelType::U8),
+            "u16" | "ushort" | "unsigned short" | "unsigned short int" | "uint16" | "uint16_t" => {
+                Ok(VoxelType::U16)
+            }
+            "f32" | "float" => Ok(VoxelType::F32),
+            "f64" | "double" => Ok(VoxelType::F64),
+            _ => Err(format!("unknown voxel type {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for VoxelType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            VoxelType::U8 => "u8",
+            VoxelType::U16 => "u16",
+            VoxelType::F32 => "f32",
+            VoxelType::F64 => "f64",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Densities on a regular 3D grid, such as a frame of a smoke or cloud
+/// simulation. Voxels are stored with x varying fastest, then y, then z.
+pub struct DensityGrid {
+    size: [usize; 3],
+    voxels: Vec<f32>,
+    max: f64,
+}
+
+impl DensityGrid {
+    pub fn new(size: [usize; 3], voxels: Vec<f32>) -> Self {
+        assert_eq!(
+            voxels.len(),
+            size[0] * size[1] * size[2],
+            "voxel count mismatch"
+        );
+
+        let max = voxels.iter().copied().fold(0., f32::max) as f64;
+        Self { size, voxels, max }
+    }
+
+    /// Loads a headerless grid of `size` voxels of type `voxel_type`, in
+    /// little-endian byte order.
+    pub fn load_raw<P: AsRef<Path>>(
+        path: P,
+        size: [usize; 3],
+        voxel_type: VoxelType,
+    ) -> Result<Self, GridError> {
+        let data = fs::read(path)?;
+        decode(&data, size, voxel_type, false)
+    }
+
+    /// Loads a 3D NRRD file with raw encoding, as written by many simulation
+    /// and medical imaging tools. The data may follow the header or be in a
+    /// separate file named by its `data file` field.
+    pub fn load_nrrd<P: AsRef<Path>>(path: P) -> Result<Self, GridError> {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+
+        let magic = data.get(..4).ok_or(GridError::Truncated)?;
+        if magic != b"NRRD" {
+            return Err(GridError::UnsupportedFormat);
+        }
+
+        let mut size = None;
+        let mut voxel_type = None;
+        let mut big_endian = false;
+        let mut data_file = None;
+
+        // The header is text lines up to the first blank one
+        let mut pos = 0;
+        loop {
+            let end = data[pos..]
+                .iter()
+                .position(|&c| c == b'\n')
+                .ok_or(GridError::Truncated)?;
+            let line = std::str::from_utf8(&data[pos..pos + end])
+                .map_err(|_| GridError::InvalidHeader("header is not text".to_string()))?
+                .trim_end_matches('\r');
+            let first = pos == 0;
+            pos += end + 1;
+
+            if line.is_empty() {
+                break;
+            }
+            if first || line.starts_with('#') {
+                continue;
+            }
+
+            // Key/value pairs, which use ":=", are only metadata
+            let Some((field, value)) = line.split_once(": ") else {
+                continue;
+            };
+            let invalid = || GridError::InvalidHeader(format!("invalid {}: {:?}", field, value));
+
+            match field {
+                "dimension" if value.trim() != "3" => {
+                    return Err(GridError::InvalidHeader(
+                        "only 3D grids are supported".to_string(),
+                    ))
+                }
+                "type" => voxel_type = Some(value.trim().parse().map_err(|_| invalid())?),
+                "sizes" => {
+                    let sizes = value
+                        .split_whitespace()
+                        .map(|s| s.parse::<usize>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| invalid())?;
+                    match sizes[..] {
+                        [x, y, z] if x > 0 && y > 0 && z > 0 => size = Some([x, y, z]),
+                        _ => return Err(invalid()),
+                    }
+                }
+                "encoding" if value.trim() != "raw" => {
+                    return Err(GridError::InvalidHeader(format!(
+                        "unsupported encoding {:?}",
+                        value.trim()
+                    )))
+                }
+                "endian" => big_endian = value.trim() == "big",
+                "data file" | "datafile" => data_file = Some(value.trim().to_string()),
+                _ => {}
+            }
+        }
+
+        let missing = |field: &str| GridError::InvalidHeader(format!("no {} field", field));
+        let size = size.ok_or_else(|| missing("sizes"))?;
+        let voxel_type = voxel_type.ok_or_else(|| missing("type"))?;
+
+        match data_file {
+            Some(name) => {
+                let detached = path.parent().unwrap_or(Path::new("")).join(name);
+                decode(&fs::read(detached)?, size, voxel_type, big_endian)
+            }
+            None => decode(&data[pos..], size, voxel_type, big_endian),
+        }
+    }
+
+    pub fn size(&self) -> [usize; 3] {
+        self.size
+    }
+
+    /// The highest density anywhere in the grid.
+    pub fn max(&self) -> f64 {
+        self.max
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.voxels[(z * self.size[1] + y) * self.size[0] + x] as f64
+    }
+
+    /// The density at `p`, with the grid filling the unit cube, blended
+    /// between the nearest voxel centers.
+    pub fn density(&self, p: Point3) -> f64 {
+        // Split each coordinate into the lower voxel and how far past its
+        // center `p` lies
+        let cell = |axis: usize| {
+            let n = self.size[axis];
+            let x = (p.axis(axis) * n as f64 - 0.5).clamp(0., (n - 1) as f64);
+            let i = (x as usize).min(n.saturating_sub(2));
+            (i, (i + 1).min(n - 1), x - i as f64)
+        };
+        let (x0, x1, fx) = cell(0);
+        let (y0, y1, fy) = cell(1);
+        let (z0, z1, fz) = cell(2);
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let row = |y, z| lerp(self.voxel(x0, y, z), self.voxel(x1, y, z), fx);
+        let slice = |z| lerp(row(y0, z), row(y1, z), fy);
+        lerp(slice(z0), slice(z1), fz)
+    }
+}
+
+fn decode(
+    data: &[u8],
+    size: [usize; 3],
+    voxel_type: VoxelType,
+    big_endian: bool,
+) -> Result<DensityGrid, GridError> {
+    let count = size[0] * size[1] * size[2];
+    let bytes = data
+        .get(..count * voxel_type.size())
+        .ok_or(GridError::Truncated)?;
+    let voxels = bytes
+        .chunks_exact(voxel_type.size())
+        .map(|b| voxel_type.decode(b, big_endian))
+        .collect();
+
+    Ok(DensityGrid::new(size, voxels))
+}
+
+#[derive(Debug)]
+pub enum GridError {
+    Io(io::Error),
+    UnsupportedFormat,
+    InvalidHeader(String),
+    Truncated,
+}
+
+impl fmt::Display for GridError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GridError::Io(err) => write!(f, "{}", err),
+            GridError::UnsupportedFormat => write!(f, "not an NRRD file"),
+            GridError::InvalidHeader(message) => write!(f, "invalid NRRD header: {}", message),
+            GridError::Truncated => write!(f, "file ends before the voxel data does"),
+        }
+    }
+}
+
+impl std::error::Error for GridError {}
+
+impl From<io::Error> for GridError {
+    fn from(err: io::Error) -> Self {
+        GridError::Io(err)
+    }
+}
+
+/// How shadow rays through a `Volume` work out how much light it lets
+/// through. Both are right on average; they differ in noise and speed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Tracking {
+    /// Blocks the ray entirely or not at all, at random, which is quickest
+    /// and suits dense media, where most rays are blocked early anyway.
+    #[default]
+    Delta,
+    /// Dims the ray by the fraction the density it passes suggests, which
+    /// looks up the density more often but gives smoother soft shadows
+    /// through thin media.
+    Ratio,
+}
+
+impl FromStr for Tracking {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delta" => Ok(Tracking::Delta),
+            "ratio" => Ok(Tracking::Ratio),
+            _ => Err(format!("unknown tracking {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Tracking {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Tracking::Delta => "delta",
+            Tracking::Ratio => "ratio",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How points in fog are picked to light by sampling the lights directly.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FogSampling {
+    /// Anywhere along each ray, in proportion to how much light from the
+    /// light's center reaches there. Beams and spotlight cones through thin
+    /// fog come out with little noise, but in dense fog most points picked
+    /// are too deep in it for their light to get out.
+    #[default]
+    Equiangular,
+    /// Only where rays scatter, picked by how far light gets through the
+    /// fog, which suits dense fog, where it barely gets any way at all.
+    Distance,
+}
+
+impl FromStr for FogSampling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "equiangular" => Ok(FogSampling::Equiangular),
+            "distance" => Ok(FogSampling::Distance),
+            _ => Err(format!("unknown fog sampling {:?}", s)),
+        }
+    }
+}
+
+impl fmt::Display for FogSampling {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FogSampling::Equiangular => "equiangular",
+            FogSampling::Distance => "distance",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Smoke, cloud or other participating medium whose density varies through
+/// a box, given by a density grid stretched to fill it.
+///
+/// Rays pass through or scatter somewhere inside at random, with a chance
+/// that grows with the density they pass, so the material should be
+/// `Isotropic`. Shadow rays are dimmed as `tracking` says.
+pub struct Volume {
+    grid: Arc<DensityGrid>,
+    bounds: AABB,
+    /// Scattering per unit length at a grid density of 1.
+    density: f64,
+    material: Material,
+    tracking: Tracking,
+}
+
+impl Volume {
+    pub fn new(grid: Arc<DensityGrid>, bounds: AABB, density: f64, material: Material) -> Self {
+        Self {
+            grid,
+            bounds,
+            density,
+            material,
+            tracking: Tracking::default(),
+        }
+    }
+
+    /// The volume with shadow rays through it dimmed using `tracking`.
+    pub fn with_tracking(self, tracking: Tracking) -> Self {
+        Self { tracking, ..self }
+    }
+
+    pub fn grid(&self) -> &DensityGrid {
+        &self.grid
+    }
+
+    /// Where a ray scatters between `t_min` and `t_max`, if it does.
+    ///
+    /// Uses delta tracking: steps are taken as though the whole box were as
+    /// dense as its densest voxel, and each stopping point is accepted in
+    /// proportion to how dense the grid really is there.
+    fn scatter_distance(&self, r: Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let (t0, t1) = self.bounds.hit(r, t_min, t_max)?;
+        let majorant = self.grid.max() * self.density;
+        if majorant <= 0. {
+            return None;
+        }
+
+        let rng = &mut ray_rng(r);
+        let rate = majorant * r.direction.length();
+
+        let mut t = t0;
+        loop {
+            t -= (1. - rng.gen::<f64>()).ln() / rate;
+            if t >= t1 {
+                return None;
+            }
+            if rng.gen::<f64>() * majorant < self.density_at(r.at(t)) {
+                return Some(t);
+            }
+        }
+    }
+
+    /// The fraction of light left after crossing the volume between `t_min`
+    /// and `t_max`, by ratio tracking: the same steps as delta tracking, with
+    /// each dimming the light by the chance it would have stopped there
+    /// rather than ending it.
+    fn ratio_tracking(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        let Some((t0, t1)) = self.bounds.hit(r, t_min, t_max) else {
+            return 1.;
+        };
+        let majorant = self.grid.max() * self.density;
+        if majorant <= 0. {
+            return 1.;
+        }
+
+        let rng = &mut ray_rng(r);
+        let rate = majorant * r.direction.length();
+
+        let mut transmittance = 1.;
+        let mut t = t0;
+        loop {
+            t -= (1. - rng.gen::<f64>()).ln() / rate;
+            if t >= t1 {
+                return transmittance;
+            }
+            transmittance *= 1. - self.density_at(r.at(t)) / majorant;
+        }
+    }
+
+    /// Scattering per unit length at `p`.
+    fn density_at(&self, p: Point3) -> f64 {
+        let size = self.bounds.max - self.bounds.min;
+        let local = p - self.bounds.min;
+        let local = Vec3::new(
+            local.x() / size.x(),
+            local.y() / size.y(),
+            local.z() / size.z(),
+        );
+        self.grid.density(local) * self.density
+    }
+}
+
+/// Random numbers for tracking along `r`, seeded by the ray rather than per
+/// thread, so renders are repeatable.
+fn ray_rng(r: Ray) -> impl Rng {
+    rng::seeded(&[
+        r.origin.x().to_bits(),
+        r.origin.y().to_bits(),
+        r.origin.z().to_bits(),
+        r.direction.x().to_bits(),
+        r.direction.y().to_bits(),
+        r.direction.z().to_bits(),
+        r.time.to_bits(),
+    ])
+}
+
+impl Hit for Volume {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = self.scatter_distance(r, t_min, t_max)?;
+
+        // A medium has no surface, so the normal just faces the ray
+        let normal = -r.direction.unit_vector();
+        let derivatives = SurfaceDerivatives::flat(Vec3::zero(), Vec3::zero());
+
+        Some(HitRecord::new(
+            t,
+            r,
+            normal,
+            (0., 0.),
+            derivatives,
+            &self.material,
+        ))
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.scatter_distance(r, t_min, t_max).is_some()
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        match self.tracking {
+            Tracking::Delta if self.occluded(r, t_min, t_max) => 0.,
+            Tracking::Delta => 1.,
+            Tracking::Ratio => self.ratio_tracking(r, t_min, t_max),
+        }
+    }
+
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
+        Some(self.bounds)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add("volume", std::mem::size_of::<Self>());
+    }
+}
+
+/// A medium of the same density throughout, filling the inside of a
+/// `Dialectric` to make murky glass, juice or wax.
+///
+/// Light crossing it is dimmed by Beer–Lambert absorption, and scatters in
+/// a random direction partway through with a chance that grows with the
+/// distance crossed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Medium {
+    /// Fraction of each channel absorbed per unit distance; light keeps
+    /// `exp(-absorption * distance)` of itself.
+    pub absorption: Color,
+    /// Chance of scattering per unit distance.
+    pub scattering: f64,
+}
+
+impl Medium {
+    pub fn new(absorption: Color, scattering: f64) -> Self {
+        Self {
+            absorption,
+            scattering,
+        }
+    }
+
+    /// The fraction of light left after crossing `distance` of the medium
+    /// without scattering.
+    pub fn transmittance(&self, distance: f64) -> Color {
+        // Clear channels let light through any distance, even an endless one
+        let channel = |absorption: f64| {
+            if absorption > 0. {
+                (-absorption * distance).exp()
+            } else {
+                1.
+            }
+        };
+        Color::new(
+            channel(self.absorption.r()),
+            channel(self.absorption.g()),
+            channel(self.absorption.b()),
+        )
+    }
+
+    /// The fraction of light left after crossing `distance` of the medium,
+    /// neither absorbed nor scattered off somewhere else.
+    pub fn attenuation(&self, distance: f64) -> Color {
+        let scattered = if self.scattering > 0. {
+            (-self.scattering * distance).exp()
+        } else {
+            1.
+        };
+        self.transmittance(distance) * scattered
+    }
+
+    /// How far along a crossing of length `distance` light scatters, if it
+    /// does.
+    pub fn scatter_distance<T: Rng>(&self, rng: &mut T, distance: f64) -> Option<f64> {
+        if self.scattering <= 0. {
+            return None;
+        }
+
+        let d = -(1. - rng.gen::<f64>()).ln() / self.scattering;
+        (d < distance).then_some(d)
+    }
+}
@@ -1,19 +1,24 @@
+use std::f64::consts::PI;
+
+use crate::accel::AccelNode;
 use crate::bounds::AABB;
-use crate::ray::{Hit, HitRecord, Material, Ray};
-use crate::vector::{Point3, Vec3};
+use crate::export::Tessellation;
+use crate::ray::{Hit, HitRecord, Material, Ray, SurfaceDerivatives};
+use crate::scene::SceneStats;
+use crate::vector::{Onb, Point3, Vec3};
 
 pub struct World {
-    objects: Vec<Box<dyn Hit + Sync>>,
+    objects: Vec<Box<dyn Hit>>,
 }
 
 impl World {
-    pub fn new(objects: Vec<Box<dyn Hit + Sync>>) -> Self {
+    pub fn new(objects: Vec<Box<dyn Hit>>) -> Self {
         Self { objects }
     }
 }
 
 impl Hit for World {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut closest_hit = None;
         let mut t_max = t_max;
 
@@ -27,14 +32,66 @@ impl Hit for World {
         closest_hit
     }
 
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.objects
+            .iter()
+            .any(|object| object.occluded(r, t_min, t_max))
+    }
+
+    fn transmittance(&self, r: Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut transmittance = 1.;
+        for object in &self.objects {
+            transmittance *= object.transmittance(r, t_min, t_max);
+            if transmittance <= 0. {
+                break;
+            }
+        }
+        transmittance
+    }
+
+    fn hits<'a>(&'a self, r: Ray, t_min: f64, t_max: f64, hits: &mut Vec<HitRecord<'a>>) {
+        for object in &self.objects {
+            object.hits(r, t_min, t_max, hits);
+        }
+    }
+
     fn bounds(&self, time: (f64, f64)) -> Option<AABB> {
+        // The world is only bounded if every object in it is
         self.objects
             .iter()
-            .filter_map(|obj| obj.bounds(time))
-            .reduce(|sum, item| sum + item)
+            .map(|obj| obj.bounds(time))
+            .reduce(|sum, item| Some(sum? + item?))?
+    }
+
+    fn refit(&mut self, time: (f64, f64)) -> Option<AABB> {
+        self.objects
+            .iter_mut()
+            .map(|obj| obj.refit(time))
+            .reduce(|sum, item| Some(sum? + item?))?
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.memory += std::mem::size_of::<Self>()
+            + self.objects.capacity() * std::mem::size_of::<Box<dyn Hit>>();
+        for object in &self.objects {
+            object.count(stats);
+        }
+    }
+
+    fn accel_nodes(&self, depth: usize, time: (f64, f64), nodes: &mut Vec<AccelNode>) {
+        for object in &self.objects {
+            object.accel_nodes(depth, time, nodes);
+        }
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        for object in &self.objects {
+            object.tessellate(time, mesh);
+        }
     }
 }
 
+#[derive(Clone)]
 pub struct Sphere {
     pub center: Point3,
     pub radius: f64,
@@ -42,50 +99,63 @@ pub struct Sphere {
 }
 
 impl Sphere {
+    /// A sphere, with any light `material` gives off in total spread over
+    /// its surface.
     pub fn new(center: Point3, radius: f64, material: Material) -> Self {
+        let area = 4. * PI * radius * radius;
         Self {
             center,
             radius,
-            material,
+            material: material.emitting_over(area),
         }
     }
+
+    pub fn area(&self) -> f64 {
+        4. * PI * self.radius * self.radius
+    }
 }
 
 impl Hit for Sphere {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = r.origin - self.center;
-        let a = r.direction.length_squared();
-        let half_b = oc.dot_product(r.direction);
-        let c = oc.length_squared() - self.radius * self.radius;
-
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0. {
-            return None;
-        }
-
-        let sqrtd = discriminant.sqrt();
-        let roots = [
-            (-half_b - sqrtd) / a, // 1st root
-            (-half_b + sqrtd) / a, // 2nd root
-        ];
-
-        // Find the nearest root within the specified range (t_min, t_max)
-        let t = match roots.into_iter().find(|&t| t_min <= t && t <= t_max) {
-            Some(t) => t,
-            None => return None,
-        };
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = sphere_root(self.center, self.radius, r, t_min, t_max)?;
 
         let p = r.at(t);
         let outward_normal = (p - self.center) / self.radius;
+        let (uv, derivatives) = sphere_uv(outward_normal, self.radius);
 
-        Some(HitRecord::new(t, r, outward_normal, &self.material))
+        Some(HitRecord::new(
+            t,
+            r,
+            outward_normal,
+            uv,
+            derivatives,
+            &self.material,
+        ))
     }
 
-    fn bounds(&self, time: (f64, f64)) -> Option<crate::bounds::AABB> {
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        sphere_root(self.center, self.radius, r, t_min, t_max).is_some()
+    }
+
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
         let octant = Vec3::new(self.radius, self.radius, self.radius);
 
         Some(AABB::new(self.center - octant, self.center + octant))
     }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add_surface("sphere", std::mem::size_of::<Self>(), &self.material);
+    }
+
+    fn tessellate(&self, _time: f64, mesh: &mut Tessellation) {
+        mesh.add_sphere(
+            self.center,
+            self.radius,
+            (0., PI),
+            (0., 2. * PI),
+            &self.material,
+        );
+    }
 }
 
 pub struct MovingSphere {
@@ -119,33 +189,21 @@ impl MovingSphere {
 }
 
 impl Hit for MovingSphere {
-    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = r.origin - self.center(r.time);
-        let a = r.direction.length_squared();
-        let half_b = oc.dot_product(r.direction);
-        let c = oc.length_squared() - self.radius * self.radius;
-
-        let discriminant = half_b * half_b - a * c;
-        if discriminant < 0. {
-            return None;
-        }
-
-        let sqrtd = discriminant.sqrt();
-        let roots = [
-            (-half_b - sqrtd) / a, // 1st root
-            (-half_b + sqrtd) / a, // 2nd root
-        ];
-
-        // Find the nearest root within the specified range (t_min, t_max)
-        let t = match roots.into_iter().find(|&t| t_min <= t && t <= t_max) {
-            Some(t) => t,
-            None => return None,
-        };
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = sphere_root(self.center(r.time), self.radius, r, t_min, t_max)?;
 
         let p = r.at(t);
         let outward_normal = (p - self.center(r.time)) / self.radius;
+        let (uv, derivatives) = sphere_uv(outward_normal, self.radius);
+
+        Some(HitRecord {
+            velocity: (self.center.1 - self.center.0) / (self.time.1 - self.time.0),
+            ..HitRecord::new(t, r, outward_normal, uv, derivatives, &self.material)
+        })
+    }
 
-        Some(HitRecord::new(t, r, outward_normal, &self.material))
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        sphere_root(self.center(r.time), self.radius, r, t_min, t_max).is_some()
     }
 
     fn bounds(&self, time: (f64, f64)) -> Option<AABB> {
@@ -158,4 +216,450 @@ impl Hit for MovingSphere {
 
         Some(a + b)
     }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add_surface("moving sphere", std::mem::size_of::<Self>(), &self.material);
+    }
+
+    fn tessellate(&self, time: f64, mesh: &mut Tessellation) {
+        mesh.add_sphere(
+            self.center(time),
+            self.radius,
+            (0., PI),
+            (0., 2. * PI),
+            &self.material,
+        );
+    }
+}
+
+/// The part of a sphere between two polar angles and two azimuths, for domes,
+/// bowls, hemispheres and wedges. Rays can see both its outside and, through
+/// the opening, its inside.
+///
+/// Angles are in degrees, using the same directions as sphere texture
+/// coordinates: `theta` goes from 0 at the bottom pole to 180 at the top, and
+/// `phi` from 0 to 360 around the y axis, starting at -x. Texture coordinates
+/// span the section, so a whole texture covers it.
+#[derive(Clone)]
+pub struct SphereSection {
+    pub center: Point3,
+    pub radius: f64,
+    /// Range of polar angles in radians.
+    theta: (f64, f64),
+    /// Range of azimuths in radians.
+    phi: (f64, f64),
+    pub material: Material,
+}
+
+impl SphereSection {
+    pub fn new(
+        center: Point3,
+        radius: f64,
+        theta: (f64, f64),
+        phi: (f64, f64),
+        material: Material,
+    ) -> Self {
+        let clamp = |(a, b): (f64, f64), max: f64| {
+            let (a, b) = (a.clamp(0., max), b.clamp(0., max));
+            (a.min(b).to_radians(), a.max(b).to_radians())
+        };
+
+        Self {
+            center,
+            radius,
+            theta: clamp(theta, 180.),
+            phi: clamp(phi, 360.),
+            material,
+        }
+    }
+
+    /// The top half of a sphere, open at the bottom.
+    pub fn dome(center: Point3, radius: f64, material: Material) -> Self {
+        Self::new(center, radius, (90., 180.), (0., 360.), material)
+    }
+
+    /// The bottom half of a sphere, open at the top.
+    pub fn bowl(center: Point3, radius: f64, material: Material) -> Self {
+        Self::new(center, radius, (0., 90.), (0., 360.), material)
+    }
+
+    /// The polar angle and azimuth of the point with unit normal `n`, if it
+    /// is part of the section.
+    fn angles(&self, n: Vec3) -> Option<(f64, f64)> {
+        let theta = (-n.y()).clamp(-1., 1.).acos();
+        let phi = (-n.z()).atan2(n.x()) + PI;
+
+        let inside = |x: f64, (min, max): (f64, f64)| min <= x && x <= max;
+        (inside(theta, self.theta) && inside(phi, self.phi)).then_some((theta, phi))
+    }
+
+    /// The nearest distance along `r` within `t_min` and `t_max` where it
+    /// meets the section, along with the normal there.
+    fn intersect(&self, r: Ray, t_min: f64, t_max: f64) -> Option<(f64, Vec3, (f64, f64))> {
+        sphere_roots(self.center, self.radius, r)?
+            .into_iter()
+            .filter(|&t| t_min <= t && t <= t_max)
+            .find_map(|t| {
+                let n = (r.at(t) - self.center) / self.radius;
+                Some((t, n, self.angles(n)?))
+            })
+    }
+}
+
+impl Hit for SphereSection {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, outward_normal, (theta, phi)) = self.intersect(r, t_min, t_max)?;
+
+        // Stretch the whole sphere's coordinates over the section
+        let (_, derivatives) = sphere_uv(outward_normal, self.radius);
+        let u_range = (self.phi.1 - self.phi.0) / (2. * PI);
+        let v_range = (self.theta.1 - self.theta.0) / PI;
+        let uv = (
+            (phi - self.phi.0) / (self.phi.1 - self.phi.0).max(f64::EPSILON),
+            (theta - self.theta.0) / (self.theta.1 - self.theta.0).max(f64::EPSILON),
+        );
+        let derivatives = SurfaceDerivatives {
+            dpdu: derivatives.dpdu * u_range,
+            dpdv: derivatives.dpdv * v_range,
+            dndu: derivatives.dndu * u_range,
+            dndv: derivatives.dndv * v_range,
+        };
+
+        Some(HitRecord::new(
+            t,
+            r,
+            outward_normal,
+            uv,
+            derivatives,
+            &self.material,
+        ))
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    /// Encloses just the section rather than the whole sphere.
+    ///
+    /// Each coordinate of a point on the sphere is a product of a function
+    /// of theta and one of phi, so its extremes are found among the ends of
+    /// each range and the angles in between where sine or cosine peak.
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
+        let within = |(min, max): (f64, f64), extra: &[f64]| {
+            let mut angles = vec![min, max];
+            angles.extend(extra.iter().filter(|&&a| min < a && a < max));
+            angles
+        };
+        let thetas = within(self.theta, &[PI / 2.]);
+        let phis = within(self.phi, &[PI / 2., PI, 3. * PI / 2.]);
+
+        let point = |theta: f64, phi: f64| {
+            let (sin_theta, cos_theta) = theta.sin_cos();
+            let (sin_phi, cos_phi) = phi.sin_cos();
+            let n = Vec3::new(-sin_theta * cos_phi, -cos_theta, sin_theta * sin_phi);
+            self.center + n * self.radius
+        };
+
+        thetas
+            .iter()
+            .flat_map(|&theta| phis.iter().map(move |&phi| (theta, phi)))
+            .map(|(theta, phi)| {
+                let p = point(theta, phi);
+                AABB::new(p, p)
+            })
+            .reduce(|a, b| a + b)
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add_surface(
+            "sphere section",
+            std::mem::size_of::<Self>(),
+            &self.material,
+        );
+    }
+
+    fn tessellate(&self, _time: f64, mesh: &mut Tessellation) {
+        mesh.add_sphere(
+            self.center,
+            self.radius,
+            self.theta,
+            self.phi,
+            &self.material,
+        );
+    }
+}
+
+/// Rectangles thinner than this along an axis get their bounds padded, since
+/// the slab test never hits a box with no volume.
+const BOUNDS_PADDING: f64 = 1e-4;
+
+/// A flat parallelogram with a corner at `corner` and sides `u` and `v`,
+/// e.g. a wall or, with a `DiffuseLight` material, an area light. It faces
+/// along `u × v`, which is the side a light shines from.
+///
+/// Texture coordinates run from 0 to 1 along each side.
+#[derive(Clone)]
+pub struct Rect {
+    pub corner: Point3,
+    pub u: Vec3,
+    pub v: Vec3,
+    pub material: Material,
+}
+
+impl Rect {
+    /// A rectangle, with any light `material` gives off in total spread over
+    /// its front.
+    pub fn new(corner: Point3, u: Vec3, v: Vec3, material: Material) -> Self {
+        let area = u.cross_product(v).length();
+        Self {
+            corner,
+            u,
+            v,
+            material: material.emitting_over(area),
+        }
+    }
+
+    /// The unit normal on the side it faces.
+    pub fn normal(&self) -> Vec3 {
+        self.u.cross_product(self.v).unit_vector()
+    }
+
+    pub fn area(&self) -> f64 {
+        self.u.cross_product(self.v).length()
+    }
+
+    /// Where `r` crosses the rectangle between `t_min` and `t_max`, as the
+    /// distance along it and the fractions of the way along each side.
+    pub(crate) fn intersect(&self, r: Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let n = self.u.cross_product(self.v);
+        let denominator = n.dot_product(r.direction);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+
+        let t = n.dot_product(self.corner - r.origin) / denominator;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        // Project the crossing onto the sides
+        let w = n / n.length_squared();
+        let q = r.at(t) - self.corner;
+        let a = w.dot_product(q.cross_product(self.v));
+        let b = w.dot_product(self.u.cross_product(q));
+        let on_side = |x: f64| (0. ..=1.).contains(&x);
+        (on_side(a) && on_side(b)).then_some((t, a, b))
+    }
+}
+
+impl Hit for Rect {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, a, b) = self.intersect(r, t_min, t_max)?;
+
+        Some(HitRecord::new(
+            t,
+            r,
+            self.normal(),
+            (a, b),
+            SurfaceDerivatives::flat(self.u, self.v),
+            &self.material,
+        ))
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
+        let bounds = AABB::new(self.corner, self.corner)
+            .include(self.corner + self.u)
+            .include(self.corner + self.v)
+            .include(self.corner + self.u + self.v);
+        let padding = Vec3::new(BOUNDS_PADDING, BOUNDS_PADDING, BOUNDS_PADDING);
+
+        Some(AABB::new(bounds.min - padding, bounds.max + padding))
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add_surface("rectangle", std::mem::size_of::<Self>(), &self.material);
+    }
+
+    fn tessellate(&self, _time: f64, mesh: &mut Tessellation) {
+        let [a, b, c, d] = [
+            self.corner,
+            self.corner + self.u,
+            self.corner + self.u + self.v,
+            self.corner + self.v,
+        ];
+        mesh.add_triangle_facing([a, b, c], self.normal(), &self.material);
+        mesh.add_triangle_facing([a, c, d], self.normal(), &self.material);
+    }
+}
+
+/// A flat disk facing along `normal`, e.g. the face of a cylinder or, with a
+/// `DiffuseLight` material, a light such as a round ceiling panel, which
+/// shines from the side it faces.
+///
+/// Texture coordinates run from 0 to 1 across the square around it.
+#[derive(Clone)]
+pub struct Disk {
+    pub center: Point3,
+    /// Unit normal on the side it faces.
+    pub normal: Vec3,
+    pub radius: f64,
+    pub material: Material,
+}
+
+impl Disk {
+    /// A disk, with any light `material` gives off in total spread over its
+    /// front.
+    pub fn new(center: Point3, normal: Vec3, radius: f64, material: Material) -> Self {
+        Self {
+            center,
+            normal: normal.unit_vector(),
+            radius,
+            material: material.emitting_over(PI * radius * radius),
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        PI * self.radius * self.radius
+    }
+
+    /// Directions across the disk, along which texture coordinates run.
+    pub fn basis(&self) -> Onb {
+        Onb::new(self.normal)
+    }
+
+    /// Where `r` crosses the disk between `t_min` and `t_max`, as the
+    /// distance along it and its texture coordinates there.
+    pub(crate) fn intersect(&self, r: Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let denominator = self.normal.dot_product(r.direction);
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+
+        let t = self.normal.dot_product(self.center - r.origin) / denominator;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let q = r.at(t) - self.center;
+        if q.length_squared() > self.radius * self.radius {
+            return None;
+        }
+
+        let basis = self.basis();
+        let a = 0.5 + q.dot_product(basis.u) / (2. * self.radius);
+        let b = 0.5 + q.dot_product(basis.v) / (2. * self.radius);
+        Some((t, a, b))
+    }
+}
+
+impl Hit for Disk {
+    fn hit(&self, r: Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, a, b) = self.intersect(r, t_min, t_max)?;
+        let basis = self.basis();
+
+        Some(HitRecord::new(
+            t,
+            r,
+            self.normal,
+            (a, b),
+            SurfaceDerivatives::flat(basis.u * (2. * self.radius), basis.v * (2. * self.radius)),
+            &self.material,
+        ))
+    }
+
+    fn occluded(&self, r: Ray, t_min: f64, t_max: f64) -> bool {
+        self.intersect(r, t_min, t_max).is_some()
+    }
+
+    fn bounds(&self, _time: (f64, f64)) -> Option<AABB> {
+        // How far the rim reaches along each axis
+        let n = self.normal;
+        let reach = |component: f64| self.radius * (1. - component * component).max(0.).sqrt();
+        let extent = Vec3::new(reach(n.x()), reach(n.y()), reach(n.z()))
+            + Vec3::new(BOUNDS_PADDING, BOUNDS_PADDING, BOUNDS_PADDING);
+
+        Some(AABB::new(self.center - extent, self.center + extent))
+    }
+
+    fn count(&self, stats: &mut SceneStats) {
+        stats.add_surface("disk", std::mem::size_of::<Self>(), &self.material);
+    }
+
+    fn tessellate(&self, _time: f64, mesh: &mut Tessellation) {
+        let basis = self.basis();
+        let segments = mesh.segments(self.radius, 2. * PI);
+        let rim = |i: usize| {
+            let (sin, cos) = (2. * PI * i as f64 / segments as f64).sin_cos();
+            self.center + (basis.u * cos + basis.v * sin) * self.radius
+        };
+        for i in 0..segments {
+            mesh.add_triangle_facing(
+                [self.center, rim(i), rim(i + 1)],
+                self.normal,
+                &self.material,
+            );
+        }
+    }
+}
+
+/// Texture coordinates of the point on a sphere with (unit) normal `n`, with
+/// u going around the y axis and v from the bottom pole to the top.
+pub(crate) fn sphere_uv(n: Vec3, radius: f64) -> ((f64, f64), SurfaceDerivatives) {
+    let theta = (-n.y()).clamp(-1., 1.).acos();
+    let phi = (-n.z()).atan2(n.x()) + PI;
+    let uv = (phi / (2. * PI), theta / PI);
+
+    // n = (-sin(theta) cos(phi), -cos(theta), sin(theta) sin(phi))
+    let (sin_theta, cos_theta) = theta.sin_cos();
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let dndu = Vec3::new(sin_theta * sin_phi, 0., sin_theta * cos_phi) * (2. * PI);
+    let dndv = Vec3::new(-cos_theta * cos_phi, sin_theta, cos_theta * sin_phi) * PI;
+
+    let derivatives = SurfaceDerivatives {
+        dpdu: dndu * radius,
+        dpdv: dndv * radius,
+        dndu,
+        dndv,
+    };
+
+    (uv, derivatives)
+}
+
+/// The nearest distance along `r` within `t_min` and `t_max` where it meets
+/// the sphere at `center`.
+pub(crate) fn sphere_root(
+    center: Point3,
+    radius: f64,
+    r: Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<f64> {
+    // Find the nearest root within the specified range (t_min, t_max)
+    sphere_roots(center, radius, r)?
+        .into_iter()
+        .find(|&t| t_min <= t && t <= t_max)
+}
+
+/// Both distances along `r` where it meets the sphere at `center`, nearest
+/// first.
+pub(crate) fn sphere_roots(center: Point3, radius: f64, r: Ray) -> Option<[f64; 2]> {
+    let oc = r.origin - center;
+    let a = r.direction.length_squared();
+    let half_b = oc.dot_product(r.direction);
+    let c = oc.length_squared() - radius * radius;
+
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0. {
+        return None;
+    }
+
+    let sqrtd = discriminant.sqrt();
+    Some([
+        (-half_b - sqrtd) / a, // 1st root
+        (-half_b + sqrtd) / a, // 2nd root
+    ])
 }
@@ -0,0 +1,120 @@
+use std::sync::OnceLock;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::perlin::{permutation, POINT_COUNT};
+use crate::vector::{Point3, Vec3};
+
+/// Seed for the noise shared by the built-in textures, so a scene looks the
+/// same from one render to the next.
+const SHARED_SEED: u64 = 0xce11;
+
+/// How the distance to a feature point is measured.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    /// Round cells.
+    Euclidean,
+    /// Diamond shaped cells.
+    Manhattan,
+    /// Square cells, like scales or tiles.
+    Chebyshev,
+}
+
+impl Metric {
+    fn distance(self, d: Vec3) -> f64 {
+        match self {
+            Metric::Euclidean => d.length(),
+            Metric::Manhattan => d.x().abs() + d.y().abs() + d.z().abs(),
+            Metric::Chebyshev => d.x().abs().max(d.y().abs()).max(d.z().abs()),
+        }
+    }
+}
+
+/// Which distances a Worley texture is shaded by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// Distance to the closest point, dark at the cell centers.
+    F1,
+    /// Distance to the second closest point.
+    F2,
+    /// Zero along the borders between cells, for cracks and outlines.
+    F2MinusF1,
+}
+
+/// Cellular noise: one random feature point in each unit cell of space,
+/// shaded by the distances to the nearest of them.
+pub struct Worley {
+    offsets: Vec<Vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl Worley {
+    pub fn new<T: Rng>(rng: &mut T) -> Self {
+        let offsets = (0..POINT_COUNT).map(|_| Vec3::random(rng)).collect();
+
+        Self {
+            offsets,
+            perm_x: permutation(rng),
+            perm_y: permutation(rng),
+            perm_z: permutation(rng),
+        }
+    }
+
+    /// The noise used by textures.
+    pub fn shared() -> &'static Worley {
+        static SHARED: OnceLock<Worley> = OnceLock::new();
+        SHARED.get_or_init(|| Worley::new(&mut StdRng::seed_from_u64(SHARED_SEED)))
+    }
+
+    /// The distances from `p` to the closest and second closest feature
+    /// points.
+    pub fn distances(&self, p: Point3, metric: Metric) -> (f64, f64) {
+        let cell = (
+            p.x().floor() as i64,
+            p.y().floor() as i64,
+            p.z().floor() as i64,
+        );
+
+        let mut f1 = f64::INFINITY;
+        let mut f2 = f64::INFINITY;
+
+        // A point is never further than one cell from its closest features
+        for i in cell.0 - 1..=cell.0 + 1 {
+            for j in cell.1 - 1..=cell.1 + 1 {
+                for k in cell.2 - 1..=cell.2 + 1 {
+                    let feature = Point3::new(i as f64, j as f64, k as f64) + self.offset(i, j, k);
+                    let distance = metric.distance(feature - p);
+
+                    if distance < f1 {
+                        f2 = f1;
+                        f1 = distance;
+                    } else if distance < f2 {
+                        f2 = distance;
+                    }
+                }
+            }
+        }
+
+        (f1, f2)
+    }
+
+    pub fn value(&self, p: Point3, metric: Metric, feature: Feature) -> f64 {
+        let (f1, f2) = self.distances(p, metric);
+
+        match feature {
+            Feature::F1 => f1,
+            Feature::F2 => f2,
+            Feature::F2MinusF1 => f2 - f1,
+        }
+    }
+
+    fn offset(&self, i: i64, j: i64, k: i64) -> Vec3 {
+        let index = self.perm_x[i as usize & (POINT_COUNT - 1)]
+            ^ self.perm_y[j as usize & (POINT_COUNT - 1)]
+            ^ self.perm_z[k as usize & (POINT_COUNT - 1)];
+        self.offsets[index]
+    }
+}